@@ -0,0 +1,32 @@
+//! What a poll costs against the devices of the machine it runs on:
+//! `AudioState::update` and drawing a full frame. Needs macOS, run with
+//! `cargo bench`. Read-only, nothing is changed.
+
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use mac_controls::audio::{self, AudioState};
+use mac_controls::config::Config;
+use mac_controls::state::AppState;
+use mac_controls::tui::Renderer;
+
+fn update(c: &mut Criterion) {
+    audio::set_read_only(true);
+    let mut audio = AudioState::new(&Config::load());
+    c.bench_function("update", |b| b.iter(|| audio.update()));
+}
+
+fn draw(c: &mut Criterion) {
+    audio::set_read_only(true);
+    let mut state = AppState::new();
+    // Every device read, not the names-only first frame
+    state.audio = AudioState::new(&Config::load());
+    // A new renderer has nothing on screen, so each frame is drawn whole
+    c.bench_function("draw", |b| {
+        b.iter(|| Renderer::new().draw(&mut io::sink(), &state))
+    });
+}
+
+criterion_group!(benches, update, draw);
+criterion_main!(benches);
@@ -1,4 +1,13 @@
 fn main() {
     println!("cargo:rustc-link-lib=framework=IOKit");
     println!("cargo:rustc-link-lib=framework=CoreAudio");
+    println!("cargo:rustc-link-lib=framework=AppKit");
+    println!("cargo:rustc-link-lib=framework=Carbon");
+    println!("cargo:rustc-link-lib=framework=AVFoundation");
+    println!("cargo:rustc-link-lib=framework=IOBluetooth");
+    println!("cargo:rustc-link-lib=objc");
+    if std::env::var_os("CARGO_FEATURE_GESTURES").is_some() {
+        println!("cargo:rustc-link-search=framework=/System/Library/PrivateFrameworks");
+        println!("cargo:rustc-link-lib=framework=MultitouchSupport");
+    }
 }
@@ -0,0 +1,58 @@
+//! Alert and sound effect volume. It isn't a CoreAudio property: macOS
+//! keeps it in the global preferences as a fraction of the alert device's
+//! volume, and only picks up changes made through its own channels, so
+//! it's set through AppleScript like the Sound settings do.
+//!
+//! The device alerts play on is CoreAudio's, see `audio::alert_device`.
+
+use std::process::Command;
+
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::number::CFNumber;
+use core_foundation::string::{CFString, CFStringRef};
+
+use crate::audio;
+
+/// Alert volume change per arrow press.
+pub const STEP: f32 = 0.1;
+const VOLUME_KEY: &str = "com.apple.sound.beep.volume";
+
+extern "C" {
+    static kCFPreferencesAnyApplication: CFStringRef;
+    fn CFPreferencesAppSynchronize(app: CFStringRef) -> bool;
+    fn CFPreferencesCopyAppValue(key: CFStringRef, app: CFStringRef) -> CFTypeRef;
+}
+
+/// Alert volume, 0.0 - 1.0. Full when never changed.
+pub fn volume() -> f32 {
+    let key = CFString::new(VOLUME_KEY);
+    let value = unsafe {
+        // Another process changed it, the cached value is stale
+        CFPreferencesAppSynchronize(kCFPreferencesAnyApplication);
+        let value =
+            CFPreferencesCopyAppValue(key.as_concrete_TypeRef(), kCFPreferencesAnyApplication);
+        if value.is_null() {
+            return 1.0;
+        }
+        CFType::wrap_under_create_rule(value)
+    };
+    value
+        .downcast::<CFNumber>()
+        .and_then(|n| n.to_f32())
+        .map_or(1.0, |v| v.clamp(0.0, 1.0))
+}
+
+pub fn set_volume(level: f32) -> Result<(), String> {
+    if audio::is_read_only() {
+        return Ok(());
+    }
+    let percent = (level.clamp(0.0, 1.0) * 100.0).round();
+    let status = Command::new("osascript")
+        .args(["-e", &format!("set volume alert volume {percent}")])
+        .status()
+        .map_err(|e| format!("Unable to run osascript: {e}"))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err("Couldn't set the alert volume".to_string()),
+    }
+}
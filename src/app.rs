@@ -0,0 +1,575 @@
+//! The `mac-controls` command: subcommands like `list` or `backup` run and
+//! exit, anything else starts the app, which feeds key, device, timer and
+//! remote actions through `AppState::apply` and runs the effects it
+//! returns. `main.rs` only hands it the arguments.
+
+use std::fs::File;
+use std::io::{self, stdin, stdout, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::os::raw::c_int;
+use std::panic;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use termion::event::{parse_event, Event};
+use termion::raw::IntoRawMode;
+
+#[cfg(feature = "gestures")]
+use crate::gestures;
+use crate::{
+    attach, backup, cli, explorer, ipc, keymap, log, macros, mediakeys, profiles, quick, shortcut,
+    splash, stats, store, tokens, watch, wizard,
+};
+
+use crate::audio::{self, Channel};
+use crate::console::Console;
+use crate::events::{Action, EventTap, TapStatus};
+use crate::homeassistant::HomeAssistant;
+use crate::instance::Instance;
+use crate::listener::Listeners;
+use crate::meter::{self, Meter};
+use crate::plugins::{self, Plugin};
+use crate::reducer::Effect;
+use crate::session::{self, Recorder};
+use crate::state::AppState;
+use crate::tasks::{Cancel, Tasks};
+use crate::tui::{self, Renderer};
+
+/// How long background tasks get to stop on exit.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Report a command line problem and quit.
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1)
+}
+
+/// Run the command in `args`, without the program name, or the app.
+/// `--record <file>` logs performed actions, `replay <file>` re-applies
+/// them.
+pub fn run(args: Vec<String>) {
+    // `--restore-mutes` turns workaround mutes into system mutes on exit
+    let restore_mutes = args.iter().any(|a| a == "--restore-mutes");
+    // `--read-only` disables every setter, for use as a dashboard
+    audio::set_read_only(args.iter().any(|a| a == "--read-only"));
+    let mut recorder = args
+        .iter()
+        .position(|a| a == "--record")
+        .and_then(|i| args.get(i + 1))
+        .map(|path| Recorder::create(path).unwrap_or_else(|e| fail(e)));
+    // `backup create|restore [file]` moves the app data to another Mac
+    if args.first().map(|a| a.as_str()) == Some("backup") {
+        match backup::run(&args[1..]) {
+            Ok(message) => println!("{message}"),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    // `profile save|apply|diff|list [name]` snapshots and restores the setup
+    if args.first().map(|a| a.as_str()) == Some("profile") {
+        match profiles::run(&args[1..]) {
+            Ok(message) => println!("{message}"),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    // `property get|set <device> <query>` reads and writes raw properties
+    if args.first().map(|a| a.as_str()) == Some("property") {
+        match explorer::run(&args[1..]) {
+            Ok(message) => println!("{message}"),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    // `server token [revoke <token>]` manages tokens for network access
+    if args.first().map(|a| a.as_str()) == Some("server") {
+        match tokens::run(&args[1..]) {
+            Ok(message) => println!("{message}"),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    // `list`, `status` and `summary` show devices, `set-volume`, `mute`,
+    // `unmute` and `default` change one, then exit without key events
+    if args
+        .first()
+        .is_some_and(|a| cli::COMMANDS.contains(&a.as_str()))
+    {
+        match cli::run(&args) {
+            Ok(message) if message.is_empty() => {}
+            Ok(message) => println!("{message}"),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+    // `shortcut <operation> ...` is for automation, with stable exit codes
+    if args.first().map(|a| a.as_str()) == Some("shortcut") {
+        match shortcut::run(&args[1..]) {
+            Ok(message) => println!("{message}"),
+            Err((code, e)) => {
+                eprintln!("{e}");
+                std::process::exit(code)
+            }
+        }
+        return;
+    }
+    // `watch` prints a JSON line per device change until stdout closes
+    if args.first().map(|a| a.as_str()) == Some("watch") {
+        watch::run();
+        return;
+    }
+    // `stats` prints how often actions were used
+    if args.first().map(|a| a.as_str()) == Some("stats") {
+        print!("{}", stats::report());
+        return;
+    }
+    // `macro <name>` runs a saved macro and exits
+    let replay = match args.first().map(|a| a.as_str()) {
+        Some("replay") => match args.get(1) {
+            Some(path) => Some(session::load(path).unwrap_or_else(|e| fail(e))),
+            None => fail("Usage: mac-controls replay <file>"),
+        },
+        Some("macro") => match args.get(1) {
+            Some(name) => {
+                let mut actions = macros::load(name).unwrap_or_else(|e| fail(e));
+                actions.push(Action::Exit);
+                Some(actions.into_iter().map(|a| (Duration::ZERO, a)).collect())
+            }
+            None => fail("Usage: mac-controls macro <name>"),
+        },
+        // Anything else is a `mac-controls-<name>` plugin
+        Some(name) if !matches!(name, "daemon" | "attach") && !name.starts_with('-') => {
+            match plugins::run(name, &args[1..]) {
+                Ok(code) => std::process::exit(code),
+                Err(e) => fail(e),
+            }
+        }
+        _ => None,
+    };
+
+    // Replayed actions aren't counted in the usage stats
+    let replaying = replay.is_some();
+    // `daemon` runs without the TUI, taking commands over a Unix socket
+    let daemon = args.first().map(|a| a.as_str()) == Some("daemon");
+    // Without a terminal (launchd, cron, a pipe) there's no TUI, just the
+    // key bindings and macros. `--tui` forces it.
+    let tui = !daemon && (args.iter().any(|a| a == "--tui") || termion::is_tty(&stdout()));
+    // `--takeover` replaces a running instance instead of attaching to or
+    // refusing to start next to it
+    let takeover = args.iter().any(|a| a == "--takeover");
+    let attach_to = match args.first().map(|a| a.as_str()) {
+        // `attach <host>:<port>` is the daemon on another Mac
+        Some("attach") => {
+            let (Some(address), true) = (args.get(1), tui) else {
+                fail("Usage: MAC_CONTROLS_TOKEN=<token> mac-controls attach <host>:<port>, in a terminal");
+            };
+            let token = std::env::var("MAC_CONTROLS_TOKEN").unwrap_or_else(|_| {
+                fail("Set MAC_CONTROLS_TOKEN to a token from `mac-controls server token` on that Mac")
+            });
+            Some(ipc::Client::connect_remote(address, &token).unwrap_or_else(|e| fail(e)))
+        }
+        _ if tui && !takeover => ipc::Client::connect(),
+        _ => None,
+    };
+    // Held until exit, one instance's taps and listeners are enough
+    let instance = match (&attach_to, takeover) {
+        (Some(_), _) => None,
+        (None, true) => Some(Instance::take_over().unwrap_or_else(|e| fail(e))),
+        (None, false) => Some(Instance::acquire().unwrap_or_else(|e| fail(e))),
+    };
+    let stdin = stdin();
+    let mut stdout: Box<dyn Write> = if tui {
+        let saved = saved_terminal();
+        let mut stdout = stdout()
+            .into_raw_mode()
+            .unwrap_or_else(|e| fail(format!("Unable to set up the terminal: {e}")));
+        // A panic on any thread would leave the terminal raw and without
+        // a cursor
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal(saved.as_ref());
+            default_hook(info);
+            std::process::exit(101);
+        }));
+        // A running daemon owns the tap and the mute takeovers, a second
+        // instance would fight it
+        if let Some(client) = attach_to {
+            log::info("Attached to the daemon");
+            let detached = attach::run(&mut stdout, stdin, client);
+            let _ = write!(&mut stdout, "{}", termion::cursor::Show);
+            drop(stdout);
+            if let Err(e) = detached {
+                fail(e);
+            }
+            return;
+        }
+        if splash::is_needed() && !splash::run(&mut stdout, &stdin) {
+            let _ = write!(&mut stdout, "{}", termion::cursor::Show);
+            return;
+        }
+        if wizard::is_needed() && !audio::is_read_only() {
+            if let Err(e) = wizard::run(&mut stdout, &stdin) {
+                log::error(e);
+            }
+        }
+        Box::new(stdout)
+    } else {
+        Box::new(io::sink())
+    };
+    log::info(match tui {
+        true => "Started",
+        false => "Started without a terminal, the TUI is off",
+    });
+    let mut state = AppState::new();
+    state.replaying = replaying;
+    if !state.key_access {
+        log::warn("Missing permissions, running without key events");
+    } else if !state.permissions.can_post() {
+        log::warn("Missing Accessibility, bound keys can't be swallowed or volume keys routed");
+    }
+
+    // Listen for events in separate threads
+    let (tx1, rx) = channel();
+    let tx2 = tx1.clone();
+    let meter_tx = tx1.clone();
+    let tx4 = tx1.clone();
+    let tx5 = tx1.clone();
+    let tx6 = tx1.clone();
+    // Key bindings are dispatched back through the loop
+    let bound_tx = tx1.clone();
+    // Everything else feeding the loop, stopped together on exit
+    let mut tasks = Tasks::new();
+    // Tap into OS key events, restarted by the watchdog
+    let mut tap = state.key_access.then(|| {
+        let tx = tx1.clone();
+        EventTap::start(
+            state.tap_health.clone(),
+            state.tap_control.clone(),
+            move |action| {
+                let _ = tx.send(action);
+            },
+        )
+    });
+    // Quick actions over HTTP, e.g. from an iPhone Shortcut
+    if let Some((port, token, lan, tls)) = state.quick.clone() {
+        let tx = tx1.clone();
+        let started = quick::start(&mut tasks, port, lan, token, tls, move |action| {
+            let _ = tx.send(action);
+        });
+        if let Err(e) = started {
+            state.notify(e);
+        }
+    }
+    // Control API of the daemon
+    if daemon {
+        let tx = tx1.clone();
+        let started = ipc::start(&mut tasks, move |action| {
+            let _ = tx.send(action);
+        });
+        if let Err(e) = started {
+            fail(e);
+        }
+        log::info(format!("Listening on {:?}", ipc::socket_path()));
+        if let Some(port) = state.remote_port {
+            let tx = tx1.clone();
+            let started = ipc::start_remote(&mut tasks, port, move |action| {
+                let _ = tx.send(action);
+            });
+            match started {
+                Ok(()) => log::info(format!("Taking remote terminals on port {port}")),
+                Err(e) => fail(e),
+            }
+        }
+    }
+    // Plugins get their events on stdin and send commands on stdout
+    let mut plugins = Vec::new();
+    for (name, events) in state.plugins.clone() {
+        let tx = tx1.clone();
+        let started = Plugin::start(&name, move |action| {
+            let _ = tx.send(action);
+        });
+        match started {
+            Ok(plugin) => {
+                let plugin = Arc::new(plugin);
+                state.sinks.attach(&events, plugin.clone());
+                plugins.push(plugin);
+            }
+            Err(e) => state.notify(e),
+        }
+    }
+    // Home Assistant over MQTT, connected on the first sync
+    let mut home_assistant = state.mqtt.clone().map(|config| {
+        let tx = tx1.clone();
+        HomeAssistant::new(config, move |action| {
+            let _ = tx.send(action);
+        })
+    });
+    #[cfg(feature = "gestures")]
+    {
+        let tx = tx6.clone();
+        let _ = gestures::start(move |action| {
+            let _ = tx.send(action);
+        });
+    }
+    let route_keys = state.route_keys.clone();
+    // Taking the volume keys needs an active tap
+    if state.key_access && state.permissions.can_post() {
+        // Hardware volume keys, only taken while routing is on
+        tasks.spawn("media keys", move |cancel| {
+            let _ = mediakeys::media_key_tap(route_keys, cancel, move |action| {
+                let _ = tx6.send(action);
+            });
+        });
+    }
+    if tui {
+        // Terminal key events for focused control
+        tasks.spawn("terminal", move |cancel| read_terminal_keys(&cancel, &tx2));
+        // Laid out again right away instead of on the next poll
+        let tx = tx1.clone();
+        tasks.spawn("resize", move |cancel| {
+            tui::watch_resize(&cancel, |action| {
+                let _ = tx.send(action);
+            });
+        });
+    }
+    if let Some(actions) = replay {
+        tasks.spawn("replay", move |cancel| {
+            session::replay(actions, &cancel, |action| {
+                let _ = tx4.send(action);
+            });
+        });
+    }
+
+    // Meter the default input, restarted whenever the default changes
+    let mut meter: Option<Meter> = None;
+    let start_meter = |state: &mut AppState, meter: &mut Option<Meter>| {
+        let active = state.audio.active_id(Channel::Input);
+        if meter.as_ref().map(|m| m.device()) == active {
+            return;
+        }
+        *meter = None;
+        state.meter.reset();
+        state.calibration = None;
+        if let Some(auto_gain) = state.auto_gain.as_mut() {
+            auto_gain.reset();
+        }
+        state.gain_suggestion = state
+            .audio
+            .active_device(Channel::Input)
+            .and_then(|d| meter::saved_gain(&d.uid).map(|level| (d.uid.clone(), level)));
+        if let Some(id) = active {
+            let tx = meter_tx.clone();
+            *meter = Meter::start(id, move |action| {
+                let _ = tx.send(action);
+            })
+            .ok();
+        }
+    };
+    start_meter(&mut state, &mut meter);
+    state.collect_notices();
+    state.check_osd();
+    state.check_mute_since();
+    state.check_rate_mismatch();
+
+    // CoreAudio notifications for device, default and volume changes
+    let mut listeners = Listeners::new(state.listener_stats.clone(), move |action| {
+        let _ = tx5.send(action);
+    });
+    listeners.watch(&state.audio.device_ids());
+
+    // Paused while another user is on the console
+    let mut console = Console::new();
+
+    // Initial draw
+    let _ = writeln!(stdout, "{}{}", termion::clear::All, termion::cursor::Hide);
+    let mut renderer = Renderer::new();
+    renderer.draw(&mut stdout, &state);
+
+    // Waiting for events or the next deadline, the poll or a frame. Draws
+    // in a burst are coalesced into one frame that goes out once the channel
+    // is quiet. Senders are kept for the loop's own use, so this only ends
+    // on exit
+    let poll_interval = state.poll_interval;
+    let mut next_poll = Instant::now() + poll_interval;
+    'events: loop {
+        let until_poll = next_poll.saturating_duration_since(Instant::now());
+        let action = match renderer.pending() {
+            Some(wait) if wait < until_poll => match rx.recv_timeout(wait) {
+                Ok(action) => action,
+                Err(RecvTimeoutError::Timeout) => {
+                    renderer.flush(&mut stdout, &state);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            // Due, even while events keep coming
+            _ if until_poll.is_zero() => Action::Poll,
+            _ => match rx.recv_timeout(until_poll) {
+                Ok(action) => action,
+                Err(RecvTimeoutError::Timeout) => Action::Poll,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+        };
+        if matches!(action, Action::Poll) {
+            next_poll = Instant::now() + poll_interval;
+        }
+        let Some(action) = state.resolve(action) else {
+            continue;
+        };
+        // Keys, servers, plugins and timers all wait for the user to be back
+        if !console.is_active() && !matches!(action, Action::Poll | Action::Exit) {
+            continue;
+        }
+        if matches!(action, Action::Poll) {
+            if instance.as_ref().is_some_and(|i| i.takeover_requested()) {
+                log::info("Another instance is taking over, exiting");
+                break;
+            }
+            match console.check() {
+                Some(false) => {
+                    log::info("Another user is active, pausing");
+                    if let Some(tap) = &mut tap {
+                        tap.stop();
+                    }
+                    meter = None;
+                    state.meter.reset();
+                    state.calibration = None;
+                    store::flush(true);
+                    continue;
+                }
+                Some(true) => {
+                    log::info("Back on the console, resuming");
+                    if let Some(tap) = &mut tap {
+                        tap.restart();
+                    }
+                }
+                None if !console.is_active() => continue,
+                None => (),
+            }
+            // Watchdog: bring back a dead or disabled event tap
+            if let Some(tap) = &mut tap {
+                if state.tap_health.should_restart() {
+                    log::warn("Event tap died, restarting it");
+                    tap.restart();
+                } else if state.tap_health.status() == TapStatus::Disabled {
+                    state.tap_health.reenable("found disabled");
+                }
+            }
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(&action);
+        }
+        for effect in state.apply(action) {
+            match effect {
+                Effect::Draw => renderer.draw(&mut stdout, &state),
+                Effect::Redraw => {
+                    renderer.invalidate();
+                    renderer.draw(&mut stdout, &state);
+                }
+                Effect::Dispatch(action) => {
+                    let _ = bound_tx.send(action);
+                }
+                Effect::WatchDevices => {
+                    start_meter(&mut state, &mut meter);
+                    listeners.watch(&state.audio.device_ids());
+                }
+                Effect::SyncHomeAssistant => {
+                    if let Some(home_assistant) = &mut home_assistant {
+                        home_assistant.sync(&state.audio);
+                    }
+                }
+                Effect::Exit => break 'events,
+                effect => state.run(effect),
+            }
+        }
+    }
+
+    // Clean up before exit
+    for plugin in &plugins {
+        plugin.stop();
+    }
+    if let Some(home_assistant) = &mut home_assistant {
+        home_assistant.disconnect();
+    }
+    if let Some(tap) = &mut tap {
+        tap.stop();
+    }
+    drop(listeners);
+    drop(meter);
+    tasks.shutdown(SHUTDOWN_TIMEOUT);
+    // After the socket task, which wakes up through the socket
+    if daemon {
+        ipc::stop();
+    }
+    state.audio.save_history();
+    if restore_mutes {
+        state.audio.restore_system_mutes();
+    }
+    store::flush(true);
+    let _ = write!(&mut stdout, "{}", termion::cursor::Show);
+    let _ = stdout.flush();
+}
+
+/// Terminal keys until cancelled. Reads the descriptor itself, bytes in
+/// stdin's buffer wouldn't wake `wait_readable`.
+fn read_terminal_keys(cancel: &Cancel, tx: &Sender<Action>) {
+    // Belongs to stdin, not closed here
+    let mut terminal = ManuallyDrop::new(unsafe { File::from_raw_fd(0) });
+    let mut buffer = [0; 64];
+    while cancel.wait_readable(0) {
+        let read = match terminal.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        let mut rest = &buffer[..read];
+        while let Some((&byte, after)) = rest.split_first() {
+            let action = if let Some((key, len)) = keymap::parse_shifted(rest) {
+                rest = &rest[len..];
+                Some(Action::ShiftedKey(key))
+            } else {
+                let mut bytes = after.iter();
+                let event = parse_event(byte, &mut (&mut bytes).map(|&b| Ok::<u8, io::Error>(b)));
+                rest = bytes.as_slice();
+                match event {
+                    Ok(Event::Key(key)) => Some(Action::TerminalKey(key)),
+                    _ => None,
+                }
+            };
+            if action.is_some_and(|action| tx.send(action).is_err()) {
+                return;
+            }
+        }
+    }
+}
+
+/// A `struct termios`, only ever handed back to `tcsetattr`, so it's left
+/// opaque. Bigger than any platform's.
+#[repr(C, align(8))]
+struct Termios([u8; 256]);
+
+extern "C" {
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+}
+
+const STDOUT: c_int = 1;
+const TCSANOW: c_int = 0;
+
+/// The terminal's settings before raw mode, for `restore_terminal`.
+fn saved_terminal() -> Option<Termios> {
+    let mut termios = Termios([0; 256]);
+    (unsafe { tcgetattr(STDOUT, &mut termios) } == 0).then_some(termios)
+}
+
+/// Undo raw mode and show the cursor, for exits that skip the terminal's
+/// own cleanup, like a panic.
+fn restore_terminal(saved: Option<&Termios>) {
+    if let Some(termios) = saved {
+        unsafe { tcsetattr(STDOUT, TCSANOW, termios) };
+    }
+    print!("{}{}\r\n", termion::cursor::Show, termion::style::Reset);
+    let _ = io::stdout().flush();
+}
@@ -0,0 +1,527 @@
+//! Per-app volume through Core Audio process taps, macOS 14.4 and up.
+//!
+//! Turning an app down taps its audio with the original muted, and plays
+//! the tap back scaled on the default output, through a private aggregate
+//! device of that output and the tap. Back at full volume, the tap is
+//! removed and the app plays on its own again.
+
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::{CFString, CFStringRef};
+use std::collections::HashMap;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::audio::{self, Channel};
+use crate::coreaudio::*;
+use crate::objc::{
+    msg_send, msg_send_bool, msg_send_long, msg_send_object, msg_send_u32, objc_autoreleasePoolPop,
+    objc_autoreleasePoolPush, objc_getClass, sel_registerName,
+};
+
+/// First macOS with process taps that can be listed and named.
+const MIN_VERSION: (u32, u32) = (14, 4);
+/// `RTLD_DEFAULT`, every loaded image.
+const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
+/// `CATapMutedWhenTapped`: the app is silent only while the tap is read.
+const MUTED_WHEN_TAPPED: isize = 2;
+/// `CATapUnmuted`: the tapped audio still plays as usual.
+const UNMUTED: isize = 0;
+/// Volume change per arrow press.
+pub const STEP: f32 = 0.1;
+
+extern "C" {
+    fn sysctlbyname(
+        name: *const c_char,
+        oldp: *mut c_void,
+        oldlenp: *mut usize,
+        newp: *mut c_void,
+        newlen: usize,
+    ) -> c_int;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn proc_name(pid: c_int, buffer: *mut c_void, size: u32) -> c_int;
+
+}
+
+/// A process with a Core Audio client.
+#[derive(Debug, Clone)]
+pub struct App {
+    /// The process object, what taps are made of.
+    pub object: AudioObjectID,
+    pub pid: i32,
+    pub name: String,
+    pub bundle: String,
+    /// Playing audio right now.
+    pub playing: bool,
+}
+
+/// Why per-app volume isn't available here, if it isn't.
+pub fn unsupported() -> Option<String> {
+    let version = os_version();
+    let parts: Vec<u32> = version.split('.').filter_map(|p| p.parse().ok()).collect();
+    let (major, minor) = (
+        parts.first().copied().unwrap_or(0),
+        parts.get(1).copied().unwrap_or(0),
+    );
+    if (major, minor) < MIN_VERSION {
+        let version = match version.is_empty() {
+            true => "an unknown version".to_string(),
+            false => version,
+        };
+        return Some(format!(
+            "Per-app volume needs macOS {}.{} or later, this Mac runs {version}",
+            MIN_VERSION.0, MIN_VERSION.1
+        ));
+    }
+    if tap_functions().is_none() {
+        return Some("Process taps aren't available on this system".to_string());
+    }
+    None
+}
+
+/// `kern.osproductversion`, like "14.4.1".
+fn os_version() -> String {
+    let mut buffer = [0u8; 32];
+    let mut len = buffer.len();
+    let result = unsafe {
+        sysctlbyname(
+            c"kern.osproductversion".as_ptr(),
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        return String::new();
+    }
+    let end = buffer[..len].iter().position(|b| *b == 0).unwrap_or(len);
+    String::from_utf8_lossy(&buffer[..end]).to_string()
+}
+
+/// The process tap functions, when this macOS has them.
+pub fn tap_functions() -> Option<(
+    AudioHardwareCreateProcessTap,
+    AudioHardwareDestroyProcessTap,
+)> {
+    unsafe {
+        let create = dlsym(RTLD_DEFAULT, c"AudioHardwareCreateProcessTap".as_ptr());
+        let destroy = dlsym(RTLD_DEFAULT, c"AudioHardwareDestroyProcessTap".as_ptr());
+        if create.is_null() || destroy.is_null() {
+            return None;
+        }
+        Some((
+            std::mem::transmute::<*mut c_void, AudioHardwareCreateProcessTap>(create),
+            std::mem::transmute::<*mut c_void, AudioHardwareDestroyProcessTap>(destroy),
+        ))
+    }
+}
+
+/// Processes with audio, playing ones first, then by name.
+pub fn list() -> Result<Vec<App>, String> {
+    if let Some(reason) = unsupported() {
+        return Err(reason);
+    }
+    let system = kAudioObjectSystemObject;
+    let bytes = audio::property_bytes(
+        &system,
+        kAudioHardwarePropertyProcessObjectList,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    )?;
+    let mut apps: Vec<App> = bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .filter_map(app)
+        .collect();
+    apps.sort_by_key(|app| (!app.playing, app.name.to_lowercase()));
+    Ok(apps)
+}
+
+fn app(object: AudioObjectID) -> Option<App> {
+    let pid = read_u32(object, kAudioProcessPropertyPID)? as i32;
+    let bundle = read_string(object, kAudioProcessPropertyBundleID).unwrap_or_default();
+    let playing = read_u32(object, kAudioProcessPropertyIsRunningOutput) == Some(1);
+    let name = process_name(pid)
+        .or_else(|| bundle.rsplit('.').next().map(|n| n.to_string()))
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| format!("pid {pid}"));
+    Some(App {
+        object,
+        pid,
+        name,
+        bundle,
+        playing,
+    })
+}
+
+fn read_u32(object: AudioObjectID, selector: u32) -> Option<u32> {
+    let bytes = audio::property_bytes(
+        &object,
+        selector,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    )
+    .ok()?;
+    Some(u32::from_ne_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+fn read_string(object: AudioObjectID, selector: u32) -> Option<String> {
+    let bytes = audio::property_bytes(
+        &object,
+        selector,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    )
+    .ok()?;
+    let pointer = usize::from_ne_bytes(bytes.get(..8)?.try_into().ok()?) as CFStringRef;
+    if pointer.is_null() {
+        return None;
+    }
+    // The property hands over a retained string
+    Some(unsafe { CFString::wrap_under_create_rule(pointer) }.to_string())
+}
+
+pub fn process_name(pid: i32) -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let len = unsafe { proc_name(pid, buffer.as_mut_ptr() as *mut c_void, buffer.len() as u32) };
+    (len > 0).then(|| String::from_utf8_lossy(&buffer[..len as usize]).to_string())
+}
+
+/// App volumes other than full, by process ID.
+#[derive(Debug, Default)]
+pub struct AppVolumes {
+    apps: HashMap<i32, AppVolume>,
+}
+
+#[derive(Debug)]
+struct AppVolume {
+    level: f32,
+    muted: bool,
+    tap: Tap,
+}
+
+impl AppVolumes {
+    /// (level, muted) of an app, full and unmuted unless changed.
+    pub fn get(&self, pid: i32) -> (f32, bool) {
+        self.apps
+            .get(&pid)
+            .map_or((1.0, false), |volume| (volume.level, volume.muted))
+    }
+
+    /// Move an app's volume, tapping it on the first change.
+    pub fn move_level(&mut self, app: &App, amount: f32) -> Result<(), String> {
+        let (level, muted) = self.get(app.pid);
+        self.set(app, (level + amount).clamp(0.0, 1.0), muted)
+    }
+
+    pub fn toggle_mute(&mut self, app: &App) -> Result<(), String> {
+        let (level, muted) = self.get(app.pid);
+        self.set(app, level, !muted)
+    }
+
+    fn set(&mut self, app: &App, level: f32, muted: bool) -> Result<(), String> {
+        if audio::is_read_only() {
+            return Err("Read-only mode, changes are disabled".to_string());
+        }
+        if level >= 1.0 && !muted {
+            // Dropping the tap gives the app its own output back
+            self.apps.remove(&app.pid);
+            return Ok(());
+        }
+        let gain = if muted { 0.0 } else { level };
+        match self.apps.get_mut(&app.pid) {
+            Some(volume) => {
+                volume.tap.set_gain(gain);
+                volume.level = level;
+                volume.muted = muted;
+            }
+            None => {
+                let tap = Tap::start(app, gain)?;
+                self.apps.insert(app.pid, AppVolume { level, muted, tap });
+            }
+        }
+        Ok(())
+    }
+
+    /// Let go of apps that quit.
+    pub fn retain(&mut self, apps: &[App]) {
+        self.apps
+            .retain(|pid, _| apps.iter().any(|app| app.pid == *pid));
+    }
+}
+
+/// A process tap played back on an output at a gain. Torn down when
+/// dropped.
+#[derive(Debug)]
+struct Tap {
+    tap: AudioObjectID,
+    aggregate: AudioObjectID,
+    proc_id: AudioDeviceIOProcID,
+    context: *mut TapContext,
+    destroy: AudioHardwareDestroyProcessTap,
+}
+
+#[derive(Debug)]
+struct TapContext {
+    /// f32 bits, read on the audio thread.
+    gain: AtomicU32,
+}
+
+impl Tap {
+    fn start(app: &App, gain: f32) -> Result<Self, String> {
+        let (create, destroy) = tap_functions().ok_or("Process taps aren't available")?;
+        let output = audio::default_device(Channel::Output);
+        if output == 0 {
+            return Err("No default output to play the app on".to_string());
+        }
+        let (tap, tap_uid) = unsafe { create_tap(create, app)? };
+        let aggregate = match create_aggregate(&audio::device_uid(&output), &tap_uid, app) {
+            Ok(aggregate) => aggregate,
+            Err(e) => {
+                unsafe { destroy(tap) };
+                return Err(e);
+            }
+        };
+        let context = Box::into_raw(Box::new(TapContext {
+            gain: AtomicU32::new(gain.to_bits()),
+        }));
+        let mut proc_id: AudioDeviceIOProcID = None;
+        unsafe {
+            let failed = if AudioDeviceCreateIOProcID(
+                aggregate,
+                tap_proc,
+                context as *mut c_void,
+                &mut proc_id,
+            ) != NO_ERR
+            {
+                Some("Unable to create the app's IOProc")
+            } else if AudioDeviceStart(aggregate, proc_id) != NO_ERR {
+                AudioDeviceDestroyIOProcID(aggregate, proc_id);
+                Some("Unable to start the app's IOProc")
+            } else {
+                None
+            };
+            if let Some(message) = failed {
+                AudioHardwareDestroyAggregateDevice(aggregate);
+                destroy(tap);
+                drop(Box::from_raw(context));
+                return Err(message.to_string());
+            }
+        }
+        Ok(Tap {
+            tap,
+            aggregate,
+            proc_id,
+            context,
+            destroy,
+        })
+    }
+
+    fn set_gain(&self, gain: f32) {
+        unsafe { &*self.context }
+            .gain
+            .store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        unsafe {
+            // Stop waits for the IOProc to return, like the meter's
+            AudioDeviceStop(self.aggregate, self.proc_id);
+            AudioDeviceDestroyIOProcID(self.aggregate, self.proc_id);
+            AudioHardwareDestroyAggregateDevice(self.aggregate);
+            (self.destroy)(self.tap);
+            drop(Box::from_raw(self.context));
+        }
+    }
+}
+
+/// A private stereo tap of the app, muted while it's read. Returns the tap
+/// and its UID.
+unsafe fn create_tap(
+    create: AudioHardwareCreateProcessTap,
+    app: &App,
+) -> Result<(AudioObjectID, String), String> {
+    let class = objc_getClass(c"CATapDescription".as_ptr());
+    if class.is_null() {
+        return Err("Process taps aren't available".to_string());
+    }
+    let pool = objc_autoreleasePoolPush();
+    let number = msg_send_u32(
+        objc_getClass(c"NSNumber".as_ptr()),
+        sel_registerName(c"numberWithUnsignedInt:".as_ptr()),
+        app.object,
+    );
+    let processes = msg_send_object(
+        objc_getClass(c"NSArray".as_ptr()),
+        sel_registerName(c"arrayWithObject:".as_ptr()),
+        number,
+    );
+    let description = msg_send(class, sel_registerName(c"alloc".as_ptr()));
+    let description = msg_send_object(
+        description,
+        sel_registerName(c"initStereoMixdownOfProcesses:".as_ptr()),
+        processes,
+    );
+    let result = register_tap(create, description, MUTED_WHEN_TAPPED, &app.name);
+    objc_autoreleasePoolPop(pool);
+    result
+}
+
+/// A private, unmuted stereo tap of everything playing, for recording.
+/// Returns the tap and its UID.
+pub fn create_global_tap(
+    create: AudioHardwareCreateProcessTap,
+) -> Result<(AudioObjectID, String), String> {
+    unsafe {
+        let class = objc_getClass(c"CATapDescription".as_ptr());
+        if class.is_null() {
+            return Err("Process taps aren't available".to_string());
+        }
+        let pool = objc_autoreleasePoolPush();
+        let none = msg_send(
+            objc_getClass(c"NSArray".as_ptr()),
+            sel_registerName(c"array".as_ptr()),
+        );
+        let description = msg_send(class, sel_registerName(c"alloc".as_ptr()));
+        let description = msg_send_object(
+            description,
+            sel_registerName(c"initStereoGlobalTapButExcludeProcesses:".as_ptr()),
+            none,
+        );
+        let result = register_tap(create, description, UNMUTED, "the output");
+        objc_autoreleasePoolPop(pool);
+        result
+    }
+}
+
+/// Create a private tap from a `CATapDescription`, which is released.
+unsafe fn register_tap(
+    create: AudioHardwareCreateProcessTap,
+    description: *mut c_void,
+    mute_behavior: isize,
+    name: &str,
+) -> Result<(AudioObjectID, String), String> {
+    if description.is_null() {
+        return Err(format!("Unable to describe a tap of {name}"));
+    }
+    msg_send_long(
+        description,
+        sel_registerName(c"setMuteBehavior:".as_ptr()),
+        mute_behavior,
+    );
+    msg_send_bool(description, sel_registerName(c"setPrivate:".as_ptr()), true);
+    let uuid = msg_send(description, sel_registerName(c"UUID".as_ptr()));
+    let uid = msg_send(uuid, sel_registerName(c"UUIDString".as_ptr()));
+    // NSString is toll-free bridged
+    let uid = CFString::wrap_under_get_rule(uid as CFStringRef).to_string();
+    let mut tap: AudioObjectID = 0;
+    let status = create(description, &mut tap);
+    msg_send(description, sel_registerName(c"release".as_ptr()));
+    match status {
+        NO_ERR => Ok((tap, uid)),
+        status => Err(format!("Unable to tap {name}: {status}")),
+    }
+}
+
+/// A private aggregate of the output and the tap, so one IOProc reads the
+/// tap and writes the output.
+fn create_aggregate(output_uid: &str, tap_uid: &str, app: &App) -> Result<AudioObjectID, String> {
+    let pair = |key: &str, value: CFType| (CFString::new(key).as_CFType(), value);
+    let entry =
+        |uid: &str| CFDictionary::from_CFType_pairs(&[pair("uid", CFString::new(uid).as_CFType())]);
+    let tap = CFDictionary::from_CFType_pairs(&[
+        pair("uid", CFString::new(tap_uid).as_CFType()),
+        pair("drift", CFBoolean::true_value().as_CFType()),
+    ]);
+    let description = CFDictionary::from_CFType_pairs(&[
+        pair(
+            "uid",
+            CFString::new(&format!("mac-controls.app.{}", app.pid)).as_CFType(),
+        ),
+        pair(
+            "name",
+            CFString::new(&format!("mac-controls: {}", app.name)).as_CFType(),
+        ),
+        pair("private", CFBoolean::true_value().as_CFType()),
+        pair("master", CFString::new(output_uid).as_CFType()),
+        pair(
+            "subdevices",
+            CFArray::from_CFTypes(&[entry(output_uid)]).as_CFType(),
+        ),
+        pair("taps", CFArray::from_CFTypes(&[tap]).as_CFType()),
+        pair("tapautostart", CFBoolean::true_value().as_CFType()),
+    ]);
+    let mut aggregate: AudioObjectID = 0;
+    let status = unsafe {
+        AudioHardwareCreateAggregateDevice(description.as_concrete_TypeRef(), &mut aggregate)
+    };
+    match status {
+        NO_ERR => Ok(aggregate),
+        status => Err(format!(
+            "Unable to route {} to the output: {status}",
+            app.name
+        )),
+    }
+}
+
+/// Runs on CoreAudio's IO thread. The tap is the aggregate's last input
+/// stream, interleaved stereo, played on its first output stream.
+extern "C" fn tap_proc(
+    _device: AudioObjectID,
+    _now: *const AudioTimeStamp,
+    input: *const AudioBufferList,
+    _input_time: *const AudioTimeStamp,
+    output: *mut AudioBufferList,
+    _output_time: *const AudioTimeStamp,
+    client_data: *mut c_void,
+) -> OSStatus {
+    if output.is_null() || client_data.is_null() {
+        return NO_ERR;
+    }
+    unsafe {
+        let ctx = &*(client_data as *const TapContext);
+        let gain = f32::from_bits(ctx.gain.load(Ordering::Relaxed));
+        let source = match input.as_ref() {
+            Some(list) => {
+                std::slice::from_raw_parts(list.mBuffers.as_ptr(), list.mNumberBuffers as usize)
+                    .last()
+                    .filter(|buffer| !buffer.mData.is_null() && buffer.mNumberChannels > 0)
+            }
+            None => None,
+        };
+        let list = &mut *output;
+        let buffers = std::slice::from_raw_parts_mut(
+            list.mBuffers.as_mut_ptr(),
+            list.mNumberBuffers as usize,
+        );
+        for (i, buffer) in buffers.iter_mut().enumerate() {
+            if buffer.mData.is_null() {
+                continue;
+            }
+            let len = buffer.mDataByteSize as usize / std::mem::size_of::<Float32>();
+            let samples = std::slice::from_raw_parts_mut(buffer.mData as *mut Float32, len);
+            let source = match (i, source) {
+                (0, Some(source)) => source,
+                _ => {
+                    samples.fill(0.0);
+                    continue;
+                }
+            };
+            let in_channels = source.mNumberChannels as usize;
+            let in_len = source.mDataByteSize as usize / std::mem::size_of::<Float32>();
+            let tapped = std::slice::from_raw_parts(source.mData as *const Float32, in_len);
+            let out_channels = buffer.mNumberChannels.max(1) as usize;
+            for (j, sample) in samples.iter_mut().enumerate() {
+                let (frame, channel) = (j / out_channels, j % out_channels);
+                let from = frame * in_channels + channel.min(in_channels - 1);
+                *sample = tapped.get(from).map_or(0.0, |s| s * gain);
+            }
+        }
+    }
+    NO_ERR
+}
@@ -0,0 +1,296 @@
+//! The TUI attached to a running `mac-controls daemon`, on this Mac or,
+//! with `mac-controls attach <host>:<port>`, another one. The default
+//! devices come from the daemon's status and every change goes over its
+//! socket, so the daemon's tap and mute takeovers stay the only ones. Keys
+//! go through the same keymap as the full TUI, config changes included;
+//! the actions that need more than the default devices say so. Quitting
+//! detaches and leaves the daemon running.
+
+use std::collections::HashMap;
+use std::io::{Stdin, Write};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use termion::event::Key;
+use termion::input::TermRead;
+
+use crate::audio::Channel;
+use crate::config::Config;
+use crate::events::{Action, UiMode, FINE_STEPS};
+use crate::ipc::Client;
+use crate::keymap::Keymap;
+
+/// How often the daemon's status is read.
+const REFRESH: Duration = Duration::from_millis(500);
+const STEP: f32 = 0.1;
+
+/// Actions that work attached, the footer lists the keys bound to them.
+const SUPPORTED: &[&str] = &[
+    "mode output",
+    "mode input",
+    "cursor-next",
+    "cursor-prev",
+    "volume-down",
+    "volume-up",
+    "toggle-mute",
+    "mode back",
+    "exit",
+];
+
+/// What a key does attached.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Leave, the daemon keeps running.
+    Detach,
+    /// Only what's shown changes.
+    Show,
+    /// A command line for the daemon.
+    Send(String),
+    /// Bound to an action only the full TUI has.
+    Unavailable(String),
+}
+
+/// The mode and channel being edited, like the full TUI's edit modes.
+#[derive(Debug)]
+struct View {
+    mode: UiMode,
+    selected: Channel,
+}
+
+impl View {
+    /// What a key does, given the selected channel's volume and mute.
+    /// None when it's unbound.
+    fn key(
+        &mut self,
+        keymap: &Keymap,
+        key: Key,
+        (level, muted): (Option<f32>, bool),
+    ) -> Option<Step> {
+        let name = channel_name(self.selected);
+        let step = match keymap.action(self.mode, key)? {
+            Action::Exit => Step::Detach,
+            Action::ModeBack if self.mode == UiMode::View => Step::Detach,
+            Action::ModeBack => {
+                self.mode = UiMode::View;
+                Step::Show
+            }
+            Action::ModeSwitch(UiMode::EditInput) => self.edit(Channel::Input),
+            Action::ModeSwitch(UiMode::EditOutput) => self.edit(Channel::Output),
+            // Two rows, either way is the other one
+            Action::CursorNext | Action::CursorPrev => self.edit(match self.selected {
+                Channel::Input => Channel::Output,
+                Channel::Output => Channel::Input,
+            }),
+            action @ (Action::VolumeUp { fine } | Action::VolumeDown { fine }) => {
+                let Some(level) = level else {
+                    return Some(Step::Unavailable(format!("The {name} has no volume")));
+                };
+                let mut step = if fine { STEP / FINE_STEPS } else { STEP };
+                if matches!(action, Action::VolumeDown { .. }) {
+                    step = -step;
+                }
+                let level = (level + step).clamp(0.0, 1.0);
+                Step::Send(format!(
+                    "{{\"cmd\":\"set_volume\",\"channel\":\"{name}\",\"level\":{level}}}"
+                ))
+            }
+            Action::ToggleMute => Step::Send(format!(
+                "{{\"cmd\":\"mute\",\"channel\":\"{name}\",\"muted\":{}}}",
+                !muted
+            )),
+            action => {
+                let name = action.to_record().unwrap_or_else(|| format!("{action:?}"));
+                Step::Unavailable(format!("{name} needs the full TUI, stop the daemon for it"))
+            }
+        };
+        Some(step)
+    }
+
+    fn edit(&mut self, channel: Channel) -> Step {
+        self.selected = channel;
+        self.mode = match channel {
+            Channel::Input => UiMode::EditInput,
+            Channel::Output => UiMode::EditOutput,
+        };
+        Step::Show
+    }
+}
+
+/// Run until the user detaches (Ok) or the daemon goes away.
+pub fn run(out: &mut impl Write, stdin: Stdin, mut client: Client) -> Result<(), String> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for key in stdin.lock().keys().flatten() {
+            if tx.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    // Key presses come fast, attached they aren't rate limited
+    client.send("{\"cmd\":\"attach\"}")?;
+    let keymap = Keymap::new(&Config::load().keymap);
+    // Editing the output, so the arrows work right away
+    let mut view = View {
+        mode: UiMode::EditOutput,
+        selected: Channel::Output,
+    };
+    let mut notice = String::new();
+    let _ = write!(out, "{}{}", termion::clear::All, termion::cursor::Hide);
+    loop {
+        let Some(status) = client.status() else {
+            return Err("The daemon stopped".to_string());
+        };
+        draw(out, &keymap, &view, &status, &notice);
+        let key = match rx.recv_timeout(REFRESH) {
+            Ok(key) => key,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        let (_, level, muted) = device(&status, view.selected);
+        notice = match view.key(&keymap, key, (level, muted)) {
+            Some(Step::Detach) => return Ok(()),
+            Some(Step::Send(command)) => client.send(&command).err().unwrap_or_default(),
+            Some(Step::Unavailable(message)) => message,
+            Some(Step::Show) | None => String::new(),
+        };
+    }
+}
+
+fn channel_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Input => "input",
+        Channel::Output => "output",
+    }
+}
+
+/// The default device's name, volume if it has one, and mute, from the
+/// daemon's status.
+fn device(status: &HashMap<String, String>, channel: Channel) -> (Option<&str>, Option<f32>, bool) {
+    let field = |name: &str| {
+        status
+            .get(&format!("{}.{name}", channel_name(channel)))
+            .map(|value| value.as_str())
+    };
+    (
+        field("name"),
+        field("level").and_then(|level| level.parse().ok()),
+        field("muted") == Some("true"),
+    )
+}
+
+/// The keys bound to what works attached in a mode, like the full TUI's
+/// footer.
+fn footer(keymap: &Keymap, mode: UiMode) -> String {
+    keymap
+        .bindings(mode)
+        .into_iter()
+        .filter(|(_, action)| SUPPORTED.contains(action))
+        .map(|(key, action)| format!("{key} {action}"))
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+fn draw(
+    out: &mut impl Write,
+    keymap: &Keymap,
+    view: &View,
+    status: &HashMap<String, String>,
+    notice: &str,
+) {
+    let clear_line = termion::clear::CurrentLine;
+    let mut text = format!(
+        "{}{clear_line}Audio Devices - attached to the daemon\r\n\r\n",
+        termion::cursor::Goto(1, 2)
+    );
+    for channel in [Channel::Output, Channel::Input] {
+        let (name, level, muted) = device(status, channel);
+        let name = name.unwrap_or("none");
+        let volume = match (level, muted) {
+            (_, true) => "muted".to_string(),
+            (Some(level), false) => format!("{:.0}%", level * 100.0),
+            (None, false) => "-".to_string(),
+        };
+        let editing = view.mode != UiMode::View && channel == view.selected;
+        let cursor = if editing { ">" } else { " " };
+        text.push_str(&format!(
+            "{clear_line}{cursor} {:<7} {volume:>6}  {name}\r\n",
+            channel_name(channel)
+        ));
+    }
+    text.push_str(&format!(
+        "\r\n{clear_line}{notice}\r\n{clear_line}[attached] {}\r\n{}",
+        footer(keymap, view.mode),
+        termion::clear::AfterCursor
+    ));
+    let _ = write!(out, "{text}");
+    let _ = out.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(step: Option<Step>) -> String {
+        match step {
+            Some(Step::Send(command)) => command,
+            step => panic!("nothing sent: {step:?}"),
+        }
+    }
+
+    #[test]
+    fn keys_follow_the_keymap() {
+        let keymap = Keymap::new(&[]);
+        let mut view = View {
+            mode: UiMode::EditOutput,
+            selected: Channel::Output,
+        };
+        let command = send(view.key(&keymap, Key::Char('m'), (Some(0.5), false)));
+        assert_eq!(
+            command,
+            "{\"cmd\":\"mute\",\"channel\":\"output\",\"muted\":true}"
+        );
+        // `/` searches in the full TUI, there's no list to search here
+        assert!(matches!(
+            view.key(&keymap, Key::Char('/'), (Some(0.5), false)),
+            Some(Step::Unavailable(_))
+        ));
+        let command = send(view.key(&keymap, Key::Left, (Some(0.5), false)));
+        assert_eq!(
+            command,
+            "{\"cmd\":\"set_volume\",\"channel\":\"output\",\"level\":0.4}"
+        );
+
+        assert_eq!(
+            view.key(&keymap, Key::Char('i'), (None, false)),
+            Some(Step::Show)
+        );
+        assert_eq!(
+            (view.mode, view.selected),
+            (UiMode::EditInput, Channel::Input)
+        );
+        assert_eq!(view.key(&keymap, Key::Esc, (None, false)), Some(Step::Show));
+        assert_eq!(
+            view.key(&keymap, Key::Esc, (None, false)),
+            Some(Step::Detach)
+        );
+    }
+
+    #[test]
+    fn config_changes_apply() {
+        let keymap = Keymap::new(&[(
+            Some(UiMode::EditOutput),
+            "/".to_string(),
+            "toggle-mute".to_string(),
+        )]);
+        let mut view = View {
+            mode: UiMode::EditOutput,
+            selected: Channel::Output,
+        };
+        let command = send(view.key(&keymap, Key::Char('/'), (Some(0.5), true)));
+        assert_eq!(
+            command,
+            "{\"cmd\":\"mute\",\"channel\":\"output\",\"muted\":false}"
+        );
+        assert!(footer(&keymap, UiMode::EditOutput).contains("/ toggle-mute"));
+    }
+}
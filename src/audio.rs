@@ -1,25 +1,348 @@
+//! Audio devices, their volumes and mutes. `AudioState` keeps track of
+//! them, the free functions are the CoreAudio calls underneath. Setters do
+//! nothing in read-only mode.
+//!
 //! This repo helped me sort out how to work with CoreAudio
 //! https://github.com/ewrobinson/ERVolumeAdjust
 
 use core_foundation::{
-    base::FromVoid,
+    array::{CFArray, CFArrayRef},
+    base::{CFType, FromVoid, TCFType},
+    boolean::CFBoolean,
+    dictionary::CFDictionary,
     string::{CFString, CFStringRef},
 };
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::os::raw::c_void;
-use std::{borrow::BorrowMut, cell::RefCell};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::apps;
+use crate::backend::{AudioBackend, CoreAudio};
+use crate::config::{Config, FallbackConfig};
 use crate::coreaudio::*;
+use crate::error::ControlError;
+use crate::group::{self, Group};
+use crate::history::History;
+use crate::listener::fourcc;
+use crate::log;
+use crate::mutes::{self, Effect};
+use crate::night::{self, NightConfig};
+use crate::registry::{DeviceSettings, MutePolicy, Registry};
 
 const ZERO: f32 = 0.0;
 const FULL: f32 = 1.0;
+/// First wait before retrying a refused volume change, doubled per try.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+const RETRY_ATTEMPTS: u32 = 5;
+/// Threads reading devices for `update`.
+const READERS: usize = 4;
+/// Time between the steps of a volume ramp.
+const RAMP_STEP: Duration = Duration::from_millis(15);
+/// How long `update` waits for its reads, the ones back later are used by
+/// the next update.
+const READ_WAIT: Duration = Duration::from_millis(50);
+/// The multi-output device `toggle_broadcast` makes, reused from run to run.
+pub const BROADCAST_UID: &str = "mac-controls.broadcast";
+pub const BROADCAST_NAME: &str = "All Outputs";
+
+/// Read-only mode turns every CoreAudio setter into a no-op, so the app can
+/// be used purely as a dashboard.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// Property reads made so far, for the doctor's cost per poll.
+static QUERIES: AtomicU64 = AtomicU64::new(0);
+
+pub fn query_count() -> u64 {
+    QUERIES.load(Ordering::Relaxed)
+}
 
 #[derive(Debug)]
 pub struct AudioState {
     active_input: Option<usize>,
     active_output: Option<usize>,
+    /// Where alerts and sound effects play.
+    active_alert: Option<usize>,
     devices: Vec<Device>,
-    mutes: Vec<AudioDeviceID>,
+    groups: Vec<Group>,
+    registry: Registry,
+    history: History,
+    /// False until the first update, so devices attached at startup aren't
+    /// counted as new connections.
+    started: bool,
+    max_jump: Option<f32>,
+    /// Output levels of disconnected devices by UID, to catch a device
+    /// coming back much louder.
+    last_levels: HashMap<String, f32>,
+    /// Messages for the user, collected with `take_notices`. Shared, so
+    /// failures deep in `&self` calls land here too.
+    notices: RefCell<Vec<String>>,
+    /// (input, output) pairs from the config, by UID or name.
+    pairs: Vec<(String, String)>,
+    /// UIDs of devices marked for bulk operations.
+    marked: Vec<String>,
+    /// Aliases from the config, by UID or name.
+    aliases: Vec<(String, String)>,
+    /// Devices connected (true) or disconnected since the last
+    /// `take_connections`, by label.
+    connections: Vec<(String, bool)>,
+    /// Volume changes the device refused, tried again by `retry_volumes`.
+    retries: RefCell<Vec<Retry>>,
+    /// Devices to switch to when the default disconnects, from the config.
+    fallback: Option<FallbackConfig>,
+    /// Leave the defaults to macOS while AirPods are involved.
+    airpods_auto_switch: bool,
+    /// UID of the default output before `toggle_broadcast`, to go back to.
+    broadcast_previous: Option<String>,
+    /// Lower speaker cap at night, from the config.
+    night: Option<NightConfig>,
+    night_active: bool,
+    /// Devices being read on another thread, see `deferred`.
+    probing: Option<Receiver<HashMap<AudioDeviceID, Probe>>>,
+    /// Read ahead of `update` adding the devices.
+    probes: HashMap<AudioDeviceID, Probe>,
+    readers: Readers,
+    ramper: Ramper,
+    backend: Arc<dyn AudioBackend>,
+}
+
+/// Everything read from a device as it's added, besides its UID and name.
+/// Plain data, so startup can read it off the main thread.
+#[derive(Debug, Clone, Default)]
+pub struct Probe {
+    /// Input and output volume, when the device has them.
+    volumes: (Option<f32>, Option<f32>),
+    /// Whether each channel, input then output, can be the default.
+    selectable: [bool; 2],
+    settable: [bool; 2],
+    stereo: Option<(f32, f32)>,
+    sample_rate: Option<f64>,
+    sources: Sources,
+    hog_owner: Option<i32>,
+    transport: UInt32,
+    manufacturer: Option<String>,
+    model: Option<String>,
+}
+
+impl Probe {
+    pub fn read(backend: &dyn AudioBackend, id: AudioDeviceID) -> Self {
+        let channels = [Channel::Input, Channel::Output];
+        Probe {
+            volumes: backend.volumes(id),
+            selectable: channels.map(|channel| backend.can_be_default(channel, id)),
+            settable: channels.map(|channel| backend.volume_settable(id, channel)),
+            stereo: backend.stereo_levels(id),
+            sample_rate: backend.sample_rate(id),
+            sources: backend.sources(id),
+            hog_owner: backend.hog_owner(id),
+            transport: backend.transport(id),
+            manufacturer: backend.manufacturer(id),
+            model: backend.model(id),
+        }
+    }
+}
+
+/// What `update` reads from each known device.
+#[derive(Debug, Clone)]
+struct Reading {
+    volumes: (Option<f32>, Option<f32>),
+    stereo: Option<(f32, f32)>,
+    sample_rate: Option<f64>,
+    /// Plugging in headphones switches the built-in output's source.
+    sources: Sources,
+    hog_owner: Option<i32>,
+}
+
+/// Worker threads for `update`'s device reads, so a slow device, like
+/// Bluetooth headphones waking up, only holds back its own values instead
+/// of the whole tick. The workers end when this is dropped.
+#[derive(Debug)]
+struct Readers {
+    jobs: Sender<AudioDeviceID>,
+    results: Receiver<(AudioDeviceID, Reading)>,
+    /// Sent and not back yet, not sent again until they are.
+    pending: HashSet<AudioDeviceID>,
+}
+
+impl Readers {
+    fn start(backend: &Arc<dyn AudioBackend>) -> Self {
+        let (jobs, queue) = mpsc::channel::<AudioDeviceID>();
+        let (done, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..READERS {
+            let queue = queue.clone();
+            let done = done.clone();
+            let backend = backend.clone();
+            std::thread::spawn(move || loop {
+                let Ok(id) = queue.lock().unwrap().recv() else {
+                    return;
+                };
+                let reading = Reading {
+                    volumes: backend.volumes(id),
+                    stereo: backend.stereo_levels(id),
+                    sample_rate: backend.sample_rate(id),
+                    sources: backend.sources(id),
+                    hog_owner: backend.hog_owner(id),
+                };
+                if done.send((id, reading)).is_err() {
+                    return;
+                }
+            });
+        }
+        Readers {
+            jobs,
+            results,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// Read the devices that aren't being read already. Returns what's
+    /// back within `READ_WAIT`, including reads left from earlier calls.
+    fn read(&mut self, ids: &[AudioDeviceID]) -> HashMap<AudioDeviceID, Reading> {
+        for id in ids {
+            if self.pending.insert(*id) {
+                let _ = self.jobs.send(*id);
+            }
+        }
+        let until = Instant::now() + READ_WAIT;
+        let mut readings = HashMap::new();
+        while !self.pending.is_empty() {
+            let wait = until.saturating_duration_since(Instant::now());
+            let Ok((id, reading)) = self.results.recv_timeout(wait) else {
+                break;
+            };
+            self.pending.remove(&id);
+            readings.insert(id, reading);
+        }
+        readings
+    }
+}
+
+/// Ramps by channel, with the number they were started under, to notice
+/// being replaced, and the level they last set.
+#[derive(Debug, Default)]
+struct Ramps {
+    started: u64,
+    running: HashMap<(AudioDeviceID, Channel), (u64, f32)>,
+}
+
+/// Volume changes spread over a short time in small steps, each ramp on a
+/// thread of its own, so arrow presses and unmutes don't jump on
+/// headphones. A change to a channel that's still ramping carries on from
+/// where that ramp got to.
+#[derive(Debug, Clone)]
+struct Ramper {
+    duration: Duration,
+    ramps: Arc<Mutex<Ramps>>,
+    backend: Arc<dyn AudioBackend>,
+}
+
+impl Ramper {
+    fn new(duration: Duration, backend: Arc<dyn AudioBackend>) -> Self {
+        Ramper {
+            duration,
+            ramps: Arc::default(),
+            backend,
+        }
+    }
+
+    /// Set a channel's volume, ramping from `from`. Drops to zero are
+    /// mutes and happen at once, as does everything with ramps off. The
+    /// first step is made here, so a refused change still returns an error.
+    fn set(
+        &self,
+        id: AudioDeviceID,
+        channel: Channel,
+        from: f32,
+        to: f32,
+    ) -> Result<(), ControlError> {
+        let key = (id, channel);
+        let steps = (self.duration.as_millis() / RAMP_STEP.as_millis()) as u32;
+        if steps < 2 || to == ZERO || is_read_only() {
+            self.ramps.lock().unwrap().running.remove(&key);
+            return self.backend.set_volume(id, channel, to);
+        }
+        let (number, from) = {
+            let mut ramps = self.ramps.lock().unwrap();
+            let from = ramps.running.get(&key).map_or(from, |(_, level)| *level);
+            ramps.started += 1;
+            let number = ramps.started;
+            ramps.running.insert(key, (number, from));
+            (number, from)
+        };
+        let level_at = move |step: u32| from + (to - from) * step as f32 / steps as f32;
+        if let Err(e) = self.backend.set_volume(id, channel, level_at(1)) {
+            self.ramps.lock().unwrap().running.remove(&key);
+            return Err(e);
+        }
+        let ramps = self.ramps.clone();
+        let backend = self.backend.clone();
+        std::thread::spawn(move || {
+            for step in 2..=steps {
+                std::thread::sleep(RAMP_STEP);
+                let level = level_at(step);
+                {
+                    let mut ramps = ramps.lock().unwrap();
+                    match ramps.running.get_mut(&key) {
+                        // Replaced by a newer change
+                        Some((running, _)) if *running != number => return,
+                        None => return,
+                        Some((_, last)) => *last = level,
+                    }
+                }
+                // Nobody to tell mid-ramp, and the rest would fail too
+                if let Err(e) = backend.set_volume(id, channel, level) {
+                    log::warn(e);
+                    ramps.lock().unwrap().running.remove(&key);
+                    return;
+                }
+            }
+            let mut ramps = ramps.lock().unwrap();
+            if ramps.running.get(&key).map(|(running, _)| *running) == Some(number) {
+                ramps.running.remove(&key);
+            }
+        });
+        Ok(())
+    }
+
+    /// Wait for every running ramp to reach its level.
+    fn wait(&self) {
+        while !self.ramps.lock().unwrap().running.is_empty() {
+            std::thread::sleep(RAMP_STEP);
+        }
+    }
+
+    /// While ramping, what the device reports is a step on the way.
+    fn is_ramping(&self, id: AudioDeviceID, channel: Channel) -> bool {
+        self.ramps
+            .lock()
+            .unwrap()
+            .running
+            .contains_key(&(id, channel))
+    }
+}
+
+/// A volume change to try again, for devices that are busy or still
+/// settling after a change of default.
+#[derive(Debug)]
+struct Retry {
+    id: AudioDeviceID,
+    channel: Channel,
+    level: f32,
+    attempts: u32,
+    due: Instant,
 }
 
 #[derive(Debug)]
@@ -29,6 +352,112 @@ pub struct Device {
     pub name: String,
     pub input: RefCell<Volume>,
     pub output: RefCell<Volume>,
+    /// Left and right output levels, for devices with a volume per channel.
+    pub stereo: Cell<Option<(f32, f32)>>,
+    /// Sample rate in Hz.
+    pub sample_rate: Cell<Option<f64>>,
+    pub sources: RefCell<Sources>,
+    /// Process with exclusive access (hog mode), none while every app can
+    /// play on the device.
+    pub hog_owner: Cell<Option<i32>>,
+    /// Registry settings, loaded when the device appears.
+    pub settings: DeviceSettings,
+    pub category: Category,
+    /// Combines other devices, see `AudioState::members`.
+    pub is_aggregate: bool,
+    /// Maker and model, to tell apart devices with the same generic name.
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+}
+
+/// A device that is, or could be, part of an aggregate device.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub uid: String,
+    /// The UID when the device isn't connected.
+    pub label: String,
+    pub is_member: bool,
+    /// Resampling to follow the aggregate's clock, for members only.
+    pub drift_correction: Option<bool>,
+}
+
+/// Kind of connection, from the device's transport type. The list is
+/// grouped by it, in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    BuiltIn,
+    Bluetooth,
+    Usb,
+    Virtual,
+    Aggregate,
+    AirPlay,
+    Other,
+}
+
+impl Category {
+    fn from_transport(transport: UInt32) -> Self {
+        let is = |types: &[UInt32]| types.contains(&transport);
+        if is(&[kAudioDeviceTransportTypeBuiltIn]) {
+            Category::BuiltIn
+        } else if is(&[
+            kAudioDeviceTransportTypeBluetooth,
+            kAudioDeviceTransportTypeBluetoothLE,
+        ]) {
+            Category::Bluetooth
+        } else if is(&[kAudioDeviceTransportTypeUSB]) {
+            Category::Usb
+        } else if is(&[kAudioDeviceTransportTypeVirtual]) {
+            Category::Virtual
+        } else if is(&[kAudioDeviceTransportTypeAggregate]) {
+            Category::Aggregate
+        } else if is(&[kAudioDeviceTransportTypeAirPlay]) {
+            Category::AirPlay
+        } else {
+            Category::Other
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::BuiltIn => "Built-in",
+            Category::Bluetooth => "Bluetooth",
+            Category::Usb => "USB",
+            Category::Virtual => "Virtual",
+            Category::Aggregate => "Aggregate",
+            Category::AirPlay => "AirPlay",
+            Category::Other => "Other",
+        }
+    }
+
+    /// Software devices, like BlackHole or an app's own, rather than
+    /// hardware.
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Category::Virtual | Category::Aggregate)
+    }
+}
+
+impl Device {
+    /// Check a UID, name or alias from the config against this device.
+    pub fn matches(&self, key: &str) -> bool {
+        self.uid == key || self.name == key || self.settings.alias.as_deref() == Some(key)
+    }
+
+    pub fn volume(&self, channel: Channel) -> &RefCell<Volume> {
+        match channel {
+            Channel::Input => &self.input,
+            Channel::Output => &self.output,
+        }
+    }
+
+    /// Name to display: the user's alias, or the device's own name.
+    pub fn label(&self) -> &str {
+        self.settings.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Output balance, -1 (left only) to 1 (right only).
+    pub fn balance(&self) -> Option<f32> {
+        self.stereo.get().map(balance_of)
+    }
 }
 
 #[derive(Debug)]
@@ -37,126 +466,567 @@ pub struct Volume {
     pub selectable: bool,
     pub level: f32,
     pub cache: f32,
+    /// System mute flag, as opposed to our volume-zero workaround.
+    pub native_mute: bool,
+    /// Muted by the volume-zero workaround, `cache` holds the level to
+    /// unmute to. Kept per channel, so a headset's mic and speakers never
+    /// share it.
+    pub workaround_mute: bool,
+    /// False when the driver reports a volume but refuses changes, like
+    /// some HDMI outputs.
+    pub settable: bool,
 }
 
+/// How a device channel is muted.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuteState {
+    Unmuted,
+    /// The system mute flag is set.
+    Native,
+    /// Taken over by the workaround, volume set to zero.
+    Workaround,
+}
+
+impl MuteState {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            MuteState::Unmuted => "unmuted",
+            MuteState::Native => "native",
+            MuteState::Workaround => "workaround (volume=0)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Channel {
     Input,
     Output,
 }
 
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Input => "input",
+            Channel::Output => "output",
+        }
+    }
+}
+
+/// Volume and mute combinations that leave a device silent in a way that
+/// looks like it shouldn't be. The most common "no sound" report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SilentState {
+    /// Volume at zero on a device with a working system mute, so macOS
+    /// shows it unmuted.
+    ZeroVolume,
+    /// System mute set on a device whose mute is taken over, where muting
+    /// is expected to be a zero volume.
+    MutedWithVolume,
+}
+
+impl SilentState {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SilentState::ZeroVolume => "volume is 0 but not muted",
+            SilentState::MutedWithVolume => "system mute is on",
+        }
+    }
+}
+
+/// Volume restored by a fix when there's no cached level to go back to.
+const FIX_LEVEL: f32 = 0.25;
+
 /// AudioState API
 impl AudioState {
     /// Init new AudioState and sync with OS.
-    pub fn new() -> Self {
-        let mut audio = AudioState {
+    pub fn new(config: &Config) -> Self {
+        AudioState::with_backend(config, Arc::new(CoreAudio))
+    }
+
+    /// Like `new`, on another audio system than CoreAudio, like a
+    /// `MockBackend`. Changes to balance, stereo channels, sample rates,
+    /// hog mode, the alert device and aggregates still go to CoreAudio.
+    pub fn with_backend(config: &Config, backend: Arc<dyn AudioBackend>) -> Self {
+        let mut audio = AudioState::empty(config, backend);
+        audio.update();
+        audio
+    }
+
+    /// Like `new`, but only device names are read right away, for a first
+    /// draw without waiting on slow devices. The rest is read on another
+    /// thread and filled in by the first `update` after it's done; until
+    /// then devices have no volumes.
+    pub fn deferred(config: &Config) -> Self {
+        let mut audio = AudioState::empty(config, Arc::new(CoreAudio));
+        let backend = audio.backend.clone();
+        let ids = backend.device_ids().unwrap_or_else(|e| {
+            // The first update tries again
+            audio.notices.get_mut().push(e.to_string());
+            Vec::new()
+        });
+        for &id in &ids {
+            let (uid, name) = (backend.device_uid(id), backend.device_name(id));
+            let settings = audio.settings_for(&uid, &name);
+            let device = new_device(id, uid, name, settings, &Probe::default());
+            audio.devices.push(device);
+        }
+        audio.check_defaults();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let probes = ids
+                .iter()
+                .map(|&id| (id, Probe::read(&*backend, id)))
+                .collect();
+            let _ = tx.send(probes);
+        });
+        audio.probing = Some(rx);
+        audio
+    }
+
+    fn empty(config: &Config, backend: Arc<dyn AudioBackend>) -> Self {
+        AudioState {
             active_input: None,
             active_output: None,
+            active_alert: None,
             devices: Vec::new(),
-            mutes: Vec::new(),
-        };
-        audio.update();
-        audio
+            groups: group::load(),
+            registry: Registry::load(),
+            history: History::load(),
+            started: false,
+            max_jump: config.max_volume_jump,
+            last_levels: HashMap::new(),
+            notices: RefCell::default(),
+            pairs: config.pairs.clone(),
+            marked: Vec::new(),
+            aliases: config.aliases.clone(),
+            connections: Vec::new(),
+            retries: RefCell::default(),
+            fallback: config.fallback.clone(),
+            airpods_auto_switch: config.airpods_auto_switch,
+            broadcast_previous: None,
+            night: config.night.clone(),
+            night_active: false,
+            probing: None,
+            probes: HashMap::new(),
+            readers: Readers::start(&backend),
+            ramper: Ramper::new(config.volume_ramp, backend.clone()),
+            backend,
+        }
+    }
+
+    /// Registry settings for a device, with an alias from the config when
+    /// the registry has none.
+    fn settings_for(&self, uid: &str, name: &str) -> DeviceSettings {
+        let mut settings = self.registry.settings(uid);
+        if settings.alias.is_none() {
+            settings.alias = self
+                .aliases
+                .iter()
+                .find(|(key, _)| key == uid || key == name)
+                .map(|(_, alias)| alias.clone());
+        }
+        settings
     }
 
     /// Checks state against the OS, making updates where needed.
     pub fn update(&mut self) {
-        let ids = device_ids();
-        let all = HashSet::<_>::from_iter(ids.into_iter());
+        if let Some(probing) = &self.probing {
+            match probing.try_recv() {
+                Err(TryRecvError::Empty) => {
+                    self.check_defaults();
+                    return;
+                }
+                probes => {
+                    // Added again below with everything read, as the
+                    // first update
+                    self.probes = probes.unwrap_or_default();
+                    self.probing = None;
+                    self.devices.clear();
+                    self.active_input = None;
+                    self.active_output = None;
+                    self.active_alert = None;
+                }
+            }
+        }
+        let prev_input = self.active_id(Channel::Input);
+        // A failed listing isn't every device gone, they're kept as they are
+        let ids = match self.backend.device_ids() {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::warn(format!("Unable to list audio devices: {e}"));
+                return;
+            }
+        };
+        let all = HashSet::<_>::from_iter(ids);
         let curr = HashSet::from_iter(self.devices.iter().map(|d| d.id));
 
-        // update existing devices
-        for id in all.intersection(&curr) {
-            let is_muted = self.mutes.contains(id);
+        // update existing devices, the ones slow to read keep their values
+        // until a later update
+        let existing: Vec<_> = all.intersection(&curr).copied().collect();
+        let readings = self.readers.read(&existing);
+        let mut changed_outputs = Vec::new();
+        for id in &existing {
             if let Some(device) = self.devices.iter_mut().find(|d| d.id == *id) {
-                let (sys_vol_in, sys_vol_out) = volume_level(&id);
+                self.history.seen(&device.uid);
+                let Some(reading) = readings.get(id) else {
+                    self.mute_check(id);
+                    continue;
+                };
+                // Ramping channels report the steps, not where they're going
+                let ramping = |channel| self.ramper.is_ramping(*id, channel);
+                let sys_vol_in = reading.volumes.0.filter(|_| !ramping(Channel::Input));
+                let sys_vol_out = reading.volumes.1.filter(|_| !ramping(Channel::Output));
                 if let Some(level) = sys_vol_in {
-                    update_channel(id, &device.input, &mut self.mutes, level, is_muted);
+                    update_channel(&device.input, level);
                 }
                 if let Some(level) = sys_vol_out {
-                    update_channel(id, &device.output, &mut self.mutes, level, is_muted);
+                    let (prev, is_muted) = {
+                        let output = device.output.borrow();
+                        (output.level, output.workaround_mute)
+                    };
+                    let level = match is_muted {
+                        true => level,
+                        false => guard_jump(
+                            &*self.backend,
+                            self.max_jump,
+                            device,
+                            prev,
+                            level,
+                            &self.notices,
+                        ),
+                    };
+                    if level != prev {
+                        changed_outputs.push((*id, level));
+                    }
+                    update_channel(&device.output, level);
                 }
+                device.stereo.set(reading.stereo);
+                device.sample_rate.set(reading.sample_rate);
+                device.sources.replace(reading.sources.clone());
+                device.hog_owner.set(reading.hog_owner);
+                enforce_cap(
+                    &*self.backend,
+                    device,
+                    cap_of(device, self.night.as_ref().filter(|_| self.night_active)),
+                    &self.notices,
+                );
                 self.mute_check(id);
             }
         }
+        // Changes from anywhere else, like the menu bar or volume keys
+        for (id, level) in changed_outputs {
+            self.sync_mirror(&id, level);
+        }
 
-        // add/remove
+        // add/remove, the defaults by ID as removals move the indexes
+        let defaults = [Channel::Input, Channel::Output].map(|c| (c, self.active_id(c)));
+        let mut connections_changed = false;
+        let mut lost_defaults = Vec::new();
         for id in all.symmetric_difference(&curr) {
+            connections_changed = true;
             if all.contains(id) {
                 // add new device
-                let (vol_in, vol_out) = volume_level(&id);
-                self.devices.push(Device {
-                    id: *id,
-                    uid: device_uid(&id),
-                    name: device_name(&id),
-                    input: RefCell::new(Volume {
-                        enabled: vol_in.is_some(),
-                        selectable: can_be_default_device(Channel::Input, &id),
-                        level: vol_in.unwrap_or(ZERO),
-                        cache: vol_in.unwrap_or(ZERO),
-                    }),
-                    output: RefCell::new(Volume {
-                        enabled: vol_out.is_some(),
-                        selectable: can_be_default_device(Channel::Output, &id),
-                        level: vol_out.unwrap_or(ZERO),
-                        cache: vol_out.unwrap_or(ZERO),
-                    }),
-                });
+                let probe = self
+                    .probes
+                    .remove(id)
+                    .unwrap_or_else(|| Probe::read(&*self.backend, *id));
+                let vol_out = probe.volumes.1;
+                let uid = self.backend.device_uid(*id);
+                let name = self.backend.device_name(*id);
+                self.history.connected(&uid, &name, self.started);
+                let settings = self.settings_for(&uid, &name);
+                self.devices
+                    .push(new_device(*id, uid, name, settings, &probe));
+                let device = self.devices.last().unwrap();
+                if self.started {
+                    self.connections.push((device.label().to_string(), true));
+                }
+                if let (Some(&prev), Some(level)) = (self.last_levels.get(&device.uid), vol_out) {
+                    let level = guard_jump(
+                        &*self.backend,
+                        self.max_jump,
+                        device,
+                        prev,
+                        level,
+                        &self.notices,
+                    );
+                    device.output.borrow_mut().level = level;
+                }
+                if self.started || device.settings.apply_at_start {
+                    apply_preferred_volumes(&*self.backend, device, &self.notices);
+                }
+                enforce_cap(
+                    &*self.backend,
+                    device,
+                    cap_of(device, self.night.as_ref().filter(|_| self.night_active)),
+                    &self.notices,
+                );
+                restore_volumes(&self.registry, device);
                 self.mute_check(id);
             } else {
                 // remove
                 if let Some(i) = self.devices.iter().position(|d| d.id == *id) {
-                    self.devices.remove(i);
+                    for (channel, default) in defaults {
+                        if default == Some(*id) {
+                            lost_defaults.push((channel, self.devices[i].name.clone()));
+                        }
+                    }
+                    let device = self.devices.remove(i);
+                    self.history.disconnected(&device.uid);
+                    self.connections.push((device.label().to_string(), false));
+                    let level = device.output.borrow().level;
+                    self.last_levels.insert(device.uid, level);
                 }
-                if let Some(i) = self.mutes.iter().position(|m_id| *m_id == *id) {
-                    self.mutes.remove(i);
+            }
+        }
+
+        if connections_changed {
+            report(&self.notices, self.history.save());
+        }
+        self.started = true;
+        self.probes.clear();
+        self.save_volumes();
+        self.check_defaults();
+        for (channel, name) in lost_defaults {
+            self.fall_back(channel, &name);
+        }
+
+        // Follow input/output pairs when the default input changes
+        let input = self.active_id(Channel::Input);
+        if prev_input.is_some() && input != prev_input {
+            let name = self
+                .active_device(Channel::Input)
+                .map(|d| d.name.clone())
+                .unwrap_or_default();
+            if let Some(output) = self.paired_output() {
+                if Some(output) != self.active_id(Channel::Output)
+                    && !self.leaves_default_to_airpods(Channel::Output, &name)
+                {
+                    report(
+                        &self.notices,
+                        self.backend.set_default_device(Channel::Output, output),
+                    );
+                    if let Some(i) = self.devices.iter().position(|d| d.id == output) {
+                        self.active_output = Some(i);
+                    }
                 }
             }
         }
+    }
 
-        // Check which devices are selected
-        if let Some(i) = self
-            .devices
-            .iter()
-            .position(|d| d.id == default_device(Channel::Input))
-        {
-            self.active_input = Some(i);
+    /// Whether `[airpods] auto_switch` leaves the default of a channel to
+    /// macOS, with AirPods connected or `involved`, the name of the device
+    /// that's coming or going. Says so in the notices when it does.
+    pub fn leaves_default_to_airpods(&mut self, channel: Channel, involved: &str) -> bool {
+        let leaves = self.airpods_auto_switch
+            && (is_airpods(involved) || self.devices.iter().any(|d| is_airpods(&d.name)));
+        if leaves {
+            self.notices.get_mut().push(format!(
+                "Left the default {} to AirPods automatic switching",
+                channel.as_str()
+            ));
         }
-        if let Some(i) = self
-            .devices
-            .iter()
-            .position(|d| d.id == default_device(Channel::Output))
-        {
-            self.active_output = Some(i);
+        leaves
+    }
+
+    /// After the default device of a channel disconnected, make the first
+    /// connected one of `[fallback]` the default, and pull the new default
+    /// output down to the safe volume, whichever device it is. `lost` is
+    /// the name of the device that went away.
+    fn fall_back(&mut self, channel: Channel, lost: &str) {
+        let Some(fallback) = self.fallback.clone().filter(|_| !is_read_only()) else {
+            return;
+        };
+        let ranked: &[String] = match self.leaves_default_to_airpods(channel, lost) {
+            true => &[],
+            false => match channel {
+                Channel::Input => &fallback.inputs,
+                Channel::Output => &fallback.outputs,
+            },
+        };
+        let next = ranked.iter().find_map(|key| {
+            self.find_device(key)
+                .ok()
+                .filter(|d| self.backend.can_be_default(channel, d.id))
+                .map(|d| d.id)
+        });
+        if let Some(id) = next.filter(|id| Some(*id) != self.active_id(channel)) {
+            report(&self.notices, self.backend.set_default_device(channel, id));
+            let i = self.devices.iter().position(|d| d.id == id);
+            match channel {
+                Channel::Input => self.active_input = i,
+                Channel::Output => self.active_output = i,
+            }
+        }
+        let (Channel::Output, Some(max)) = (channel, fallback.max_volume) else {
+            return;
+        };
+        let Some(device) = self.active_device(Channel::Output) else {
+            return;
+        };
+        let level = device.output.borrow().level;
+        if device.output.borrow().enabled && level > max {
+            let result = self.backend.set_volume(device.id, Channel::Output, max);
+            update_channel(&device.output, max);
+            let notice = format!(
+                "Default output disconnected, {} turned down from {:.0}% to {:.0}%",
+                device.label(),
+                level * 100.0,
+                max * 100.0
+            );
+            report(&self.notices, result);
+            self.notices.get_mut().push(notice);
         }
     }
 
-    /// Get a sorted list of audio devices (active_in, active_out, muted, device).
+    /// Check which devices are selected.
+    /// None for a default that isn't known, like one that was just
+    /// removed, rather than an index left from before.
+    fn check_defaults(&mut self) {
+        let position = |id| self.devices.iter().position(|d| d.id == id);
+        self.active_input = position(self.backend.default_device(Channel::Input));
+        self.active_output = position(self.backend.default_device(Channel::Output));
+        let alert = self.backend.alert_device();
+        self.active_alert = self.devices.iter().position(|d| d.id == alert);
+    }
+
+    /// Get a sorted list of visible audio devices (active_in, active_out,
+    /// muted on either channel, device).
     pub fn device_list(&self) -> Vec<(bool, bool, bool, &Device)> {
         let mut list: Vec<(bool, bool, bool, &Device)> = self
             .devices
             .iter()
             .enumerate()
+            .filter(|(_, d)| !d.settings.hidden)
             .map(|(i, d)| {
                 (
                     self.active_input == Some(i),
                     self.active_output == Some(i),
-                    self.mutes.contains(&d.id),
+                    self.is_muted(d, Channel::Input) || self.is_muted(d, Channel::Output),
                     d,
                 )
             })
             .collect();
-        list.sort_by_key(|(_, _, _, d)| &d.name);
+        list.sort_by_key(|(_, _, _, d)| (d.category, -d.settings.priority, d.label()));
         list
     }
 
+    /// Messages for the user since the last call, like a pulled back
+    /// volume jump.
+    pub fn take_notices(&mut self) -> Vec<String> {
+        self.notices.take()
+    }
+
+    /// Devices hot-plugged since the last call, by label, with true for
+    /// connected and false for disconnected.
+    pub fn take_connections(&mut self) -> Vec<(String, bool)> {
+        std::mem::take(&mut self.connections)
+    }
+
+    /// A device's label, numbered like "USB Audio Device #2" when other
+    /// connected devices have the same one.
+    pub fn unique_name(&self, device: &Device) -> String {
+        let entries = self.name_entries();
+        let i = entries.iter().position(|(uid, _)| *uid == device.uid);
+        i.map_or(device.label().to_string(), |i| {
+            unique_names(&entries).swap_remove(i)
+        })
+    }
+
+    /// The device a key means: a UID, a name from `unique_name`, or a
+    /// name or alias only one device has.
+    pub fn find_device(&self, key: &str) -> Result<&Device, ControlError> {
+        match resolve_device(&self.name_entries(), key) {
+            Ok(i) => Ok(&self.devices[i]),
+            // The device's own name, under an alias
+            Err(e) => {
+                let mut named = self.devices.iter().filter(|d| d.name == key);
+                match (named.next(), named.next()) {
+                    (Some(device), None) => Ok(device),
+                    _ => Err(e),
+                }
+            }
+        }
+    }
+
+    /// (UID, label) of every device, in order.
+    fn name_entries(&self) -> Vec<(&str, &str)> {
+        self.devices
+            .iter()
+            .map(|d| (d.uid.as_str(), d.label()))
+            .collect()
+    }
+
+    /// Every connected device, hidden ones too, in no particular order.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// Connection history of every device seen so far.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Save history, so attached devices get an accurate last seen time.
+    pub fn save_history(&self) {
+        report(&self.notices, self.history.save());
+    }
+
+    /// IDs of all known devices.
+    pub fn device_ids(&self) -> Vec<AudioDeviceID> {
+        self.devices.iter().map(|d| d.id).collect()
+    }
+
+    /// Device currently set as the system default for a channel.
+    pub fn active_device(&self, channel: Channel) -> Option<&Device> {
+        let active = match channel {
+            Channel::Input => self.active_input,
+            Channel::Output => self.active_output,
+        };
+        active.and_then(|i| self.devices.get(i))
+    }
+
+    /// ID of the device currently set as the system default for a channel.
+    pub fn active_id(&self, channel: Channel) -> Option<AudioDeviceID> {
+        self.active_device(channel).map(|d| d.id)
+    }
+
+    /// The device alerts and sound effects play on.
+    pub fn alert_device(&self) -> Option<&Device> {
+        self.devices.get(self.active_alert?)
+    }
+
+    pub fn can_be_alert(&self, id: AudioDeviceID) -> bool {
+        self.backend.can_be_alert_device(id)
+    }
+
+    /// Play alerts and sound effects on a device.
+    pub fn set_alert(&mut self, uid: &str) -> Result<(), ControlError> {
+        let device = self
+            .devices
+            .iter()
+            .find(|d| d.uid == uid)
+            .ok_or_else(|| ControlError::Device(format!("No device {uid}")))?;
+        let result = match self.backend.can_be_alert_device(device.id) {
+            true => self.backend.set_alert_device(device.id),
+            false => Err(ControlError::Device(format!(
+                "{} can't play alerts",
+                device.label()
+            ))),
+        };
+        self.update();
+        result
+    }
+
     /// Fetch a devices input state -> (volume, muted)
     pub fn input(&self, id: &AudioDeviceID) -> Option<(f32, bool)> {
         if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
             let vol = device.input.borrow();
             match vol.enabled {
-                true => Some((vol.level, self.mutes.contains(id))),
-                false => None,
+                true => Some((vol.level, self.is_muted(device, Channel::Input))),
+                false => self
+                    .aggregate_level(device, Channel::Input)
+                    .map(|level| (level, false)),
             }
         } else {
             None
@@ -168,121 +1038,753 @@ impl AudioState {
         if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
             let vol = device.output.borrow();
             match vol.enabled {
-                true => Some((vol.level, self.mutes.contains(id))),
-                false => None,
+                true => Some((vol.level, self.is_muted(device, Channel::Output))),
+                false => self
+                    .aggregate_level(device, Channel::Output)
+                    .map(|level| (level, false)),
             }
         } else {
             None
         }
     }
 
-    /// Adjust volume by variable amount (with max/min of 1.0/0.0)
+    /// Adjust volume by variable amount (with max/min of 1.0/0.0). Applies
+    /// to all marked devices if there are any, otherwise the active one.
     pub fn move_volume(&mut self, channel: Channel, amount: f32) {
-        {
-            let (id, mut vol_ref) = match channel {
-                Channel::Input if self.active_input.is_some() => {
-                    let device = &self.devices[self.active_input.unwrap()];
-                    (device.id, device.input.borrow_mut())
-                }
-                Channel::Output if self.active_output.is_some() => {
-                    let device = &self.devices[self.active_output.unwrap()];
-                    (device.id, device.output.borrow_mut())
+        for id in self.targets(channel) {
+            if let Some(device) = self.devices.iter().find(|d| d.id == id) {
+                if self.is_locked(device, channel) {
+                    self.notices
+                        .get_mut()
+                        .push(format!("{}: volume locked by driver", device.label()));
                 }
-                _ => return,
-            };
-            if vol_ref.enabled {
-                let mut next_level = vol_ref.level + amount;
-                next_level = if next_level < ZERO { ZERO } else { next_level };
-                next_level = if next_level > FULL { FULL } else { next_level };
-                vol_ref.level = next_level;
-                vol_ref.cache = next_level;
-                set_volume(&id, channel, next_level);
             }
+            self.adjust_volume(&id, channel, amount);
         }
         self.update();
     }
 
-    // Toggle workaround mute for input or output.
-    pub fn toggle_mute(&mut self, channel: Channel) {
-        {
-            let (id, vol_state) = match channel {
-                Channel::Input if self.active_input.is_some() => {
-                    let device = &self.devices[self.active_input.unwrap()];
-                    (device.id, device.input.borrow())
-                }
-                Channel::Output if self.active_output.is_some() => {
-                    let device = &self.devices[self.active_output.unwrap()];
-                    (device.id, device.output.borrow())
-                }
-                _ => return,
-            };
-            if vol_state.enabled {
-                if self.mutes.contains(&id) {
-                    set_volume(&id, channel, vol_state.cache);
-                } else {
-                    set_volume(&id, channel, ZERO);
-                }
-            }
+    /// Whether a device has a volume for the channel that can't be changed.
+    pub fn is_locked(&self, device: &Device, channel: Channel) -> bool {
+        let volume = device.volume(channel).borrow();
+        volume.enabled && !volume.settable
+    }
+
+    /// Name of the group an output belongs to, if any.
+    pub fn group_of(&self, device: &Device) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|g| g.contains(&device.uid))
+            .map(|g| g.name.as_str())
+    }
+
+    /// Add or remove the active output from its volume group, keeping the
+    /// current level difference to the other members as its offset.
+    pub fn toggle_grouped(&mut self) {
+        let (uid, level) = match self.active_device(Channel::Output) {
+            Some(device) => (device.uid.clone(), device.output.borrow().level),
+            None => return,
+        };
+        if self.groups.is_empty() {
+            self.groups.push(Group::new("Group"));
         }
-        self.update();
+        let index = self
+            .groups
+            .iter()
+            .position(|g| g.contains(&uid))
+            .unwrap_or(0);
+        let base = self.group_level(&self.groups[index]).unwrap_or(level);
+        self.groups[index].toggle(&uid, level - base);
+        report(&self.notices, group::save(&self.groups));
     }
 
-    /// Select next input.
-    pub fn next_input(&mut self) {
-        let in_ids: Vec<&u32> = self
-            .devices
+    /// Members of an aggregate device in its order, then the devices that
+    /// could be added.
+    pub fn members(&self, aggregate: &Device) -> Vec<Member> {
+        if !aggregate.is_aggregate {
+            return Vec::new();
+        }
+        let label = |uid: &str| match self.devices.iter().find(|d| d.uid == uid) {
+            Some(device) => device.label().to_string(),
+            None => uid.to_string(),
+        };
+        let drift = self.backend.drift_correction(aggregate.id);
+        let uids = self.backend.aggregate_uids(aggregate.id);
+        let mut members: Vec<Member> = uids
             .iter()
-            .filter_map(|d| {
-                let in_ref = d.input.borrow();
-                if in_ref.enabled && in_ref.selectable {
-                    Some(&d.id)
-                } else {
-                    None
-                }
+            .map(|uid| Member {
+                uid: uid.clone(),
+                label: label(uid),
+                is_member: true,
+                drift_correction: drift.iter().find(|(u, _)| u == uid).map(|(_, on)| *on),
             })
             .collect();
-        if let Some(i) = self.active_input {
-            let active_device = &self.devices[i];
-            if let Some(pos) = in_ids.iter().position(|&id| *id == active_device.id) {
-                let next = if pos < in_ids.len() - 1 { pos + 1 } else { 0 };
-                set_default_device(Channel::Input, in_ids[next])
+        members.extend(
+            self.devices
+                .iter()
+                .filter(|d| !d.is_aggregate && !d.settings.hidden && !uids.contains(&d.uid))
+                .map(|d| Member {
+                    uid: d.uid.clone(),
+                    label: d.label().to_string(),
+                    is_member: false,
+                    drift_correction: None,
+                }),
+        );
+        members
+    }
+
+    /// Add a device to an aggregate, or remove it.
+    pub fn toggle_member(&mut self, aggregate: &AudioDeviceID, uid: &str) {
+        let mut uids = self.backend.aggregate_uids(*aggregate);
+        match uids.iter().position(|u| u == uid) {
+            Some(i) => {
+                uids.remove(i);
             }
+            None => uids.push(uid.to_string()),
         }
+        report(
+            &self.notices,
+            self.backend.set_aggregate_uids(*aggregate, &uids),
+        );
         self.update();
     }
 
-    /// Select previous input.
-    pub fn prev_input(&mut self) {
-        let in_ids: Vec<&u32> = self
-            .devices
-            .iter()
-            .filter_map(|d| {
-                let in_ref = d.input.borrow();
-                if in_ref.enabled && in_ref.selectable {
-                    Some(&d.id)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        if let Some(i) = self.active_input {
-            let active_device = &self.devices[i];
-            if let Some(pos) = in_ids.iter().position(|&id| *id == active_device.id) {
-                let next = if pos == 0 { in_ids.len() - 1 } else { pos - 1 };
-                set_default_device(Channel::Input, in_ids[next])
-            }
+    /// Play on every physical output through one multi-output device, or go back.
+    pub fn toggle_broadcast(&mut self) -> Result<String, ControlError> {
+        let active = self.active_device(Channel::Output).map(|d| d.uid.clone());
+        if active.as_deref() == Some(BROADCAST_UID) {
+            let previous = self.broadcast_previous.take();
+            let device = self
+                .devices
+                .iter()
+                .find(|d| Some(&d.uid) == previous.as_ref())
+                .or(self.broadcast_outputs().first().copied())
+                .ok_or("No output to go back to")?;
+            let label = device.label().to_string();
+            self.backend
+                .set_default_device(Channel::Output, device.id)?;
+            self.update();
+            return Ok(format!("Back to {label}"));
+        }
+        let outputs = self.broadcast_outputs();
+        if outputs.len() < 2 {
+            return Err("Playing everywhere needs two outputs or more".into());
         }
+        // The current output keeps the clock, the rest follow it
+        let mut uids: Vec<String> = outputs.iter().map(|d| d.uid.clone()).collect();
+        if let Some(i) = uids.iter().position(|uid| Some(uid) == active.as_ref()) {
+            uids[..=i].rotate_right(1);
+        }
+        let id = match self.devices.iter().find(|d| d.uid == BROADCAST_UID) {
+            Some(device) => {
+                self.backend.set_aggregate_uids(device.id, &uids)?;
+                device.id
+            }
+            None => self.backend.create_multi_output(&uids)?,
+        };
+        self.backend.set_default_device(Channel::Output, id)?;
+        self.broadcast_previous = active;
         self.update();
+        Ok(format!("Playing on {} outputs at once", uids.len()))
     }
 
-    /// Select next output.
-    pub fn next_output(&mut self) {
-        let out_ids: Vec<&u32> = self
-            .devices
+    /// Connected outputs that can play on their own, AirPlay included.
+    fn broadcast_outputs(&self) -> Vec<&Device> {
+        self.devices
             .iter()
-            .filter_map(|d| {
+            .filter(|d| !d.category.is_virtual() && !d.settings.hidden)
+            .filter(|d| d.output.borrow().selectable)
+            .collect()
+    }
+
+    /// Take exclusive access to a device for this app, or give it back.
+    /// Another process's hold can only be let go by that process.
+    pub fn toggle_hog(&mut self, uid: &str) -> Result<String, ControlError> {
+        let device = self
+            .devices
+            .iter()
+            .find(|d| d.uid == uid)
+            .ok_or_else(|| ControlError::Device(format!("No device {uid}")))?;
+        let own = std::process::id() as i32;
+        match self.backend.hog_owner(device.id) {
+            Some(pid) if pid != own => {
+                let process = apps::process_name(pid).unwrap_or("another app".to_string());
+                return Err(ControlError::Device(format!(
+                    "{} is held by {process} ({pid}), only it can let go",
+                    device.label()
+                )));
+            }
+            _ if !self.backend.can_hog(device.id) => {
+                return Err(ControlError::Device(format!(
+                    "{} has no exclusive access",
+                    device.label()
+                )))
+            }
+            _ => {}
+        }
+        self.backend.toggle_hog(device.id)?;
+        let owner = self.backend.hog_owner(device.id);
+        device.hog_owner.set(owner);
+        Ok(match owner {
+            Some(_) => format!(
+                "{} is this app's alone, others can't play on it",
+                device.label()
+            ),
+            None => format!("{} is shared again", device.label()),
+        })
+    }
+
+    /// Turn drift correction of an aggregate's member on or off.
+    pub fn toggle_drift(&mut self, aggregate: &AudioDeviceID, uid: &str) {
+        let Some((_, on)) = self
+            .backend
+            .drift_correction(*aggregate)
+            .into_iter()
+            .find(|(u, _)| u == uid)
+        else {
+            return;
+        };
+        if self
+            .backend
+            .set_drift_correction(*aggregate, uid, !on)
+            .is_err()
+        {
+            self.notices
+                .get_mut()
+                .push("Couldn't change drift correction".to_string());
+        }
+    }
+
+    /// Set volume of the active device to an exact level (0.0 - 1.0)
+    pub fn set_level(&mut self, channel: Channel, level: f32) {
+        let current = match self.active_device(channel) {
+            Some(device) => self
+                .aggregate_level(device, channel)
+                .unwrap_or(device.volume(channel).borrow().level),
+            None => return,
+        };
+        if let Some(id) = self.active_id(channel) {
+            self.adjust_volume(&id, channel, level - current);
+        }
+        self.update();
+    }
+
+    /// Set every device a channel operation applies to, the marked ones or
+    /// else the active one, to an exact level (0.0 - 1.0).
+    pub fn set_targets_level(&mut self, channel: Channel, level: f32) {
+        for id in self.targets(channel) {
+            self.set_level_of(&id, channel, level);
+        }
+        self.update();
+    }
+
+    /// Set a specific device's volume to an exact level, default or not.
+    /// Fast path, like `move_volume_of`.
+    pub fn set_level_of(&mut self, id: &AudioDeviceID, channel: Channel, level: f32) {
+        let Some(device) = self.devices.iter().find(|d| d.id == *id) else {
+            return;
+        };
+        let current = self
+            .aggregate_level(device, channel)
+            .unwrap_or(device.volume(channel).borrow().level);
+        self.adjust_volume(id, channel, level - current);
+    }
+
+    // Toggle workaround mute for input or output. With marked devices, mutes
+    // all of them, or unmutes them all if they're all muted already.
+    pub fn toggle_mute(&mut self, channel: Channel) {
+        let ids = self.targets(channel);
+        let muted = |id: &AudioDeviceID| {
+            let device = self.devices.iter().find(|d| d.id == *id);
+            device.is_some_and(|d| self.is_muted(d, channel))
+        };
+        let all_muted = ids.iter().all(muted);
+        let toggled: Vec<_> = ids
+            .into_iter()
+            .filter(|id| muted(id) == all_muted)
+            .collect();
+        for id in toggled {
+            self.toggle_device_mute(&id, channel);
+        }
+        self.update();
+    }
+
+    /// Adjust a specific device's volume, default or not.
+    ///
+    /// This is the fast path for global hotkeys: only the property of the
+    /// cached device is written, skipping the device enumeration in
+    /// `update()`. The property listener (or the next poll) reconciles the
+    /// rest of the state.
+    pub fn move_volume_of(&mut self, id: &AudioDeviceID, channel: Channel, amount: f32) {
+        self.adjust_volume(id, channel, amount);
+    }
+
+    /// Toggle a specific device's mute, default or not. Fast path, like
+    /// `move_volume_of`.
+    pub fn toggle_mute_of(&mut self, id: &AudioDeviceID, channel: Channel) {
+        self.toggle_device_mute(id, channel);
+    }
+
+    /// Pan a device's stereo output, -1 (left only) to 1 (right only).
+    pub fn set_balance_of(&mut self, id: &AudioDeviceID, balance: f32) -> Result<(), ControlError> {
+        set_balance(id, balance)?;
+        if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
+            device.stereo.set(self.backend.stereo_levels(*id));
+        }
+        Ok(())
+    }
+
+    /// Pick the output channels a device plays stereo on, by number from 1.
+    pub fn set_stereo_channels_of(
+        &mut self,
+        id: &AudioDeviceID,
+        pair: (UInt32, UInt32),
+    ) -> Result<(), ControlError> {
+        set_stereo_channels(id, pair)?;
+        if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
+            device.stereo.set(self.backend.stereo_levels(*id));
+        }
+        Ok(())
+    }
+
+    /// Set a device's sample rate.
+    pub fn set_sample_rate_of(
+        &mut self,
+        id: &AudioDeviceID,
+        rate: f64,
+    ) -> Result<(), ControlError> {
+        self.backend.set_sample_rate(*id, rate)?;
+        if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
+            device.sample_rate.set(Some(rate));
+        }
+        Ok(())
+    }
+
+    /// Switch a device to its next sample rate, wrapping around, with the
+    /// new rate.
+    pub fn cycle_sample_rate_of(&mut self, id: &AudioDeviceID) -> Result<f64, ControlError> {
+        let rates = self.backend.sample_rates(*id);
+        let current = self
+            .backend
+            .sample_rate(*id)
+            .ok_or_else(|| ControlError::Device(format!("Device {id} has no sample rate")))?;
+        let next = rates
+            .iter()
+            .find(|rate| **rate > current)
+            .or(rates.first())
+            .copied()
+            .filter(|rate| *rate != current)
+            .ok_or_else(|| ControlError::Device(format!("Device {id} has only one sample rate")))?;
+        self.backend.set_sample_rate(*id, next)?;
+        if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
+            device.sample_rate.set(Some(next));
+        }
+        Ok(next)
+    }
+
+    /// Switch a device to its next source of a kind, returning its name.
+    pub fn cycle_source_of(
+        &mut self,
+        id: &AudioDeviceID,
+        kind: SourceKind,
+    ) -> Result<String, ControlError> {
+        let sources = self.backend.sources(*id).get(kind).clone();
+        if sources.available.len() < 2 {
+            return Err(ControlError::Device(format!(
+                "Device {id} has no {}s to choose from",
+                kind.name()
+            )));
+        }
+        let next = sources
+            .available
+            .iter()
+            .position(|(source, _)| Some(*source) == sources.active)
+            .map_or(0, |i| (i + 1) % sources.available.len());
+        let (source, name) = sources.available[next].clone();
+        self.backend.set_source(*id, kind, source)?;
+        if let Some(device) = self.devices.iter().find(|d| d.id == *id) {
+            let mut cached = device.sources.borrow_mut();
+            *cached.get_mut(kind) = SourceList {
+                active: Some(source),
+                ..sources
+            };
+        }
+        Ok(name)
+    }
+
+    /// Wait for volume ramps to finish, before a one-shot command exits
+    /// and cuts them short.
+    pub fn finish_ramps(&self) {
+        self.ramper.wait();
+    }
+
+    /// Set a device channel's volume and mute together, like a profile
+    /// has them. Unmutes first and mutes last, so a muted mic is never live
+    /// in between, and a workaround mute keeps the volume for its unmute.
+    pub fn restore_channel(
+        &mut self,
+        id: &AudioDeviceID,
+        channel: Channel,
+        level: Option<f32>,
+        muted: bool,
+    ) {
+        let find = |state: &Self| state.devices.iter().position(|d| d.id == *id);
+        let Some(i) = find(self) else {
+            return;
+        };
+        if is_read_only() || !self.devices[i].volume(channel).borrow().enabled {
+            return;
+        }
+        if !muted && self.is_muted(&self.devices[i], channel) {
+            self.toggle_device_mute(id, channel);
+            self.update();
+        }
+        let Some(i) = find(self) else {
+            return;
+        };
+        let device = &self.devices[i];
+        if let Some(level) = level {
+            let workaround = self.mute_state(device, channel) == MuteState::Workaround;
+            let mut vol_ref = device.volume(channel).borrow_mut();
+            vol_ref.cache = level;
+            if !workaround && vol_ref.settable {
+                let prev = vol_ref.level;
+                vol_ref.level = level;
+                report(&self.notices, self.ramper.set(*id, channel, prev, level));
+            }
+        }
+        if muted && !self.is_muted(device, channel) {
+            self.toggle_device_mute(id, channel);
+        }
+        self.update();
+    }
+
+    /// Mark or unmark the active device for bulk operations.
+    /// Mark a device for bulk operations, or unmark it, by UID.
+    pub fn toggle_mark(&mut self, uid: &str) {
+        match self.marked.iter().position(|m| m == uid) {
+            Some(i) => {
+                self.marked.remove(i);
+            }
+            None => self.marked.push(uid.to_string()),
+        }
+    }
+
+    pub fn is_marked(&self, device: &Device) -> bool {
+        self.marked.contains(&device.uid)
+    }
+
+    pub fn marked_count(&self) -> usize {
+        self.marked.len()
+    }
+
+    /// Hide all marked devices from the list, saved in the registry.
+    pub fn hide_marked(&mut self) {
+        for uid in std::mem::take(&mut self.marked) {
+            self.hide(&uid);
+        }
+    }
+
+    /// Leave a device out of the list from now on.
+    pub fn hide(&mut self, uid: &str) {
+        if let Err(e) = self.registry.set_hidden(uid, true) {
+            self.notices
+                .get_mut()
+                .push(format!("Couldn't save device settings: {e}"));
+        }
+        for device in self.devices.iter_mut().filter(|d| d.uid == uid) {
+            device.settings.hidden = true;
+        }
+    }
+
+    /// Hand workaround mutes back to the system: restore the cached volume
+    /// and set the real mute flag, so other tools see a muted device instead
+    /// of one left at zero volume.
+    pub fn restore_system_mutes(&mut self) {
+        for device in &self.devices {
+            let id = device.id;
+            for channel in [Channel::Input, Channel::Output] {
+                let mut vol_ref = device.volume(channel).borrow_mut();
+                if !vol_ref.workaround_mute {
+                    continue;
+                }
+                if vol_ref.enabled
+                    && vol_ref.level == ZERO
+                    && self.backend.can_set_mute(id, channel)
+                {
+                    report(&self.notices, self.backend.set_mute(id, channel, true));
+                    report(
+                        &self.notices,
+                        self.backend.set_volume(id, channel, vol_ref.cache),
+                    );
+                }
+                vol_ref.workaround_mute = false;
+            }
+        }
+    }
+
+    /// Whether a device channel is muted, and by what.
+    pub fn mute_state(&self, device: &Device, channel: Channel) -> MuteState {
+        let vol_ref = match channel {
+            Channel::Input => device.input.borrow(),
+            Channel::Output => device.output.borrow(),
+        };
+        if vol_ref.native_mute {
+            MuteState::Native
+        } else if vol_ref.workaround_mute && vol_ref.level == ZERO {
+            MuteState::Workaround
+        } else {
+            MuteState::Unmuted
+        }
+    }
+
+    /// Whether a device channel is muted, natively or by the workaround.
+    pub fn is_muted(&self, device: &Device, channel: Channel) -> bool {
+        self.mute_state(device, channel) != MuteState::Unmuted
+    }
+
+    /// Whether a device channel is silent in a confusing way.
+    pub fn silent_state(&self, device: &Device, channel: Channel) -> Option<SilentState> {
+        let volume = match channel {
+            Channel::Input => device.input.borrow(),
+            Channel::Output => device.output.borrow(),
+        };
+        if !volume.enabled {
+            return None;
+        }
+        let takeover = self.takes_over_mute(&device.id);
+        match (volume.native_mute, volume.level == ZERO) {
+            (false, true) if !takeover => Some(SilentState::ZeroVolume),
+            (true, false) if takeover => Some(SilentState::MutedWithVolume),
+            _ => None,
+        }
+    }
+
+    /// Make the active device of a channel audible again: restore its volume
+    /// or clear the system mute, whichever left it silent.
+    pub fn fix_silent(&mut self, channel: Channel) {
+        let device = match self.active_device(channel) {
+            Some(device) => device,
+            None => return,
+        };
+        let id = device.id;
+        let result = match self.silent_state(device, channel) {
+            Some(SilentState::ZeroVolume) => {
+                let cache = match channel {
+                    Channel::Input => device.input.borrow().cache,
+                    Channel::Output => device.output.borrow().cache,
+                };
+                let level = if cache > ZERO { cache } else { FIX_LEVEL };
+                device.volume(channel).borrow_mut().workaround_mute = false;
+                self.backend.set_volume(id, channel, level)
+            }
+            Some(SilentState::MutedWithVolume) => self.backend.set_mute(id, channel, false),
+            None => return,
+        };
+        report(&self.notices, result);
+        self.update();
+    }
+
+    /// Sample rates of the default input and output, when they're separate
+    /// devices running apart, a common cause of crackling.
+    pub fn rate_mismatch(&self) -> Option<(f64, f64)> {
+        let input = self.active_device(Channel::Input)?;
+        let output = self.active_device(Channel::Output)?;
+        if input.id == output.id {
+            return None;
+        }
+        let rates = (input.sample_rate.get()?, output.sample_rate.get()?);
+        (rates.0 != rates.1).then_some(rates)
+    }
+
+    /// Run the default input at the output's rate, or the other way around
+    /// when it can't, or both at a rate they share. Returns that rate.
+    pub fn align_sample_rates(&mut self) -> Result<f64, ControlError> {
+        let (input_rate, output_rate) = self
+            .rate_mismatch()
+            .ok_or("The input and output already run at one rate")?;
+        let input = self.active_id(Channel::Input).ok_or("No input")?;
+        let output = self.active_id(Channel::Output).ok_or("No output")?;
+        let input_rates = self.backend.sample_rates(input);
+        let output_rates = self.backend.sample_rates(output);
+        let rate = if input_rates.contains(&output_rate) {
+            self.backend.set_sample_rate(input, output_rate)?;
+            output_rate
+        } else if output_rates.contains(&input_rate) {
+            self.backend.set_sample_rate(output, input_rate)?;
+            input_rate
+        } else {
+            // 48 kHz suits most hardware, otherwise the highest shared
+            let shared: Vec<f64> = input_rates
+                .into_iter()
+                .filter(|rate| output_rates.contains(rate))
+                .collect();
+            let rate = shared
+                .iter()
+                .copied()
+                .find(|rate| *rate == 48000.0)
+                .or(shared.iter().copied().reduce(f64::max))
+                .ok_or("The input and output have no sample rate in common")?;
+            self.backend.set_sample_rate(input, rate)?;
+            self.backend.set_sample_rate(output, rate)?;
+            rate
+        };
+        self.update();
+        Ok(rate)
+    }
+
+    /// Mute takeover policy for a device.
+    pub fn mute_policy(&self, device: &Device) -> MutePolicy {
+        device.settings.mute_policy
+    }
+
+    /// Name of the output a device's volume is mirrored with, if any.
+    pub fn mirror_of(&self, device: &Device) -> Option<String> {
+        let mirror = device.settings.mirror.as_ref()?;
+        Some(
+            self.devices
+                .iter()
+                .find(|d| d.uid == *mirror)
+                .map_or(mirror.clone(), |d| d.label().to_string()),
+        )
+    }
+
+    /// Mirror an output's volume with the default output, or unlink it
+    /// from its mirror. Returns a message describing the result.
+    pub fn toggle_mirror(&mut self, uid: &str) -> Result<String, ControlError> {
+        let device = self
+            .devices
+            .iter()
+            .find(|d| d.uid == uid)
+            .ok_or_else(|| ControlError::Device(format!("No device {uid}")))?;
+        let links = match &device.settings.mirror {
+            Some(mirror) => vec![(uid.to_string(), None), (mirror.clone(), None)],
+            None => {
+                let output = self
+                    .active_device(Channel::Output)
+                    .ok_or("No default output to mirror")?;
+                if output.uid == uid {
+                    return Err("Pick another output than the default to mirror".into());
+                }
+                if !device.output.borrow().enabled || output.settings.mirror.is_some() {
+                    return Err(ControlError::Device(format!(
+                        "Can't mirror {} with {}",
+                        device.label(),
+                        output.label()
+                    )));
+                }
+                vec![
+                    (uid.to_string(), Some(output.uid.clone())),
+                    (output.uid.clone(), Some(uid.to_string())),
+                ]
+            }
+        };
+        let message = match &links[0].1 {
+            Some(_) => format!("{} mirrors the default output's volume", device.label()),
+            None => format!("{} no longer mirrors a volume", device.label()),
+        };
+        for (uid, mirror) in links {
+            self.registry.set_mirror(&uid, mirror.as_deref())?;
+            for device in self.devices.iter_mut().filter(|d| d.uid == uid) {
+                device.settings.mirror = mirror.clone();
+            }
+        }
+        // Start out at the default output's level
+        if let Some(output) = self.active_device(Channel::Output) {
+            let level = output.output.borrow().level;
+            self.sync_mirror(&output.id, level);
+        }
+        Ok(message)
+    }
+
+    /// Name a device, or with none go back to the config's alias or the
+    /// device's own name. Kept in `devices.toml`.
+    pub fn set_alias(&mut self, uid: &str, alias: Option<&str>) -> Result<(), ControlError> {
+        self.registry.set_alias(uid, alias)?;
+        for i in 0..self.devices.len() {
+            if self.devices[i].uid == uid {
+                let alias = self.settings_for(uid, &self.devices[i].name).alias;
+                self.devices[i].settings.alias = alias;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch the active device to the next mute takeover policy.
+    /// Switch a device to its next mute policy, by UID.
+    pub fn cycle_mute_policy(&mut self, uid: &str) {
+        let next = self.registry.mute_policy(uid).next();
+        report(&self.notices, self.registry.set_mute_policy(uid, next));
+        for device in self.devices.iter_mut().filter(|d| d.uid == uid) {
+            device.settings.mute_policy = next;
+        }
+        self.update();
+    }
+
+    /// Select next input.
+    pub fn next_input(&mut self) {
+        let in_ids: Vec<&u32> = self
+            .devices
+            .iter()
+            .filter_map(|d| {
+                let in_ref = d.input.borrow();
+                if in_ref.enabled && in_ref.selectable && !d.settings.hidden {
+                    Some(&d.id)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if let Some(i) = self.active_input {
+            let active_device = &self.devices[i];
+            if let Some(pos) = in_ids.iter().position(|&id| *id == active_device.id) {
+                let next = if pos < in_ids.len() - 1 { pos + 1 } else { 0 };
+                report(
+                    &self.notices,
+                    self.backend
+                        .set_default_device(Channel::Input, *in_ids[next]),
+                )
+            }
+        }
+        self.update();
+    }
+
+    /// Select previous input.
+    pub fn prev_input(&mut self) {
+        let in_ids: Vec<&u32> = self
+            .devices
+            .iter()
+            .filter_map(|d| {
+                let in_ref = d.input.borrow();
+                if in_ref.enabled && in_ref.selectable && !d.settings.hidden {
+                    Some(&d.id)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if let Some(i) = self.active_input {
+            let active_device = &self.devices[i];
+            if let Some(pos) = in_ids.iter().position(|&id| *id == active_device.id) {
+                let next = if pos == 0 { in_ids.len() - 1 } else { pos - 1 };
+                report(
+                    &self.notices,
+                    self.backend
+                        .set_default_device(Channel::Input, *in_ids[next]),
+                )
+            }
+        }
+        self.update();
+    }
+
+    /// Select next output.
+    pub fn next_output(&mut self) {
+        let out_ids: Vec<&u32> = self
+            .devices
+            .iter()
+            .filter_map(|d| {
                 let out_ref = d.output.borrow();
-                if out_ref.enabled && out_ref.selectable {
+                if out_ref.enabled && out_ref.selectable && !d.settings.hidden {
                     Some(&d.id)
                 } else {
                     None
@@ -293,7 +1795,11 @@ impl AudioState {
             let active_device = &self.devices[i];
             if let Some(pos) = out_ids.iter().position(|&id| *id == active_device.id) {
                 let next = if pos < out_ids.len() - 1 { pos + 1 } else { 0 };
-                set_default_device(Channel::Output, out_ids[next])
+                report(
+                    &self.notices,
+                    self.backend
+                        .set_default_device(Channel::Output, *out_ids[next]),
+                )
             }
         }
         self.update();
@@ -306,7 +1812,7 @@ impl AudioState {
             .iter()
             .filter_map(|d| {
                 let out_ref = d.output.borrow();
-                if out_ref.enabled && out_ref.selectable {
+                if out_ref.enabled && out_ref.selectable && !d.settings.hidden {
                     Some(&d.id)
                 } else {
                     None
@@ -317,14 +1823,365 @@ impl AudioState {
             let active_device = &self.devices[i];
             if let Some(pos) = out_ids.iter().position(|&id| *id == active_device.id) {
                 let next = if pos == 0 { out_ids.len() - 1 } else { pos - 1 };
-                set_default_device(Channel::Output, out_ids[next])
+                report(
+                    &self.notices,
+                    self.backend
+                        .set_default_device(Channel::Output, *out_ids[next]),
+                )
             }
         }
         self.update();
     }
+
+    /// Make a device the default for a channel, by UID.
+    pub fn set_default(&mut self, channel: Channel, uid: &str) -> Result<(), ControlError> {
+        let device = self
+            .devices
+            .iter()
+            .find(|d| d.uid == uid)
+            .ok_or_else(|| ControlError::Device(format!("No device {uid}")))?;
+        let volume = device.volume(channel).borrow();
+        let result = match volume.enabled && volume.selectable {
+            true => self.backend.set_default_device(channel, device.id),
+            false => Err(ControlError::Device(format!(
+                "{} can't be a default device",
+                device.label()
+            ))),
+        };
+        drop(volume);
+        self.update();
+        result
+    }
 }
 
 impl AudioState {
+    /// Devices a channel operation applies to: the marked ones having that
+    /// channel, or else the active device.
+    fn targets(&self, channel: Channel) -> Vec<AudioDeviceID> {
+        if self.marked.is_empty() {
+            return self.active_id(channel).into_iter().collect();
+        }
+        self.devices
+            .iter()
+            .filter(|d| self.marked.contains(&d.uid))
+            .filter(|d| match channel {
+                Channel::Input => d.input.borrow().enabled,
+                Channel::Output => d.output.borrow().enabled,
+            })
+            .map(|d| d.id)
+            .collect()
+    }
+
+    fn adjust_volume(&self, id: &AudioDeviceID, channel: Channel, amount: f32) {
+        let device = match self.devices.iter().find(|d| d.id == *id) {
+            Some(device) => device,
+            None => return,
+        };
+        let mut vol_ref = match channel {
+            Channel::Input => device.input.borrow_mut(),
+            Channel::Output => device.output.borrow_mut(),
+        };
+        if vol_ref.enabled {
+            let mut next_level = vol_ref.level + amount;
+            next_level = if next_level < ZERO { ZERO } else { next_level };
+            next_level = if next_level > FULL { FULL } else { next_level };
+            if channel == Channel::Output {
+                next_level = next_level.min(self.cap(id));
+            }
+            let prev = vol_ref.level;
+            vol_ref.level = next_level;
+            vol_ref.cache = next_level;
+            self.set_or_retry(id, channel, prev, next_level);
+            if channel == Channel::Output {
+                drop(vol_ref);
+                self.sync_group(id, next_level);
+                self.sync_mirror(id, next_level);
+            }
+        } else if device.is_aggregate {
+            drop(vol_ref);
+            self.adjust_aggregate(id, channel, amount);
+        }
+    }
+
+    /// Set a volume, queueing a retry when the device refuses it.
+    fn set_or_retry(&self, id: &AudioDeviceID, channel: Channel, from: f32, level: f32) {
+        let mut retries = self.retries.borrow_mut();
+        // A newer change replaces a pending one
+        retries.retain(|retry| (retry.id, retry.channel) != (*id, channel));
+        if let Err(e) = self.ramper.set(*id, channel, from, level) {
+            log::warn(format!("{e}, retrying"));
+            retries.push(Retry {
+                id: *id,
+                channel,
+                level,
+                attempts: 1,
+                due: Instant::now() + RETRY_DELAY,
+            });
+        }
+    }
+
+    /// Try the queued volume changes that are due, on the poll timer. After
+    /// `RETRY_ATTEMPTS` the change is given up with a notice.
+    pub fn retry_volumes(&mut self) {
+        let now = Instant::now();
+        let mut retries = std::mem::take(self.retries.get_mut());
+        if retries.is_empty() {
+            return;
+        }
+        retries.retain_mut(|retry| {
+            if retry.due > now {
+                return true;
+            }
+            // Gone, nothing left to change
+            let Some(device) = self.devices.iter().find(|d| d.id == retry.id) else {
+                return false;
+            };
+            match self
+                .backend
+                .set_volume(retry.id, retry.channel, retry.level)
+            {
+                Ok(()) => {
+                    log::info(format!("{}: volume change went through", device.label()));
+                    false
+                }
+                Err(_) if retry.attempts < RETRY_ATTEMPTS => {
+                    retry.due = now + RETRY_DELAY * 2u32.pow(retry.attempts);
+                    retry.attempts += 1;
+                    true
+                }
+                Err(e) => {
+                    let notice = format!("{}: volume change failed, {e}", device.label());
+                    self.notices.get_mut().push(notice);
+                    false
+                }
+            }
+        });
+        *self.retries.get_mut() = retries;
+    }
+
+    /// Whether a device channel has a volume change waiting for a retry.
+    pub fn is_retrying(&self, device: &Device, channel: Channel) -> bool {
+        self.retries
+            .borrow()
+            .iter()
+            .any(|retry| retry.id == device.id && retry.channel == channel)
+    }
+
+    /// Aggregates usually have no volume of their own, so move all their
+    /// members by the same amount, stopping when the loudest or quietest one
+    /// hits the limit so their offsets stay the same.
+    fn adjust_aggregate(&self, id: &AudioDeviceID, channel: Channel, amount: f32) {
+        let levels: Vec<(AudioDeviceID, f32)> = self
+            .aggregate_members(id, channel)
+            .iter()
+            .map(|d| (d.id, d.volume(channel).borrow().level))
+            .collect();
+        let highest = levels.iter().map(|(_, l)| *l).fold(ZERO, f32::max);
+        let lowest = levels.iter().map(|(_, l)| *l).fold(FULL, f32::min);
+        let amount = amount.clamp(ZERO - lowest, FULL - highest);
+        for (member, _) in levels {
+            self.adjust_volume(&member, channel, amount);
+        }
+    }
+
+    /// Connected members of an aggregate device with a volume for the
+    /// channel.
+    fn aggregate_members(&self, id: &AudioDeviceID, channel: Channel) -> Vec<&Device> {
+        let uids = self.backend.aggregate_uids(*id);
+        self.devices
+            .iter()
+            .filter(|d| uids.contains(&d.uid) && d.volume(channel).borrow().enabled)
+            .collect()
+    }
+
+    /// Level shown for an aggregate without its own volume: its loudest
+    /// member's.
+    fn aggregate_level(&self, device: &Device, channel: Channel) -> Option<f32> {
+        if !device.is_aggregate || device.volume(channel).borrow().enabled {
+            return None;
+        }
+        self.aggregate_members(&device.id, channel)
+            .iter()
+            .map(|d| d.volume(channel).borrow().level)
+            .reduce(f32::max)
+    }
+
+    fn toggle_device_mute(&mut self, id: &AudioDeviceID, channel: Channel) {
+        if is_read_only() {
+            return;
+        }
+        let device = match self.devices.iter().find(|d| d.id == *id) {
+            Some(device) => device,
+            None => return,
+        };
+        if !device.volume(channel).borrow().enabled {
+            return;
+        }
+        // Per channel: one without a settable mute falls back to the workaround
+        let native = !self.takes_over_mute(id) && self.backend.can_set_mute(*id, channel);
+        let (mute_in, mute_out) = self.backend.mutes(*id);
+        let sys_muted = match channel {
+            Channel::Input => mute_in == Some(true),
+            Channel::Output => mute_out == Some(true),
+        };
+        let mut vol_ref = device.volume(channel).borrow_mut();
+        let effects = mutes::toggle(*id, channel, &mut vol_ref, native, sys_muted);
+        self.run_effects(&effects);
+    }
+
+    /// Apply a level change on one output to the rest of its group.
+    fn sync_group(&self, id: &AudioDeviceID, level: f32) {
+        let uid = match self.devices.iter().find(|d| d.id == *id) {
+            Some(device) => &device.uid,
+            None => return,
+        };
+        let group = match self.groups.iter().find(|g| g.contains(uid)) {
+            Some(group) => group,
+            None => return,
+        };
+        let base = level - group.offset(uid).unwrap_or(ZERO);
+        for (member, offset) in &group.members {
+            if member == uid {
+                continue;
+            }
+            if let Some(device) = self.devices.iter().find(|d| d.uid == *member) {
+                let mut vol_ref = device.output.borrow_mut();
+                if vol_ref.enabled {
+                    let next_level = (base + offset).clamp(ZERO, self.cap(&device.id));
+                    vol_ref.level = next_level;
+                    vol_ref.cache = next_level;
+                    report(
+                        &self.notices,
+                        self.backend
+                            .set_volume(device.id, Channel::Output, next_level),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Keep the levels of workaround-muted channels in the registry, by
+    /// UID, so unmuting after a restart doesn't leave them at zero, and
+    /// every channel's last level above zero, for when it comes back at
+    /// zero. Disconnected devices keep theirs for when they're back.
+    fn save_volumes(&mut self) {
+        for device in &self.devices {
+            for channel in [Channel::Input, Channel::Output] {
+                let volume = device.volume(channel).borrow();
+                let level = volume.workaround_mute.then_some(volume.cache);
+                report(
+                    &self.notices,
+                    self.registry.set_muted_volume(&device.uid, channel, level),
+                );
+                if volume.enabled && volume.cache > ZERO {
+                    report(
+                        &self.notices,
+                        self.registry
+                            .set_last_volume(&device.uid, channel, volume.cache),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Apply an output's level to the output mirrored with it, if connected.
+    /// The mirror's own change then matches what update() reads back, so it
+    /// doesn't bounce back.
+    fn sync_mirror(&self, id: &AudioDeviceID, level: f32) {
+        let Some(mirror) = self
+            .devices
+            .iter()
+            .find(|d| d.id == *id)
+            .and_then(|d| d.settings.mirror.as_ref())
+        else {
+            return;
+        };
+        let Some(device) = self.devices.iter().find(|d| d.uid == *mirror) else {
+            return;
+        };
+        let mut vol_ref = device.output.borrow_mut();
+        let next_level = level.min(self.cap(&device.id));
+        if vol_ref.enabled && vol_ref.settable && vol_ref.level != next_level {
+            vol_ref.level = next_level;
+            vol_ref.cache = next_level;
+            report(
+                &self.notices,
+                self.backend
+                    .set_volume(device.id, Channel::Output, next_level),
+            );
+        }
+    }
+
+    /// Output paired with the active input in the config, if connected.
+    fn paired_output(&self) -> Option<AudioDeviceID> {
+        let input = self.active_device(Channel::Input)?;
+        let (_, output) = self.pairs.iter().find(|(i, _)| input.matches(i))?;
+        self.devices
+            .iter()
+            .find(|d| d.matches(output) && d.output.borrow().selectable)
+            .map(|d| d.id)
+    }
+
+    /// Output volume cap for a device.
+    fn cap(&self, id: &AudioDeviceID) -> f32 {
+        let night = self.night.as_ref().filter(|_| self.night_active);
+        self.devices
+            .iter()
+            .find(|d| d.id == *id)
+            .and_then(|d| cap_of(d, night))
+            .unwrap_or(FULL)
+    }
+
+    /// Turn the night cap on or off when its start or end time passes,
+    /// pulling speakers down to it.
+    pub fn check_night(&mut self) {
+        let Some(night) = &self.night else {
+            return;
+        };
+        let active = night.is_night(night::local_minutes());
+        if active == self.night_active {
+            return;
+        }
+        self.night_active = active;
+        self.notices.get_mut().push(match active {
+            true => format!("🌙 Night: speakers capped at {:.0}%", night.cap * 100.0),
+            false => "☀️ Morning: speaker caps back to normal".to_string(),
+        });
+        self.update();
+    }
+
+    /// Current group level, derived from the first connected member.
+    fn group_level(&self, group: &Group) -> Option<f32> {
+        group.members.iter().find_map(|(member, offset)| {
+            self.devices
+                .iter()
+                .find(|d| d.uid == *member)
+                .map(|d| d.output.borrow().level - offset)
+        })
+    }
+
+    /// Whether the volume-zero workaround handles mute for a device, as
+    /// opposed to the native system mute.
+    fn takes_over_mute(&self, id: &AudioDeviceID) -> bool {
+        if is_read_only() {
+            // Can't unmute the system, so just mirror its mute
+            return false;
+        }
+        let device = match self.devices.iter().find(|d| d.id == *id) {
+            Some(device) => device,
+            None => return true,
+        };
+        match device.settings.mute_policy {
+            MutePolicy::Always => true,
+            MutePolicy::Never => false,
+            MutePolicy::Auto => {
+                device.category == Category::Bluetooth
+                    && self.backend.has_mute(*id, Channel::Input)
+                    && self.backend.has_mute(*id, Channel::Output)
+            }
+        }
+    }
+
     /// Monterey introduced a bug where a mute change is applied to both input
     /// and output of a bluetooth device, making it impossible to mute the mic
     /// without muting speakers.
@@ -332,141 +2189,509 @@ impl AudioState {
     /// Here we check if a new system mute is set, if so, takeover control.
     /// Save the current volume level, set volume to 0 if muted, and unmute
     /// the system. We use our cached volume level to unmute.
+    ///
+    /// Without a takeover, the system mute is mirrored, and flipping it
+    /// (from Control Center, a headset button or another app) while the
+    /// workaround mute is on hands the channel back to the system mute, so
+    /// the two can't disagree about whether the mic is live.
     fn mute_check(&mut self, id: &AudioDeviceID) {
-        let (mute_in, mute_out) = device_mutes(&id);
-        let new_in = mute_in.is_some() && mute_in.unwrap();
-        let new_out = mute_out.is_some() && mute_out.unwrap();
-        if new_in || new_out {
-            let chan: Channel;
-            let mut chan_state = if mute_in.is_some() {
-                chan = Channel::Input;
-                // TODO: ugly access
-                self.devices
-                    .iter_mut()
-                    .find(|d| d.id == *id)
-                    .unwrap()
-                    .input
-                    .borrow_mut()
-            } else if mute_out.is_some() {
-                chan = Channel::Output;
-                // TODO: ugly access
-                self.devices
-                    .iter_mut()
-                    .find(|d| d.id == *id)
-                    .unwrap()
-                    .output
-                    .borrow_mut()
-            } else {
-                return;
-            };
-            // set volume to 0 (sys and state)
-            set_volume(&id, chan, ZERO);
-            // cache current volume level
-            let vol_ref = chan_state.borrow_mut();
-            vol_ref.cache = vol_ref.level;
-            vol_ref.level = ZERO;
+        let (mute_in, mute_out) = self.backend.mutes(*id);
+        let takes_over = self.takes_over_mute(id);
+        let Some(device) = self.devices.iter().find(|d| d.id == *id) else {
+            return;
+        };
+        // Each channel on its own, a headset's muted mic leaves its
+        // speakers alone. Without a takeover the native mute is left alone,
+        // only mirrored in `native_mute`, so it survives restarts
+        for (chan, muted) in [(Channel::Input, mute_in), (Channel::Output, mute_out)] {
+            let mut vol_ref = device.volume(chan).borrow_mut();
+            let (effects, lifted) = mutes::observe(*id, chan, &mut vol_ref, takes_over, muted);
+            if lifted {
+                log::info(format!(
+                    "{} {} mute changed by the system, workaround mute lifted",
+                    device.label(),
+                    chan.as_str()
+                ));
+            }
+            self.run_effects(&effects);
+        }
+    }
 
-            // unmute system
-            set_mute(&id, chan, false);
-            // add ID to mutes state
-            if !self.mutes.contains(&id) {
-                self.mutes.push(*id);
+    /// Make the writes the mute logic decided on. Unmutes ramp up from
+    /// zero, mutes are immediate.
+    fn run_effects(&self, effects: &[Effect]) {
+        for effect in effects {
+            report(
+                &self.notices,
+                match *effect {
+                    Effect::SetVolume(id, channel, level) => {
+                        self.ramper.set(id, channel, ZERO, level)
+                    }
+                    Effect::SetMute(id, channel, muted) => {
+                        self.backend.set_mute(id, channel, muted)
+                    }
+                },
+            );
+        }
+    }
+}
+
+/// Pull an output volume increase bigger than `max_jump` back to the
+/// previous level, returning the level the device ends up at. Protects
+/// against e.g. AirPods reconnecting at full volume.
+fn guard_jump(
+    backend: &dyn AudioBackend,
+    max_jump: Option<f32>,
+    device: &Device,
+    prev: f32,
+    level: f32,
+    notices: &RefCell<Vec<String>>,
+) -> f32 {
+    match max_jump {
+        Some(max) if level - prev > max && !is_read_only() => {
+            report(
+                notices,
+                backend.set_volume(device.id, Channel::Output, prev),
+            );
+            notices.borrow_mut().push(format!(
+                "⚠️  {} jumped to {:.0}%, kept at {:.0}%",
+                device.label(),
+                level * 100.0,
+                prev * 100.0
+            ));
+            prev
+        }
+        _ => level,
+    }
+}
+
+fn new_device(
+    id: AudioDeviceID,
+    uid: String,
+    name: String,
+    settings: DeviceSettings,
+    probe: &Probe,
+) -> Device {
+    let volume = |level: Option<f32>, i: usize| {
+        RefCell::new(Volume {
+            enabled: level.is_some(),
+            selectable: probe.selectable[i],
+            level: level.unwrap_or(ZERO),
+            cache: level.unwrap_or(ZERO),
+            native_mute: false,
+            workaround_mute: false,
+            settable: probe.settable[i],
+        })
+    };
+    Device {
+        id,
+        uid,
+        name,
+        input: volume(probe.volumes.0, 0),
+        output: volume(probe.volumes.1, 1),
+        stereo: Cell::new(probe.stereo),
+        sample_rate: Cell::new(probe.sample_rate),
+        sources: RefCell::new(probe.sources.clone()),
+        hog_owner: Cell::new(probe.hog_owner),
+        settings,
+        category: Category::from_transport(probe.transport),
+        is_aggregate: probe.transport == kAudioDeviceTransportTypeAggregate,
+        manufacturer: probe.manufacturer.clone(),
+        model: probe.model.clone(),
+    }
+}
+
+/// Take back a workaround mute from before a restart or disconnect, if the
+/// channel is still at zero. Anything else means it was unmuted elsewhere.
+/// A channel back at zero without one unmutes to its last level instead.
+fn restore_volumes(registry: &Registry, device: &Device) {
+    for channel in [Channel::Input, Channel::Output] {
+        let mut vol_ref = device.volume(channel).borrow_mut();
+        if !vol_ref.enabled || vol_ref.level != ZERO {
+            continue;
+        }
+        if let Some(cache) = registry.muted_volume(&device.uid, channel) {
+            vol_ref.cache = cache;
+            vol_ref.workaround_mute = true;
+        } else if let Some(last) = registry.last_volume(&device.uid, channel) {
+            vol_ref.cache = last;
+        }
+    }
+}
+
+/// Set the registry's preferred volumes and system mutes on a newly
+/// connected device.
+fn apply_preferred_volumes(
+    backend: &dyn AudioBackend,
+    device: &Device,
+    notices: &RefCell<Vec<String>>,
+) {
+    let settings = &device.settings;
+    for (channel, vol_state, level, muted) in [
+        (
+            Channel::Input,
+            &device.input,
+            settings.preferred_input_volume,
+            settings.preferred_input_mute,
+        ),
+        (
+            Channel::Output,
+            &device.output,
+            settings.preferred_volume,
+            settings.preferred_mute,
+        ),
+    ] {
+        let mut vol_ref = vol_state.borrow_mut();
+        if let (true, Some(level)) = (vol_ref.enabled, level) {
+            vol_ref.level = level;
+            vol_ref.cache = level;
+            report(notices, backend.set_volume(device.id, channel, level));
+        }
+        if let (true, Some(muted)) = (backend.can_set_mute(device.id, channel), muted) {
+            vol_ref.native_mute = muted;
+            report(notices, backend.set_mute(device.id, channel, muted));
+        }
+    }
+}
+
+/// A device's output cap: the registry's, lowered by the night cap for
+/// speakers while it's on.
+fn cap_of(device: &Device, night: Option<&NightConfig>) -> Option<f32> {
+    let night_cap = night
+        .filter(|night| !night.is_headphones(device))
+        .map(|night| night.cap);
+    match (device.settings.volume_cap, night_cap) {
+        (Some(cap), Some(night_cap)) => Some(cap.min(night_cap)),
+        (cap, night_cap) => cap.or(night_cap),
+    }
+}
+
+/// Pull output volume back down to its cap, wherever it was raised from.
+fn enforce_cap(
+    backend: &dyn AudioBackend,
+    device: &Device,
+    cap: Option<f32>,
+    notices: &RefCell<Vec<String>>,
+) {
+    if let Some(cap) = cap {
+        let mut vol_ref = device.output.borrow_mut();
+        if vol_ref.enabled && vol_ref.level > cap && !is_read_only() {
+            vol_ref.level = cap;
+            report(notices, backend.set_volume(device.id, Channel::Output, cap));
+        }
+    }
+}
+
+fn update_channel(vol_state: &RefCell<Volume>, level: f32) {
+    let mut v_ref = vol_state.borrow_mut();
+    v_ref.enabled = true;
+    v_ref.level = level;
+    // Changed elsewhere, like the menu bar, it's the level to unmute to
+    if level > ZERO {
+        v_ref.cache = level;
+    }
+    if level > ZERO && v_ref.workaround_mute {
+        // volume raised, no longer muted
+        v_ref.workaround_mute = false;
+    } else if level == ZERO && !v_ref.workaround_mute {
+        // volume dropped to zero, muted
+        v_ref.workaround_mute = true;
+    }
+}
+
+/// First get the size of the "devices" data. Divide that by the size of a u32
+/// to get the number of devices. Finally, fetch the data in a u32 vec.
+pub fn device_ids() -> Result<Vec<u32>, ControlError> {
+    let prop_size = query_size(
+        &kAudioObjectSystemObject,
+        kAudioHardwarePropertyDevices,
+        kAudioObjectPropertyScopeGlobal,
+    )?;
+    let num_devices = prop_size as usize / std::mem::size_of::<AudioDeviceID>();
+    if num_devices == 0 {
+        return Ok(vec![]);
+    }
+    query_audio_object::<UInt32>(
+        &kAudioObjectSystemObject,
+        kAudioHardwarePropertyDevices,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        num_devices,
+    )
+}
+
+/// Get device's human readable name.
+pub fn device_name(id: &u32) -> String {
+    string_property(id, kAudioDevicePropertyDeviceNameCFString)
+}
+
+/// Maker of a device, `None` when it doesn't say.
+pub fn device_manufacturer(id: &u32) -> Option<String> {
+    Some(string_property(id, kAudioObjectPropertyManufacturer)).filter(|m| !m.is_empty())
+}
+
+/// Model of a device: its model name, or else the model UID drivers use
+/// to identify a kind of device.
+pub fn device_model(id: &u32) -> Option<String> {
+    [kAudioObjectPropertyModelName, kAudioDevicePropertyModelUID]
+        .into_iter()
+        .map(|selector| string_property(id, selector))
+        .find(|model| !model.is_empty())
+}
+
+/// Whether a device's own name is AirPods or Beats, which macOS switches
+/// between the user's Macs, iPhones and iPads when automatic switching is on.
+fn is_airpods(name: &str) -> bool {
+    ["AirPods", "Beats"].iter().any(|n| name.contains(n))
+}
+
+/// Names that tell devices apart, for (UID, name) pairs and in their
+/// order: the name, numbered in UID order like "USB Audio Device #2" when
+/// others have the same one.
+pub fn unique_names(devices: &[(&str, &str)]) -> Vec<String> {
+    devices
+        .iter()
+        .map(|(uid, name)| {
+            let mut twins: Vec<&str> = devices
+                .iter()
+                .filter(|(_, other)| other == name)
+                .map(|(uid, _)| *uid)
+                .collect();
+            if twins.len() < 2 {
+                return name.to_string();
+            }
+            twins.sort();
+            let index = twins.iter().position(|twin| twin == uid).unwrap_or(0) + 1;
+            format!("{name} #{index}")
+        })
+        .collect()
+}
+
+/// Index of the (UID, name) pair a key means: a UID, a name from
+/// `unique_names`, or a name only one device has. A shared name is an
+/// error listing the devices it could be, so nothing changes on a guess.
+pub fn resolve_device(devices: &[(&str, &str)], key: &str) -> Result<usize, ControlError> {
+    if let Some(i) = devices.iter().position(|(uid, _)| *uid == key) {
+        return Ok(i);
+    }
+    let names = unique_names(devices);
+    if let Some(i) = names.iter().position(|name| name == key) {
+        return Ok(i);
+    }
+    let named: Vec<usize> = (0..devices.len())
+        .filter(|i| devices[*i].1 == key)
+        .collect();
+    match named[..] {
+        [i] => Ok(i),
+        [] => Err(ControlError::Device(format!("No device {key}"))),
+        _ => Err(ControlError::Device(format!(
+            "{} devices are called {key}, pick one of {}",
+            named.len(),
+            named
+                .iter()
+                .map(|i| format!("\"{}\"", names[*i]))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// A CFString property, empty when the device doesn't have it.
+fn string_property(id: &u32, selector: AudioObjectPropertySelector) -> String {
+    unsafe {
+        // Get pointer bytes, then throw out head and tail, converting the
+        // body of bytes to a CFStringRef (a typed pointer)
+        let buf = query_audio_object::<u8>(
+            id,
+            selector,
+            kAudioObjectPropertyScopeGlobal,
+            kAudioObjectPropertyElementMain,
+            8,
+        )
+        .unwrap_or_default();
+        let (_, string_ref, _) = buf.align_to::<CFStringRef>();
+        string_ref
+            .first()
+            .map_or(String::new(), |r| ref_to_string(*r))
+    }
+}
+
+/// Transport type, like USB or Bluetooth. 0 when unknown.
+pub(crate) fn device_transport(id: &u32) -> UInt32 {
+    query_first::<UInt32>(
+        id,
+        kAudioDevicePropertyTransportType,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    )
+    .unwrap_or(0)
+}
+
+/// UIDs of an aggregate device's members.
+pub fn aggregate_uids(id: &u32) -> Vec<String> {
+    if !query_exists(
+        id,
+        kAudioAggregateDevicePropertyFullSubDeviceList,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    ) {
+        return Vec::new();
+    }
+    unsafe {
+        let Ok(buf) = query_audio_object::<u8>(
+            id,
+            kAudioAggregateDevicePropertyFullSubDeviceList,
+            kAudioObjectPropertyScopeGlobal,
+            kAudioObjectPropertyElementMain,
+            8,
+        ) else {
+            return Vec::new();
+        };
+        let (_, array_ref, _) = buf.align_to::<CFArrayRef>();
+        match array_ref.first() {
+            Some(array_ref) if !array_ref.is_null() => {
+                CFArray::<CFString>::wrap_under_create_rule(*array_ref)
+                    .iter()
+                    .map(|uid| uid.to_string())
+                    .collect()
             }
+            _ => Vec::new(),
         }
     }
 }
 
-fn update_channel(
-    id: &u32,
-    vol_state: &RefCell<Volume>,
-    mutes: &mut Vec<u32>,
-    level: f32,
-    is_muted: bool,
-) {
-    let mut v_ref = vol_state.borrow_mut();
-    v_ref.enabled = true;
-    v_ref.level = level;
-    if level > ZERO && is_muted {
-        // volume raised, remove from mutes
-        if let Some(i) = mutes.iter().position(|mid| *mid == *id) {
-            mutes.remove(i);
-        }
-    } else if level == ZERO && !is_muted {
-        // volume dropped to zero, add to mutes
-        mutes.push(*id);
+pub fn set_aggregate_uids(id: &u32, uids: &[String]) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let uids: Vec<CFString> = uids.iter().map(|uid| CFString::new(uid)).collect();
+    let array = CFArray::from_CFTypes(&uids);
+    set_audio_object_prop(
+        id,
+        kAudioAggregateDevicePropertyFullSubDeviceList,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        array.as_concrete_TypeRef(),
+    )
+}
+
+/// A public multi-output device playing on every one of `uids`, the first
+/// keeping the clock and the rest corrected for drift. Public, unlike the
+/// app's own aggregates, so it stays the default after the app exits.
+pub fn create_multi_output(uids: &[String]) -> Result<AudioDeviceID, ControlError> {
+    if is_read_only() {
+        return Err(ControlError::ReadOnly);
+    }
+    let pair = |key: &str, value: CFType| (CFString::new(key).as_CFType(), value);
+    let entries: Vec<CFDictionary<CFType, CFType>> = uids
+        .iter()
+        .map(|uid| {
+            CFDictionary::from_CFType_pairs(&[
+                pair("uid", CFString::new(uid).as_CFType()),
+                pair("drift", CFBoolean::true_value().as_CFType()),
+            ])
+        })
+        .collect();
+    let description = CFDictionary::from_CFType_pairs(&[
+        pair("uid", CFString::new(BROADCAST_UID).as_CFType()),
+        pair("name", CFString::new(BROADCAST_NAME).as_CFType()),
+        pair("master", CFString::new(&uids[0]).as_CFType()),
+        pair("stacked", CFBoolean::true_value().as_CFType()),
+        pair("subdevices", CFArray::from_CFTypes(&entries).as_CFType()),
+    ]);
+    let mut aggregate: AudioObjectID = 0;
+    let status = unsafe {
+        AudioHardwareCreateAggregateDevice(description.as_concrete_TypeRef(), &mut aggregate)
+    };
+    match status {
+        NO_ERR => Ok(aggregate),
+        status => Err(ControlError::Other(format!(
+            "Unable to make a multi-output device: {status}"
+        ))),
     }
 }
 
-/// First get the size of the "devices" data. Divide that by the size of a u32
-/// to get the number of devices. Finally, fetch the data in a u32 vec.
-fn device_ids() -> Vec<u32> {
-    let prop_size = query_size(
-        &kAudioObjectSystemObject,
-        kAudioHardwarePropertyDevices,
+/// (UID, sub-device object, drift correction) of an aggregate's connected
+/// members. Drift correction is set on these objects, not the devices.
+pub fn sub_device_drift(id: &u32) -> Vec<(String, AudioObjectID, bool)> {
+    let count = match query_size(
+        id,
+        kAudioObjectPropertyOwnedObjects,
         kAudioObjectPropertyScopeGlobal,
-    )
-    .expect("Query audio object size");
-    let num_devices = prop_size as usize / std::mem::size_of::<AudioDeviceID>();
-    if num_devices == 0 {
-        return vec![];
+    ) {
+        Ok(size) => size as usize / std::mem::size_of::<AudioObjectID>(),
+        Err(_) => return Vec::new(),
+    };
+    if count == 0 {
+        return Vec::new();
     }
-    query_audio_object::<UInt32>(
-        &kAudioObjectSystemObject,
-        kAudioHardwarePropertyDevices,
+    query_audio_object::<AudioObjectID>(
+        id,
+        kAudioObjectPropertyOwnedObjects,
         kAudioObjectPropertyScopeGlobal,
         kAudioObjectPropertyElementMain,
-        num_devices,
+        count,
     )
-}
-
-/// Get device's human readable name.
-fn device_name(id: &u32) -> String {
-    unsafe {
-        // Get pointer bytes, then throw out head and tail, converting the
-        // body of bytes to a CFStringRef
-        let name_buf = query_audio_object::<u8>(
-            id,
-            kAudioDevicePropertyDeviceNameCFString,
+    .unwrap_or_default()
+    .into_iter()
+    .filter(|sub| {
+        query_first::<UInt32>(
+            sub,
+            kAudioObjectPropertyClass,
+            kAudioObjectPropertyScopeGlobal,
+            kAudioObjectPropertyElementMain,
+        ) == Some(kAudioSubDeviceClassID)
+    })
+    .map(|sub| {
+        let drift = query_first::<UInt32>(
+            &sub,
+            kAudioSubDevicePropertyDriftCompensation,
             kAudioObjectPropertyScopeGlobal,
             kAudioObjectPropertyElementMain,
-            8,
         );
-        let (_, name_ref, _) = name_buf.align_to::<CFStringRef>();
-        ref_to_string(name_ref[0])
+        (device_uid(&sub), sub, drift == Some(1))
+    })
+    .collect()
+}
+
+/// Turn drift correction of an aggregate's member on or off.
+pub fn set_drift_correction(id: &u32, uid: &str, on: bool) -> Result<(), ControlError> {
+    let sub = sub_device_drift(id)
+        .into_iter()
+        .find(|(u, _, _)| u == uid)
+        .map(|(_, sub, _)| sub)
+        .ok_or_else(|| ControlError::Device(format!("{uid} isn't a connected member")))?;
+    if is_read_only() {
+        return Ok(());
     }
+    set_audio_object_prop(
+        &sub,
+        kAudioSubDevicePropertyDriftCompensation,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        on as UInt32,
+    )
 }
 
 /// Get device's unique ID string.
-fn device_uid(id: &u32) -> String {
-    unsafe {
-        // Get pointer bytes, then throw out head and tail, converting the
-        // body of bytes to a CFStringRef (a typed pointer)
-        let uid_buf = query_audio_object::<u8>(
-            id,
-            kAudioDevicePropertyDeviceUID,
-            kAudioObjectPropertyScopeGlobal,
-            kAudioObjectPropertyElementMain,
-            8,
-        );
-        let (_, uid_ref, _) = uid_buf.align_to::<CFStringRef>();
-        ref_to_string(uid_ref[0])
-    }
+pub fn device_uid(id: &u32) -> String {
+    string_property(id, kAudioDevicePropertyDeviceUID)
 }
 
 /// Get current input/output levels for device.
-fn volume_level(id: &u32) -> (Option<f32>, Option<f32>) {
+pub fn volume_level(id: &u32) -> (Option<f32>, Option<f32>) {
     let out_chans = query_size(
         id,
         kAudioDevicePropertyStreams,
         kAudioDevicePropertyScopeOutput,
     )
-    .unwrap();
+    .unwrap_or(0);
     let in_chans = query_size(
         id,
         kAudioDevicePropertyStreams,
         kAudioDevicePropertyScopeInput,
     )
-    .unwrap();
+    .unwrap_or(0);
 
     // TODO: Check what other channels are doing
     // iterate through channels checking if it has volume
@@ -479,17 +2704,26 @@ fn volume_level(id: &u32) -> (Option<f32>, Option<f32>) {
             kAudioDevicePropertyScopeOutput,
             i,
         ) {
-            let vol_buf = query_audio_object::<Float32>(
+            out_volume = query_first::<Float32>(
                 id,
                 kAudioDevicePropertyVolumeScalar,
                 kAudioDevicePropertyScopeOutput,
                 i,
-                1,
             );
-            out_volume = Some(vol_buf[0]);
             break;
         }
     }
+    // Without a main volume the first channel was found, but the louder one
+    // stands for the device so panning doesn't look like a volume change
+    let has_main = query_exists(
+        id,
+        kAudioDevicePropertyVolumeScalar,
+        kAudioDevicePropertyScopeOutput,
+        kAudioObjectPropertyElementMain,
+    );
+    if let (false, Some((left, right))) = (has_main, stereo_levels(id)) {
+        out_volume = Some(left.max(right));
+    }
     for i in 0..in_chans {
         if query_exists(
             id,
@@ -497,14 +2731,12 @@ fn volume_level(id: &u32) -> (Option<f32>, Option<f32>) {
             kAudioDevicePropertyScopeInput,
             i,
         ) {
-            let vol_buf = query_audio_object::<Float32>(
+            in_volume = query_first::<Float32>(
                 id,
                 kAudioDevicePropertyVolumeScalar,
                 kAudioDevicePropertyScopeInput,
                 i,
-                1,
             );
-            in_volume = Some(vol_buf[0]);
             break;
         }
     }
@@ -512,7 +2744,7 @@ fn volume_level(id: &u32) -> (Option<f32>, Option<f32>) {
 }
 
 /// Get (input, output) mute state for a device
-fn device_mutes(id: &u32) -> (Option<bool>, Option<bool>) {
+pub fn device_mutes(id: &u32) -> (Option<bool>, Option<bool>) {
     let mut in_mute = None;
     let mut out_mute = None;
     if query_exists(
@@ -521,14 +2753,13 @@ fn device_mutes(id: &u32) -> (Option<bool>, Option<bool>) {
         kAudioDevicePropertyScopeOutput,
         kAudioObjectPropertyElementMain,
     ) {
-        let muted = query_audio_object::<UInt32>(
+        let muted = query_first::<UInt32>(
             id,
             kAudioDevicePropertyMute,
             kAudioDevicePropertyScopeOutput,
             kAudioObjectPropertyElementMain,
-            1,
         );
-        out_mute = Some(muted[0] == 1);
+        out_mute = muted.map(|m| m == 1);
     }
 
     if query_exists(
@@ -537,100 +2768,703 @@ fn device_mutes(id: &u32) -> (Option<bool>, Option<bool>) {
         kAudioDevicePropertyScopeInput,
         kAudioObjectPropertyElementMain,
     ) {
-        let muted = query_audio_object::<UInt32>(
+        let muted = query_first::<UInt32>(
             id,
             kAudioDevicePropertyMute,
             kAudioDevicePropertyScopeInput,
             kAudioObjectPropertyElementMain,
-            1,
         );
-        in_mute = Some(muted[0] == 1);
+        in_mute = muted.map(|m| m == 1);
+    }
+    (in_mute, out_mute)
+}
+
+/// Find currently active device
+pub fn default_device(signal: Channel) -> AudioObjectID {
+    let selector = match signal {
+        Channel::Input => kAudioHardwarePropertyDefaultInputDevice,
+        Channel::Output => kAudioHardwarePropertyDefaultOutputDevice,
+    };
+    let d = query_first::<UInt32>(
+        &kAudioObjectSystemObject,
+        selector,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    );
+    // 0 is no device, like CoreAudio's kAudioObjectUnknown
+    d.unwrap_or(0)
+}
+
+/// Device alerts and sound effects play on, "Play sound effects through"
+/// in the Sound settings.
+pub fn alert_device() -> AudioObjectID {
+    let d = query_first::<UInt32>(
+        &kAudioObjectSystemObject,
+        kAudioHardwarePropertyDefaultSystemOutputDevice,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+    );
+    d.unwrap_or(0)
+}
+
+pub fn can_be_alert_device(id: &u32) -> bool {
+    let res = query_first::<UInt32>(
+        id,
+        kAudioDevicePropertyDeviceCanBeDefaultSystemDevice,
+        kAudioDevicePropertyScopeOutput,
+        kAudioObjectPropertyElementMain,
+    );
+    res == Some(1)
+}
+
+pub fn set_alert_device(id: &u32) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    set_audio_object_prop(
+        &kAudioObjectSystemObject,
+        kAudioHardwarePropertyDefaultSystemOutputDevice,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        *id,
+    )
+}
+
+/// Check if device can be made active
+pub fn can_be_default_device(signal: Channel, id: &u32) -> bool {
+    let scope = match signal {
+        Channel::Input => kAudioDevicePropertyScopeInput,
+        Channel::Output => kAudioDevicePropertyScopeOutput,
+    };
+    let res = query_first::<UInt32>(
+        id,
+        kAudioDevicePropertyDeviceCanBeDefaultDevice,
+        scope,
+        kAudioObjectPropertyElementMain,
+    );
+    res == Some(1)
+}
+
+/// Whether the device has output streams to play on.
+pub fn has_output(id: &u32) -> bool {
+    query_size(
+        id,
+        kAudioDevicePropertyStreams,
+        kAudioDevicePropertyScopeOutput,
+    )
+    .unwrap_or(0)
+        > 0
+}
+
+/// Set active device
+pub fn set_default_device(signal: Channel, id: &u32) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let selector = match signal {
+        Channel::Input => kAudioHardwarePropertyDefaultInputDevice,
+        Channel::Output => kAudioHardwarePropertyDefaultOutputDevice,
+    };
+
+    set_audio_object_prop(
+        &kAudioObjectSystemObject,
+        selector,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        *id,
+    )
+}
+
+/// Keep a failed change that has no caller to return it to, like a device
+/// going away mid-change, for the status line.
+fn report(notices: &RefCell<Vec<String>>, result: Result<(), impl fmt::Display>) {
+    if let Err(e) = result {
+        log::warn(e.to_string());
+        notices.borrow_mut().push(e.to_string());
+    }
+}
+
+/// Change device's volume
+pub fn set_volume(id: &u32, channel: Channel, volume: f32) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let scope = match channel {
+        Channel::Input => kAudioDevicePropertyScopeInput,
+        Channel::Output => kAudioDevicePropertyScopeOutput,
+    };
+
+    // Number of channels
+    let channels = query_size(id, kAudioDevicePropertyStreams, scope)?;
+
+    // Keep the output's balance
+    let stereo = match channel {
+        Channel::Input => None,
+        Channel::Output => stereo_channels(id).zip(stereo_levels(id)),
+    };
+    let gain = |element: UInt32| match stereo {
+        Some(((left, _), levels)) if element == left => balance_gains(balance_of(levels)).0,
+        Some(((_, right), levels)) if element == right => balance_gains(balance_of(levels)).1,
+        _ => FULL,
+    };
+
+    // Iterate through channels, check if settable, then set
+    for i in 0..channels {
+        if query_settable(id, kAudioDevicePropertyVolumeScalar, scope, i) {
+            let level = volume * gain(i);
+            set_audio_object_prop(id, kAudioDevicePropertyVolumeScalar, scope, i, level)?;
+        }
+    }
+    Ok(())
+}
+
+/// Elements of the left and right channels of a device's stereo output,
+/// usually 1 and 2.
+pub fn stereo_channels(id: &u32) -> Option<(UInt32, UInt32)> {
+    let selector = kAudioDevicePropertyPreferredChannelsForStereo;
+    let scope = kAudioDevicePropertyScopeOutput;
+    if !query_exists(id, selector, scope, kAudioObjectPropertyElementMain) {
+        return None;
+    }
+    match query_audio_object::<UInt32>(id, selector, scope, kAudioObjectPropertyElementMain, 2)
+        .ok()?[..]
+    {
+        [left, right] => Some((left, right)),
+        _ => None,
+    }
+}
+
+/// Set the output channels used for stereo, what Audio MIDI Setup calls
+/// "Configure Speakers".
+pub fn set_stereo_channels(id: &u32, (left, right): (UInt32, UInt32)) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Err(ControlError::ReadOnly);
+    }
+    let count = output_channel_count(id);
+    if left == 0 || right == 0 || left > count || right > count {
+        return Err(ControlError::Device(format!(
+            "Device {id} has output channels 1 to {count}"
+        )));
+    }
+    set_audio_object_prop(
+        id,
+        kAudioDevicePropertyPreferredChannelsForStereo,
+        kAudioDevicePropertyScopeOutput,
+        kAudioObjectPropertyElementMain,
+        [left, right],
+    )
+}
+
+/// Output channels over all of a device's output streams.
+pub fn output_channel_count(id: &u32) -> UInt32 {
+    let Ok(bytes) = property_bytes(
+        id,
+        kAudioDevicePropertyStreamConfiguration,
+        kAudioDevicePropertyScopeOutput,
+        kAudioObjectPropertyElementMain,
+    ) else {
+        return 0;
+    };
+    // An AudioBufferList: the buffer count, then buffers of (channels,
+    // byte size, data pointer) after padding to the pointer's alignment
+    let read = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map_or(0, |b| UInt32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let buffer_size = std::mem::size_of::<AudioBuffer>();
+    let first = std::mem::offset_of!(AudioBufferList, mBuffers);
+    (0..read(0) as usize)
+        .map(|i| read(first + i * buffer_size))
+        .sum()
+}
+
+/// Output levels of the left and right channels, for devices with a
+/// volume per channel.
+pub fn stereo_levels(id: &u32) -> Option<(f32, f32)> {
+    let (left, right) = stereo_channels(id)?;
+    let level = |element| {
+        let scope = kAudioDevicePropertyScopeOutput;
+        if !query_exists(id, kAudioDevicePropertyVolumeScalar, scope, element) {
+            return None;
+        }
+        query_first::<Float32>(id, kAudioDevicePropertyVolumeScalar, scope, element)
+    };
+    Some((level(left)?, level(right)?))
+}
+
+/// Balance of left and right levels, -1 (left only) to 1 (right only).
+pub fn balance_of((left, right): (f32, f32)) -> f32 {
+    let level = left.max(right);
+    if level <= ZERO {
+        return ZERO;
+    }
+    (right - left) / level
+}
+
+/// Like `centered` or `30% left`.
+pub fn describe_balance(balance: f32) -> String {
+    let percent = (balance.abs() * 100.0).round();
+    match balance {
+        _ if percent == 0.0 => "centered".to_string(),
+        b if b < 0.0 => format!("{percent}% left"),
+        _ => format!("{percent}% right"),
+    }
+}
+
+/// (left, right) gains for a balance, the louder side at full level.
+fn balance_gains(balance: f32) -> (f32, f32) {
+    ((FULL - balance).min(FULL), (FULL + balance).min(FULL))
+}
+
+/// Pan a device's stereo output, keeping the louder channel's level.
+pub fn set_balance(id: &u32, balance: f32) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let (left, right) = stereo_channels(id)
+        .ok_or_else(|| ControlError::Device(format!("Device {id} has no stereo output")))?;
+    let levels = stereo_levels(id)
+        .ok_or_else(|| ControlError::Device(format!("Device {id} has no volume per channel")))?;
+    let scope = kAudioDevicePropertyScopeOutput;
+    if !query_settable(id, kAudioDevicePropertyVolumeScalar, scope, left)
+        || !query_settable(id, kAudioDevicePropertyVolumeScalar, scope, right)
+    {
+        return Err(ControlError::Device(format!(
+            "The channel volumes of device {id} can't be changed"
+        )));
+    }
+    let level = levels.0.max(levels.1);
+    let (gain_left, gain_right) = balance_gains(balance.clamp(-FULL, FULL));
+    let selector = kAudioDevicePropertyVolumeScalar;
+    set_audio_object_prop(id, selector, scope, left, level * gain_left)?;
+    set_audio_object_prop(id, selector, scope, right, level * gain_right)
+}
+
+/// Check if device has a mute property for a channel
+pub fn has_mute(id: &u32, channel: Channel) -> bool {
+    let scope = match channel {
+        Channel::Input => kAudioDevicePropertyScopeInput,
+        Channel::Output => kAudioDevicePropertyScopeOutput,
+    };
+    query_exists(
+        id,
+        kAudioDevicePropertyMute,
+        scope,
+        kAudioObjectPropertyElementMain,
+    )
+}
+
+/// Check if device's mute can be changed for a channel
+pub fn can_set_mute(id: &u32, channel: Channel) -> bool {
+    let scope = match channel {
+        Channel::Input => kAudioDevicePropertyScopeInput,
+        Channel::Output => kAudioDevicePropertyScopeOutput,
+    };
+    query_settable(
+        id,
+        kAudioDevicePropertyMute,
+        scope,
+        kAudioObjectPropertyElementMain,
+    )
+}
+
+/// Set device's mute state
+pub fn set_mute(id: &u32, channel: Channel, enabled: bool) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let mute_val: UInt32 = if enabled { 1 } else { 0 };
+    let scope = match channel {
+        Channel::Input => kAudioDevicePropertyScopeInput,
+        Channel::Output => kAudioDevicePropertyScopeOutput,
+    };
+    set_audio_object_prop(
+        id,
+        kAudioDevicePropertyMute,
+        scope,
+        kAudioObjectPropertyElementMain,
+        mute_val,
+    )
+}
+
+/// Rates offered for continuous ranges, which some interfaces report.
+const COMMON_RATES: &[f64] = &[
+    8000.0, 16000.0, 22050.0, 44100.0, 48000.0, 88200.0, 96000.0, 176400.0, 192000.0,
+];
+
+/// Current sample rate in Hz.
+pub fn sample_rate(id: &u32) -> Option<f64> {
+    let selector = kAudioDevicePropertyNominalSampleRate;
+    let scope = kAudioObjectPropertyScopeGlobal;
+    if !query_exists(id, selector, scope, kAudioObjectPropertyElementMain) {
+        return None;
+    }
+    query_first::<Float64>(id, selector, scope, kAudioObjectPropertyElementMain)
+}
+
+/// Sample rates the device can switch to, lowest first.
+pub fn sample_rates(id: &u32) -> Vec<f64> {
+    let selector = kAudioDevicePropertyAvailableNominalSampleRates;
+    let scope = kAudioObjectPropertyScopeGlobal;
+    let Ok(size) = query_size(id, selector, scope) else {
+        return Vec::new();
+    };
+    let len = size as usize / std::mem::size_of::<AudioValueRange>();
+    let ranges = query_audio_object::<AudioValueRange>(
+        id,
+        selector,
+        scope,
+        kAudioObjectPropertyElementMain,
+        len,
+    )
+    .unwrap_or_default();
+    let mut rates: Vec<f64> = Vec::new();
+    for range in ranges {
+        let in_range = COMMON_RATES
+            .iter()
+            .filter(|r| (range.mMinimum..=range.mMaximum).contains(*r));
+        match range.mMinimum == range.mMaximum {
+            true => rates.push(range.mMinimum),
+            false => rates.extend(in_range),
+        }
+    }
+    rates.sort_by(|a, b| a.total_cmp(b));
+    rates.dedup();
+    rates
+}
+
+pub fn set_sample_rate(id: &u32, rate: f64) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    set_audio_object_prop(
+        id,
+        kAudioDevicePropertyNominalSampleRate,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        rate,
+    )
+}
+
+/// Process with exclusive access to the device, none when it's free.
+pub fn hog_owner(id: &u32) -> Option<i32> {
+    let selector = kAudioDevicePropertyHogMode;
+    let scope = kAudioObjectPropertyScopeGlobal;
+    if !query_exists(id, selector, scope, kAudioObjectPropertyElementMain) {
+        return None;
     }
-    (in_mute, out_mute)
+    query_first::<SInt32>(id, selector, scope, kAudioObjectPropertyElementMain)
+        .filter(|pid| *pid > 0)
 }
 
-/// Find currently active device
-fn default_device(signal: Channel) -> AudioObjectID {
-    let selector = match signal {
-        Channel::Input => kAudioHardwarePropertyDefaultInputDevice,
-        Channel::Output => kAudioHardwarePropertyDefaultOutputDevice,
-    };
-    let d = query_audio_object::<UInt32>(
-        &kAudioObjectSystemObject,
-        selector,
+pub fn can_hog(id: &u32) -> bool {
+    query_settable(
+        id,
+        kAudioDevicePropertyHogMode,
         kAudioObjectPropertyScopeGlobal,
         kAudioObjectPropertyElementMain,
-        1,
-    );
-    d[0]
+    )
 }
 
-/// Check if device can be made active
-fn can_be_default_device(signal: Channel, id: &u32) -> bool {
-    let scope = match signal {
+/// Setting hog mode toggles it, whatever the value: a free device goes to
+/// this process, and this process's hold is let go.
+pub fn toggle_hog_mode(id: &u32) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    set_audio_object_prop(
+        id,
+        kAudioDevicePropertyHogMode,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
+        std::process::id() as SInt32,
+    )
+}
+
+/// Bits per sample of a channel's first stream, as the hardware runs it.
+pub fn bit_depth(id: &u32, channel: Channel) -> Option<u32> {
+    let scope = match channel {
         Channel::Input => kAudioDevicePropertyScopeInput,
         Channel::Output => kAudioDevicePropertyScopeOutput,
     };
-    let res = query_audio_object::<UInt32>(
+    let size = query_size(id, kAudioDevicePropertyStreams, scope).ok()?;
+    let len = size as usize / std::mem::size_of::<AudioObjectID>();
+    let streams = query_audio_object::<AudioObjectID>(
         id,
-        kAudioDevicePropertyDeviceCanBeDefaultDevice,
+        kAudioDevicePropertyStreams,
         scope,
         kAudioObjectPropertyElementMain,
-        1,
+        len,
+    )
+    .ok()?;
+    let stream = streams.first()?;
+    let format = query_first::<AudioStreamBasicDescription>(
+        stream,
+        kAudioStreamPropertyPhysicalFormat,
+        kAudioObjectPropertyScopeGlobal,
+        kAudioObjectPropertyElementMain,
     );
-    res.len() > 0 && res[0] == 1
+    format.map(|f| f.mBitsPerChannel).filter(|bits| *bits > 0)
 }
 
-/// Set active device
-fn set_default_device(signal: Channel, id: &u32) {
-    let selector = match signal {
-        Channel::Input => kAudioHardwarePropertyDefaultInputDevice,
-        Channel::Output => kAudioHardwarePropertyDefaultOutputDevice,
+/// What a device source selects: where a channel's sound goes or comes
+/// from, like "Headphones" on the built-in output, or what the device
+/// syncs its clock to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceKind {
+    Data(Channel),
+    Clock,
+}
+
+impl SourceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SourceKind::Data(Channel::Input) => "input source",
+            SourceKind::Data(Channel::Output) => "output source",
+            SourceKind::Clock => "clock source",
+        }
+    }
+
+    /// (active, available, name for ID) selectors, and the scope.
+    fn selectors(
+        self,
+    ) -> (
+        AudioObjectPropertySelector,
+        AudioObjectPropertySelector,
+        AudioObjectPropertySelector,
+        AudioObjectPropertyScope,
+    ) {
+        let data = (
+            kAudioDevicePropertyDataSource,
+            kAudioDevicePropertyDataSources,
+            kAudioDevicePropertyDataSourceNameForIDCFString,
+        );
+        let ((active, available, name), scope) = match self {
+            SourceKind::Data(Channel::Input) => (data, kAudioDevicePropertyScopeInput),
+            SourceKind::Data(Channel::Output) => (data, kAudioDevicePropertyScopeOutput),
+            SourceKind::Clock => (
+                (
+                    kAudioDevicePropertyClockSource,
+                    kAudioDevicePropertyClockSources,
+                    kAudioDevicePropertyClockSourceNameForIDCFString,
+                ),
+                kAudioObjectPropertyScopeGlobal,
+            ),
+        };
+        (active, available, name, scope)
+    }
+}
+
+/// A device's sources of one kind, by ID and name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceList {
+    pub available: Vec<(UInt32, String)>,
+    pub active: Option<UInt32>,
+}
+
+impl SourceList {
+    pub fn read(id: &u32, kind: SourceKind) -> Self {
+        let (active, available, _, scope) = kind.selectors();
+        let Ok(size) = query_size(id, available, scope) else {
+            return SourceList::default();
+        };
+        let len = size as usize / std::mem::size_of::<UInt32>();
+        let available: Vec<_> = query_audio_object::<UInt32>(
+            id,
+            available,
+            scope,
+            kAudioObjectPropertyElementMain,
+            len,
+        )
+        .unwrap_or_default()
+        .into_iter()
+        .map(|source| (source, source_name(id, kind, source)))
+        .collect();
+        let active = match available.is_empty() {
+            true => None,
+            false => query_first::<UInt32>(id, active, scope, kAudioObjectPropertyElementMain),
+        };
+        SourceList { available, active }
+    }
+
+    pub fn active_name(&self) -> Option<&str> {
+        self.available
+            .iter()
+            .find(|(source, _)| Some(*source) == self.active)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Data sources per channel and clock sources. Most devices have none,
+/// or only one of each.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sources {
+    pub input: SourceList,
+    pub output: SourceList,
+    pub clock: SourceList,
+}
+
+impl Sources {
+    pub fn read(id: &u32) -> Self {
+        Sources {
+            input: SourceList::read(id, SourceKind::Data(Channel::Input)),
+            output: SourceList::read(id, SourceKind::Data(Channel::Output)),
+            clock: SourceList::read(id, SourceKind::Clock),
+        }
+    }
+
+    pub fn get(&self, kind: SourceKind) -> &SourceList {
+        match kind {
+            SourceKind::Data(Channel::Input) => &self.input,
+            SourceKind::Data(Channel::Output) => &self.output,
+            SourceKind::Clock => &self.clock,
+        }
+    }
+
+    pub fn get_mut(&mut self, kind: SourceKind) -> &mut SourceList {
+        match kind {
+            SourceKind::Data(Channel::Input) => &mut self.input,
+            SourceKind::Data(Channel::Output) => &mut self.output,
+            SourceKind::Clock => &mut self.clock,
+        }
+    }
+}
+
+/// Name of a source, or its ID when the device has none.
+fn source_name(id: &u32, kind: SourceKind, source: UInt32) -> String {
+    let (_, _, selector, scope) = kind.selectors();
+    let mut source = source;
+    let mut name: CFStringRef = std::ptr::null();
+    let mut translation = AudioValueTranslation {
+        mInputData: std::ptr::addr_of_mut!(source) as *mut c_void,
+        mInputDataSize: std::mem::size_of::<UInt32>() as UInt32,
+        mOutputData: std::ptr::addr_of_mut!(name) as *mut c_void,
+        mOutputDataSize: std::mem::size_of::<CFStringRef>() as UInt32,
+    };
+    let mut size = std::mem::size_of::<AudioValueTranslation>() as UInt32;
+    let address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: scope,
+        mElement: kAudioObjectPropertyElementMain,
+    };
+    let status = unsafe {
+        AudioObjectGetPropertyData(
+            *id,
+            &address,
+            0,
+            std::ptr::null(),
+            &mut size,
+            std::ptr::addr_of_mut!(translation) as *mut c_void,
+        )
     };
+    match status == NO_ERR && !name.is_null() {
+        // A copy, released with the wrapper
+        true => unsafe { CFString::wrap_under_create_rule(name) }.to_string(),
+        false => fourcc(source),
+    }
+}
 
-    set_audio_object_prop(
-        &kAudioObjectSystemObject,
-        selector,
-        kAudioObjectPropertyScopeGlobal,
-        kAudioObjectPropertyElementMain,
-        *id,
-    )
-    .unwrap();
+pub fn set_source(id: &u32, kind: SourceKind, source: UInt32) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let (active, _, _, scope) = kind.selectors();
+    set_audio_object_prop(id, active, scope, kAudioObjectPropertyElementMain, source)
 }
 
-/// Change device's volume
-fn set_volume(id: &u32, channel: Channel, volume: f32) {
+/// Like `44.1 kHz` or `48 kHz`.
+pub fn describe_rate(rate: f64) -> String {
+    format!("{} kHz", (rate / 100.0).round() / 10.0)
+}
+
+/// Element of the channel's first volume scalar, which the dB properties
+/// are read from too.
+fn volume_element(id: &u32, scope: AudioObjectPropertyScope) -> Option<UInt32> {
+    let channels = query_size(id, kAudioDevicePropertyStreams, scope).unwrap_or(0);
+    (0..channels).find(|i| query_exists(id, kAudioDevicePropertyVolumeScalar, scope, *i))
+}
+
+/// Current volume in dB, as the device reports it.
+pub fn volume_db(id: &u32, channel: Channel) -> Option<f32> {
     let scope = match channel {
         Channel::Input => kAudioDevicePropertyScopeInput,
         Channel::Output => kAudioDevicePropertyScopeOutput,
     };
-
-    // Number of channels
-    let channels = query_size(id, kAudioDevicePropertyStreams, scope).unwrap();
-
-    // Iterate through channels, check if settable, then set
-    for i in 0..channels {
-        if query_settable(id, kAudioDevicePropertyVolumeScalar, scope, i) {
-            set_audio_object_prop(id, kAudioDevicePropertyVolumeScalar, scope, i, volume).unwrap();
-        }
+    let element = volume_element(id, scope)?;
+    if !query_exists(id, kAudioDevicePropertyVolumeDecibels, scope, element) {
+        return None;
     }
+    query_first::<Float32>(id, kAudioDevicePropertyVolumeDecibels, scope, element)
 }
 
-/// Set device's mute state
-fn set_mute(id: &u32, channel: Channel, enabled: bool) {
-    let mute_val: UInt32 = if enabled { 1 } else { 0 };
+/// Run one of the device's volume conversions, which take their input in
+/// the output buffer.
+fn convert_volume(
+    id: &u32,
+    channel: Channel,
+    selector: AudioObjectPropertySelector,
+    value: f32,
+) -> Option<f32> {
     let scope = match channel {
         Channel::Input => kAudioDevicePropertyScopeInput,
         Channel::Output => kAudioDevicePropertyScopeOutput,
     };
-    set_audio_object_prop(
+    let element = volume_element(id, scope)?;
+    if !query_exists(id, selector, scope, element) {
+        return None;
+    }
+    let prop_address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: scope,
+        mElement: element,
+    };
+    let mut value: Float32 = value;
+    let mut data_size = std::mem::size_of::<Float32>() as UInt32;
+    let status = unsafe {
+        AudioObjectGetPropertyData(
+            *id,
+            &prop_address,
+            0,
+            std::ptr::null(),
+            &mut data_size,
+            std::ptr::addr_of_mut!(value) as *mut c_void,
+        )
+    };
+    (status == NO_ERR).then_some(value)
+}
+
+/// A volume scalar in dB, on the device's own curve.
+pub fn scalar_to_db(id: &u32, channel: Channel, scalar: f32) -> Option<f32> {
+    convert_volume(
         id,
-        kAudioDevicePropertyMute,
-        scope,
-        kAudioObjectPropertyElementMain,
-        mute_val,
+        channel,
+        kAudioDevicePropertyVolumeScalarToDecibels,
+        scalar,
     )
-    .unwrap();
+}
+
+pub fn db_to_scalar(id: &u32, channel: Channel, db: f32) -> Option<f32> {
+    convert_volume(id, channel, kAudioDevicePropertyVolumeDecibelsToScalar, db)
+}
+
+/// Scalar change that moves the volume by a step in dB, for devices that
+/// know their dB curve.
+pub fn db_step(id: &u32, channel: Channel, step_db: f32) -> Option<f32> {
+    let current = volume_db(id, channel)?;
+    let from = db_to_scalar(id, channel, current)?;
+    let to = db_to_scalar(id, channel, current + step_db)?;
+    Some(to - from)
+}
+
+/// Whether any of the channel's volume scalars can be set.
+pub fn volume_settable(id: &u32, channel: Channel) -> bool {
+    let scope = match channel {
+        Channel::Input => kAudioDevicePropertyScopeInput,
+        Channel::Output => kAudioDevicePropertyScopeOutput,
+    };
+    let channels = query_size(id, kAudioDevicePropertyStreams, scope).unwrap_or(0);
+    (0..channels).any(|i| query_settable(id, kAudioDevicePropertyVolumeScalar, scope, i))
 }
 
 /// Check if audio property exists on object
@@ -645,7 +3479,83 @@ fn query_exists(
         mScope: scope,
         mElement: element,
     };
-    unsafe { AudioObjectHasProperty(object_id.clone(), &prop_address) > 0 }
+    unsafe { AudioObjectHasProperty(*object_id, &prop_address) > 0 }
+}
+
+/// A property's raw data, for the property explorer.
+pub fn property_bytes(
+    id: &AudioObjectID,
+    selector: AudioObjectPropertySelector,
+    scope: AudioObjectPropertyScope,
+    element: AudioObjectPropertyElement,
+) -> Result<Vec<u8>, ControlError> {
+    if !query_exists(id, selector, scope, element) {
+        return Err(ControlError::Device(format!(
+            "Object {id} has no '{}' in '{}' element {element}",
+            fourcc(selector),
+            fourcc(scope)
+        )));
+    }
+    let prop_address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: scope,
+        mElement: element,
+    };
+    let mut size: UInt32 = 0;
+    let status = unsafe {
+        AudioObjectGetPropertyDataSize(*id, &prop_address, 0, std::ptr::null(), &mut size)
+    };
+    check(status, "size", *id, selector)?;
+    let mut bytes = vec![0u8; size as usize];
+    let status = unsafe {
+        AudioObjectGetPropertyData(
+            *id,
+            &prop_address,
+            0,
+            std::ptr::null(),
+            &mut size,
+            bytes.as_mut_ptr() as *mut c_void,
+        )
+    };
+    check(status, "read", *id, selector)?;
+    bytes.truncate(size as usize);
+    Ok(bytes)
+}
+
+/// Write a property's raw data, for the property explorer. Refused when
+/// the property isn't settable, or read-only.
+pub fn set_property_bytes(
+    id: &AudioObjectID,
+    selector: AudioObjectPropertySelector,
+    scope: AudioObjectPropertyScope,
+    element: AudioObjectPropertyElement,
+    bytes: &[u8],
+) -> Result<(), ControlError> {
+    if is_read_only() {
+        return Err(ControlError::ReadOnly);
+    }
+    if !query_settable(id, selector, scope, element) {
+        return Err(ControlError::Device(format!(
+            "'{}' isn't settable",
+            fourcc(selector)
+        )));
+    }
+    let prop_address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: scope,
+        mElement: element,
+    };
+    let status = unsafe {
+        AudioObjectSetPropertyData(
+            *id,
+            &prop_address,
+            0,
+            std::ptr::null(),
+            bytes.len() as UInt32,
+            bytes.as_ptr() as *const c_void,
+        )
+    };
+    check(status, "set", *id, selector)
 }
 
 /// Query size of a property's buffer
@@ -653,38 +3563,37 @@ fn query_size(
     object_id: &AudioObjectID,
     selector: AudioObjectPropertySelector,
     scope: AudioObjectPropertyScope,
-) -> Result<UInt32, ()> {
+) -> Result<UInt32, ControlError> {
     let mut prop_size: UInt32 = 0;
     let prop_address = AudioObjectPropertyAddress {
         mSelector: selector,
         mScope: scope,
         mElement: kAudioObjectPropertyElementMain,
     };
-    unsafe {
-        if AudioObjectGetPropertyDataSize(
-            object_id.clone(),
+    let status = unsafe {
+        AudioObjectGetPropertyDataSize(
+            *object_id,
             &prop_address,
             0,
             std::ptr::null(),
             &mut prop_size,
-        ) == NO_ERR
-        {
-            Ok(prop_size)
-        } else {
-            Err(())
-        }
-    }
+        )
+    };
+    check(status, "size", *object_id, selector)?;
+    Ok(prop_size)
 }
 
-/// Query an audio property
+/// Query an audio property, up to `len` values of it.
 fn query_audio_object<T: Clone + Default + Sized>(
     object_id: &AudioObjectID,
     selector: AudioObjectPropertySelector,
     scope: AudioObjectPropertyScope,
     element: AudioObjectPropertyElement,
     len: usize,
-) -> Vec<T> {
-    // Size of the buffer going in
+) -> Result<Vec<T>, ControlError> {
+    QUERIES.fetch_add(1, Ordering::Relaxed);
+    let mut buf = vec![T::default(); len];
+    // Size of the buffer going in, and of the data coming out
     let mut data_size: UInt32 = (std::mem::size_of::<T>() * len) as UInt32;
     // This struct is the "query"
     let prop_address = AudioObjectPropertyAddress {
@@ -692,20 +3601,32 @@ fn query_audio_object<T: Clone + Default + Sized>(
         mScope: scope,
         mElement: element,
     };
-    unsafe {
-        let buf = buf_ptr::<T>(len);
-        // TODO: handle possible OSStatus error? Like set_audio_object_prop
+    let status = unsafe {
         AudioObjectGetPropertyData(
-            object_id.clone(),
+            *object_id,
             &prop_address,
             0,
             std::ptr::null(),
             &mut data_size,
-            buf,
-        );
-        let result_len = data_size / std::mem::size_of::<T>() as UInt32;
-        vec_from_ptr::<T>(buf, result_len as usize)
-    }
+            buf.as_mut_ptr() as *mut c_void,
+        )
+    };
+    check(status, "read", *object_id, selector)?;
+    buf.truncate(data_size as usize / std::mem::size_of::<T>());
+    Ok(buf)
+}
+
+/// The first value of a property, `None` when it can't be read.
+fn query_first<T: Clone + Default + Sized>(
+    object_id: &AudioObjectID,
+    selector: AudioObjectPropertySelector,
+    scope: AudioObjectPropertyScope,
+    element: AudioObjectPropertyElement,
+) -> Option<T> {
+    query_audio_object::<T>(object_id, selector, scope, element, 1)
+        .ok()?
+        .first()
+        .cloned()
 }
 
 fn query_settable(
@@ -721,61 +3642,370 @@ fn query_settable(
         mElement: element,
     };
     unsafe {
-        AudioObjectIsPropertySettable(object_id.clone(), &prop_address, &mut settable);
+        AudioObjectIsPropertySettable(*object_id, &prop_address, &mut settable);
     }
     settable > 0
 }
 
-fn set_audio_object_prop<T: Clone + Default + Sized>(
+fn set_audio_object_prop<T: Sized>(
     object_id: &AudioObjectID,
     selector: AudioObjectPropertySelector,
     scope: AudioObjectPropertyScope,
     element: AudioObjectPropertyElement,
     input: T,
-) -> Result<(), String> {
+) -> Result<(), ControlError> {
     let data_size = std::mem::size_of::<T>() as UInt32;
     let prop_address = AudioObjectPropertyAddress {
         mSelector: selector,
         mScope: scope,
         mElement: element,
     };
-    unsafe {
-        if AudioObjectSetPropertyData(
-            object_id.clone(),
+    let status = unsafe {
+        AudioObjectSetPropertyData(
+            *object_id,
             &prop_address,
             0,
             std::ptr::null(),
             data_size,
             std::ptr::addr_of!(input) as *const c_void,
-        ) == NO_ERR
-        {
-            Ok(())
-        } else {
-            Err("Unable to set audio object prop".to_string())
-        }
-    }
+        )
+    };
+    check(status, "set", *object_id, selector)
 }
 
 fn ref_to_string(cf_str_ref: CFStringRef) -> String {
+    // Left null by a failed query
+    if cf_str_ref.is_null() {
+        return String::new();
+    }
     unsafe {
         let cfs = CFString::from_void(cf_str_ref as *const c_void);
         cfs.to_string()
     }
 }
 
-fn buf_ptr<T: Clone + Default>(len: usize) -> *mut c_void {
-    let mut v: Vec<T> = vec![];
-    v.reserve_exact(len);
-    v.resize_with(len, Default::default);
-    let mut boxed_buffer = v.into_boxed_slice();
-    let data = boxed_buffer.as_mut_ptr();
-    std::mem::forget(boxed_buffer);
-    data as *mut c_void
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MockBackend, MockDevice};
+    use crate::store;
 
-fn vec_from_ptr<T>(ptr: *mut c_void, len: usize) -> Vec<T> {
-    unsafe {
-        let v: Vec<T> = Vec::from_raw_parts(ptr as *mut T, len, len);
-        v
+    fn state(config: Config, backend: &MockBackend) -> AudioState {
+        store::use_temp_home();
+        let config = Config {
+            volume_ramp: Duration::ZERO,
+            ..config
+        };
+        AudioState::with_backend(&config, Arc::new(backend.clone()))
+    }
+
+    /// A device with both channels, UIDs unique to the test so saved
+    /// levels don't carry over between tests.
+    fn device(test: &str, name: &str, channels: [bool; 2]) -> MockDevice {
+        let level = |has| Some(0.5).filter(|_| has);
+        MockDevice {
+            uid: format!("{test}.{name}"),
+            name: name.to_string(),
+            channels,
+            volumes: channels.map(level),
+            mutes: channels.map(|has| Some(false).filter(|_| has)),
+            ..MockDevice::default()
+        }
+    }
+
+    fn output_level(audio: &AudioState) -> Option<f32> {
+        audio
+            .active_device(Channel::Output)
+            .map(|d| d.output.borrow().level)
+    }
+
+    #[test]
+    fn reads_devices_and_defaults() {
+        let backend = MockBackend::new();
+        backend.add(1, device("defaults", "Mic", [true, false]));
+        backend.add(2, device("defaults", "Speakers", [false, true]));
+        backend.set_default_device(Channel::Input, 1).unwrap();
+        backend.set_default_device(Channel::Output, 2).unwrap();
+        let audio = state(Config::default(), &backend);
+        assert_eq!(audio.devices().len(), 2);
+        assert_eq!(audio.active_id(Channel::Input), Some(1));
+        assert_eq!(audio.active_id(Channel::Output), Some(2));
+        assert_eq!(output_level(&audio), Some(0.5));
+    }
+
+    #[test]
+    fn moves_and_mutes_the_default() {
+        let backend = MockBackend::new();
+        backend.add(1, device("move", "Speakers", [false, true]));
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let mut audio = state(Config::default(), &backend);
+        audio.move_volume(Channel::Output, 0.25);
+        assert_eq!(backend.volume(1, Channel::Output), Some(0.75));
+        assert_eq!(output_level(&audio), Some(0.75));
+
+        audio.toggle_mute(Channel::Output);
+        assert_eq!(backend.mute(1, Channel::Output), Some(true));
+        let device = audio.active_device(Channel::Output).unwrap();
+        assert_eq!(audio.mute_state(device, Channel::Output), MuteState::Native);
+        assert!(backend.writes().ends_with(&[
+            "set-volume 1 output 0.75".into(),
+            "set-mute 1 output true".into()
+        ]));
+    }
+
+    #[test]
+    fn falls_back_when_the_default_goes() {
+        let backend = MockBackend::new();
+        backend.add(1, device("fallback", "Speakers", [false, true]));
+        backend.add(2, device("fallback", "Headphones", [false, true]));
+        backend.set_default_device(Channel::Output, 2).unwrap();
+        let config = Config {
+            fallback: Some(FallbackConfig {
+                outputs: vec!["Speakers".to_string()],
+                inputs: Vec::new(),
+                max_volume: Some(0.3),
+            }),
+            ..Config::default()
+        };
+        let mut audio = state(config, &backend);
+        backend.remove(2);
+        audio.update();
+        assert_eq!(audio.active_id(Channel::Output), Some(1));
+        assert_eq!(backend.volume(1, Channel::Output), Some(0.3));
+        assert!(backend.writes().ends_with(&[
+            "set-default output 1".into(),
+            "set-volume 1 output 0.30".into()
+        ]));
+    }
+
+    #[test]
+    fn removes_several_devices_in_one_poll() {
+        let backend = MockBackend::new();
+        for (id, name) in [(1, "Desk"), (2, "Dock"), (3, "Speakers"), (4, "Monitor")] {
+            backend.add(id, device("several", name, [false, true]));
+        }
+        backend.set_default_device(Channel::Output, 3).unwrap();
+        let config = Config {
+            fallback: Some(FallbackConfig {
+                outputs: vec!["Monitor".to_string()],
+                inputs: Vec::new(),
+                max_volume: None,
+            }),
+            ..Config::default()
+        };
+        let mut audio = state(config, &backend);
+        // The default stays, behind two removed devices
+        backend.remove(1);
+        backend.remove(2);
+        audio.update();
+        assert_eq!(audio.active_id(Channel::Output), Some(3));
+        assert_eq!(audio.devices().len(), 2);
+
+        // The default goes with another device
+        backend.add(1, device("several", "Desk", [false, true]));
+        audio.update();
+        backend.remove(1);
+        backend.remove(3);
+        audio.update();
+        assert_eq!(audio.active_id(Channel::Output), Some(4));
+        assert_eq!(backend.default_device(Channel::Output), 4);
+    }
+
+    fn paired(test: &str, airpods_auto_switch: bool) -> (MockBackend, AudioState) {
+        let backend = MockBackend::new();
+        backend.add(1, device(test, "Built-in", [true, true]));
+        backend.add(2, device(test, "Headset Mic", [true, false]));
+        backend.add(3, device(test, "AirPods Pro", [false, true]));
+        backend.set_default_device(Channel::Input, 1).unwrap();
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let config = Config {
+            pairs: vec![("Headset Mic".to_string(), "AirPods Pro".to_string())],
+            airpods_auto_switch,
+            ..Config::default()
+        };
+        let mut audio = state(config, &backend);
+        backend.set_default_device(Channel::Input, 2).unwrap();
+        audio.update();
+        (backend, audio)
+    }
+
+    #[test]
+    fn output_follows_its_paired_input() {
+        let (backend, mut audio) = paired("pairs", false);
+        assert_eq!(audio.active_id(Channel::Output), Some(3));
+        assert_eq!(backend.default_device(Channel::Output), 3);
+        assert!(audio.take_notices().is_empty());
+    }
+
+    #[test]
+    fn pairs_leave_airpods_to_macos() {
+        let (backend, mut audio) = paired("airpods", true);
+        assert_eq!(audio.active_id(Channel::Output), Some(1));
+        assert_eq!(backend.default_device(Channel::Output), 1);
+        assert_eq!(
+            audio.take_notices(),
+            ["Left the default output to AirPods automatic switching"]
+        );
+    }
+
+    #[test]
+    fn mutes_by_volume_without_a_system_mute() {
+        let backend = MockBackend::new();
+        let hdmi = MockDevice {
+            mutes: [None, None],
+            ..device("workaround", "HDMI", [false, true])
+        };
+        backend.add(1, hdmi);
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let mut audio = state(Config::default(), &backend);
+        let mute_state = |audio: &AudioState| {
+            let device = audio.active_device(Channel::Output).unwrap();
+            audio.mute_state(device, Channel::Output)
+        };
+
+        audio.toggle_mute(Channel::Output);
+        assert_eq!(backend.volume(1, Channel::Output), Some(0.0));
+        assert_eq!(mute_state(&audio), MuteState::Workaround);
+        audio.update();
+        assert_eq!(mute_state(&audio), MuteState::Workaround);
+
+        audio.toggle_mute(Channel::Output);
+        assert_eq!(backend.volume(1, Channel::Output), Some(0.5));
+        assert_eq!(mute_state(&audio), MuteState::Unmuted);
+        assert!(!backend.writes().iter().any(|w| w.starts_with("set-mute")));
+    }
+
+    #[test]
+    fn updates_pick_up_changes_made_elsewhere() {
+        let backend = MockBackend::new();
+        backend.add(1, device("elsewhere", "Speakers", [false, true]));
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let mut audio = state(Config::default(), &backend);
+        // Like another app would
+        backend.set_volume(1, Channel::Output, 0.2).unwrap();
+        backend.add(2, device("elsewhere", "Headphones", [false, true]));
+        let writes = backend.writes();
+        audio.update();
+        assert_eq!(output_level(&audio), Some(0.2));
+        assert_eq!(audio.devices().len(), 2);
+        // Reading writes nothing back
+        assert_eq!(backend.writes(), writes);
+
+        backend.set_default_device(Channel::Output, 2).unwrap();
+        audio.update();
+        assert_eq!(audio.active_id(Channel::Output), Some(2));
+    }
+
+    #[test]
+    fn next_and_previous_output_wrap_around() {
+        let backend = MockBackend::new();
+        for (id, name) in [(1, "Desk"), (2, "Dock"), (3, "Speakers")] {
+            backend.add(id, device("select", name, [false, true]));
+        }
+        backend.set_default_device(Channel::Output, 3).unwrap();
+        let mut audio = state(Config::default(), &backend);
+        let order = audio.devices().iter().map(|d| d.id).collect::<Vec<_>>();
+        let after = |id| order[(order.iter().position(|i| *i == id).unwrap() + 1) % 3];
+
+        audio.next_output();
+        assert_eq!(audio.active_id(Channel::Output), Some(after(3)));
+        audio.prev_output();
+        assert_eq!(audio.active_id(Channel::Output), Some(3));
+        assert_eq!(backend.default_device(Channel::Output), 3);
+    }
+
+    #[test]
+    fn cycles_sample_rates_and_sources() {
+        let backend = MockBackend::new();
+        let sources = Sources {
+            output: SourceList {
+                available: vec![(1, "Speakers".to_string()), (2, "Headphones".to_string())],
+                active: Some(1),
+            },
+            ..Sources::default()
+        };
+        let interface = MockDevice {
+            sample_rates: vec![44100.0, 48000.0],
+            sample_rate: Some(48000.0),
+            sources,
+            ..device("formats", "Interface", [true, true])
+        };
+        backend.add(1, interface);
+        let mut audio = state(Config::default(), &backend);
+
+        assert_eq!(audio.cycle_sample_rate_of(&1).unwrap(), 44100.0);
+        assert_eq!(backend.sample_rate(1), Some(44100.0));
+        assert_eq!(audio.devices()[0].sample_rate.get(), Some(44100.0));
+        let kind = SourceKind::Data(Channel::Output);
+        assert_eq!(audio.cycle_source_of(&1, kind).unwrap(), "Headphones");
+        assert_eq!(backend.sources(1).output.active, Some(2));
+        assert!(audio.cycle_source_of(&1, SourceKind::Clock).is_err());
+        assert_eq!(
+            backend.writes().last().map(String::as_str),
+            Some("set-source 1 output source 2")
+        );
+    }
+
+    #[test]
+    fn takes_and_gives_back_exclusive_access() {
+        let backend = MockBackend::new();
+        let dac = MockDevice {
+            can_hog: true,
+            ..device("hog", "DAC", [false, true])
+        };
+        backend.add(1, dac);
+        backend.add(2, device("hog", "Speakers", [false, true]));
+        let mut audio = state(Config::default(), &backend);
+
+        audio.toggle_hog("hog.DAC").unwrap();
+        assert_eq!(backend.hog_owner(1), Some(std::process::id() as i32));
+        audio.toggle_hog("hog.DAC").unwrap();
+        assert_eq!(backend.hog_owner(1), None);
+        assert!(audio.toggle_hog("hog.Speakers").is_err());
+    }
+
+    #[test]
+    fn plays_everywhere_through_a_multi_output() {
+        let backend = MockBackend::new();
+        backend.add(1, device("broadcast", "Speakers", [false, true]));
+        backend.add(2, device("broadcast", "Headphones", [false, true]));
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let mut audio = state(Config::default(), &backend);
+
+        audio.toggle_broadcast().unwrap();
+        let aggregate = backend.default_device(Channel::Output);
+        assert_eq!(backend.device_uid(aggregate), BROADCAST_UID);
+        // The output before keeps the clock
+        assert_eq!(
+            backend.aggregate_uids(aggregate),
+            ["broadcast.Speakers", "broadcast.Headphones"]
+        );
+        let device = audio.active_device(Channel::Output).unwrap();
+        assert!(device.is_aggregate);
+        assert_eq!(audio.members(device).len(), 2);
+
+        audio.toggle_member(&aggregate, "broadcast.Headphones");
+        assert_eq!(backend.aggregate_uids(aggregate), ["broadcast.Speakers"]);
+        audio.toggle_drift(&aggregate, "broadcast.Speakers");
+        assert_eq!(
+            backend.drift_correction(aggregate),
+            [("broadcast.Speakers".to_string(), false)]
+        );
+
+        assert_eq!(audio.toggle_broadcast().unwrap(), "Back to Speakers");
+        assert_eq!(backend.default_device(Channel::Output), 1);
+    }
+
+    #[test]
+    fn sets_the_alert_device() {
+        let backend = MockBackend::new();
+        backend.add(1, device("alert", "Mic", [true, false]));
+        backend.add(2, device("alert", "Speakers", [false, true]));
+        let mut audio = state(Config::default(), &backend);
+        audio.set_alert("alert.Speakers").unwrap();
+        assert_eq!(backend.alert_device(), 2);
+        assert_eq!(audio.alert_device().map(|d| d.id), Some(2));
+        assert!(audio.set_alert("alert.Mic").is_err());
+        assert!(audio.set_alert("alert.Gone").is_err());
     }
 }
@@ -0,0 +1,128 @@
+//! Remote commands, one line each in `audit.log`: when, from where, what
+//! and how it went. The file is rotated at `MAX_SIZE`, keeping one old
+//! file, so it can't fill the disk. Every remote command goes through
+//! `dispatch`, which also limits how often each source can send them, so a
+//! looping automation can't spam volume changes.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::events::Action;
+use crate::history::now;
+use crate::log;
+use crate::store;
+
+const AUDIT_FILE: &str = "audit.log";
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// Commands per source within `RATE_WINDOW`, more are refused.
+const RATE_LIMIT: usize = 10;
+/// The same for each socket connection and plugin, on this Mac.
+const LOCAL_RATE_LIMIT: usize = 100;
+/// Sources of attached terminals, which send a command per key press and
+/// aren't limited.
+pub const ATTACHED: &str = "attached:";
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+/// The error of a refused command.
+pub const RATE_LIMITED: &str = "rate limited";
+
+/// Writers from different connections take turns.
+static LOCK: Mutex<()> = Mutex::new(());
+/// Recent command times by source.
+static RECENT: Mutex<BTreeMap<String, VecDeque<Instant>>> = Mutex::new(BTreeMap::new());
+
+/// Run a remote command from `source`, a client address, a socket
+/// connection like `socket:3`, `mqtt` or a plugin, unless it's over the
+/// rate limit, and record how it went.
+/// Failed commands count too, so guessing tokens is just as slow.
+pub fn dispatch(
+    source: &str,
+    command: &str,
+    action: Result<Action, String>,
+    handler: &dyn Fn(Action),
+) -> Result<(), String> {
+    let result = match action {
+        _ if !allow(source) => Err(RATE_LIMITED.to_string()),
+        Ok(action) => {
+            handler(action);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    };
+    record(source, command, result.as_ref().err().map_or("ok", |e| e));
+    result
+}
+
+/// Check a remote terminal's token, counted and recorded like a command
+/// so guessing is just as slow.
+pub fn authenticate(source: &str, valid: bool) -> Result<(), String> {
+    let result = match valid {
+        _ if !allow(source) => Err(RATE_LIMITED.to_string()),
+        true => Ok(()),
+        false => Err("Invalid token".to_string()),
+    };
+    record(source, "auth", result.as_ref().err().map_or("ok", |e| e));
+    result
+}
+
+/// Count a command, false when the source is over the limit.
+fn allow(source: &str) -> bool {
+    if source.starts_with(ATTACHED) {
+        return true;
+    }
+    let mut recent = RECENT.lock().unwrap();
+    recent.retain(|_, times| {
+        while times.front().is_some_and(|t| t.elapsed() > RATE_WINDOW) {
+            times.pop_front();
+        }
+        !times.is_empty()
+    });
+    let limit = match source.starts_with("socket:") || source.starts_with("plugin:") {
+        true => LOCAL_RATE_LIMIT,
+        false => RATE_LIMIT,
+    };
+    let times = recent.entry(source.to_string()).or_default();
+    if times.len() >= limit {
+        return false;
+    }
+    times.push_back(Instant::now());
+    true
+}
+
+/// Append a command, like `1718000000 192.168.1.20 GET /quick/mute-input ok`.
+pub fn record(source: &str, command: &str, result: &str) {
+    let _lock = LOCK.lock().unwrap();
+    let path = store::data_dir().join(AUDIT_FILE);
+    if fs::metadata(&path).is_ok_and(|m| m.len() > MAX_SIZE) {
+        let _ = fs::rename(&path, path.with_extension("log.1"));
+    }
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{} {source} {command} {result}", now()));
+    if let Err(e) = written {
+        log::warn(format!("Unable to write {path:?}: {e}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_each_connection_but_not_attached_terminals() {
+        for _ in 0..LOCAL_RATE_LIMIT {
+            assert!(allow("socket:1"));
+        }
+        assert!(!allow("socket:1"));
+        // Another connection has its own limit
+        assert!(allow("socket:2"));
+        for _ in 0..LOCAL_RATE_LIMIT * 2 {
+            assert!(allow("attached:3"));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Input auto-gain: follows the live mic level and nudges the default
+//! input's volume to keep speech between two levels, for mics whose gain
+//! is reset by every call app. From `[autogain]`:
+//!
+//! ```toml
+//! [autogain]
+//! low = -30     # dBFS, quieter speech turns the input up
+//! high = -18    # louder speech turns it down
+//! attack = 0.5  # seconds to follow speech getting louder
+//! release = 4   # seconds to follow it getting quieter
+//! ```
+//!
+//! Only speech counts: reports near the noise floor are pauses, which
+//! would otherwise turn the gain up until the room hiss is loud.
+
+use std::time::{Duration, Instant};
+
+use crate::meter::{self, REPORT_INTERVAL};
+
+/// Speech is this far above the noise floor, in dB.
+const SPEECH_ABOVE_FLOOR: f32 = 10.0;
+/// And louder than this, in dBFS, before there's a floor to go by.
+const MIN_SPEECH_DB: f32 = -60.0;
+/// Time between changes, each at most `MAX_STEP_DB`, so the gain moves
+/// gently instead of chasing every word.
+const ADJUST_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_STEP_DB: f32 = 1.5;
+/// Lowest input volume it turns down to.
+const MIN_LEVEL: f32 = 0.05;
+/// How long after a change the list shows it's adjusting.
+const SHOWN_FOR: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoGainConfig {
+    /// Speech level range in dBFS.
+    pub low: f32,
+    pub high: f32,
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+impl Default for AutoGainConfig {
+    fn default() -> Self {
+        AutoGainConfig {
+            low: -30.0,
+            high: -18.0,
+            attack: Duration::from_millis(500),
+            release: Duration::from_secs(4),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AutoGain {
+    config: AutoGainConfig,
+    /// Smoothed speech level in dBFS, none until someone speaks.
+    speech: Option<f32>,
+    last_change: Option<Instant>,
+}
+
+impl AutoGain {
+    pub fn new(config: AutoGainConfig) -> Self {
+        AutoGain {
+            config,
+            speech: None,
+            last_change: None,
+        }
+    }
+
+    /// Start over, for another device.
+    pub fn reset(&mut self) {
+        self.speech = None;
+        self.last_change = None;
+    }
+
+    /// Take a meter report made at input volume `level`, with the noise
+    /// floor if known. The volume to change to, when it's time for one.
+    pub fn push(&mut self, rms: f32, floor: Option<f32>, level: f32) -> Option<f32> {
+        let db = meter::to_db(rms);
+        let threshold = floor.map_or(MIN_SPEECH_DB, |floor| {
+            (floor + SPEECH_ABOVE_FLOOR).max(MIN_SPEECH_DB)
+        });
+        if db < threshold || level <= 0.0 {
+            return None;
+        }
+        let speech = match self.speech {
+            Some(speech) => {
+                let time = match db > speech {
+                    true => self.config.attack,
+                    false => self.config.release,
+                };
+                let weight = 1.0 - (-REPORT_INTERVAL.as_secs_f32() / time.as_secs_f32()).exp();
+                speech + (db - speech) * weight
+            }
+            None => db,
+        };
+        self.speech = Some(speech);
+        if self
+            .last_change
+            .is_some_and(|t| t.elapsed() < ADJUST_INTERVAL)
+        {
+            return None;
+        }
+        let off = if speech > self.config.high {
+            self.config.high - speech
+        } else if speech < self.config.low {
+            self.config.low - speech
+        } else {
+            return None;
+        };
+        // Like calibration, assumes levels scale with the volume scalar
+        let step = off.clamp(-MAX_STEP_DB, MAX_STEP_DB);
+        let next = (level * 10_f32.powf(step / 20.0)).clamp(MIN_LEVEL, 1.0);
+        if (next - level).abs() < 0.005 {
+            return None;
+        }
+        // What was heard at the old volume, moved to the new one
+        self.speech = Some(speech + meter::to_db(next / level));
+        self.last_change = Some(Instant::now());
+        Some(next)
+    }
+
+    /// Whether it changed the volume just now, for the list.
+    pub fn is_adjusting(&self) -> bool {
+        self.last_change.is_some_and(|t| t.elapsed() < SHOWN_FOR)
+    }
+}
@@ -0,0 +1,815 @@
+//! The audio system as a trait: devices, volumes, mutes, defaults and
+//! change notifications. `CoreAudio` is the real one, through the `audio`
+//! module's calls; `MockBackend` keeps devices in memory, for code that
+//! should run without a Mac's devices.
+//!
+//! `AudioState` reads and changes devices through one, see
+//! `AudioState::with_backend`. What only some devices have, like sample
+//! rates, sources, hog mode and aggregates, reads as missing by default.
+//! Balance and stereo channels are only read here; changing them is left
+//! to CoreAudio.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::audio::{self, Channel, SourceKind, Sources, BROADCAST_NAME, BROADCAST_UID};
+use crate::coreaudio::{
+    kAudioDevicePropertyClockSource, kAudioDevicePropertyDataSource, kAudioDevicePropertyHogMode,
+    kAudioDevicePropertyMute, kAudioDevicePropertyNominalSampleRate,
+    kAudioDevicePropertyVolumeScalar, kAudioDeviceTransportTypeAggregate,
+    kAudioHardwarePropertyDefaultInputDevice, kAudioHardwarePropertyDefaultOutputDevice,
+    kAudioHardwarePropertyDefaultSystemOutputDevice, kAudioHardwarePropertyDevices,
+    kAudioObjectSystemObject, AudioObjectID,
+};
+use crate::error::ControlError;
+use crate::events::Action;
+use crate::listener::{ListenerStats, Listeners};
+use crate::log;
+
+/// Called with `Action::PropertyChanged` when something changes.
+pub type Handler = Box<dyn Fn(Action) + Send>;
+
+/// Keeps a `listen` handler called until dropped.
+pub type Subscription = Box<dyn Any>;
+
+pub trait AudioBackend: Send + Sync {
+    fn device_ids(&self) -> Result<Vec<AudioObjectID>, ControlError>;
+    fn device_uid(&self, id: AudioObjectID) -> String;
+    fn device_name(&self, id: AudioObjectID) -> String;
+    /// 0.0 - 1.0, none without a volume on the channel.
+    fn volume(&self, id: AudioObjectID, channel: Channel) -> Option<f32>;
+    fn set_volume(
+        &self,
+        id: AudioObjectID,
+        channel: Channel,
+        level: f32,
+    ) -> Result<(), ControlError>;
+    /// None without a system mute on the channel.
+    fn mute(&self, id: AudioObjectID, channel: Channel) -> Option<bool>;
+    fn set_mute(
+        &self,
+        id: AudioObjectID,
+        channel: Channel,
+        muted: bool,
+    ) -> Result<(), ControlError>;
+    /// 0 without one.
+    fn default_device(&self, channel: Channel) -> AudioObjectID;
+    fn set_default_device(&self, channel: Channel, id: AudioObjectID) -> Result<(), ControlError>;
+    /// Whether the device has the channel at all, which a default needs.
+    fn can_be_default(&self, channel: Channel, id: AudioObjectID) -> bool;
+    /// Call `handler` on changes to the device list, the defaults, and
+    /// the volumes and mutes of the devices there are now.
+    fn listen(&self, handler: Handler) -> Subscription;
+
+    /// Input and output volume, for backends that read both at once.
+    fn volumes(&self, id: AudioObjectID) -> (Option<f32>, Option<f32>) {
+        (
+            self.volume(id, Channel::Input),
+            self.volume(id, Channel::Output),
+        )
+    }
+
+    /// Input and output system mute.
+    fn mutes(&self, id: AudioObjectID) -> (Option<bool>, Option<bool>) {
+        (
+            self.mute(id, Channel::Input),
+            self.mute(id, Channel::Output),
+        )
+    }
+
+    fn volume_settable(&self, id: AudioObjectID, channel: Channel) -> bool {
+        self.volume(id, channel).is_some()
+    }
+
+    fn has_mute(&self, id: AudioObjectID, channel: Channel) -> bool {
+        self.mute(id, channel).is_some()
+    }
+
+    fn can_set_mute(&self, id: AudioObjectID, channel: Channel) -> bool {
+        self.mute(id, channel).is_some()
+    }
+
+    /// Where alerts and sound effects play.
+    fn alert_device(&self) -> AudioObjectID {
+        self.default_device(Channel::Output)
+    }
+
+    /// Left and right output volume, for devices with one per channel.
+    fn stereo_levels(&self, _id: AudioObjectID) -> Option<(f32, f32)> {
+        None
+    }
+
+    fn sample_rate(&self, _id: AudioObjectID) -> Option<f64> {
+        None
+    }
+
+    /// Rates `set_sample_rate` takes, lowest first.
+    fn sample_rates(&self, _id: AudioObjectID) -> Vec<f64> {
+        Vec::new()
+    }
+
+    fn set_sample_rate(&self, id: AudioObjectID, rate: f64) -> Result<(), ControlError>;
+
+    fn sources(&self, _id: AudioObjectID) -> Sources {
+        Sources::default()
+    }
+
+    fn set_source(
+        &self,
+        id: AudioObjectID,
+        kind: SourceKind,
+        source: u32,
+    ) -> Result<(), ControlError>;
+
+    /// PID of the process with exclusive access.
+    fn hog_owner(&self, _id: AudioObjectID) -> Option<i32> {
+        None
+    }
+
+    fn can_hog(&self, _id: AudioObjectID) -> bool {
+        false
+    }
+
+    /// Take exclusive access for this process, or let go of it.
+    fn toggle_hog(&self, id: AudioObjectID) -> Result<(), ControlError>;
+
+    fn can_be_alert_device(&self, id: AudioObjectID) -> bool {
+        self.can_be_default(Channel::Output, id)
+    }
+
+    fn set_alert_device(&self, id: AudioObjectID) -> Result<(), ControlError>;
+
+    /// UIDs of an aggregate's members, in its order.
+    fn aggregate_uids(&self, _id: AudioObjectID) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn set_aggregate_uids(&self, id: AudioObjectID, uids: &[String]) -> Result<(), ControlError>;
+
+    /// Drift correction of an aggregate's connected members, by UID.
+    fn drift_correction(&self, _id: AudioObjectID) -> Vec<(String, bool)> {
+        Vec::new()
+    }
+
+    fn set_drift_correction(
+        &self,
+        id: AudioObjectID,
+        uid: &str,
+        on: bool,
+    ) -> Result<(), ControlError>;
+
+    /// Make the multi-output device `toggle_broadcast` plays through,
+    /// `BROADCAST_UID`, on every one of `uids`.
+    fn create_multi_output(&self, uids: &[String]) -> Result<AudioObjectID, ControlError>;
+
+    /// Transport type, like USB or Bluetooth. 0 when unknown.
+    fn transport(&self, _id: AudioObjectID) -> u32 {
+        0
+    }
+
+    fn manufacturer(&self, _id: AudioObjectID) -> Option<String> {
+        None
+    }
+
+    fn model(&self, _id: AudioObjectID) -> Option<String> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn AudioBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AudioBackend")
+    }
+}
+
+/// The system's devices.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CoreAudio;
+
+impl AudioBackend for CoreAudio {
+    fn device_ids(&self) -> Result<Vec<AudioObjectID>, ControlError> {
+        audio::device_ids()
+    }
+
+    fn device_uid(&self, id: AudioObjectID) -> String {
+        audio::device_uid(&id)
+    }
+
+    fn device_name(&self, id: AudioObjectID) -> String {
+        audio::device_name(&id)
+    }
+
+    fn volume(&self, id: AudioObjectID, channel: Channel) -> Option<f32> {
+        let (input, output) = audio::volume_level(&id);
+        match channel {
+            Channel::Input => input,
+            Channel::Output => output,
+        }
+    }
+
+    fn set_volume(
+        &self,
+        id: AudioObjectID,
+        channel: Channel,
+        level: f32,
+    ) -> Result<(), ControlError> {
+        audio::set_volume(&id, channel, level)
+    }
+
+    fn mute(&self, id: AudioObjectID, channel: Channel) -> Option<bool> {
+        let (input, output) = audio::device_mutes(&id);
+        match channel {
+            Channel::Input => input,
+            Channel::Output => output,
+        }
+    }
+
+    fn set_mute(
+        &self,
+        id: AudioObjectID,
+        channel: Channel,
+        muted: bool,
+    ) -> Result<(), ControlError> {
+        audio::set_mute(&id, channel, muted)
+    }
+
+    fn default_device(&self, channel: Channel) -> AudioObjectID {
+        audio::default_device(channel)
+    }
+
+    fn set_default_device(&self, channel: Channel, id: AudioObjectID) -> Result<(), ControlError> {
+        audio::set_default_device(channel, &id)
+    }
+
+    fn can_be_default(&self, channel: Channel, id: AudioObjectID) -> bool {
+        audio::can_be_default_device(channel, &id)
+    }
+
+    fn listen(&self, handler: Handler) -> Subscription {
+        let mut listeners = Listeners::new(ListenerStats::default(), handler);
+        // Devices seen later are watched from their first update
+        listeners.watch(&audio::device_ids().unwrap_or_else(|e| {
+            log::warn(e);
+            Vec::new()
+        }));
+        Box::new(listeners)
+    }
+
+    fn volumes(&self, id: AudioObjectID) -> (Option<f32>, Option<f32>) {
+        audio::volume_level(&id)
+    }
+
+    fn mutes(&self, id: AudioObjectID) -> (Option<bool>, Option<bool>) {
+        audio::device_mutes(&id)
+    }
+
+    fn volume_settable(&self, id: AudioObjectID, channel: Channel) -> bool {
+        audio::volume_settable(&id, channel)
+    }
+
+    fn has_mute(&self, id: AudioObjectID, channel: Channel) -> bool {
+        audio::has_mute(&id, channel)
+    }
+
+    fn can_set_mute(&self, id: AudioObjectID, channel: Channel) -> bool {
+        audio::can_set_mute(&id, channel)
+    }
+
+    fn alert_device(&self) -> AudioObjectID {
+        audio::alert_device()
+    }
+
+    fn stereo_levels(&self, id: AudioObjectID) -> Option<(f32, f32)> {
+        audio::stereo_levels(&id)
+    }
+
+    fn sample_rate(&self, id: AudioObjectID) -> Option<f64> {
+        audio::sample_rate(&id)
+    }
+
+    fn sample_rates(&self, id: AudioObjectID) -> Vec<f64> {
+        audio::sample_rates(&id)
+    }
+
+    fn set_sample_rate(&self, id: AudioObjectID, rate: f64) -> Result<(), ControlError> {
+        audio::set_sample_rate(&id, rate)
+    }
+
+    fn sources(&self, id: AudioObjectID) -> Sources {
+        Sources::read(&id)
+    }
+
+    fn set_source(
+        &self,
+        id: AudioObjectID,
+        kind: SourceKind,
+        source: u32,
+    ) -> Result<(), ControlError> {
+        audio::set_source(&id, kind, source)
+    }
+
+    fn hog_owner(&self, id: AudioObjectID) -> Option<i32> {
+        audio::hog_owner(&id)
+    }
+
+    fn can_hog(&self, id: AudioObjectID) -> bool {
+        audio::can_hog(&id)
+    }
+
+    fn toggle_hog(&self, id: AudioObjectID) -> Result<(), ControlError> {
+        audio::toggle_hog_mode(&id)
+    }
+
+    fn can_be_alert_device(&self, id: AudioObjectID) -> bool {
+        audio::can_be_alert_device(&id)
+    }
+
+    fn set_alert_device(&self, id: AudioObjectID) -> Result<(), ControlError> {
+        audio::set_alert_device(&id)
+    }
+
+    fn aggregate_uids(&self, id: AudioObjectID) -> Vec<String> {
+        audio::aggregate_uids(&id)
+    }
+
+    fn set_aggregate_uids(&self, id: AudioObjectID, uids: &[String]) -> Result<(), ControlError> {
+        audio::set_aggregate_uids(&id, uids)
+    }
+
+    fn drift_correction(&self, id: AudioObjectID) -> Vec<(String, bool)> {
+        audio::sub_device_drift(&id)
+            .into_iter()
+            .map(|(uid, _, on)| (uid, on))
+            .collect()
+    }
+
+    fn set_drift_correction(
+        &self,
+        id: AudioObjectID,
+        uid: &str,
+        on: bool,
+    ) -> Result<(), ControlError> {
+        audio::set_drift_correction(&id, uid, on)
+    }
+
+    fn create_multi_output(&self, uids: &[String]) -> Result<AudioObjectID, ControlError> {
+        audio::create_multi_output(uids)
+    }
+
+    fn transport(&self, id: AudioObjectID) -> u32 {
+        audio::device_transport(&id)
+    }
+
+    fn manufacturer(&self, id: AudioObjectID) -> Option<String> {
+        audio::device_manufacturer(&id)
+    }
+
+    fn model(&self, id: AudioObjectID) -> Option<String> {
+        audio::device_model(&id)
+    }
+}
+
+/// A device of `MockBackend`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockDevice {
+    pub uid: String,
+    pub name: String,
+    /// Whether it has input and output at all, input first.
+    pub channels: [bool; 2],
+    /// Volume and system mute per channel, input first, none where the
+    /// device doesn't have them.
+    pub volumes: [Option<f32>; 2],
+    pub mutes: [Option<bool>; 2],
+    /// Rates it can run at, and the one it does.
+    pub sample_rates: Vec<f64>,
+    pub sample_rate: Option<f64>,
+    pub sources: Sources,
+    /// Whether it has hog mode at all, and who holds it.
+    pub can_hog: bool,
+    pub hog_owner: Option<i32>,
+    /// Member UIDs with their drift correction, for an aggregate.
+    pub members: Option<Vec<(String, bool)>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    devices: BTreeMap<AudioObjectID, MockDevice>,
+    defaults: [AudioObjectID; 2],
+    alert: AudioObjectID,
+    handlers: Vec<Handler>,
+    /// Every change made through the backend, oldest first.
+    writes: Vec<String>,
+}
+
+/// Devices in memory. Changes go to the listening handlers like
+/// CoreAudio's notifications, and are kept in `writes` to check.
+#[derive(Clone, Default)]
+pub struct MockBackend(Arc<Mutex<MockState>>);
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend::default()
+    }
+
+    /// Connect a device, as if plugged in.
+    pub fn add(&self, id: AudioObjectID, device: MockDevice) {
+        self.0.lock().unwrap().devices.insert(id, device);
+        self.notify(kAudioObjectSystemObject, kAudioHardwarePropertyDevices);
+    }
+
+    /// Disconnect a device. A default it was is left unset, 0.
+    pub fn remove(&self, id: AudioObjectID) {
+        let mut state = self.0.lock().unwrap();
+        state.devices.remove(&id);
+        for default in &mut state.defaults {
+            if *default == id {
+                *default = 0;
+            }
+        }
+        drop(state);
+        self.notify(kAudioObjectSystemObject, kAudioHardwarePropertyDevices);
+    }
+
+    /// The changes made so far, like "set-volume 42 output 0.50".
+    pub fn writes(&self) -> Vec<String> {
+        self.0.lock().unwrap().writes.clone()
+    }
+
+    /// Call the handlers without the lock, so they can use the backend.
+    /// Changes they make, or other threads make meanwhile, aren't passed
+    /// on to them.
+    fn notify(&self, id: AudioObjectID, selector: u32) {
+        let handlers = std::mem::take(&mut self.0.lock().unwrap().handlers);
+        for handler in &handlers {
+            handler(Action::PropertyChanged { id, selector });
+        }
+        let mut state = self.0.lock().unwrap();
+        let added = std::mem::replace(&mut state.handlers, handlers);
+        state.handlers.extend(added);
+    }
+
+    /// Change a device through `change`, failing for unknown devices and
+    /// what they don't have, like a channel or a sample rate.
+    fn change(
+        &self,
+        id: AudioObjectID,
+        write: String,
+        change: impl FnOnce(&mut MockDevice) -> bool,
+    ) -> Result<(), ControlError> {
+        let mut state = self.0.lock().unwrap();
+        let device = state
+            .devices
+            .get_mut(&id)
+            .ok_or_else(|| ControlError::Device(format!("No device {id}")))?;
+        if !change(device) {
+            return Err(ControlError::Device(format!(
+                "Device {id} doesn't have that"
+            )));
+        }
+        state.writes.push(write);
+        Ok(())
+    }
+}
+
+fn index(channel: Channel) -> usize {
+    match channel {
+        Channel::Input => 0,
+        Channel::Output => 1,
+    }
+}
+
+impl AudioBackend for MockBackend {
+    fn device_ids(&self) -> Result<Vec<AudioObjectID>, ControlError> {
+        Ok(self.0.lock().unwrap().devices.keys().copied().collect())
+    }
+
+    fn device_uid(&self, id: AudioObjectID) -> String {
+        let state = self.0.lock().unwrap();
+        state
+            .devices
+            .get(&id)
+            .map(|d| d.uid.clone())
+            .unwrap_or_default()
+    }
+
+    fn device_name(&self, id: AudioObjectID) -> String {
+        let state = self.0.lock().unwrap();
+        state
+            .devices
+            .get(&id)
+            .map(|d| d.name.clone())
+            .unwrap_or_default()
+    }
+
+    fn volume(&self, id: AudioObjectID, channel: Channel) -> Option<f32> {
+        self.0.lock().unwrap().devices.get(&id)?.volumes[index(channel)]
+    }
+
+    fn set_volume(
+        &self,
+        id: AudioObjectID,
+        channel: Channel,
+        level: f32,
+    ) -> Result<(), ControlError> {
+        let write = format!("set-volume {id} {} {level:.2}", channel.as_str());
+        self.change(id, write, |device| {
+            match &mut device.volumes[index(channel)] {
+                Some(volume) => {
+                    *volume = level.clamp(0.0, 1.0);
+                    true
+                }
+                None => false,
+            }
+        })?;
+        self.notify(id, kAudioDevicePropertyVolumeScalar);
+        Ok(())
+    }
+
+    fn mute(&self, id: AudioObjectID, channel: Channel) -> Option<bool> {
+        self.0.lock().unwrap().devices.get(&id)?.mutes[index(channel)]
+    }
+
+    fn set_mute(
+        &self,
+        id: AudioObjectID,
+        channel: Channel,
+        muted: bool,
+    ) -> Result<(), ControlError> {
+        let write = format!("set-mute {id} {} {muted}", channel.as_str());
+        self.change(id, write, |device| {
+            match &mut device.mutes[index(channel)] {
+                Some(mute) => {
+                    *mute = muted;
+                    true
+                }
+                None => false,
+            }
+        })?;
+        self.notify(id, kAudioDevicePropertyMute);
+        Ok(())
+    }
+
+    fn default_device(&self, channel: Channel) -> AudioObjectID {
+        self.0.lock().unwrap().defaults[index(channel)]
+    }
+
+    fn set_default_device(&self, channel: Channel, id: AudioObjectID) -> Result<(), ControlError> {
+        let write = format!("set-default {} {id}", channel.as_str());
+        self.change(id, write, |device| device.channels[index(channel)])?;
+        self.0.lock().unwrap().defaults[index(channel)] = id;
+        let selector = match channel {
+            Channel::Input => kAudioHardwarePropertyDefaultInputDevice,
+            Channel::Output => kAudioHardwarePropertyDefaultOutputDevice,
+        };
+        self.notify(kAudioObjectSystemObject, selector);
+        Ok(())
+    }
+
+    fn can_be_default(&self, channel: Channel, id: AudioObjectID) -> bool {
+        let state = self.0.lock().unwrap();
+        state
+            .devices
+            .get(&id)
+            .is_some_and(|d| d.channels[index(channel)])
+    }
+
+    fn listen(&self, handler: Handler) -> Subscription {
+        self.0.lock().unwrap().handlers.push(handler);
+        // Handlers stay for the mock's lifetime
+        Box::new(())
+    }
+
+    fn alert_device(&self) -> AudioObjectID {
+        self.0.lock().unwrap().alert
+    }
+
+    fn sample_rate(&self, id: AudioObjectID) -> Option<f64> {
+        self.0.lock().unwrap().devices.get(&id)?.sample_rate
+    }
+
+    fn sample_rates(&self, id: AudioObjectID) -> Vec<f64> {
+        let state = self.0.lock().unwrap();
+        state
+            .devices
+            .get(&id)
+            .map(|d| d.sample_rates.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_sample_rate(&self, id: AudioObjectID, rate: f64) -> Result<(), ControlError> {
+        let write = format!("set-rate {id} {rate}");
+        self.change(id, write, |device| {
+            let supported = device.sample_rates.contains(&rate);
+            if supported {
+                device.sample_rate = Some(rate);
+            }
+            supported
+        })?;
+        self.notify(id, kAudioDevicePropertyNominalSampleRate);
+        Ok(())
+    }
+
+    fn sources(&self, id: AudioObjectID) -> Sources {
+        let state = self.0.lock().unwrap();
+        state
+            .devices
+            .get(&id)
+            .map(|d| d.sources.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_source(
+        &self,
+        id: AudioObjectID,
+        kind: SourceKind,
+        source: u32,
+    ) -> Result<(), ControlError> {
+        let write = format!("set-source {id} {} {source}", kind.name());
+        self.change(id, write, |device| {
+            let list = device.sources.get_mut(kind);
+            let known = list.available.iter().any(|(s, _)| *s == source);
+            if known {
+                list.active = Some(source);
+            }
+            known
+        })?;
+        let selector = match kind {
+            SourceKind::Data(_) => kAudioDevicePropertyDataSource,
+            SourceKind::Clock => kAudioDevicePropertyClockSource,
+        };
+        self.notify(id, selector);
+        Ok(())
+    }
+
+    fn hog_owner(&self, id: AudioObjectID) -> Option<i32> {
+        self.0.lock().unwrap().devices.get(&id)?.hog_owner
+    }
+
+    fn can_hog(&self, id: AudioObjectID) -> bool {
+        let state = self.0.lock().unwrap();
+        state.devices.get(&id).is_some_and(|d| d.can_hog)
+    }
+
+    fn toggle_hog(&self, id: AudioObjectID) -> Result<(), ControlError> {
+        let write = format!("toggle-hog {id}");
+        self.change(id, write, |device| {
+            device.hog_owner = match device.hog_owner {
+                Some(_) => None,
+                None => Some(std::process::id() as i32),
+            };
+            device.can_hog
+        })?;
+        self.notify(id, kAudioDevicePropertyHogMode);
+        Ok(())
+    }
+
+    fn set_alert_device(&self, id: AudioObjectID) -> Result<(), ControlError> {
+        let write = format!("set-alert {id}");
+        self.change(id, write, |device| device.channels[index(Channel::Output)])?;
+        self.0.lock().unwrap().alert = id;
+        self.notify(
+            kAudioObjectSystemObject,
+            kAudioHardwarePropertyDefaultSystemOutputDevice,
+        );
+        Ok(())
+    }
+
+    fn transport(&self, id: AudioObjectID) -> u32 {
+        let state = self.0.lock().unwrap();
+        match state.devices.get(&id).is_some_and(|d| d.members.is_some()) {
+            true => kAudioDeviceTransportTypeAggregate,
+            false => 0,
+        }
+    }
+
+    fn aggregate_uids(&self, id: AudioObjectID) -> Vec<String> {
+        self.drift_correction(id)
+            .into_iter()
+            .map(|(uid, _)| uid)
+            .collect()
+    }
+
+    fn set_aggregate_uids(&self, id: AudioObjectID, uids: &[String]) -> Result<(), ControlError> {
+        let write = format!("set-members {id} {}", uids.join(","));
+        self.change(id, write, |device| match &mut device.members {
+            Some(members) => {
+                let drift = |uid: &String| members.iter().any(|(u, on)| u == uid && *on);
+                *members = uids.iter().map(|uid| (uid.clone(), drift(uid))).collect();
+                true
+            }
+            None => false,
+        })?;
+        self.notify(kAudioObjectSystemObject, kAudioHardwarePropertyDevices);
+        Ok(())
+    }
+
+    fn drift_correction(&self, id: AudioObjectID) -> Vec<(String, bool)> {
+        let state = self.0.lock().unwrap();
+        state
+            .devices
+            .get(&id)
+            .and_then(|d| d.members.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_drift_correction(
+        &self,
+        id: AudioObjectID,
+        uid: &str,
+        on: bool,
+    ) -> Result<(), ControlError> {
+        let write = format!("set-drift {id} {uid} {on}");
+        self.change(id, write, |device| {
+            let member = device.members.iter_mut().flatten().find(|(u, _)| u == uid);
+            match member {
+                Some((_, drift)) => {
+                    *drift = on;
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    fn create_multi_output(&self, uids: &[String]) -> Result<AudioObjectID, ControlError> {
+        let mut state = self.0.lock().unwrap();
+        let id = state.devices.keys().max().map_or(1, |id| id + 1);
+        state
+            .writes
+            .push(format!("create-multi-output {id} {}", uids.join(",")));
+        drop(state);
+        self.add(
+            id,
+            MockDevice {
+                uid: BROADCAST_UID.to_string(),
+                name: BROADCAST_NAME.to_string(),
+                channels: [false, true],
+                members: Some(uids.iter().map(|uid| (uid.clone(), true)).collect()),
+                ..MockDevice::default()
+            },
+        );
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn output(name: &str) -> MockDevice {
+        MockDevice {
+            uid: name.to_lowercase(),
+            name: name.to_string(),
+            channels: [false, true],
+            volumes: [None, Some(0.5)],
+            mutes: [None, Some(false)],
+            ..MockDevice::default()
+        }
+    }
+
+    #[test]
+    fn handlers_can_use_the_backend() {
+        let backend = MockBackend::new();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let (inner, counted) = (backend.clone(), seen.clone());
+        backend.listen(Box::new(move |_| {
+            counted.store(inner.device_ids().unwrap().len(), Ordering::SeqCst);
+        }));
+        backend.add(1, output("Speakers"));
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+        backend.add(2, output("Headphones"));
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn default_needs_the_channel_not_a_volume() {
+        let backend = MockBackend::new();
+        backend.add(
+            1,
+            MockDevice {
+                volumes: [None, None],
+                ..output("HDMI")
+            },
+        );
+        assert!(backend.can_be_default(Channel::Output, 1));
+        assert!(backend.set_default_device(Channel::Output, 1).is_ok());
+        assert_eq!(backend.default_device(Channel::Output), 1);
+        assert!(!backend.can_be_default(Channel::Input, 1));
+        assert!(backend.set_default_device(Channel::Input, 1).is_err());
+        assert_eq!(backend.writes(), ["set-default output 1"]);
+    }
+
+    #[test]
+    fn missing_channels_are_refused() {
+        let backend = MockBackend::new();
+        backend.add(1, output("Speakers"));
+        assert!(backend.set_volume(1, Channel::Input, 0.2).is_err());
+        assert!(backend.set_mute(1, Channel::Input, true).is_err());
+        assert!(backend.set_volume(2, Channel::Output, 0.2).is_err());
+        assert!(backend.set_volume(1, Channel::Output, 1.5).is_ok());
+        assert_eq!(backend.volume(1, Channel::Output), Some(1.0));
+    }
+}
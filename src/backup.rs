@@ -0,0 +1,68 @@
+//! `mac-controls backup create|restore [file]`: everything in the data dir
+//! (config, device registry, history, macros, UI state) in one archive, for
+//! moving to a new Mac. Uses the system `tar`.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::store;
+
+const DEFAULT_FILE: &str = "mac-controls-backup.tar.gz";
+
+pub fn run(args: &[String]) -> Result<String, String> {
+    let file = args.get(1).map(|f| f.as_str()).unwrap_or(DEFAULT_FILE);
+    // tar runs in the data dir
+    let file = std::env::current_dir()
+        .map_err(|e| format!("Unable to read the current dir: {e}"))?
+        .join(file);
+    match args.first().map(|a| a.as_str()) {
+        Some("create") => create(&file),
+        Some("restore") => restore(&file),
+        _ => Err("Usage: mac-controls backup create|restore [file]".to_string()),
+    }
+}
+
+fn create(file: &Path) -> Result<String, String> {
+    let dir = store::data_dir();
+    if !dir.exists() {
+        return Err(format!("Nothing to back up, {dir:?} doesn't exist"));
+    }
+    tar(&[
+        "-czf".as_ref(),
+        file.as_os_str(),
+        "-C".as_ref(),
+        dir.as_os_str(),
+        ".".as_ref(),
+    ])?;
+    Ok(format!("Backed up {dir:?} to {file:?}"))
+}
+
+/// Files in the archive replace the current ones, others are kept.
+fn restore(file: &Path) -> Result<String, String> {
+    if !file.exists() {
+        return Err(format!("{file:?} not found"));
+    }
+    let dir = store::data_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Unable to create {dir:?}: {e}"))?;
+    tar(&[
+        "-xzf".as_ref(),
+        file.as_os_str(),
+        "-C".as_ref(),
+        dir.as_os_str(),
+    ])?;
+    Ok(format!("Restored {file:?} to {dir:?}"))
+}
+
+fn tar(args: &[&std::ffi::OsStr]) -> Result<(), String> {
+    let output = Command::new("tar")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Unable to run tar: {e}"))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
@@ -0,0 +1,98 @@
+//! Battery levels of Bluetooth audio devices, read through IOBluetooth.
+//! CoreAudio UIDs of Bluetooth devices start with their address, like
+//! `AC-90-85-12-34-56:output`, which is what IOBluetooth looks them up by.
+//!
+//! AirPods report each bud and the case, other headsets a single level.
+//! The battery getters are private IOBluetooth API, so each is checked for
+//! before it's called.
+
+use core_foundation::base::TCFType;
+use core_foundation::string::CFString;
+use std::os::raw::{c_char, c_void};
+
+use crate::objc::{
+    msg_send_get_byte, msg_send_object, objc_autoreleasePoolPop, objc_autoreleasePoolPush,
+    objc_getClass, responds, sel_registerName,
+};
+
+/// Battery percentages, `None` where the device doesn't report one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Battery {
+    pub single: Option<u8>,
+    pub left: Option<u8>,
+    pub right: Option<u8>,
+    pub case: Option<u8>,
+}
+
+impl Battery {
+    /// The level that runs out first, for one number summaries.
+    pub fn lowest(&self) -> Option<u8> {
+        [self.single, self.left, self.right]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Like "80%", or "L 80% R 75% case 40%" for earbuds.
+    pub fn describe(&self) -> String {
+        if let (Some(single), None, None) = (self.single, self.left, self.right) {
+            return format!("{single}%");
+        }
+        [("L", self.left), ("R", self.right), ("case", self.case)]
+            .into_iter()
+            .filter_map(|(name, level)| Some(format!("{name} {}%", level?)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The Bluetooth address in a CoreAudio UID, if it has one.
+pub fn address(uid: &str) -> Option<&str> {
+    let address = uid.get(..17)?;
+    let valid = address.split('-').count() == 6
+        && address
+            .split('-')
+            .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()));
+    valid.then_some(address)
+}
+
+/// Battery of the Bluetooth device behind a CoreAudio UID, `None` when it
+/// isn't one or reports nothing.
+pub fn battery(uid: &str) -> Option<Battery> {
+    let address = address(uid)?;
+    unsafe {
+        let class = objc_getClass(c"IOBluetoothDevice".as_ptr());
+        if class.is_null() {
+            return None;
+        }
+        let pool = objc_autoreleasePoolPush();
+        let address = CFString::new(address);
+        // CFString is toll-free bridged to NSString
+        let device = msg_send_object(
+            class,
+            sel_registerName(c"deviceWithAddressString:".as_ptr()),
+            address.as_concrete_TypeRef() as *mut c_void,
+        );
+        let battery = match device.is_null() {
+            true => None,
+            false => Some(Battery {
+                single: percent(device, c"batteryPercentSingle".as_ptr()),
+                left: percent(device, c"batteryPercentLeft".as_ptr()),
+                right: percent(device, c"batteryPercentRight".as_ptr()),
+                case: percent(device, c"batteryPercentCase".as_ptr()),
+            }),
+        };
+        objc_autoreleasePoolPop(pool);
+        battery.filter(|battery| *battery != Battery::default())
+    }
+}
+
+/// A battery getter's value, 0 meaning unknown.
+unsafe fn percent(device: *mut c_void, name: *const c_char) -> Option<u8> {
+    let getter = sel_registerName(name);
+    if !responds(device, getter) {
+        return None;
+    }
+    let level = msg_send_get_byte(device, getter);
+    (1..=100).contains(&level).then_some(level)
+}
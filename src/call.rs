@@ -0,0 +1,177 @@
+//! `mac-controls check-call`: a pre-meeting checklist against the setup in
+//! `[call]`, the expected default devices, mic volume range, headset
+//! battery and Do Not Disturb. `--fix` changes what the app can change.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::audio::{self, AudioState, Category, Channel};
+use crate::config::CallConfig;
+use crate::summary;
+
+/// Result of one check.
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Fixed(String),
+    /// Couldn't be checked, which doesn't fail the list.
+    Unknown(String),
+}
+
+/// The report, and whether every check passed or was fixed.
+pub fn check(audio: &mut AudioState, config: &CallConfig, fix: bool) -> (String, bool) {
+    let mut checks = Vec::new();
+    for (channel, name, expected) in [
+        (Channel::Input, "Input", &config.input),
+        (Channel::Output, "Output", &config.output),
+    ] {
+        checks.push((
+            name,
+            check_default(audio, channel, expected.as_deref(), fix),
+        ));
+    }
+    // The rest is about the new defaults
+    audio.update();
+    checks.push(("Mic volume", check_gain(audio, config, fix)));
+    checks.push(("Mic", check_mute(audio, fix)));
+    checks.push(("Headset battery", check_battery(audio, config)));
+    if let Some(wanted) = config.dnd {
+        checks.push(("Do Not Disturb", check_dnd(wanted)));
+    }
+
+    let mut passed = true;
+    let lines: Vec<String> = checks
+        .into_iter()
+        .map(|(name, outcome)| {
+            let (mark, text) = match outcome {
+                Outcome::Pass(text) => ("ok", text),
+                Outcome::Fixed(text) => ("fixed", text),
+                Outcome::Unknown(text) => ("?", text),
+                Outcome::Fail(text) => {
+                    passed = false;
+                    ("FAIL", text)
+                }
+            };
+            format!("[{mark}] {name}: {text}")
+        })
+        .collect();
+    (lines.join("\n"), passed)
+}
+
+fn check_default(
+    audio: &AudioState,
+    channel: Channel,
+    expected: Option<&str>,
+    fix: bool,
+) -> Outcome {
+    let current = audio.active_device(channel);
+    let label = current.map_or("none".to_string(), |d| d.label().to_string());
+    let Some(expected) = expected else {
+        return Outcome::Pass(label);
+    };
+    if current.is_some_and(|d| d.matches(expected)) {
+        return Outcome::Pass(label);
+    }
+    let wanted = audio
+        .device_list()
+        .into_iter()
+        .map(|(_, _, _, device)| device)
+        .find(|device| device.matches(expected));
+    match wanted {
+        Some(device) if fix => match audio::set_default_device(channel, &device.id) {
+            Ok(()) => Outcome::Fixed(format!("{label}, switched to {}", device.label())),
+            Err(e) => Outcome::Fail(format!("{label}, expected {expected}: {e}")),
+        },
+        Some(_) => Outcome::Fail(format!("{label}, expected {expected}")),
+        None => Outcome::Fail(format!("{label}, expected {expected} (not connected)")),
+    }
+}
+
+fn check_gain(audio: &AudioState, config: &CallConfig, fix: bool) -> Outcome {
+    let Some(device) = audio.active_device(Channel::Input) else {
+        return Outcome::Fail("no input".to_string());
+    };
+    let volume = device.input.borrow();
+    if !volume.enabled {
+        return Outcome::Unknown(format!("{} has no volume", device.label()));
+    }
+    let percent = |level: f32| format!("{}%", (level * 100.0).round());
+    let (min, max) = (config.min_input_volume, config.max_input_volume);
+    let target = volume.level.clamp(min, max);
+    if target == volume.level {
+        return Outcome::Pass(percent(volume.level));
+    }
+    let range = format!("expected {} to {}", percent(min), percent(max));
+    if !fix {
+        return Outcome::Fail(format!("{}, {range}", percent(volume.level)));
+    }
+    match audio::set_volume(&device.id, Channel::Input, target) {
+        Ok(()) => Outcome::Fixed(format!(
+            "{}, set to {}",
+            percent(volume.level),
+            percent(target)
+        )),
+        Err(e) => Outcome::Fail(format!("{}, {range}: {e}", percent(volume.level))),
+    }
+}
+
+fn check_mute(audio: &AudioState, fix: bool) -> Outcome {
+    let Some(device) = audio.active_device(Channel::Input) else {
+        return Outcome::Fail("no input".to_string());
+    };
+    if !audio.is_muted(device, Channel::Input) {
+        return Outcome::Pass("unmuted".to_string());
+    }
+    if !fix {
+        return Outcome::Fail("muted".to_string());
+    }
+    match audio::set_mute(&device.id, Channel::Input, false) {
+        Ok(()) => Outcome::Fixed("muted, unmuted it".to_string()),
+        Err(e) => Outcome::Fail(format!("muted: {e}")),
+    }
+}
+
+/// Bluetooth defaults only, the battery can't be fixed.
+fn check_battery(audio: &AudioState, config: &CallConfig) -> Outcome {
+    let batteries = summary::battery_levels();
+    let mut headsets: Vec<_> = [Channel::Input, Channel::Output]
+        .into_iter()
+        .filter_map(|channel| audio.active_device(channel))
+        .filter(|device| device.category == Category::Bluetooth)
+        .collect();
+    headsets.dedup_by_key(|device| device.id);
+    let Some(device) = headsets.first() else {
+        return Outcome::Pass("no Bluetooth headset".to_string());
+    };
+    match batteries.get(&device.name) {
+        Some(&percent) if percent >= config.min_battery => {
+            Outcome::Pass(format!("{} {percent}%", device.label()))
+        }
+        Some(&percent) => Outcome::Fail(format!(
+            "{} {percent}%, below {}%",
+            device.label(),
+            config.min_battery
+        )),
+        None => Outcome::Unknown(format!("{} doesn't report it", device.label())),
+    }
+}
+
+/// Focus has no API, its state is only in a file readable with Full Disk
+/// Access. Turning it on is left to the user.
+fn check_dnd(wanted: bool) -> Outcome {
+    let Some(home) = env::var_os("HOME") else {
+        return Outcome::Unknown("no home directory".to_string());
+    };
+    let path = Path::new(&home).join("Library/DoNotDisturb/DB/Assertions.json");
+    let Ok(text) = fs::read_to_string(path) else {
+        return Outcome::Unknown("unreadable, needs Full Disk Access".to_string());
+    };
+    // Active Focus modes are listed as assertion records
+    let on = text.contains("\"assertionDetails\"");
+    let state = if on { "on" } else { "off" };
+    match on == wanted {
+        true => Outcome::Pass(state.to_string()),
+        false => Outcome::Fail(format!("{state}, change it in Control Center")),
+    }
+}
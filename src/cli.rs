@@ -0,0 +1,566 @@
+//! One-shot commands for scripts, without the TUI or the event tap, so
+//! they don't need accessibility permissions:
+//!
+//! ```text
+//! mac-controls list|status [--template <template>|--format <format>]
+//! mac-controls status --watch [--template <template>|--format <format>]
+//! mac-controls summary [--markdown]
+//! mac-controls check-call [--fix]
+//! mac-controls set-volume [--device <device>] --input|--output <level>
+//! mac-controls mute|unmute [--device <device>] --input|--output
+//! mac-controls default --input|--output <device>
+//! mac-controls backlight [--level <level>]
+//! mac-controls display-sleep|lock
+//! mac-controls record-output [--file <file>] [--seconds <seconds>]
+//! ```
+//!
+//! Devices are a UID, name or alias, without `--device` the default device
+//! of the channel is changed. Levels are percent, `40` or `40%`, or a
+//! change like `+10` or `-10`, the same as `shortcut`. `record-output`
+//! records what's playing until return is pressed, or for `--seconds`.
+//!
+//! Templates print exactly what a status bar needs, like
+//! `{{out.name}} {{out.pct}}%{{#if in.muted}} 🔇{{/if}}`, see `template`.
+//! `status` has `in.` and `out.` values for the default devices: `name`,
+//! `uid`, `pct` and `muted`, plus `in` and `out` for whether there's a
+//! volume. `list` has them per device without the name and UID, which are
+//! `name` and `uid`, along with `default_in`, `default_out` and `defaults`.
+//! Both have `manufacturer` and `model`, empty when the device doesn't
+//! say, and `battery` for Bluetooth devices, like `80%` or
+//! `L 80% R 75% case 40%`, empty for the rest (prefixed with `in.` and
+//! `out.` in `status`).
+//!
+//! `--format` is one line with single braces, like
+//! `{out_name} {out_vol}% {mic_icon}`. `status` has `out_name`, `out_vol`,
+//! `out_muted`, `out_icon`, `in_name`, `in_vol`, `in_muted` and `mic_icon`
+//! for it, next to the template values. `--watch` keeps printing the line
+//! whenever it changes.
+
+use crate::audio::{self, AudioState, Channel, Device};
+use crate::bluetooth;
+use crate::call;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::listener::{ListenerStats, Listeners};
+use crate::recorder::{self, Recording};
+use crate::summary;
+use crate::system;
+use crate::template::{Template, Values};
+
+pub const COMMANDS: [&str; 12] = [
+    "list",
+    "status",
+    "summary",
+    "check-call",
+    "set-volume",
+    "mute",
+    "unmute",
+    "default",
+    "backlight",
+    "display-sleep",
+    "lock",
+    "record-output",
+];
+
+/// Output of `list` and `status` without `--template`.
+const LIST_TEMPLATE: &str = "{{defaults}}\t\
+    {{#if in}}{{in.pct}}%{{#if in.muted}} muted{{/if}}{{else}}-{{/if}}\t\
+    {{#if out}}{{out.pct}}%{{#if out.muted}} muted{{/if}}{{else}}-{{/if}}\t\
+    {{name}}\t{{uid}}";
+const STATUS_TEMPLATE: &str = "Output: {{out.name}}\
+    {{#if out}} {{out.pct}}%{{#if out.muted}} muted{{/if}}{{/if}}\n\
+    Input: {{in.name}}\
+    {{#if in}} {{in.pct}}%{{#if in.muted}} muted{{/if}}{{/if}}";
+
+const USAGE: &str = "Usage:
+  mac-controls list|status [--template <template>|--format <format>]
+  mac-controls status --watch [--template <template>|--format <format>]
+  mac-controls summary [--markdown]
+  mac-controls check-call [--fix]
+  mac-controls set-volume [--device <device>] --input|--output <level>
+  mac-controls mute|unmute [--device <device>] --input|--output
+  mac-controls default --input|--output <device>
+  mac-controls backlight [--level <level>]
+  mac-controls display-sleep|lock
+  mac-controls record-output [--file <file>] [--seconds <seconds>]";
+
+/// Run a command from `COMMANDS`, with what it printed or went wrong.
+pub fn run(args: &[String]) -> Result<String, String> {
+    let config = Config::load();
+    let mut audio = AudioState::new(&config);
+    let (command, rest) = args.split_first().ok_or(USAGE)?;
+    let options = Options::parse(rest)?;
+    match command.as_str() {
+        "list" => list(&audio, &options.template(LIST_TEMPLATE)?),
+        "status" => {
+            let template = options.template(STATUS_TEMPLATE)?;
+            match options.watch {
+                true => follow_status(&mut audio, &template, config.poll_interval()),
+                false => status(&audio, &template),
+            }
+        }
+        "summary" => Ok(summary::report(&audio, options.markdown)),
+        // A failed check fails the command, for scripts
+        "check-call" => match call::check(&mut audio, &config.call, options.fix) {
+            (report, true) => Ok(report),
+            (report, false) => Err(report),
+        },
+        "set-volume" => {
+            let (channel, level) = options.channel_value()?;
+            let level = parse_level(level)?;
+            let device = find_device(&audio, channel, options.device)?;
+            set_volume(&audio, device, channel, level)
+        }
+        "mute" | "unmute" => {
+            let channel = options.channel.ok_or(USAGE)?;
+            let uid = find_device(&audio, channel, options.device)?.uid.clone();
+            set_mute(&mut audio, &uid, channel, Some(command == "mute"))
+        }
+        "default" => {
+            let (channel, key) = options.channel_value()?;
+            let device = audio.find_device(key)?;
+            if !audio::can_be_default_device(channel, &device.id) {
+                return Err(format!(
+                    "{} can't be the default {}",
+                    device.label(),
+                    channel_name(channel)
+                ));
+            }
+            audio::set_default_device(channel, &device.id)?;
+            Ok(format!(
+                "{} is the default {}",
+                device.label(),
+                channel_name(channel)
+            ))
+        }
+        "backlight" => match options.level {
+            Some(level) => {
+                let level = match parse_level(level)? {
+                    change @ Level::By(_) => change.from(system::backlight()?),
+                    Level::To(level) => level,
+                };
+                system::set_backlight(level)?;
+                Ok(format!(
+                    "Keyboard backlight set to {}%",
+                    (level * 100.0).round()
+                ))
+            }
+            None => system::backlight()
+                .map(|level| format!("Keyboard backlight: {}%", (level * 100.0).round())),
+        },
+        "display-sleep" => system::display_sleep().map(|()| String::new()),
+        "lock" => system::lock_screen().map(|()| String::new()),
+        "record-output" => record_output(&options),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Command line options shared by the commands.
+#[derive(Debug, Default)]
+struct Options<'a> {
+    device: Option<&'a str>,
+    channel: Option<Channel>,
+    /// Argument after `--input` or `--output`, if any.
+    value: Option<&'a str>,
+    template: Option<&'a str>,
+    format: Option<&'a str>,
+    /// `--watch` for `status`.
+    watch: bool,
+    /// `--level` for `backlight`.
+    level: Option<&'a str>,
+    /// `--file` and `--seconds` for `record-output`.
+    file: Option<&'a str>,
+    seconds: Option<&'a str>,
+    markdown: bool,
+    fix: bool,
+}
+
+impl<'a> Options<'a> {
+    fn parse(args: &'a [String]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter().map(|a| a.as_str()).peekable();
+        while let Some(arg) = args.next() {
+            let channel = match arg {
+                "--device" => {
+                    options.device = Some(args.next().ok_or(USAGE)?);
+                    continue;
+                }
+                "--template" => {
+                    options.template = Some(args.next().ok_or(USAGE)?);
+                    continue;
+                }
+                "--format" => {
+                    options.format = Some(args.next().ok_or(USAGE)?);
+                    continue;
+                }
+                "--watch" => {
+                    options.watch = true;
+                    continue;
+                }
+                "--level" => {
+                    options.level = Some(args.next().ok_or(USAGE)?);
+                    continue;
+                }
+                "--file" => {
+                    options.file = Some(args.next().ok_or(USAGE)?);
+                    continue;
+                }
+                "--seconds" => {
+                    options.seconds = Some(args.next().ok_or(USAGE)?);
+                    continue;
+                }
+                "--markdown" => {
+                    options.markdown = true;
+                    continue;
+                }
+                "--fix" => {
+                    options.fix = true;
+                    continue;
+                }
+                // Handled by main, which makes every change a no-op
+                "--read-only" => continue,
+                "--input" => Channel::Input,
+                "--output" => Channel::Output,
+                _ => return Err(USAGE.to_string()),
+            };
+            if options.channel.replace(channel).is_some() {
+                return Err("Pick one of --input and --output".to_string());
+            }
+            options.value = args.next_if(|a| !a.starts_with("--"));
+        }
+        Ok(options)
+    }
+
+    fn channel_value(&self) -> Result<(Channel, &'a str), String> {
+        match (self.channel, self.value) {
+            (Some(channel), Some(value)) => Ok((channel, value)),
+            _ => Err(USAGE.to_string()),
+        }
+    }
+
+    /// The `--template` or `--format`, or the command's own.
+    fn template(&self, default: &str) -> Result<Template, String> {
+        match (self.template, self.format) {
+            (Some(_), Some(_)) => Err("Pick one of --template and --format".to_string()),
+            (_, Some(format)) => Template::parse_format(format),
+            (template, None) => Template::parse(template.unwrap_or(default)),
+        }
+    }
+}
+
+/// Record the output to `--file`, or a new file on the desktop.
+fn record_output(options: &Options) -> Result<String, String> {
+    let seconds = match options.seconds {
+        Some(text) => Some(
+            text.parse::<f64>()
+                .ok()
+                .filter(|s| *s > 0.0)
+                .ok_or(format!("Seconds must be a positive number, not {text}"))?,
+        ),
+        None => None,
+    };
+    let path = options
+        .file
+        .map_or_else(recorder::default_path, |f| f.into());
+    let recording = Recording::start(&path)?;
+    match seconds {
+        Some(seconds) => {
+            eprintln!("Recording the output to {}", path.display());
+            std::thread::sleep(std::time::Duration::from_secs_f64(seconds));
+        }
+        None => {
+            eprintln!(
+                "Recording the output to {}, press return to stop",
+                path.display()
+            );
+            let _ = std::io::stdin().read_line(&mut String::new());
+        }
+    }
+    let length = recording.stop()?;
+    Ok(format!(
+        "Saved {:.1}s to {}",
+        length.as_secs_f64(),
+        path.display()
+    ))
+}
+
+/// A level to set, or a change to the current one, as a fraction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    To(f32),
+    By(f32),
+}
+
+impl Level {
+    /// The level from the current one, 0 to 1.
+    pub fn from(self, current: f32) -> f32 {
+        match self {
+            Level::To(level) => level,
+            Level::By(change) => (current + change).clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// `40`, `40%`, `+10` or `-10`. A bare fraction like `0.4` is refused
+/// rather than read as 0.4%, `set-volume` used to take it as 40%.
+pub fn parse_level(text: &str) -> Result<Level, String> {
+    let percent = text.strip_suffix('%');
+    let number = percent.unwrap_or(text);
+    let Some(amount) = number.parse::<f32>().ok().filter(|a| a.is_finite()) else {
+        return Err(format!(
+            "Levels are percent, like 40, 40% or +10, not {text}"
+        ));
+    };
+    if percent.is_none() && number.contains('.') && amount.abs() < 1.0 {
+        return Err(format!(
+            "Levels are percent, {text} would be {text}%, write it with a % if that's meant"
+        ));
+    }
+    match number.starts_with(['+', '-']) {
+        true => Ok(Level::By(amount / 100.0)),
+        false if amount <= 100.0 => Ok(Level::To(amount / 100.0)),
+        false => Err(format!("Levels are 0% to 100%, not {text}")),
+    }
+}
+
+/// The device with a UID, name or alias, or the channel's default.
+pub fn find_device<'a>(
+    audio: &'a AudioState,
+    channel: Channel,
+    key: Option<&str>,
+) -> Result<&'a Device, String> {
+    match key {
+        Some(key) => audio.find_device(key).map_err(String::from),
+        None => audio
+            .active_device(channel)
+            .ok_or(format!("No default {}", channel_name(channel))),
+    }
+}
+
+/// Set a device's volume, when it has one that can be set.
+pub fn set_volume(
+    audio: &AudioState,
+    device: &Device,
+    channel: Channel,
+    level: Level,
+) -> Result<String, String> {
+    let current = match channel {
+        Channel::Input => audio.input(&device.id),
+        Channel::Output => audio.output(&device.id),
+    };
+    let Some((current, _)) = current.filter(|_| audio::volume_settable(&device.id, channel)) else {
+        return Err(format!("{} has no settable volume", device.label()));
+    };
+    let level = level.from(current);
+    audio::set_volume(&device.id, channel, level)?;
+    Ok(format!(
+        "{} {} volume set to {}%",
+        device.label(),
+        channel_name(channel),
+        (level * 100.0).round()
+    ))
+}
+
+/// Mute or unmute a device, or toggle it without `mute`, through the mute
+/// workaround when the device uses it.
+pub fn set_mute(
+    audio: &mut AudioState,
+    uid: &str,
+    channel: Channel,
+    mute: Option<bool>,
+) -> Result<String, String> {
+    let device = audio.find_device(uid)?;
+    let (id, label) = (device.id, device.label().to_string());
+    if !audio::has_mute(&id, channel) && !audio::volume_settable(&id, channel) {
+        return Err(format!("{label} can't be muted"));
+    }
+    let muted = audio.is_muted(device, channel);
+    let mute = mute.unwrap_or(!muted);
+    if mute != muted {
+        audio.toggle_mute_of(&id, channel);
+    }
+    Ok(match mute {
+        true => format!("{label} {} muted", channel_name(channel)),
+        false => format!("{label} {} unmuted", channel_name(channel)),
+    })
+}
+
+fn channel_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Input => "input",
+        Channel::Output => "output",
+    }
+}
+
+/// One line per device, by default tab separated: defaults (`I` and
+/// `O`), input and output levels, label and UID.
+fn list(audio: &AudioState, template: &Template) -> Result<String, String> {
+    let mut lines = Vec::new();
+    for (active_in, active_out, _, device) in audio.device_list() {
+        let defaults = match (active_in, active_out) {
+            (true, true) => "IO",
+            (true, false) => "I",
+            (false, true) => "O",
+            (false, false) => "-",
+        };
+        let mut values = Values::new();
+        values.insert("name".to_string(), audio.unique_name(device));
+        values.insert("uid".to_string(), device.uid.clone());
+        values.insert("defaults".to_string(), defaults.to_string());
+        values.insert("default_in".to_string(), active_in.to_string());
+        values.insert("default_out".to_string(), active_out.to_string());
+        values.insert("battery".to_string(), battery(Some(device)));
+        maker_values(&mut values, "", Some(device));
+        volume_values(&mut values, "in", Some(device), Channel::Input);
+        volume_values(&mut values, "out", Some(device), Channel::Output);
+        lines.push(template.render(&values)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+/// The default devices, for status bars.
+fn status(audio: &AudioState, template: &Template) -> Result<String, String> {
+    let mut values = Values::new();
+    for (prefix, channel) in [("in", Channel::Input), ("out", Channel::Output)] {
+        let device = audio.active_device(channel);
+        let label = device.map(|d| audio.unique_name(d));
+        let uid = device.map(|d| d.uid.clone());
+        values.insert(format!("{prefix}.name"), label.unwrap_or_default());
+        values.insert(format!("{prefix}.uid"), uid.unwrap_or_default());
+        values.insert(format!("{prefix}.battery"), battery(device));
+        maker_values(&mut values, &format!("{prefix}."), device);
+        volume_values(&mut values, prefix, device, channel);
+        values.insert(
+            format!("{prefix}_name"),
+            values[&format!("{prefix}.name")].clone(),
+        );
+        values.insert(
+            format!("{prefix}_vol"),
+            values[&format!("{prefix}.pct")].clone(),
+        );
+        values.insert(
+            format!("{prefix}_muted"),
+            values[&format!("{prefix}.muted")].clone(),
+        );
+    }
+    values.insert("out_icon".to_string(), output_icon(&values));
+    values.insert("mic_icon".to_string(), mic_icon(&values));
+    template.render(&values)
+}
+
+/// 🔇 to 🔊 by the default output's volume, empty without one.
+fn output_icon(values: &Values) -> String {
+    let icon = match values["out.pct"].parse::<f32>() {
+        Err(_) => "",
+        Ok(pct) if pct == 0.0 || values["out.muted"] == "true" => "🔇",
+        Ok(pct) if pct < 34.0 => "🔈",
+        Ok(pct) if pct < 67.0 => "🔉",
+        Ok(_) => "🔊",
+    };
+    icon.to_string()
+}
+
+/// 🎙 for a live default input, 🔇 when it's muted, empty without one.
+fn mic_icon(values: &Values) -> String {
+    let icon = match (values["in.name"].is_empty(), values["in.muted"] == "true") {
+        (true, _) => "",
+        (false, true) => "🔇",
+        (false, false) => "🎙",
+    };
+    icon.to_string()
+}
+
+/// `status --watch`: the line again on every change, until stdout is
+/// closed. Follows CoreAudio's notifications like `mac-controls watch`.
+fn follow_status(
+    audio: &mut AudioState,
+    template: &Template,
+    poll_interval: Duration,
+) -> Result<String, String> {
+    // Nothing here changes devices, not even mute takeovers
+    audio::set_read_only(true);
+    let (tx, rx) = mpsc::channel();
+    let mut listeners = Listeners::new(ListenerStats::default(), move |action| {
+        let _ = tx.send(action);
+    });
+    let mut stdout = io::stdout().lock();
+    let mut last = None;
+    loop {
+        let line = status(audio, template)?;
+        if last.as_ref() != Some(&line) {
+            let written = writeln!(stdout, "{line}").and_then(|()| stdout.flush());
+            // The reader went away, like a status bar restarting
+            if written.is_err() {
+                return Ok(String::new());
+            }
+            last = Some(line);
+        }
+        listeners.watch(&audio.device_ids());
+        match rx.recv_timeout(poll_interval) {
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(String::new()),
+        }
+        while rx.try_recv().is_ok() {}
+        audio.update();
+    }
+}
+
+/// `<prefix>manufacturer` and `<prefix>model`, empty when unknown.
+fn maker_values(values: &mut Values, prefix: &str, device: Option<&Device>) {
+    let (manufacturer, model) =
+        device.map_or((None, None), |d| (d.manufacturer.clone(), d.model.clone()));
+    values.insert(
+        format!("{prefix}manufacturer"),
+        manufacturer.unwrap_or_default(),
+    );
+    values.insert(format!("{prefix}model"), model.unwrap_or_default());
+}
+
+/// A Bluetooth device's battery, empty for other devices.
+fn battery(device: Option<&Device>) -> String {
+    device
+        .and_then(|device| bluetooth::battery(&device.uid))
+        .map(|battery| battery.describe())
+        .unwrap_or_default()
+}
+
+/// `<prefix>` (whether the device has the channel), `<prefix>.pct` and
+/// `<prefix>.muted`.
+fn volume_values(values: &mut Values, prefix: &str, device: Option<&Device>, channel: Channel) {
+    let (enabled, percent, muted) = match device {
+        Some(device) => {
+            let volume = device.volume(channel).borrow();
+            let percent = (volume.level * 100.0).round();
+            (volume.enabled, percent, volume.native_mute)
+        }
+        None => (false, 0.0, false),
+    };
+    let percent = match enabled {
+        true => percent.to_string(),
+        false => String::new(),
+    };
+    values.insert(prefix.to_string(), enabled.to_string());
+    values.insert(format!("{prefix}.pct"), percent);
+    values.insert(format!("{prefix}.muted"), (enabled && muted).to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_percent() {
+        assert_eq!(parse_level("40"), Ok(Level::To(0.4)));
+        assert_eq!(parse_level("40%"), Ok(Level::To(0.4)));
+        assert_eq!(parse_level("0.5%"), Ok(Level::To(0.005)));
+        assert_eq!(parse_level("+10"), Ok(Level::By(0.1)));
+        assert_eq!(parse_level("-10%"), Ok(Level::By(-0.1)));
+        assert!(parse_level("0.4").is_err());
+        assert!(parse_level("140").is_err());
+        assert!(parse_level("loud").is_err());
+        assert_eq!(Level::By(-0.1).from(0.05), 0.0);
+    }
+}
@@ -0,0 +1,1085 @@
+//! User configuration from `~/.config/mac-controls/config.toml`, with
+//! `config.d/<host name>.toml` merged over it, so one config can be shared
+//! between Macs with different devices. `MAC_CONTROLS_<SECTION>__<KEY>`
+//! environment variables win over both, for launchd agents and scripted
+//! setups.
+
+use std::ffi::CStr;
+use std::ops::RangeInclusive;
+use std::os::raw::{c_char, c_int};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::autogain::AutoGainConfig;
+use crate::display;
+use crate::events::{self, Action, Hotkey, UiMode};
+use crate::keyhistory;
+use crate::keymap;
+use crate::layout;
+use crate::log;
+use crate::night::{self, NightConfig};
+use crate::plugins;
+use crate::rules::{self, Rule};
+use crate::sinks::{self, EventKind};
+use crate::store::Store;
+use crate::tls;
+use crate::tokens;
+
+pub const CONFIG_FILE: &str = "config.toml";
+/// Per host overrides, in the data dir.
+const HOST_CONFIG_DIR: &str = "config.d";
+/// Environment variables overriding config values, like
+/// `MAC_CONTROLS_KEYS__VOLUME_STEP` for `volume_step` in `[keys]`.
+const ENV_PREFIX: &str = "MAC_CONTROLS_";
+/// Between section and key, which both can have single underscores.
+const ENV_SEPARATOR: &str = "__";
+
+extern "C" {
+    fn gethostname(name: *mut c_char, len: usize) -> c_int;
+}
+/// Chords macOS takes before any app sees them.
+const SYSTEM_CHORDS: &[(&str, &str)] = &[
+    ("cmd+tab", "app switcher"),
+    ("cmd+shift+tab", "app switcher"),
+    ("cmd+`", "window switcher"),
+    ("cmd+space", "Spotlight"),
+    ("ctrl+space", "input source switcher"),
+    ("cmd+option+escape", "Force Quit"),
+    ("ctrl+cmd+q", "Lock Screen"),
+    ("cmd+option+d", "Dock hiding"),
+    ("cmd+shift+3", "screenshot"),
+    ("cmd+shift+4", "screenshot"),
+    ("cmd+shift+5", "screenshot"),
+    ("ctrl+up", "Mission Control"),
+    ("ctrl+down", "App Exposé"),
+    ("ctrl+left", "space switching"),
+    ("ctrl+right", "space switching"),
+];
+/// Caps Lock key code, taken over by `caps_lock_mute`.
+const CAPS_LOCK: i64 = 57;
+
+/// Keys macOS reserves without modifiers, on every keyboard.
+const SYSTEM_KEYS: &[(i64, &str)] = &[
+    (99, "F3 (Mission Control)"),
+    (103, "F11 (Show Desktop)"),
+    (118, "F4 (Launchpad)"),
+    (130, "Dashboard"),
+    (131, "Launchpad"),
+    (160, "Mission Control"),
+];
+const DEFAULT_POLL_SECONDS: f32 = 0.5;
+const DEFAULT_DB_STEP: f32 = 3.0;
+const DEFAULT_PRESETS: [u8; 4] = [25, 50, 75, 100];
+const DEFAULT_BOOST_STEP: f32 = 0.2;
+const DEFAULT_BOOST_SECONDS: u64 = 30;
+const DEFAULT_VOLUME_RAMP: Duration = Duration::from_millis(150);
+/// Longer ramps make the arrows feel laggy.
+const MAX_VOLUME_RAMP_MS: u64 = 1000;
+/// Presets go on shift and the number row, 1 to 9.
+const MAX_PRESETS: usize = 9;
+/// Polling faster wastes CPU, slower makes missed notifications linger.
+const POLL_RANGE: RangeInclusive<f32> = 0.1..=10.0;
+/// Mouse buttons with a default system action.
+const SYSTEM_BUTTONS: &[(i64, &str)] = &[(3, "middle click")];
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Largest output volume increase accepted from outside the app in one
+    /// step, anything bigger is pulled back. `None` disables the check.
+    pub max_volume_jump: Option<f32>,
+    /// Volume changes made by the app are spread over this long, zero to
+    /// make them at once.
+    pub volume_ramp: Duration,
+    /// (input, output) device pairs by UID or name. When the input becomes
+    /// the default, the output follows.
+    pub pairs: Vec<(String, String)>,
+    /// AirPods switch between the user's devices on their own, so pairs,
+    /// fallbacks and rules leave the defaults alone while they're involved.
+    pub airpods_auto_switch: bool,
+    /// Volume change per arrow or volume key press, defaults to 10% for
+    /// arrows and the macOS step for volume keys.
+    pub volume_step: Option<f32>,
+    /// Volume change per arrow press in dB mode, 3 dB by default.
+    pub db_step: f32,
+    /// Volume presets in percent, set with "preset 1" and on.
+    pub presets: Vec<u8>,
+    /// How much "boost" turns the output up, and for how long.
+    pub boost_step: f32,
+    pub boost_time: Duration,
+    /// Caps Lock toggles the mic mute, with its light showing the mute.
+    pub caps_lock_mute: bool,
+    /// Keys, by name, that unmute the default input while held, or mute
+    /// it.
+    pub push_to_talk: Option<String>,
+    pub push_to_mute: Option<String>,
+    /// Chords kept for the keys pane, and a file they're appended to.
+    pub key_history: usize,
+    pub key_history_file: Option<String>,
+    /// Clicks and scrolls in the keys display and history, for screencasts.
+    pub show_mouse: bool,
+    /// Mouse button numbers bound to actions, by their recorded name.
+    pub mouse: Vec<(i64, String)>,
+    /// Names for keyboards, by HID keyboard type.
+    pub keyboards: Vec<(i64, String)>,
+    /// (keyboard type, key code, action) bindings, for keys of one keyboard
+    /// only, like a macro pad.
+    pub key_bindings: Vec<(i64, i64, String)>,
+    /// Modifier key combos tapped on their own, like "fn", bound to actions.
+    pub modifier_bindings: Vec<(String, String)>,
+    /// Chords like "cmd+option+up" bound to actions, whatever app is in
+    /// front.
+    pub hotkeys: Vec<(String, String)>,
+    /// Keep bound keys from reaching other apps.
+    pub swallow_bindings: bool,
+    /// Port for the local socket API, off when unset.
+    pub server_port: Option<u16>,
+    /// Port the daemon takes terminals from other Macs on, with a token,
+    /// off when unset.
+    pub remote_port: Option<u16>,
+    /// Make the socket API reachable from the network, with tokens.
+    pub server_lan: bool,
+    /// Port and token for quick actions over HTTP. Tokens from
+    /// `mac-controls server token` work too.
+    pub quick_port: Option<u16>,
+    pub quick_token: Option<String>,
+    /// Make quick actions reachable from the network, not just this Mac.
+    pub quick_lan: bool,
+    /// TLS for both, from `[tls]`.
+    pub tls: Option<TlsConfig>,
+    /// Terminal key changes as (mode, key name, action), no mode for keys
+    /// that work everywhere.
+    pub keymap: Vec<(Option<UiMode>, String, String)>,
+    pub theme: Theme,
+    /// Sink names by event type, from `[events]`.
+    pub events: Vec<(String, Vec<String>)>,
+    /// Command run by the shell sink.
+    pub shell_hook: Option<String>,
+    /// URL the webhook sink POSTs to.
+    pub webhook_url: Option<String>,
+    /// Actions run when the display sleeps or wakes, from `[display]`.
+    pub display: Vec<(String, Vec<String>)>,
+    /// Actions for devices connecting or becoming the default, from
+    /// `[rules]`.
+    pub rules: Vec<Rule>,
+    /// Event types by plugin name, from `[plugins]`.
+    pub plugins: Vec<(String, Vec<String>)>,
+    /// Home Assistant over MQTT, from `[mqtt]`.
+    pub mqtt: Option<MqttConfig>,
+    /// Lowest level shown in the log pane at start.
+    pub log_level: log::Level,
+    /// Seconds between checks of the devices, on top of CoreAudio's
+    /// notifications, as written. See `poll_interval()`.
+    pub poll_seconds: Option<String>,
+    /// Values that were left at their defaults for being out of range or
+    /// not numbers, reported by `validate`.
+    pub problems: Vec<String>,
+    /// Start with virtual and aggregate devices hidden from the list.
+    pub hide_virtual: bool,
+    /// Post a macOS notification for volume changes while the terminal
+    /// isn't in front.
+    pub volume_notifications: bool,
+    /// Aliases by device UID or name, used when `devices.toml` has none.
+    pub aliases: Vec<(String, String)>,
+    /// Expected setup for `mac-controls check-call`, from `[call]`.
+    pub call: CallConfig,
+    /// Speaker cap at night, from `[night]`.
+    pub night: Option<NightConfig>,
+    /// Input auto-gain, from `[autogain]`.
+    pub autogain: Option<AutoGainConfig>,
+    /// Where to go when the default device disconnects, from
+    /// `[fallback]`.
+    pub fallback: Option<FallbackConfig>,
+    /// Key codes remapped on every keyboard, from `[remap]`.
+    pub remaps: Vec<(i64, Remap)>,
+}
+
+/// What a key in `[remap]` becomes, system-wide.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Remap {
+    /// Another key code, like § typing `.
+    Key(i64),
+    /// An action, the key never reaches apps.
+    Action(String),
+}
+
+/// Certificate and key for the network servers, from `[tls]`. PEM files,
+/// the certificate with its chain.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: String,
+    pub key: String,
+}
+
+/// Devices to switch to when the default one disconnects, instead of
+/// whatever macOS picks, from `[fallback]`.
+#[derive(Debug, Clone, Default)]
+pub struct FallbackConfig {
+    /// By UID, name or alias, the first connected one wins.
+    pub outputs: Vec<String>,
+    pub inputs: Vec<String>,
+    /// The new default output's volume is pulled down to this.
+    pub max_volume: Option<f32>,
+}
+
+/// What `check-call` expects, from `[call]`.
+#[derive(Debug, Clone)]
+pub struct CallConfig {
+    /// Default devices, by UID, name or alias.
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub min_input_volume: f32,
+    pub max_input_volume: f32,
+    /// Lowest headset battery percent.
+    pub min_battery: u8,
+    /// Whether Do Not Disturb should be on, unchecked when unset.
+    pub dnd: Option<bool>,
+}
+
+impl Default for CallConfig {
+    fn default() -> Self {
+        CallConfig {
+            input: None,
+            output: None,
+            min_input_volume: 0.3,
+            max_input_volume: 1.0,
+            min_battery: 20,
+            dnd: None,
+        }
+    }
+}
+
+/// MQTT broker for Home Assistant, from `[mqtt]`.
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topic prefix Home Assistant watches for discovery.
+    pub discovery_prefix: String,
+}
+
+/// Glyphs of the device list, from `[theme]`.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// Volume bar cells: set, unset, muted and no channel.
+    pub bar_full: String,
+    pub bar_empty: String,
+    pub bar_muted: String,
+    pub bar_none: String,
+    /// Shown before marked devices.
+    pub marked: String,
+    /// Shown before the default input and output devices.
+    pub active_both: String,
+    pub active_input: String,
+    pub active_output: String,
+    pub inactive: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            bar_full: "▓".to_string(),
+            bar_empty: "▒".to_string(),
+            bar_muted: "░".to_string(),
+            bar_none: "·".to_string(),
+            marked: "▸".to_string(),
+            active_both: "↔️  ".to_string(),
+            active_input: "🎤 ".to_string(),
+            active_output: "🔊 ".to_string(),
+            inactive: "   ".to_string(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_volume_jump: Some(0.3),
+            volume_ramp: DEFAULT_VOLUME_RAMP,
+            pairs: Vec::new(),
+            airpods_auto_switch: false,
+            volume_step: None,
+            db_step: DEFAULT_DB_STEP,
+            presets: DEFAULT_PRESETS.to_vec(),
+            boost_step: DEFAULT_BOOST_STEP,
+            boost_time: Duration::from_secs(DEFAULT_BOOST_SECONDS),
+            caps_lock_mute: false,
+            push_to_talk: None,
+            push_to_mute: None,
+            key_history: keyhistory::DEFAULT_LEN,
+            key_history_file: None,
+            show_mouse: false,
+            mouse: Vec::new(),
+            keyboards: Vec::new(),
+            key_bindings: Vec::new(),
+            modifier_bindings: Vec::new(),
+            remaps: Vec::new(),
+            hotkeys: Vec::new(),
+            swallow_bindings: false,
+            server_port: None,
+            remote_port: None,
+            server_lan: false,
+            quick_port: None,
+            quick_token: None,
+            quick_lan: false,
+            tls: None,
+            keymap: Vec::new(),
+            theme: Theme::default(),
+            events: Vec::new(),
+            shell_hook: None,
+            webhook_url: None,
+            display: Vec::new(),
+            rules: Vec::new(),
+            plugins: Vec::new(),
+            mqtt: None,
+            log_level: log::Level::Info,
+            poll_seconds: None,
+            problems: Vec::new(),
+            hide_virtual: false,
+            volume_notifications: false,
+            aliases: Vec::new(),
+            call: CallConfig::default(),
+            night: None,
+            autogain: None,
+            fallback: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file, using defaults for anything not set.
+    pub fn load() -> Self {
+        let mut store = Store::open(CONFIG_FILE);
+        if let Some(host) = host_name() {
+            // Values set for this host win, section by section
+            let host_config = Store::open(&format!("{HOST_CONFIG_DIR}/{host}.toml"));
+            for (name, section) in host_config.sections {
+                store.sections.entry(name).or_default().extend(section);
+            }
+            store.problems.extend(host_config.problems);
+        }
+        apply_env(&mut store);
+        Config::from_store(&store)
+    }
+
+    fn from_store(store: &Store) -> Self {
+        let mut config = Config::default();
+        let mut problems = store.problems.clone();
+        if store.get("safety", "max_volume_jump") == Some("false") {
+            config.max_volume_jump = None;
+        } else if let Some(jump) = number(
+            store,
+            ("safety", "max_volume_jump"),
+            |v: &f32| (0.0..=1.0).contains(v),
+            "0 to 1, or false",
+            &mut problems,
+        ) {
+            // 0 turns the check off
+            config.max_volume_jump = Some(jump).filter(|v| *v > 0.0);
+        }
+        if let Some(millis) = number(
+            store,
+            ("safety", "volume_ramp"),
+            |v: &u64| *v <= MAX_VOLUME_RAMP_MS,
+            &format!("0 to {MAX_VOLUME_RAMP_MS} milliseconds"),
+            &mut problems,
+        ) {
+            config.volume_ramp = Duration::from_millis(millis);
+        }
+        if let Some(pairs) = store.sections.get("pairs") {
+            config.pairs = pairs.clone().into_iter().collect();
+        }
+        config.airpods_auto_switch = store.get("airpods", "auto_switch") == Some("true");
+        config.volume_step = number(
+            store,
+            ("keys", "volume_step"),
+            |v: &f32| *v > 0.0 && *v <= 1.0,
+            "above 0, up to 1",
+            &mut problems,
+        );
+        if let Some(step) = number(
+            store,
+            ("keys", "db_step"),
+            |v: &f32| *v > 0.0 && *v <= 24.0,
+            "above 0, up to 24 dB",
+            &mut problems,
+        ) {
+            config.db_step = step;
+        }
+        if let Some(presets) = store.get("keys", "presets") {
+            // `presets = "20, 40, 60"`, percentages
+            let presets: Option<Vec<u8>> = presets
+                .split(',')
+                .map(|p| p.trim().parse::<u8>().ok().filter(|p| *p <= 100))
+                .collect();
+            match presets {
+                Some(presets) if !presets.is_empty() && presets.len() <= MAX_PRESETS => {
+                    config.presets = presets
+                }
+                _ => log::warn(format!(
+                    "[keys] presets: expected up to {MAX_PRESETS} percentages, like \"25, 50, 75, 100\""
+                )),
+            }
+        }
+        if let Some(step) = number(
+            store,
+            ("keys", "boost_step"),
+            |v: &f32| *v > 0.0 && *v <= 1.0,
+            "above 0, up to 1",
+            &mut problems,
+        ) {
+            config.boost_step = step;
+        }
+        if let Some(seconds) = number(
+            store,
+            ("keys", "boost_seconds"),
+            |v: &u64| *v > 0,
+            "a whole number of seconds above 0",
+            &mut problems,
+        ) {
+            config.boost_time = Duration::from_secs(seconds);
+        }
+        config.caps_lock_mute = store.get("keys", "caps_lock_mute") == Some("true");
+        // `push_to_talk = "f13"`, named like hotkeys
+        let key = |key| {
+            store
+                .get("keys", key)
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+        };
+        config.push_to_talk = key("push_to_talk");
+        config.push_to_mute = key("push_to_mute");
+        config.swallow_bindings = store.get("keys", "swallow_bindings") == Some("true");
+        if let Some(len) = store
+            .get("keys", "history")
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+        {
+            config.key_history = len;
+        }
+        config.key_history_file = store
+            .get("keys", "history_file")
+            .filter(|path| !path.is_empty())
+            .map(str::to_string);
+        config.show_mouse = store.get("keys", "show_mouse") == Some("true");
+        if let Some(mouse) = store.sections.get("mouse") {
+            // `button4 = "mute-input"`
+            config.mouse = mouse
+                .iter()
+                .filter_map(|(key, action)| {
+                    let button = key.strip_prefix("button")?.parse().ok()?;
+                    Some((button, action.clone()))
+                })
+                .collect();
+        }
+        if let Some(modifiers) = store.sections.get("modifiers") {
+            // `fn = "mute-input"`, `"left-shift+right-shift" = "toggle-mute"`
+            config.modifier_bindings = modifiers
+                .iter()
+                .map(|(combo, action)| (combo.clone(), action.clone()))
+                .collect();
+        }
+        if let Some(hotkeys) = store.sections.get("hotkeys") {
+            // `"cmd+option+up" = "media volume-up"`
+            config.hotkeys = hotkeys
+                .iter()
+                .map(|(chord, action)| (chord.clone(), action.clone()))
+                .collect();
+        }
+        config.server_port = store.get("server", "port").and_then(|p| p.parse().ok());
+        config.server_lan = store.get("server", "lan") == Some("true");
+        config.remote_port = store
+            .get("daemon", "remote_port")
+            .and_then(|p| p.parse().ok());
+        config.quick_port = store.get("quick", "port").and_then(|p| p.parse().ok());
+        config.quick_token = store
+            .get("quick", "token")
+            .filter(|t| !t.is_empty())
+            .map(str::to_string);
+        config.quick_lan = store.get("quick", "lan") == Some("true");
+        match (store.get("tls", "cert"), store.get("tls", "key")) {
+            (Some(cert), Some(key)) => {
+                config.tls = Some(TlsConfig {
+                    cert: cert.to_string(),
+                    key: key.to_string(),
+                })
+            }
+            (None, None) => {}
+            _ => problems.push("tls: needs both a cert and a key".to_string()),
+        }
+        if let Some(events) = store.sections.get("events") {
+            // `connected = "notification, log"`
+            config.events = events
+                .iter()
+                .map(|(event, names)| {
+                    let names = names.split(',').map(|n| n.trim().to_string());
+                    (event.clone(), names.filter(|n| !n.is_empty()).collect())
+                })
+                .collect();
+        }
+        if let Some(display) = store.sections.get("display") {
+            // `sleep = "play-pause, mute output on"`
+            config.display = display
+                .iter()
+                .map(|(trigger, actions)| {
+                    let actions = actions.split(',').map(|a| a.trim().to_string());
+                    (trigger.clone(), actions.filter(|a| !a.is_empty()).collect())
+                })
+                .collect();
+        }
+        if let Some(rules) = store.sections.get("rules") {
+            // `"connect AirPods" = "default output, volume output 40"`
+            for (key, actions) in rules {
+                match Rule::parse(key, actions) {
+                    Some(rule) => config.rules.push(rule),
+                    None => log::warn(format!(
+                        "rules \"{key}\": expected a trigger and a device, like \"connect AirPods\""
+                    )),
+                }
+            }
+        }
+        if let Some(plugins) = store.sections.get("plugins") {
+            // `hue = "default-output, mute"`
+            config.plugins = plugins
+                .iter()
+                .map(|(name, events)| {
+                    let events = events.split(',').map(|e| e.trim().to_string());
+                    (name.clone(), events.filter(|e| !e.is_empty()).collect())
+                })
+                .collect();
+        }
+        config.shell_hook = store.get("sinks", "shell").map(str::to_string);
+        config.webhook_url = store.get("sinks", "webhook").map(str::to_string);
+        if let Some(host) = store.get("mqtt", "host") {
+            let value = |key| store.get("mqtt", key).map(str::to_string);
+            config.mqtt = Some(MqttConfig {
+                host: host.to_string(),
+                port: value("port").and_then(|p| p.parse().ok()).unwrap_or(1883),
+                username: value("username"),
+                password: value("password"),
+                discovery_prefix: value("discovery_prefix")
+                    .unwrap_or_else(|| "homeassistant".to_string()),
+            });
+        }
+        if let Some(level) = store.get("log", "level").and_then(log::Level::parse) {
+            config.log_level = level;
+        }
+        config.poll_seconds = store.get("ui", "poll_interval").map(str::to_string);
+        config.hide_virtual = store.get("ui", "hide_virtual") == Some("true");
+        config.volume_notifications = store.get("ui", "volume_notifications") == Some("true");
+        if let Some(aliases) = store.sections.get("aliases") {
+            // `"Elgato Wave:3" = "Mic"`
+            config.aliases = aliases.clone().into_iter().collect();
+        }
+        if store.sections.contains_key("night") {
+            let time = |key| store.get("night", key).and_then(night::parse_time);
+            let cap = store
+                .get("night", "cap")
+                .and_then(|v| v.parse::<f32>().ok())
+                .filter(|v| (0.0..=1.0).contains(v));
+            match (time("start"), time("end"), cap) {
+                (Some(start), Some(end), Some(cap)) => {
+                    config.night = Some(NightConfig {
+                        start,
+                        end,
+                        cap,
+                        headphones: store
+                            .get("night", "headphones")
+                            .unwrap_or_default()
+                            .split(',')
+                            .map(|h| h.trim().to_string())
+                            .filter(|h| !h.is_empty())
+                            .collect(),
+                    })
+                }
+                _ => log::warn(
+                    "[night] needs start and end times like \"22:00\" and a cap from 0 to 1",
+                ),
+            }
+        }
+        if store.sections.contains_key("fallback") {
+            let devices = |key| {
+                store
+                    .get("fallback", key)
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|d| d.trim().to_string())
+                    .filter(|d| !d.is_empty())
+                    .collect()
+            };
+            let max_volume = store
+                .get("fallback", "max_volume")
+                .map(|v| v.parse::<f32>().ok().filter(|v| (0.0..=1.0).contains(v)));
+            match max_volume {
+                Some(None) => log::warn("[fallback] max_volume: expected a volume from 0 to 1"),
+                max_volume => {
+                    config.fallback = Some(FallbackConfig {
+                        outputs: devices("outputs"),
+                        inputs: devices("inputs"),
+                        max_volume: max_volume.flatten(),
+                    })
+                }
+            }
+        }
+        if store.sections.contains_key("autogain") {
+            let number = |key| store.get("autogain", key).map(|v| v.parse::<f32>().ok());
+            let seconds = |key| {
+                number(key).map(|v| {
+                    v.filter(|v| *v > 0.0 && *v <= 60.0)
+                        .map(Duration::from_secs_f32)
+                })
+            };
+            let default = AutoGainConfig::default();
+            let levels = (
+                number("low").unwrap_or(Some(default.low)),
+                number("high").unwrap_or(Some(default.high)),
+            );
+            let times = (
+                seconds("attack").unwrap_or(Some(default.attack)),
+                seconds("release").unwrap_or(Some(default.release)),
+            );
+            match (levels, times) {
+                ((Some(low), Some(high)), (Some(attack), Some(release)))
+                    if low < high && high <= 0.0 =>
+                {
+                    config.autogain = Some(AutoGainConfig {
+                        low,
+                        high,
+                        attack,
+                        release,
+                    })
+                }
+                _ => log::warn(
+                    "[autogain] needs low below high in dBFS, up to 0, and attack and release in seconds up to 60",
+                ),
+            }
+        }
+        let call = &mut config.call;
+        call.input = store.get("call", "input").map(str::to_string);
+        call.output = store.get("call", "output").map(str::to_string);
+        let volume = |key| store.get("call", key).and_then(|v| v.parse::<f32>().ok());
+        if let Some(level) = volume("min_input_volume") {
+            call.min_input_volume = level;
+        }
+        if let Some(level) = volume("max_input_volume") {
+            call.max_input_volume = level;
+        }
+        if let Some(percent) = store
+            .get("call", "min_battery")
+            .and_then(|v| v.parse().ok())
+        {
+            call.min_battery = percent;
+        }
+        call.dnd = store.get("call", "dnd").map(|v| v == "true");
+        if let Some(keyboards) = store.sections.get("keyboards") {
+            config.keyboards = keyboards
+                .iter()
+                .filter_map(|(kind, name)| Some((kind.parse().ok()?, name.clone())))
+                .collect();
+        }
+        let theme = &mut config.theme;
+        for (key, glyph) in [
+            ("bar_full", &mut theme.bar_full),
+            ("bar_empty", &mut theme.bar_empty),
+            ("bar_muted", &mut theme.bar_muted),
+            ("bar_none", &mut theme.bar_none),
+            ("marked", &mut theme.marked),
+            ("active_both", &mut theme.active_both),
+            ("active_input", &mut theme.active_input),
+            ("active_output", &mut theme.active_output),
+            ("inactive", &mut theme.inactive),
+        ] {
+            if let Some(value) = store.get("theme", key) {
+                *glyph = value.to_string();
+            }
+        }
+        // `[keymap]` and `["keymap output"]` sections with `"m" = "action"`
+        for (name, section) in &store.sections {
+            let mode = match name.as_str() {
+                "keymap" => None,
+                name => match name.strip_prefix("keymap ").and_then(UiMode::parse) {
+                    Some(mode) => Some(mode),
+                    None => continue,
+                },
+            };
+            for (key, action) in section {
+                config.keymap.push((mode, key.clone(), action.clone()));
+            }
+        }
+        // `key code = key code` or `key code = "action"`
+        for (key_code, to) in store.sections.get("remap").into_iter().flatten() {
+            let Ok(key_code) = key_code.parse() else {
+                log::warn(format!("[remap]: {key_code} isn't a key code"));
+                continue;
+            };
+            let remap = match to.parse() {
+                Ok(to) => Remap::Key(to),
+                Err(_) => Remap::Action(to.clone()),
+            };
+            config.remaps.push((key_code, remap));
+        }
+        // `["keyboard 40"]` sections with `key code = "action"` entries
+        for (name, section) in &store.sections {
+            let keyboard = match name.strip_prefix("keyboard ").map(|k| k.parse()) {
+                Some(Ok(keyboard)) => keyboard,
+                _ => continue,
+            };
+            for (key_code, action) in section {
+                if let Ok(key_code) = key_code.parse() {
+                    config
+                        .key_bindings
+                        .push((keyboard, key_code, action.clone()));
+                }
+            }
+        }
+        config.problems = problems;
+        config
+    }
+
+    /// Time between device checks, half a second unless set.
+    pub fn poll_interval(&self) -> Duration {
+        let seconds = self.poll_seconds.as_deref().and_then(parse_poll_seconds);
+        Duration::from_secs_f32(seconds.unwrap_or(DEFAULT_POLL_SECONDS))
+    }
+
+    /// Problems with the bindings: unknown actions, keys bound twice and
+    /// keys the system already uses.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = self.problems.clone();
+        for (button, action) in &self.mouse {
+            if Action::from_record(action).is_none() {
+                errors.push(format!("button{button}: unknown action \"{action}\""));
+            }
+            if let Some((_, name)) = SYSTEM_BUTTONS.iter().find(|(b, _)| b == button) {
+                errors.push(format!("button{button}: conflicts with the system {name}"));
+            }
+        }
+        for (i, (keyboard, key_code, action)) in self.key_bindings.iter().enumerate() {
+            let binding = format!("keyboard {keyboard} key {key_code}");
+            if Action::from_record(action).is_none() {
+                errors.push(format!("{binding}: unknown action \"{action}\""));
+            }
+            if let Some((_, name)) = SYSTEM_KEYS.iter().find(|(k, _)| k == key_code) {
+                errors.push(format!("{binding}: conflicts with the system {name} key"));
+            }
+            if *key_code == CAPS_LOCK && self.caps_lock_mute {
+                errors.push(format!("{binding}: conflicts with caps_lock_mute"));
+            }
+            // Keys can be written more than one way, like 18 and 018
+            let earlier = self.key_bindings[..i]
+                .iter()
+                .find(|(k, c, _)| k == keyboard && c == key_code);
+            if let Some((_, _, other)) = earlier {
+                errors.push(format!(
+                    "{binding}: bound to both \"{other}\" and \"{action}\""
+                ));
+            }
+        }
+        for (key_code, remap) in &self.remaps {
+            let binding = format!("remap key {key_code}");
+            match remap {
+                Remap::Action(action) if Action::from_record(action).is_none() => {
+                    errors.push(format!("{binding}: unknown action \"{action}\""))
+                }
+                Remap::Key(to) if to == key_code => {
+                    errors.push(format!("{binding}: remapped to itself"))
+                }
+                _ => {}
+            }
+            if layout::is_modifier(*key_code) {
+                errors.push(format!("{binding}: modifier keys can't be remapped"));
+            }
+        }
+        for (i, (combo, action)) in self.modifier_bindings.iter().enumerate() {
+            let binding = format!("modifiers \"{combo}\"");
+            if Action::from_record(action).is_none() {
+                errors.push(format!("{binding}: unknown action \"{action}\""));
+            }
+            let flags = match events::parse_modifier_combo(combo) {
+                Ok(flags) => flags,
+                Err(e) => {
+                    errors.push(format!("{binding}: {e}"));
+                    continue;
+                }
+            };
+            // The same keys can be written in another order
+            let earlier = self.modifier_bindings[..i]
+                .iter()
+                .find(|(c, _)| events::parse_modifier_combo(c).ok() == Some(flags));
+            if let Some((_, other)) = earlier {
+                errors.push(format!(
+                    "{binding}: bound to both \"{other}\" and \"{action}\""
+                ));
+            }
+        }
+        for (i, (chord, action)) in self.hotkeys.iter().enumerate() {
+            let binding = format!("hotkeys \"{chord}\"");
+            if Action::from_record(action).is_none() {
+                errors.push(format!("{binding}: unknown action \"{action}\""));
+            }
+            let hotkey = match Hotkey::parse(chord) {
+                Ok(hotkey) => hotkey,
+                Err(e) => {
+                    errors.push(format!("{binding}: {e}"));
+                    continue;
+                }
+            };
+            let system = SYSTEM_CHORDS
+                .iter()
+                .find(|(c, _)| Hotkey::parse(c).ok().as_ref() == Some(&hotkey));
+            if let Some((_, name)) = system {
+                errors.push(format!("{binding}: taken by the system {name}"));
+            }
+            // Modifiers can be written in another order or spelling
+            let earlier = self.hotkeys[..i]
+                .iter()
+                .find(|(c, _)| Hotkey::parse(c).ok().as_ref() == Some(&hotkey));
+            if let Some((_, other)) = earlier {
+                errors.push(format!(
+                    "{binding}: bound to both \"{other}\" and \"{action}\""
+                ));
+            }
+        }
+        if let Some(seconds) = &self.poll_seconds {
+            if parse_poll_seconds(seconds).is_none() {
+                errors.push(format!(
+                    "ui poll_interval: \"{seconds}\" isn't {} to {} seconds",
+                    POLL_RANGE.start(),
+                    POLL_RANGE.end()
+                ));
+            }
+        }
+        for (i, (key, alias)) in self.aliases.iter().enumerate() {
+            if alias.is_empty() {
+                errors.push(format!("aliases \"{key}\": empty alias"));
+            }
+            if let Some((other, _)) = self.aliases[..i].iter().find(|(_, a)| a == alias) {
+                errors.push(format!(
+                    "aliases \"{key}\": \"{alias}\" is also the alias of \"{other}\""
+                ));
+            }
+        }
+        let call = &self.call;
+        let volumes = [call.min_input_volume, call.max_input_volume];
+        if volumes.iter().any(|v| !(0.0..=1.0).contains(v)) || volumes[0] > volumes[1] {
+            errors.push("call: input volumes must be 0 to 1, min below max".to_string());
+        }
+        if self.tls.is_some() && !cfg!(feature = "tls") {
+            errors.push(tls::NOT_BUILT.to_string());
+        }
+        let no_tokens = self.quick_token.is_none() && !tokens::any();
+        if self.quick_port.is_some() && no_tokens {
+            errors.push("quick: no token, make one with `mac-controls server token`".to_string());
+        }
+        if self.remote_port.is_some() && !tokens::any() {
+            errors.push("daemon: no token, make one with `mac-controls server token`".to_string());
+        }
+        for (event, names) in &self.events {
+            if EventKind::parse(event).is_none() {
+                errors.push(format!("events {event}: unknown event"));
+            }
+            for name in names {
+                if !sinks::SINK_NAMES.contains(&name.as_str()) {
+                    errors.push(format!("events {event}: unknown sink \"{name}\""));
+                } else if name == "shell" && self.shell_hook.is_none() {
+                    errors.push(format!("events {event}: no shell command in [sinks]"));
+                } else if name == "webhook" && self.webhook_url.is_none() {
+                    errors.push(format!("events {event}: no webhook URL in [sinks]"));
+                } else if name == "socket" && self.server_port.is_none() {
+                    errors.push(format!("events {event}: no port in [server]"));
+                }
+            }
+        }
+        for (trigger, actions) in &self.display {
+            if !display::TRIGGERS.contains(&trigger.as_str()) {
+                errors.push(format!(
+                    "display {trigger}: unknown trigger, use sleep or wake"
+                ));
+            }
+            for action in actions {
+                if Action::from_record(action).is_none() {
+                    errors.push(format!("display {trigger}: unknown action \"{action}\""));
+                }
+            }
+        }
+        for rule in &self.rules {
+            let name = format!("rules \"{} {}\"", rule.trigger, rule.device);
+            if !rules::TRIGGERS.contains(&rule.trigger.as_str()) {
+                errors.push(format!(
+                    "{name}: unknown trigger, use {}",
+                    rules::TRIGGERS.join(", ")
+                ));
+            }
+            for action in &rule.actions {
+                let shortcut =
+                    ["hide", "default input", "default output"].contains(&action.as_str());
+                if !shortcut && Action::from_record(action).is_none() {
+                    errors.push(format!("{name}: unknown action \"{action}\""));
+                }
+            }
+        }
+        for (name, events) in &self.plugins {
+            if plugins::find(name).is_none() {
+                errors.push(format!(
+                    "plugins {name}: no mac-controls-{name} on the PATH"
+                ));
+            }
+            for event in events {
+                if EventKind::parse(event).is_none() {
+                    errors.push(format!("plugins {name}: unknown event \"{event}\""));
+                }
+            }
+        }
+        for (mode, key, action) in &self.keymap {
+            let binding = match mode {
+                Some(mode) => format!("{} mode key \"{key}\"", mode.as_str()),
+                None => format!("key \"{key}\""),
+            };
+            if !keymap::is_key_name(key) {
+                errors.push(format!("{binding}: unknown key"));
+            }
+            if !action.is_empty() && keymap::parse_action(action).is_none() {
+                errors.push(format!("{binding}: unknown action \"{action}\""));
+            }
+        }
+        for (i, (button, action)) in self.mouse.iter().enumerate() {
+            if let Some((_, other)) = self.mouse[..i].iter().find(|(b, _)| b == button) {
+                errors.push(format!(
+                    "button{button}: bound to both \"{other}\" and \"{action}\""
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// A number from the config, None when it isn't set. One that doesn't
+/// parse or isn't `valid` is left out too, and added to `problems` with
+/// what was `expected`.
+fn number<T: FromStr>(
+    store: &Store,
+    (section, key): (&str, &str),
+    valid: impl Fn(&T) -> bool,
+    expected: &str,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let value = store.get(section, key)?;
+    let number = value.parse().ok().filter(valid);
+    if number.is_none() {
+        problems.push(format!("{section} {key}: \"{value}\" isn't {expected}"));
+    }
+    number
+}
+
+fn parse_poll_seconds(value: &str) -> Option<f32> {
+    value.parse().ok().filter(|s| POLL_RANGE.contains(s))
+}
+
+/// Set `MAC_CONTROLS_THEME__BAR_FULL=█` as `bar_full` in `[theme]`.
+fn apply_env(store: &mut Store) {
+    for (name, value) in std::env::vars() {
+        let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let name = name.to_lowercase();
+        let parts = name.split_once(ENV_SEPARATOR);
+        if let Some((section, key)) = parts.filter(|(s, k)| !s.is_empty() && !k.is_empty()) {
+            store.set(section, key, value);
+        }
+    }
+}
+
+/// Short host name, like "studio" for "studio.local".
+pub fn host_name() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { gethostname(buf.as_mut_ptr() as *mut c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let name = CStr::from_bytes_until_nul(&buf).ok()?.to_str().ok()?;
+    name.split('.')
+        .next()
+        .filter(|n| !n.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(values: &[(&str, &str, &str)]) -> Config {
+        let mut store = Store::default();
+        for (section, key, value) in values {
+            store.set(section, key, value);
+        }
+        Config::from_store(&store)
+    }
+
+    #[test]
+    fn out_of_range_values_are_reported() {
+        let config = config(&[
+            ("keys", "volume_step", "2"),
+            ("keys", "db_step", "0"),
+            ("keys", "boost_step", "loud"),
+            ("keys", "boost_seconds", "0"),
+            ("safety", "max_volume_jump", "1.5"),
+            ("safety", "volume_ramp", "5000"),
+        ]);
+        let errors = config.validate();
+        for key in [
+            "volume_step",
+            "db_step",
+            "boost_step",
+            "boost_seconds",
+            "max_volume_jump",
+            "volume_ramp",
+        ] {
+            assert!(errors.iter().any(|e| e.contains(key)), "{key}: {errors:?}");
+        }
+        assert_eq!(config.volume_step, None);
+        assert_eq!(config.db_step, DEFAULT_DB_STEP);
+        assert_eq!(config.volume_ramp, DEFAULT_VOLUME_RAMP);
+    }
+
+    #[test]
+    fn valid_values_are_taken() {
+        let config = config(&[
+            ("keys", "volume_step", "0.05"),
+            ("safety", "max_volume_jump", "false"),
+            ("safety", "volume_ramp", "0"),
+        ]);
+        assert!(config.validate().is_empty());
+        assert_eq!(config.volume_step, Some(0.05));
+        assert_eq!(config.max_volume_jump, None);
+        assert_eq!(config.volume_ramp, Duration::ZERO);
+    }
+
+    #[test]
+    fn chord_errors_name_the_wrong_part() {
+        let config = config(&[
+            ("hotkeys", "hyper+up", "mute-input"),
+            ("hotkeys", "cmd+nokey", "mute-input"),
+            ("hotkeys", "command+space", "mute-input"),
+            ("modifiers", "left-shift+meta", "mute-input"),
+        ]);
+        let errors = config.validate();
+        let has = |text: &str| errors.iter().any(|e| e.contains(text));
+        assert!(has("unknown modifier \"hyper\""), "{errors:?}");
+        assert!(has("unknown key \"nokey\""), "{errors:?}");
+        assert!(has("taken by the system Spotlight"), "{errors:?}");
+        assert!(has("unknown modifier key \"meta\""), "{errors:?}");
+    }
+
+    #[test]
+    fn same_chord_written_twice() {
+        let config = config(&[
+            ("hotkeys", "cmd+option+up", "media volume-up"),
+            ("hotkeys", "option + command + up", "mute-input"),
+        ]);
+        let errors = config.validate();
+        assert!(
+            errors.iter().any(|e| e.contains("bound to both")),
+            "{errors:?}"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Fast user switching: whether our login session is the one on the
+//! console. While another user is active, their keys aren't ours to act on
+//! and the devices are theirs to change.
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::string::CFString;
+
+/// kCGSessionOnConsoleKey, not exported as a symbol.
+const ON_CONSOLE_KEY: &str = "kCGSSessionOnConsoleKey";
+
+extern "C" {
+    fn CGSessionCopyCurrentDictionary() -> CFDictionaryRef;
+}
+
+/// True while our session is on the console. Also true without a window
+/// server session (over SSH), where there's nobody to switch to.
+pub fn is_active() -> bool {
+    let dict = unsafe { CGSessionCopyCurrentDictionary() };
+    if dict.is_null() {
+        return true;
+    }
+    let dict: CFDictionary<CFString, CFType> =
+        unsafe { CFDictionary::wrap_under_create_rule(dict) };
+    dict.find(CFString::from_static_string(ON_CONSOLE_KEY))
+        .and_then(|value| value.downcast::<CFBoolean>())
+        .is_none_or(bool::from)
+}
+
+/// Session changes, checked on the poll timer.
+#[derive(Debug)]
+pub struct Console {
+    active: bool,
+}
+
+impl Console {
+    /// Assumed active, so the first check pauses an app started in a
+    /// session that's in the background.
+    pub fn new() -> Self {
+        Console { active: true }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// The new state, when the session was switched away or back since the
+    /// last check.
+    pub fn check(&mut self) -> Option<bool> {
+        let active = is_active();
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(active)
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console::new()
+    }
+}
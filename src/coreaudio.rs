@@ -1,25 +1,68 @@
 //! FFI with CoreAudio
 #![allow(non_upper_case_globals)]
 
+use core_foundation::dictionary::CFDictionaryRef;
 use std::os::raw::{c_int, c_uchar, c_uint, c_void};
 
+use crate::error::ControlError;
+
 pub const NO_ERR: OSStatus = 0;
 pub const kAudioHardwarePropertyDevices: c_uint = 1684370979;
 pub const kAudioDevicePropertyDeviceCanBeDefaultDevice: c_uint = 1684434036;
 pub const kAudioHardwarePropertyDefaultInputDevice: c_uint = 1682533920;
 pub const kAudioHardwarePropertyDefaultOutputDevice: c_uint = 1682929012;
+pub const kAudioHardwarePropertyDefaultSystemOutputDevice: c_uint = 1934587252;
+pub const kAudioDevicePropertyDeviceCanBeDefaultSystemDevice: c_uint = 1936092276;
 pub const kAudioDevicePropertyDeviceNameCFString: c_uint = 1819173229;
 pub const kAudioDevicePropertyDeviceUID: c_uint = 1969841184;
+pub const kAudioDevicePropertyModelUID: c_uint = 1836411236;
+pub const kAudioObjectPropertyManufacturer: c_uint = 1819107691;
+pub const kAudioObjectPropertyModelName: c_uint = 1819111268;
+pub const kAudioDevicePropertyTransportType: c_uint = 1953653102;
+pub const kAudioDeviceTransportTypeBuiltIn: c_uint = 1651274862;
+pub const kAudioDeviceTransportTypeUSB: c_uint = 1970496032;
+pub const kAudioDeviceTransportTypeBluetooth: c_uint = 1651275109;
+pub const kAudioDeviceTransportTypeBluetoothLE: c_uint = 1651271009;
+pub const kAudioDeviceTransportTypeAirPlay: c_uint = 1634300528;
+pub const kAudioDeviceTransportTypeVirtual: c_uint = 1986622068;
+pub const kAudioDeviceTransportTypeAggregate: c_uint = 1735554416;
+pub const kAudioAggregateDevicePropertyFullSubDeviceList: c_uint = 1735554416;
+pub const kAudioSubDevicePropertyDriftCompensation: c_uint = 1685218932;
+pub const kAudioSubDeviceClassID: c_uint = 1634956642;
+pub const kAudioObjectPropertyOwnedObjects: c_uint = 1870098020;
+pub const kAudioObjectPropertyClass: c_uint = 1668047219;
 pub const kAudioObjectPropertyScopeGlobal: c_uint = 1735159650;
 pub const kAudioDevicePropertyScopeInput: c_uint = 1768845428;
 pub const kAudioDevicePropertyScopeOutput: c_uint = 1869968496;
 pub const kAudioDevicePropertyStreams: c_uint = 1937009955;
+pub const kAudioDevicePropertyStreamConfiguration: c_uint = 1936482681;
 pub const kAudioDevicePropertyVolumeScalar: c_uint = 1987013741;
+pub const kAudioDevicePropertyVolumeDecibels: c_uint = 1987013732;
+pub const kAudioDevicePropertyVolumeScalarToDecibels: c_uint = 1983013986;
+pub const kAudioDevicePropertyVolumeDecibelsToScalar: c_uint = 1684157046;
 pub const kAudioDevicePropertyMute: c_uint = 1836414053;
+pub const kAudioDevicePropertyPreferredChannelsForStereo: c_uint = 1684236338;
+pub const kAudioDevicePropertyNominalSampleRate: c_uint = 1853059700;
+pub const kAudioDevicePropertyAvailableNominalSampleRates: c_uint = 1853059619;
+pub const kAudioDevicePropertyDataSource: c_uint = 1936945763;
+pub const kAudioDevicePropertyDataSources: c_uint = 1936941859;
+pub const kAudioDevicePropertyDataSourceNameForIDCFString: c_uint = 1819501422;
+pub const kAudioDevicePropertyClockSource: c_uint = 1668510307;
+pub const kAudioDevicePropertyClockSources: c_uint = 1668506403;
+pub const kAudioDevicePropertyClockSourceNameForIDCFString: c_uint = 1818456942;
+pub const kAudioDevicePropertyHogMode: c_uint = 1869180523;
+pub const kAudioStreamPropertyPhysicalFormat: c_uint = 1885762592;
 pub const kAudioObjectPropertyElementMain: c_uint = 0;
+pub const kAudioObjectPropertyScopeWildcard: c_uint = 707406378;
+pub const kAudioObjectPropertyElementWildcard: c_uint = 0xFFFFFFFF;
 pub const kAudioObjectSystemObject: c_uint = 1;
+pub const kAudioHardwarePropertyProcessObjectList: c_uint = 1886548771;
+pub const kAudioProcessPropertyPID: c_uint = 1886415204;
+pub const kAudioProcessPropertyBundleID: c_uint = 1885497700;
+pub const kAudioProcessPropertyIsRunningOutput: c_uint = 1885958767;
 
 pub type Float32 = f32;
+pub type Float64 = f64;
 pub type UInt32 = c_uint;
 pub type SInt32 = c_int;
 
@@ -40,6 +83,84 @@ pub struct AudioObjectPropertyAddress {
     pub mElement: AudioObjectPropertyElement,
 }
 
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AudioValueRange {
+    pub mMinimum: Float64,
+    pub mMaximum: Float64,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AudioStreamBasicDescription {
+    pub mSampleRate: Float64,
+    pub mFormatID: UInt32,
+    pub mFormatFlags: UInt32,
+    pub mBytesPerPacket: UInt32,
+    pub mFramesPerPacket: UInt32,
+    pub mBytesPerFrame: UInt32,
+    pub mChannelsPerFrame: UInt32,
+    pub mBitsPerChannel: UInt32,
+    pub mReserved: UInt32,
+}
+
+/// Input and output of the `NameForID` properties.
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug)]
+pub struct AudioValueTranslation {
+    pub mInputData: *mut c_void,
+    pub mInputDataSize: UInt32,
+    pub mOutputData: *mut c_void,
+    pub mOutputDataSize: UInt32,
+}
+
+/// Only ever handled by pointer, so the fields are left opaque.
+#[repr(C)]
+pub struct AudioTimeStamp {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug, Copy, Clone)]
+pub struct AudioBuffer {
+    pub mNumberChannels: UInt32,
+    pub mDataByteSize: UInt32,
+    pub mData: *mut c_void,
+}
+
+/// Variable length struct: `mBuffers` really holds `mNumberBuffers` items.
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug)]
+pub struct AudioBufferList {
+    pub mNumberBuffers: UInt32,
+    pub mBuffers: [AudioBuffer; 1],
+}
+
+/// (device, now, input data, input time, output data, output time, client data)
+pub type AudioDeviceIOProc = extern "C" fn(
+    AudioObjectID,
+    *const AudioTimeStamp,
+    *const AudioBufferList,
+    *const AudioTimeStamp,
+    *mut AudioBufferList,
+    *const AudioTimeStamp,
+    *mut c_void,
+) -> OSStatus;
+pub type AudioDeviceIOProcID = Option<AudioDeviceIOProc>;
+
+/// (object, number of addresses, addresses, client data)
+pub type AudioObjectPropertyListenerProc = extern "C" fn(
+    AudioObjectID,
+    UInt32,
+    *const AudioObjectPropertyAddress,
+    *mut c_void,
+) -> OSStatus;
+
 extern "C" {
     pub fn AudioObjectHasProperty(
         inObjectID: AudioObjectID,
@@ -80,4 +201,67 @@ extern "C" {
         inDataSize: UInt32,
         inData: *const c_void,
     ) -> OSStatus;
+
+    pub fn AudioObjectAddPropertyListener(
+        inObjectID: AudioObjectID,
+        inAddress: *const AudioObjectPropertyAddress,
+        inListener: AudioObjectPropertyListenerProc,
+        inClientData: *mut c_void,
+    ) -> OSStatus;
+
+    pub fn AudioObjectRemovePropertyListener(
+        inObjectID: AudioObjectID,
+        inAddress: *const AudioObjectPropertyAddress,
+        inListener: AudioObjectPropertyListenerProc,
+        inClientData: *mut c_void,
+    ) -> OSStatus;
+
+    pub fn AudioDeviceCreateIOProcID(
+        inDevice: AudioObjectID,
+        inProc: AudioDeviceIOProc,
+        inClientData: *mut c_void,
+        outIOProcID: *mut AudioDeviceIOProcID,
+    ) -> OSStatus;
+
+    pub fn AudioDeviceDestroyIOProcID(
+        inDevice: AudioObjectID,
+        inIOProcID: AudioDeviceIOProcID,
+    ) -> OSStatus;
+
+    pub fn AudioDeviceStart(inDevice: AudioObjectID, inProcID: AudioDeviceIOProcID) -> OSStatus;
+
+    pub fn AudioDeviceStop(inDevice: AudioObjectID, inProcID: AudioDeviceIOProcID) -> OSStatus;
+
+    pub fn AudioHardwareCreateAggregateDevice(
+        inDescription: CFDictionaryRef,
+        outDeviceID: *mut AudioObjectID,
+    ) -> OSStatus;
+
+    pub fn AudioHardwareDestroyAggregateDevice(inDeviceID: AudioObjectID) -> OSStatus;
 }
+
+/// `Ok` for `NO_ERR`, otherwise the failed call, like `check(status,
+/// "set", id, kAudioDevicePropertyMute)`.
+pub fn check(
+    status: OSStatus,
+    action: &'static str,
+    object: AudioObjectID,
+    selector: AudioObjectPropertySelector,
+) -> Result<(), ControlError> {
+    match status {
+        NO_ERR => Ok(()),
+        status => Err(ControlError::CoreAudio {
+            action,
+            selector,
+            object,
+            status,
+        }),
+    }
+}
+
+/// `AudioHardwareCreateProcessTap`, macOS 14.2 and up, so looked up at
+/// runtime rather than linked. (`CATapDescription`, tap out)
+pub type AudioHardwareCreateProcessTap =
+    unsafe extern "C" fn(*mut c_void, *mut AudioObjectID) -> OSStatus;
+/// `AudioHardwareDestroyProcessTap`, looked up like the create function.
+pub type AudioHardwareDestroyProcessTap = unsafe extern "C" fn(AudioObjectID) -> OSStatus;
@@ -0,0 +1,50 @@
+//! Display sleep and wake, apart from system sleep: the screen turning off
+//! at night while the Mac keeps running. `[display]` sets actions for each:
+//!
+//! ```toml
+//! [display]
+//! sleep = "play-pause, mute output on"
+//! wake = "mute output off"
+//! ```
+
+use core_graphics::display::CGDisplay;
+
+use crate::events::Action;
+
+/// Trigger names in `[display]`.
+pub const TRIGGERS: &[&str] = &["sleep", "wake"];
+
+/// Watches the main display, checked on the poll timer.
+#[derive(Debug)]
+pub struct DisplayWatch {
+    /// None until the first check.
+    asleep: Option<bool>,
+    /// Recorded action names by trigger.
+    actions: Vec<(String, Vec<String>)>,
+}
+
+impl DisplayWatch {
+    pub fn new(actions: &[(String, Vec<String>)]) -> Self {
+        DisplayWatch {
+            asleep: None,
+            actions: actions.to_vec(),
+        }
+    }
+
+    /// The trigger and its actions, when the display went to sleep or woke
+    /// up since the last check. The first check only takes the state.
+    pub fn check(&mut self) -> Option<(&'static str, Vec<Action>)> {
+        let asleep = CGDisplay::main().is_asleep();
+        if self.asleep.replace(asleep).unwrap_or(asleep) == asleep {
+            return None;
+        }
+        let trigger = if asleep { "sleep" } else { "wake" };
+        let actions = self
+            .actions
+            .iter()
+            .filter(|(name, _)| name == trigger)
+            .flat_map(|(_, actions)| actions.iter().filter_map(|a| Action::from_record(a)))
+            .collect();
+        Some((trigger, actions))
+    }
+}
@@ -0,0 +1,113 @@
+//! The crate's error type. Messages are written for the status line, so
+//! `to_string` is what the user sees; callers that only report errors can
+//! keep them as `String`s, through `From`.
+
+use std::fmt;
+use std::io;
+use std::sync::mpsc::SendError;
+
+use crate::coreaudio::{AudioObjectID, AudioObjectPropertySelector, OSStatus};
+use crate::listener::fourcc;
+
+#[derive(Debug)]
+pub enum ControlError {
+    /// A CoreAudio call failed: what was being done ("read", "set"), the
+    /// property, the object and the status it returned.
+    CoreAudio {
+        action: &'static str,
+        selector: AudioObjectPropertySelector,
+        object: AudioObjectID,
+        status: OSStatus,
+    },
+    /// A change refused in read-only mode.
+    ReadOnly,
+    /// A device that's gone, or can't do what was asked.
+    Device(String),
+    /// Bad input, like an unknown key in a chord.
+    Invalid(String),
+    /// The other end of a channel went away, the app is shutting down.
+    Disconnected,
+    /// The terminal or a file.
+    Io(io::Error),
+    /// Anything else, as a message.
+    Other(String),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlError::CoreAudio {
+                action,
+                selector,
+                object,
+                status,
+            } => write!(
+                f,
+                "Unable to {action} '{}' of object {object}: {status}",
+                fourcc(*selector)
+            ),
+            ControlError::ReadOnly => f.write_str("Read-only, nothing was changed"),
+            ControlError::Device(message)
+            | ControlError::Invalid(message)
+            | ControlError::Other(message) => f.write_str(message),
+            ControlError::Disconnected => f.write_str("Shutting down"),
+            ControlError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ControlError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ControlError {
+    fn from(e: io::Error) -> Self {
+        ControlError::Io(e)
+    }
+}
+
+impl<T> From<SendError<T>> for ControlError {
+    fn from(_: SendError<T>) -> Self {
+        ControlError::Disconnected
+    }
+}
+
+impl From<String> for ControlError {
+    fn from(message: String) -> Self {
+        ControlError::Other(message)
+    }
+}
+
+impl From<&str> for ControlError {
+    fn from(message: &str) -> Self {
+        ControlError::Other(message.to_string())
+    }
+}
+
+impl From<ControlError> for String {
+    fn from(e: ControlError) -> Self {
+        e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coreaudio::{check, kAudioDevicePropertyMute, NO_ERR};
+
+    #[test]
+    fn failed_calls_name_the_property() {
+        assert!(check(NO_ERR, "set", 40, kAudioDevicePropertyMute).is_ok());
+        let e = check(-50, "set", 40, kAudioDevicePropertyMute).unwrap_err();
+        assert_eq!(e.to_string(), "Unable to set 'mute' of object 40: -50");
+        assert_eq!(
+            String::from(ControlError::ReadOnly),
+            "Read-only, nothing was changed"
+        );
+    }
+}
@@ -1,11 +1,33 @@
-use core_foundation::runloop::{kCFRunLoopCommonModes, CFRunLoop};
+//! Everything the app reacts to as an `Action`, and the `EventTap` that
+//! turns system-wide key, modifier and mouse events into them.
+
+use core_foundation::base::TCFType;
+use core_foundation::date::CFDate;
+use core_foundation::mach_port::CFMachPortRef;
+use core_foundation::runloop::{
+    kCFRunLoopCommonModes, CFRunLoop, CFRunLoopRef, CFRunLoopStop, CFRunLoopTimer,
+    CFRunLoopTimerContext, CFRunLoopTimerRef,
+};
 use core_graphics::{
     event::{
         CGEvent, CGEventFlags, CGEventTap, CGEventTapLocation, CGEventTapOptions,
-        CGEventTapPlacement, CGEventType, EventField,
+        CGEventTapPlacement, CGEventType, CGKeyCode, EventField,
     },
     event_source::{CGEventSource, CGEventSourceStateID},
 };
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use termion::event::Key;
+
+use crate::audio::Channel;
+use crate::error::ControlError;
+use crate::layout;
+use crate::log;
+use crate::panes::Pane;
+use crate::permissions::{Permission, Status};
 
 #[derive(Debug)]
 pub enum Action {
@@ -17,20 +39,653 @@ pub enum Action {
         key_code: i64,
         repeating: bool,
         modifiers: ModifierKeys,
+        /// HID keyboard type of the keyboard the key came from.
+        keyboard: i64,
     },
     Modifier {
         modifiers: ModifierKeys,
     },
+    /// Extra mouse button, numbered from 1 like the config.
+    MouseDown {
+        button: i64,
+    },
+    /// A click or scroll for the keys display, with `show_mouse`.
+    Pointer {
+        event: Pointer,
+        modifiers: ModifierKeys,
+    },
     ModeSwitch(UiMode),
+    /// Back to the mode before the current one.
+    ModeBack,
+    /// Key typed in the terminal, resolved through the keymap.
+    TerminalKey(Key),
+    /// An arrow key with shift, which termion doesn't parse itself.
+    ShiftedKey(Key),
+    /// Typed into the search or palette query.
+    TextInput(char),
+    TextBackspace,
+    SearchCancel,
+    PaletteNext,
+    PalettePrev,
+    PaletteRun,
+    /// Start recording a macro, or stop and ask for its name.
+    ToggleMacroRecording,
+    MacroSave,
+    MacroDiscard,
+    RunMacro(String),
+    /// Keep the name typed in rename mode as the device's alias.
+    RenameSave,
+    /// Move through and apply or save in the profile picker.
+    ProfileNext,
+    ProfilePrev,
+    ProfileApply,
+    ProfileSave,
+    /// Restore a saved profile by name.
+    ApplyProfile(String),
+    TogglePane(Pane),
+    /// Fold or unfold the highlighted device's section of the list.
+    ToggleSection,
+    /// Hide or show virtual and aggregate devices.
+    ToggleVirtual,
+    FocusNextPane,
+    CycleLogLevel,
+    ResizePane {
+        grow: bool,
+    },
+    /// Make a device (by UID) the default for a channel.
+    SetDefault(Channel, String),
+    /// Make the highlighted device the default for the mode's channel.
+    MakeDefault,
+    /// Set the active device of a channel to a level, in percent.
+    SetVolume(Channel, u8),
+    /// Set a configured volume preset, by its number from 1: on the
+    /// highlighted device in input or output mode, else the default output.
+    VolumePreset(u8),
+    /// Mute or unmute the active device of a channel.
+    SetMute(Channel, bool),
+    /// Apply the typed volume.
+    VolumeEntrySubmit,
+    /// Run the query typed on the property screen.
+    PropertyRun,
+    /// Move the highlighted device's left or right stereo channel to the
+    /// next output channel, in detail mode.
+    CycleStereoLeft,
+    CycleStereoRight,
+    /// Move both stereo channels to the next pair of outputs.
+    NextStereoPair,
+    /// Move through the apps list in apps mode.
+    AppNext,
+    AppPrev,
+    /// Change the highlighted app's volume, in apps mode.
+    AppVolumeUp,
+    AppVolumeDown,
+    AppMute,
+    /// Move through an aggregate device's members in detail mode.
+    MemberNext,
+    MemberPrev,
+    /// Add or remove the highlighted member of an aggregate device.
+    ToggleMember,
+    ToggleDrift,
+    /// Mirror the highlighted output's volume with the default output, or
+    /// unlink it, in detail mode.
+    ToggleMirror,
+    /// Take or give back exclusive access to the highlighted device, in
+    /// detail mode.
+    ToggleHog,
+    /// Beep on the highlighted output, see `tone`.
+    PlayTone,
     SelectNext,
     SelectPrev,
-    VolumeUp,
-    VolumeDown,
+    /// Step the volume of the channel being edited, a quarter step when
+    /// fine.
+    VolumeUp {
+        fine: bool,
+    },
+    VolumeDown {
+        fine: bool,
+    },
     ToggleMute,
+    ToggleInputMute,
+    /// Pause or resume whatever is playing, like the play key.
+    PlayPause,
+    /// Turn the default output up for a while, see `AppState::boost`.
+    Boost,
+    /// Play on every output at once, or go back, see
+    /// `AudioState::toggle_broadcast`.
+    Broadcast,
+    /// Built-in keyboard backlight, see `system`.
+    BacklightUp,
+    BacklightDown,
+    /// Turn the displays off, leaving the Mac running.
+    DisplaySleep,
+    LockScreen,
+    /// Open the system mic mode picker, see `micmode`.
+    MicModes,
+    /// Alert and sound effect volume, see `alerts`.
+    AlertVolumeUp,
+    AlertVolumeDown,
+    /// Play alerts on a device, by UID.
+    SetAlertDevice(String),
+    /// Start or stop recording the output, see `recorder`.
+    RecordOutput,
+    /// Make the default output audible, see `AudioState::fix_silent`.
+    FixSilent,
+    /// Run the default input and output at one sample rate.
+    AlignSampleRates,
+    CycleMutePolicy,
+    /// Switch the highlighted device to its next sample rate.
+    CycleSampleRate,
+    /// Switch the highlighted device to its next data source, like
+    /// headphones instead of speakers, or its next clock source.
+    CycleDataSource,
+    CycleClockSource,
+    /// Switch the arrows between scalar and dB volume steps.
+    ToggleDbSteps,
+    /// Pan the output in edit mode, a step or back to the center.
+    BalanceLeft,
+    BalanceRight,
+    CenterBalance,
+    ToggleGroup,
+    ToggleMark,
+    HideMarked,
+    /// Leave a device out of the list by UID, from `[rules]`.
+    HideDevice(String),
+    CursorNext,
+    CursorPrev,
+    /// Move the highlight a page of the list.
+    CursorPageNext,
+    CursorPagePrev,
+    /// The terminal changed size.
+    Resize,
+    ToggleKeyRouting,
+    ToggleSwallow,
+    /// A hardware volume key, fine with option and shift held like the
+    /// system's quarter steps.
+    MediaKey {
+        key: MediaKey,
+        repeating: bool,
+        fine: bool,
+    },
+    Calibrate,
+    ApplyGain,
+    ToggleDoctor,
+    ToggleHistory,
+    Meter {
+        rms: f32,
+        peak: f32,
+    },
+    PropertyChanged {
+        id: u32,
+        selector: u32,
+    },
+    /// A device was plugged in or paired, by label.
+    DeviceAdded(String),
+    DeviceRemoved(String),
     Poll,
     Exit,
 }
 
+impl Action {
+    /// Text form of user-performed actions, used for session recordings.
+    /// Observed events (keys, meter levels, polls) aren't recorded.
+    pub fn to_record(&self) -> Option<String> {
+        let record = match self {
+            Action::ModeSwitch(mode) => return Some(format!("mode {}", mode.as_str())),
+            Action::ModeBack => "mode back",
+            Action::ApplyProfile(name) => return Some(format!("profile {name}")),
+            Action::SetDefault(Channel::Input, uid) => return Some(format!("default input {uid}")),
+            Action::SetDefault(Channel::Output, uid) => {
+                return Some(format!("default output {uid}"))
+            }
+            Action::HideDevice(uid) => return Some(format!("hide {uid}")),
+            Action::SetAlertDevice(uid) => return Some(format!("alert-device {uid}")),
+            Action::SetVolume(Channel::Input, percent) => {
+                return Some(format!("volume input {percent}"))
+            }
+            Action::SetVolume(Channel::Output, percent) => {
+                return Some(format!("volume output {percent}"))
+            }
+            Action::VolumePreset(slot) => return Some(format!("preset {slot}")),
+            Action::SetMute(channel, muted) => {
+                let channel = match channel {
+                    Channel::Input => "input",
+                    Channel::Output => "output",
+                };
+                let state = if *muted { "on" } else { "off" };
+                return Some(format!("mute {channel} {state}"));
+            }
+            Action::SelectNext => "select-next",
+            Action::SelectPrev => "select-prev",
+            Action::VolumeUp { fine: false } => "volume-up",
+            Action::VolumeUp { fine: true } => "volume-up fine",
+            Action::VolumeDown { fine: false } => "volume-down",
+            Action::VolumeDown { fine: true } => "volume-down fine",
+            Action::ToggleMute => "toggle-mute",
+            Action::ToggleInputMute => "mute-input",
+            Action::PlayPause => "play-pause",
+            Action::Boost => "boost",
+            Action::Broadcast => "broadcast",
+            Action::BacklightUp => "backlight-up",
+            Action::BacklightDown => "backlight-down",
+            Action::DisplaySleep => "display-sleep",
+            Action::LockScreen => "lock-screen",
+            Action::RecordOutput => "record-output",
+            Action::AlertVolumeUp => "alert-volume-up",
+            Action::AlertVolumeDown => "alert-volume-down",
+            Action::MicModes => "mic-modes",
+            Action::FixSilent => "fix-silent",
+            Action::AlignSampleRates => "align-sample-rates",
+            Action::CycleMutePolicy => "cycle-mute-policy",
+            Action::CycleSampleRate => "cycle-sample-rate",
+            Action::CycleDataSource => "cycle-data-source",
+            Action::CycleClockSource => "cycle-clock-source",
+            Action::ToggleDbSteps => "toggle-db-steps",
+            Action::BalanceLeft => "balance-left",
+            Action::BalanceRight => "balance-right",
+            Action::CenterBalance => "balance-center",
+            Action::ToggleGroup => "toggle-group",
+            Action::ToggleMark => "toggle-mark",
+            Action::HideMarked => "hide-marked",
+            Action::CursorNext => "cursor-next",
+            Action::CursorPrev => "cursor-prev",
+            Action::CursorPageNext => "cursor-page-next",
+            Action::CursorPagePrev => "cursor-page-prev",
+            Action::ToggleKeyRouting => "toggle-key-routing",
+            Action::ToggleSwallow => "toggle-swallow",
+            Action::MediaKey {
+                key: MediaKey::VolumeUp,
+                ..
+            } => "media volume-up",
+            Action::MediaKey {
+                key: MediaKey::VolumeDown,
+                ..
+            } => "media volume-down",
+            Action::MediaKey {
+                key: MediaKey::Mute,
+                ..
+            } => "media mute",
+            Action::Calibrate => "calibrate",
+            Action::ApplyGain => "apply-gain",
+            _ => return None,
+        };
+        Some(record.to_string())
+    }
+
+    /// Whether the action changes devices, settings, files or the system,
+    /// as opposed to only the view. These are refused in read-only mode.
+    pub fn is_mutation(&self) -> bool {
+        matches!(
+            self,
+            Action::SelectNext
+                | Action::SelectPrev
+                | Action::VolumeUp { .. }
+                | Action::VolumeDown { .. }
+                | Action::ToggleMute
+                | Action::ToggleInputMute
+                | Action::PlayPause
+                | Action::Boost
+                | Action::Broadcast
+                | Action::BacklightUp
+                | Action::BacklightDown
+                | Action::FixSilent
+                | Action::AlignSampleRates
+                | Action::CycleMutePolicy
+                | Action::CycleSampleRate
+                | Action::CycleDataSource
+                | Action::CycleClockSource
+                | Action::BalanceLeft
+                | Action::BalanceRight
+                | Action::CenterBalance
+                | Action::ToggleGroup
+                | Action::HideMarked
+                | Action::HideDevice(_)
+                | Action::SetAlertDevice(_)
+                | Action::AlertVolumeUp
+                | Action::AlertVolumeDown
+                | Action::ToggleKeyRouting
+                | Action::MediaKey { .. }
+                | Action::ApplyGain
+                | Action::SetDefault(..)
+                | Action::ApplyProfile(_)
+                | Action::SetVolume(..)
+                | Action::VolumePreset(_)
+                | Action::CycleStereoLeft
+                | Action::CycleStereoRight
+                | Action::NextStereoPair
+                | Action::AppVolumeUp
+                | Action::AppVolumeDown
+                | Action::AppMute
+                | Action::SetMute(..)
+                | Action::ToggleMember
+                | Action::ToggleDrift
+                | Action::ToggleMirror
+                | Action::ToggleHog
+                | Action::RecordOutput
+                | Action::RenameSave
+                | Action::ProfileSave
+                | Action::DisplaySleep
+                | Action::LockScreen
+                | Action::PropertyRun
+        )
+    }
+
+    /// Parse an action written by `to_record`.
+    pub fn from_record(record: &str) -> Option<Action> {
+        let record = record.trim();
+        if let Some(mode) = record.strip_prefix("mode ").filter(|m| *m != "back") {
+            return UiMode::parse(mode).map(Action::ModeSwitch);
+        }
+        if let Some(name) = record.strip_prefix("profile ") {
+            return Some(Action::ApplyProfile(name.to_string()));
+        }
+        if let Some(uid) = record.strip_prefix("default input ") {
+            return Some(Action::SetDefault(Channel::Input, uid.to_string()));
+        }
+        if let Some(uid) = record.strip_prefix("default output ") {
+            return Some(Action::SetDefault(Channel::Output, uid.to_string()));
+        }
+        if let Some(percent) = record.strip_prefix("volume input ") {
+            let percent = percent.parse().ok().filter(|p| *p <= 100)?;
+            return Some(Action::SetVolume(Channel::Input, percent));
+        }
+        if let Some(percent) = record.strip_prefix("volume output ") {
+            let percent = percent.parse().ok().filter(|p| *p <= 100)?;
+            return Some(Action::SetVolume(Channel::Output, percent));
+        }
+        if let Some(uid) = record.strip_prefix("hide ") {
+            return Some(Action::HideDevice(uid.to_string()));
+        }
+        if let Some(uid) = record.strip_prefix("alert-device ") {
+            return Some(Action::SetAlertDevice(uid.to_string()));
+        }
+        if let Some(slot) = record.strip_prefix("preset ") {
+            let slot = slot.parse().ok().filter(|s| *s >= 1)?;
+            return Some(Action::VolumePreset(slot));
+        }
+        if let Some(mute) = record.strip_prefix("mute ") {
+            let (channel, state) = mute.split_once(' ')?;
+            let channel = match channel {
+                "input" => Channel::Input,
+                "output" => Channel::Output,
+                _ => return None,
+            };
+            let muted = match state {
+                "on" => true,
+                "off" => false,
+                _ => return None,
+            };
+            return Some(Action::SetMute(channel, muted));
+        }
+        let action = match record {
+            "mode back" => Action::ModeBack,
+            "select-next" => Action::SelectNext,
+            "select-prev" => Action::SelectPrev,
+            "volume-up" => Action::VolumeUp { fine: false },
+            "volume-up fine" => Action::VolumeUp { fine: true },
+            "volume-down" => Action::VolumeDown { fine: false },
+            "volume-down fine" => Action::VolumeDown { fine: true },
+            "toggle-mute" => Action::ToggleMute,
+            "mute-input" => Action::ToggleInputMute,
+            "play-pause" => Action::PlayPause,
+            "boost" => Action::Boost,
+            "broadcast" => Action::Broadcast,
+            "backlight-up" => Action::BacklightUp,
+            "backlight-down" => Action::BacklightDown,
+            "display-sleep" => Action::DisplaySleep,
+            "lock-screen" => Action::LockScreen,
+            "record-output" => Action::RecordOutput,
+            "alert-volume-up" => Action::AlertVolumeUp,
+            "alert-volume-down" => Action::AlertVolumeDown,
+            "mic-modes" => Action::MicModes,
+            "fix-silent" => Action::FixSilent,
+            "align-sample-rates" => Action::AlignSampleRates,
+            "cycle-mute-policy" => Action::CycleMutePolicy,
+            "cycle-sample-rate" => Action::CycleSampleRate,
+            "cycle-data-source" => Action::CycleDataSource,
+            "cycle-clock-source" => Action::CycleClockSource,
+            "toggle-db-steps" => Action::ToggleDbSteps,
+            "balance-left" => Action::BalanceLeft,
+            "balance-right" => Action::BalanceRight,
+            "balance-center" => Action::CenterBalance,
+            "toggle-group" => Action::ToggleGroup,
+            "toggle-mark" => Action::ToggleMark,
+            "hide-marked" => Action::HideMarked,
+            "cursor-next" => Action::CursorNext,
+            "cursor-prev" => Action::CursorPrev,
+            "cursor-page-next" => Action::CursorPageNext,
+            "cursor-page-prev" => Action::CursorPagePrev,
+            "toggle-key-routing" => Action::ToggleKeyRouting,
+            "toggle-swallow" => Action::ToggleSwallow,
+            "media volume-up" => Action::MediaKey {
+                key: MediaKey::VolumeUp,
+                repeating: false,
+                fine: false,
+            },
+            "media volume-down" => Action::MediaKey {
+                key: MediaKey::VolumeDown,
+                repeating: false,
+                fine: false,
+            },
+            "media mute" => Action::MediaKey {
+                key: MediaKey::Mute,
+                repeating: false,
+                fine: false,
+            },
+            "calibrate" => Action::Calibrate,
+            "apply-gain" => Action::ApplyGain,
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+/// Mouse and trackpad input shown like keys.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pointer {
+    /// Button numbered from 1: left, right, middle, then extra ones.
+    Click(i64),
+    Scroll(ScrollDirection),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Pointer {
+    /// Name in chords, like "right click" or "scroll down".
+    pub fn name(&self) -> String {
+        match self {
+            Pointer::Click(1) => "left click".to_string(),
+            Pointer::Click(2) => "right click".to_string(),
+            Pointer::Click(3) => "middle click".to_string(),
+            Pointer::Click(button) => format!("button{button} click"),
+            Pointer::Scroll(ScrollDirection::Up) => "scroll up".to_string(),
+            Pointer::Scroll(ScrollDirection::Down) => "scroll down".to_string(),
+            Pointer::Scroll(ScrollDirection::Left) => "scroll left".to_string(),
+            Pointer::Scroll(ScrollDirection::Right) => "scroll right".to_string(),
+        }
+    }
+}
+
+/// Hardware volume keys, see `mediakeys`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MediaKey {
+    VolumeUp,
+    VolumeDown,
+    Mute,
+}
+
+/// Holding a volume key repeats it. Repeats in the first second take small
+/// steps for fine control, after that full steps.
+#[derive(Debug, Default)]
+pub struct KeyRepeat {
+    /// Held key and when it was first pressed.
+    held: Option<(i64, Instant)>,
+    repeating: bool,
+}
+
+/// How long repeats keep the small step.
+const FINE_REPEAT: Duration = Duration::from_secs(1);
+
+/// Steps in a normal one for fine steps and early repeats, like the
+/// system's quarter steps.
+pub const FINE_STEPS: f32 = 4.0;
+
+impl KeyRepeat {
+    pub fn press(&mut self, key: i64, repeating: bool) {
+        if !repeating || !matches!(self.held, Some((held, _)) if held == key) {
+            self.held = Some((key, Instant::now()));
+        }
+        self.repeating = repeating;
+    }
+
+    pub fn release(&mut self, key: i64) {
+        if matches!(self.held, Some((held, _)) if held == key) {
+            self.held = None;
+            self.repeating = false;
+        }
+    }
+
+    /// Volume step for the current press of a key, given the normal step.
+    pub fn step(&self, key: i64, step: f32) -> f32 {
+        match self.held {
+            Some((held, since)) if held == key && self.repeating => {
+                if since.elapsed() < FINE_REPEAT {
+                    step / FINE_STEPS
+                } else {
+                    step
+                }
+            }
+            _ => step,
+        }
+    }
+}
+
+/// Modifier keys by config name, with their device dependent event flag,
+/// which tells left and right apart.
+const MODIFIER_KEYS: &[(&str, u64)] = &[
+    ("left-control", 0x1),
+    ("left-shift", 0x2),
+    ("right-shift", 0x4),
+    ("left-command", 0x8),
+    ("right-command", 0x10),
+    ("left-option", 0x20),
+    ("right-option", 0x40),
+    ("right-control", 0x2000),
+    ("fn", 0x800000),
+];
+
+/// Modifier keys pressed and released together, within this time, make a
+/// tap. Longer holds are left alone.
+const MODIFIER_TAP_TIME: Duration = Duration::from_millis(500);
+
+/// Flags of a modifier combo like "left-shift+right-shift", or the name
+/// that isn't a modifier key.
+pub fn parse_modifier_combo(combo: &str) -> Result<u64, ControlError> {
+    combo.split('+').try_fold(0, |flags, name| {
+        let (_, flag) = MODIFIER_KEYS
+            .iter()
+            .find(|(n, _)| *n == name.trim())
+            .ok_or_else(|| {
+                ControlError::Invalid(format!("unknown modifier key \"{}\"", name.trim()))
+            })?;
+        Ok(flags | flag)
+    })
+}
+
+/// Spots modifier keys tapped on their own, like Fn pressed and released
+/// with no other key in between, for modifier-only bindings.
+#[derive(Debug, Default)]
+pub struct ModifierTap {
+    /// Every modifier held since the first press, and when that was.
+    pressed: Option<(u64, Instant)>,
+    /// Another key was pressed meanwhile, so this is a shortcut.
+    spoiled: bool,
+}
+
+impl ModifierTap {
+    /// Take the held modifier keys after a change. Returns the combo once
+    /// they're all released, if it was a tap.
+    pub fn update(&mut self, held: u64) -> Option<u64> {
+        if held == 0 {
+            let (combo, since) = self.pressed.take()?;
+            let spoiled = std::mem::take(&mut self.spoiled);
+            return (!spoiled && since.elapsed() < MODIFIER_TAP_TIME).then_some(combo);
+        }
+        match &mut self.pressed {
+            Some((combo, _)) => *combo |= held,
+            None => self.pressed = Some((held, Instant::now())),
+        }
+        None
+    }
+
+    /// Another key was pressed.
+    pub fn spoil(&mut self) {
+        if self.pressed.is_some() {
+            self.spoiled = true;
+        }
+    }
+}
+
+/// A chord like "cmd+option+up" that works whatever app is in front. The
+/// key is named as in `KeyboardLayout::key_name`, so letters follow the
+/// layout.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hotkey {
+    key: String,
+    command: bool,
+    option: bool,
+    control: bool,
+    shift: bool,
+    func: bool,
+}
+
+impl Hotkey {
+    /// The chord, or which part of it is wrong.
+    pub fn parse(chord: &str) -> Result<Self, ControlError> {
+        let invalid = |message: String| ControlError::Invalid(message);
+        let mut parts: Vec<String> = chord.split('+').map(|p| p.trim().to_lowercase()).collect();
+        let key = parts
+            .pop()
+            .filter(|k| !k.is_empty())
+            .ok_or_else(|| invalid("no key after the modifiers".to_string()))?;
+        if !layout::is_key_name(&key) {
+            return Err(invalid(format!("unknown key \"{key}\"")));
+        }
+        let mut hotkey = Hotkey {
+            key,
+            ..Default::default()
+        };
+        for part in parts {
+            let held = match part.as_str() {
+                "cmd" | "command" => &mut hotkey.command,
+                "opt" | "option" | "alt" => &mut hotkey.option,
+                "ctrl" | "control" => &mut hotkey.control,
+                "shift" => &mut hotkey.shift,
+                "fn" => &mut hotkey.func,
+                _ => return Err(invalid(format!("unknown modifier \"{part}\""))),
+            };
+            *held = true;
+        }
+        Ok(hotkey)
+    }
+
+    /// Fn only counts when the chord has it, macOS sets it for arrow and
+    /// function keys.
+    pub fn matches(&self, key_name: &str, modifiers: &ModifierKeys) -> bool {
+        self.key == key_name.to_lowercase()
+            && self.command == modifiers.command
+            && self.option == modifiers.option
+            && self.control == modifiers.control
+            && self.shift == modifiers.shift
+            && (!self.func || modifiers.func)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct ModifierKeys {
     pub caps_lock: bool,
@@ -39,6 +694,8 @@ pub struct ModifierKeys {
     pub option: bool,
     pub command: bool,
     pub func: bool,
+    /// Held modifier keys as `MODIFIER_KEYS` flags.
+    pub held: u64,
 }
 
 impl ModifierKeys {
@@ -71,32 +728,429 @@ pub enum UiMode {
     View,
     EditInput,
     EditOutput,
+    /// Typing filters the device list.
+    Search,
+    /// Everything about the highlighted device.
+    Detail,
+    /// Fuzzy search over every action.
+    Palette,
+    /// Naming a just recorded macro.
+    MacroName,
+    /// Typing an exact volume for the channel being edited.
+    Volume,
+    /// Picking a saved profile, or naming one for the current setup.
+    Profiles,
+    /// Raw CoreAudio properties of the highlighted device.
+    Property,
+    /// Per-app volumes.
+    Apps,
+    /// Keyboard backlight, display sleep and screen lock.
+    System,
+    /// The device alerts play on, and their volume.
+    Alert,
+    /// Typing a new name for the highlighted device.
+    Rename,
 }
 
-#[repr(C)]
-enum IOHIDRequestType {
-    IOHIDRequestTypePostEvent,
-    IOHIDRequestTypeListenEvent,
+impl UiMode {
+    pub const ALL: [UiMode; 14] = [
+        UiMode::View,
+        UiMode::EditInput,
+        UiMode::EditOutput,
+        UiMode::Search,
+        UiMode::Detail,
+        UiMode::Palette,
+        UiMode::MacroName,
+        UiMode::Volume,
+        UiMode::Profiles,
+        UiMode::Property,
+        UiMode::Apps,
+        UiMode::System,
+        UiMode::Alert,
+        UiMode::Rename,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Name used in recordings and keymap config sections.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UiMode::View => "view",
+            UiMode::EditInput => "input",
+            UiMode::EditOutput => "output",
+            UiMode::Search => "search",
+            UiMode::Detail => "detail",
+            UiMode::Palette => "palette",
+            UiMode::MacroName => "macro-name",
+            UiMode::Volume => "volume",
+            UiMode::Profiles => "profiles",
+            UiMode::Property => "property",
+            UiMode::Apps => "apps",
+            UiMode::System => "system",
+            UiMode::Alert => "alert",
+            UiMode::Rename => "rename",
+        }
+    }
+
+    /// Modes where typing edits a query instead of running keys.
+    pub fn takes_text(self) -> bool {
+        matches!(
+            self,
+            UiMode::Search
+                | UiMode::Palette
+                | UiMode::MacroName
+                | UiMode::Volume
+                | UiMode::Profiles
+                | UiMode::Property
+                | UiMode::Rename
+        )
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        UiMode::ALL.into_iter().find(|mode| mode.as_str() == name)
+    }
 }
 
+/// Caps Lock, for IOHIDSetModifierLockState
+const CAPS_LOCK_STATE: i32 = 1;
+/// kIOHIDParamConnectType
+const PARAM_CONNECT_TYPE: u32 = 1;
+
 extern "C" {
-    fn IOHIDRequestAccess(requestType: IOHIDRequestType) -> bool;
+    static mach_task_self_: u32;
+    fn IOServiceMatching(name: *const std::os::raw::c_char) -> *mut std::os::raw::c_void;
+    fn IOServiceGetMatchingService(main_port: u32, matching: *mut std::os::raw::c_void) -> u32;
+    fn IOServiceOpen(service: u32, owning_task: u32, connect_type: u32, connect: *mut u32) -> i32;
+    fn IOServiceClose(connect: u32) -> i32;
+    fn IOObjectRelease(object: u32) -> i32;
+    fn IOHIDSetModifierLockState(connect: u32, selector: i32, state: bool) -> i32;
 }
 
-/// Request accessibility and input monitoring permissions from macOS
-pub fn request_accessibility_access() -> bool {
+/// Turn Caps Lock (and its light) on or off.
+pub fn set_caps_lock(enabled: bool) -> Result<(), ControlError> {
     unsafe {
-        let has_access = IOHIDRequestAccess(IOHIDRequestType::IOHIDRequestTypeListenEvent);
-        let has_input = IOHIDRequestAccess(IOHIDRequestType::IOHIDRequestTypePostEvent);
-        has_access && has_input
+        let service = IOServiceGetMatchingService(0, IOServiceMatching(c"IOHIDSystem".as_ptr()));
+        if service == 0 {
+            return Err("IOHIDSystem not found.".into());
+        }
+        let mut connect = 0;
+        let status = IOServiceOpen(service, mach_task_self_, PARAM_CONNECT_TYPE, &mut connect);
+        IOObjectRelease(service);
+        if status != 0 {
+            return Err(format!("Failed to open IOHIDSystem ({status}).").into());
+        }
+        let status = IOHIDSetModifierLockState(connect, CAPS_LOCK_STATE, enabled);
+        IOServiceClose(connect);
+        match status {
+            0 => Ok(()),
+            _ => Err(format!("Failed to set Caps Lock ({status}).").into()),
+        }
+    }
+}
+
+/// What macOS lets the event taps do, see `permission_status`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PermissionStatus {
+    /// Input Monitoring, to see key events at all.
+    pub listen: Status,
+    /// Accessibility, to swallow key events and take the volume keys.
+    pub post: Status,
+}
+
+impl PermissionStatus {
+    pub fn can_listen(&self) -> bool {
+        self.listen == Status::Granted
+    }
+
+    pub fn can_post(&self) -> bool {
+        self.post == Status::Granted
+    }
+}
+
+/// Key event permissions, each granted, denied or undetermined (`Unknown`,
+/// not asked yet). Checking never shows a prompt.
+pub fn permission_status() -> PermissionStatus {
+    PermissionStatus {
+        listen: Permission::InputMonitoring.status(),
+        post: Permission::Accessibility.status(),
+    }
+}
+
+/// Seconds between run loop heartbeats of the tap thread.
+const HEARTBEAT: f64 = 1.0;
+/// A heartbeat older than this means the run loop is stuck.
+const STALLED_AFTER: Duration = Duration::from_secs(3);
+/// Minimum time between restarts, so a tap that can't be created (e.g. a
+/// revoked permission) isn't retried on every poll.
+const RESTART_BACKOFF: Duration = Duration::from_secs(5);
+/// How long the status line mentions that macOS turned the tap off.
+pub const DISABLED_NOTICE: Duration = Duration::from_secs(30);
+
+extern "C" {
+    fn CGEventTapEnable(tap: CFMachPortRef, enable: bool);
+    fn CGEventTapIsEnabled(tap: CFMachPortRef) -> bool;
+}
+
+/// Health of the event tap thread, checked by the watchdog in the main loop.
+/// Without it a dead tap looks the same as no keys being pressed.
+#[derive(Debug, Default)]
+pub struct TapHealth {
+    running: AtomicBool,
+    /// Whether the tap is active (can swallow events) or listen-only.
+    pub active: AtomicBool,
+    /// The tap's mach port, 0 while there is none.
+    port: AtomicUsize,
+    /// The tap thread's run loop, 0 while there is none.
+    run_loop: AtomicUsize,
+    heartbeat: Mutex<Option<Instant>>,
+    started: Mutex<Option<Instant>>,
+    /// Times the thread was restarted after dying.
+    pub restarts: AtomicU32,
+    /// Times macOS disabled the tap and it was turned back on.
+    pub reenables: AtomicU32,
+    /// Why macOS last disabled the tap, and when.
+    last_disabled: Mutex<Option<(&'static str, Instant)>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapStatus {
+    Running,
+    /// macOS turned the tap off, e.g. after a slow callback.
+    Disabled,
+    /// The thread is there, but its run loop stopped beating.
+    Stalled,
+    Dead,
+}
+
+impl TapStatus {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TapStatus::Running => "running",
+            TapStatus::Disabled => "disabled",
+            TapStatus::Stalled => "stalled",
+            TapStatus::Dead => "dead",
+        }
+    }
+}
+
+impl TapHealth {
+    /// Record a (re)start of the tap thread, call before spawning it.
+    pub fn start(&self) {
+        let mut started = self.started.lock().unwrap();
+        if started.is_some() {
+            self.restarts.fetch_add(1, Ordering::Relaxed);
+        }
+        *started = Some(Instant::now());
+        *self.heartbeat.lock().unwrap() = Some(Instant::now());
+        self.running.store(true, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> TapStatus {
+        if !self.running.load(Ordering::Relaxed) {
+            return TapStatus::Dead;
+        }
+        let port = self.port.load(Ordering::Relaxed);
+        if port != 0 && unsafe { !CGEventTapIsEnabled(port as CFMachPortRef) } {
+            return TapStatus::Disabled;
+        }
+        match *self.heartbeat.lock().unwrap() {
+            Some(beat) if beat.elapsed() > STALLED_AFTER => TapStatus::Stalled,
+            _ => TapStatus::Running,
+        }
+    }
+
+    /// Whether the thread died and is due for a restart.
+    pub fn should_restart(&self) -> bool {
+        self.status() == TapStatus::Dead
+            && !matches!(*self.started.lock().unwrap(), Some(t) if t.elapsed() < RESTART_BACKOFF)
+    }
+
+    /// Turn the tap back on after macOS disabled it, `reason` being what
+    /// the status line and doctor say about it.
+    pub fn reenable(&self, reason: &'static str) {
+        let port = self.port.load(Ordering::Relaxed);
+        if port != 0 {
+            unsafe { CGEventTapEnable(port as CFMachPortRef, true) };
+            self.reenables.fetch_add(1, Ordering::Relaxed);
+            log::warn(format!(
+                "Event tap disabled by macOS ({reason}), re-enabled"
+            ));
+            *self.last_disabled.lock().unwrap() = Some((reason, Instant::now()));
+        }
+    }
+
+    pub fn last_disabled(&self) -> Option<(&'static str, Instant)> {
+        *self.last_disabled.lock().unwrap()
+    }
+
+    fn beat(&self) {
+        *self.heartbeat.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// Marks the tap dead when the thread leaves `event_tap`, panics included.
+struct TapGuard(Arc<TapHealth>);
+
+impl Drop for TapGuard {
+    fn drop(&mut self) {
+        self.0.port.store(0, Ordering::Relaxed);
+        self.0.run_loop.store(0, Ordering::Relaxed);
+        self.0.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// What the event tap has to do beyond listening, shared with the tap
+/// thread. Only an active tap can swallow events and active taps cost more,
+/// so the tap stays listen-only until something needs swallowing.
+#[derive(Debug, Default)]
+pub struct TapControl {
+    /// (keyboard type, key code) of keys to swallow.
+    swallow: Mutex<Vec<(i64, i64)>>,
+    /// Asks the tap thread to leave, see `EventTap::stop`.
+    stop: AtomicBool,
+    /// Also tap clicks and scrolls, taken when the tap is created.
+    mouse: AtomicBool,
+    /// Key codes typed as another key, or swallowed for an action when
+    /// `None`, see `[remap]`.
+    remap: Mutex<Vec<(i64, Option<i64>)>>,
+}
+
+impl TapControl {
+    /// Keys to keep from reaching other apps. The tap switches between
+    /// active and listen-only within a heartbeat.
+    pub fn set_swallowed(&self, keys: Vec<(i64, i64)>) {
+        *self.swallow.lock().unwrap() = keys;
+    }
+
+    /// Keys to change on every keyboard, active like swallowing.
+    pub fn set_remapped(&self, keys: Vec<(i64, Option<i64>)>) {
+        *self.remap.lock().unwrap() = keys;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_swallowing() || !self.remap.lock().unwrap().is_empty()
+    }
+
+    /// Whether bound keys are kept from other apps.
+    pub fn is_swallowing(&self) -> bool {
+        !self.swallow.lock().unwrap().is_empty()
+    }
+
+    /// Send `Action::Pointer` for clicks and scrolls, from the next tap
+    /// restart.
+    pub fn set_mouse(&self, mouse: bool) {
+        self.mouse.store(mouse, Ordering::Relaxed);
+    }
+
+    fn swallows(&self, keyboard: i64, key_code: i64) -> bool {
+        self.swallow.lock().unwrap().contains(&(keyboard, key_code))
+    }
+
+    fn remapped(&self, key_code: i64) -> Option<Option<i64>> {
+        let remap = self.remap.lock().unwrap();
+        remap
+            .iter()
+            .find(|(from, _)| *from == key_code)
+            .map(|(_, to)| *to)
+    }
+}
+
+/// Timer context of a running tap.
+struct TapTimer {
+    health: Arc<TapHealth>,
+    control: Arc<TapControl>,
+    active: bool,
+}
+
+extern "C" fn on_heartbeat(_timer: CFRunLoopTimerRef, info: *mut c_void) {
+    let timer = unsafe { &*(info as *const TapTimer) };
+    timer.health.beat();
+    if timer.control.stop.load(Ordering::Relaxed) || timer.control.is_active() != timer.active {
+        // Leave the run loop so the tap is recreated in the other mode
+        CFRunLoop::get_current().stop();
     }
 }
 
-pub fn event_tap<F>(handler: F) -> Result<(), String>
+/// Handler for tap events, kept for restarts.
+type TapHandler = Arc<dyn Fn(Action) + Send + Sync>;
+
+/// The event tap thread, which taps into OS key events (no focus required)
+/// until stopped.
+pub struct EventTap {
+    health: Arc<TapHealth>,
+    control: Arc<TapControl>,
+    handler: TapHandler,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EventTap {
+    pub fn start<F>(health: Arc<TapHealth>, control: Arc<TapControl>, handler: F) -> Self
+    where
+        F: Fn(Action) + Send + Sync + 'static,
+    {
+        let mut tap = EventTap {
+            health,
+            control,
+            handler: Arc::new(handler),
+            thread: None,
+        };
+        tap.spawn();
+        tap
+    }
+
+    fn spawn(&mut self) {
+        self.health.start();
+        let health = self.health.clone();
+        let control = self.control.clone();
+        let handler = self.handler.clone();
+        self.thread = Some(thread::spawn(move || {
+            if let Err(e) = event_tap(health, control, |action| handler(action)) {
+                log::error(e);
+            }
+        }));
+    }
+
+    /// End the tap and wait for its thread. A stalled run loop can't be
+    /// stopped, so its thread is left behind instead of blocking.
+    pub fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        if self.health.status() == TapStatus::Stalled {
+            return;
+        }
+        self.control.stop.store(true, Ordering::Relaxed);
+        let run_loop = self.health.run_loop.load(Ordering::Relaxed);
+        if run_loop != 0 {
+            // Otherwise the next heartbeat stops it
+            unsafe { CFRunLoopStop(run_loop as CFRunLoopRef) };
+        }
+        let _ = thread.join();
+        self.control.stop.store(false, Ordering::Relaxed);
+    }
+
+    /// Replace the tap thread with a new one, e.g. after it died.
+    pub fn restart(&mut self) {
+        self.stop();
+        self.spawn();
+    }
+}
+
+fn event_tap<F>(
+    health: Arc<TapHealth>,
+    control: Arc<TapControl>,
+    handler: F,
+) -> Result<(), ControlError>
 where
     F: Fn(Action),
 {
+    let _guard = TapGuard(health.clone());
     let curr_loop = CFRunLoop::get_current();
+    health
+        .run_loop
+        .store(curr_loop.as_concrete_TypeRef() as usize, Ordering::Relaxed);
 
     // Create an event to check state of flags, like caps lock, instead of
     // waiting for first modifier change.
@@ -108,49 +1162,176 @@ where
         }
     }
 
-    // Setup event tap listener
-    match CGEventTap::new(
-        CGEventTapLocation::HID,
-        CGEventTapPlacement::HeadInsertEventTap,
-        CGEventTapOptions::Default,
-        vec![
+    loop {
+        let active = control.is_active();
+        health.active.store(active, Ordering::Relaxed);
+
+        // Heartbeat, so the watchdog can tell the run loop is alive
+        let tap_timer = TapTimer {
+            health: health.clone(),
+            control: control.clone(),
+            active,
+        };
+        let mut context = CFRunLoopTimerContext {
+            version: 0,
+            info: &tap_timer as *const TapTimer as *mut c_void,
+            retain: None,
+            release: None,
+            copyDescription: None,
+        };
+        let now = CFDate::now().abs_time();
+        let timer = CFRunLoopTimer::new(now, HEARTBEAT, 0, 0, on_heartbeat, &mut context);
+        unsafe {
+            curr_loop.add_timer(&timer, kCFRunLoopCommonModes);
+        }
+
+        // Setup event tap listener
+        let options = match active {
+            true => CGEventTapOptions::Default,
+            false => CGEventTapOptions::ListenOnly,
+        };
+        let mut events = vec![
             CGEventType::KeyDown,
             CGEventType::KeyUp,
             CGEventType::FlagsChanged,
-        ],
-        |_, event_type, event| {
-            let key_code = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE);
-            let repeating =
-                event.get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT) > 0;
-            let flags = event.get_flags();
-            let modifiers = flags_to_modifiers(&flags);
-            match event_type {
-                CGEventType::KeyDown => handler(Action::KeyDown {
-                    key_code,
-                    modifiers,
-                    repeating,
-                }),
-                CGEventType::KeyUp => handler(Action::KeyUp {
-                    key_code,
-                    modifiers,
-                }),
-                CGEventType::FlagsChanged => handler(Action::Modifier { modifiers }),
-                _ => (),
-            }
-            None
-        },
-    ) {
-        Ok(tap) => unsafe {
-            let loop_source = tap
-                .mach_port
-                .create_runloop_source(0)
-                .expect("Connect to run loop.");
-            curr_loop.add_source(&loop_source, kCFRunLoopCommonModes);
-            tap.enable();
-            CFRunLoop::run_current();
-            Ok(())
-        },
-        Err(_) => Err("Failed to create event tap.".to_string()),
+            CGEventType::OtherMouseDown,
+        ];
+        let mouse = control.mouse.load(Ordering::Relaxed);
+        if mouse {
+            events.extend([
+                CGEventType::LeftMouseDown,
+                CGEventType::RightMouseDown,
+                CGEventType::ScrollWheel,
+            ]);
+        }
+        let tap = CGEventTap::new(
+            CGEventTapLocation::HID,
+            CGEventTapPlacement::HeadInsertEventTap,
+            options,
+            events,
+            |_, event_type, event| {
+                let key_code = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE);
+                let is_key = matches!(event_type, CGEventType::KeyDown | CGEventType::KeyUp);
+                let remap = match active && is_key {
+                    true => control.remapped(key_code),
+                    false => None,
+                };
+                // Apps and bindings see the new key
+                let (key_code, replacement) = match remap {
+                    Some(Some(to)) => (
+                        to,
+                        remapped_event(event, to, matches!(event_type, CGEventType::KeyDown)),
+                    ),
+                    _ => (key_code, None),
+                };
+                let repeating =
+                    event.get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT) > 0;
+                let keyboard =
+                    event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYBOARD_TYPE);
+                let flags = event.get_flags();
+                let modifiers = flags_to_modifiers(&flags);
+                match event_type {
+                    CGEventType::KeyDown => handler(Action::KeyDown {
+                        key_code,
+                        modifiers,
+                        repeating,
+                        keyboard,
+                    }),
+                    CGEventType::KeyUp => handler(Action::KeyUp {
+                        key_code,
+                        modifiers,
+                    }),
+                    CGEventType::FlagsChanged => handler(Action::Modifier { modifiers }),
+                    CGEventType::TapDisabledByTimeout => health.reenable("too slow to respond"),
+                    CGEventType::TapDisabledByUserInput => health.reenable("user input"),
+                    CGEventType::OtherMouseDown => handler(Action::MouseDown {
+                        button: event
+                            .get_integer_value_field(EventField::MOUSE_EVENT_BUTTON_NUMBER)
+                            + 1,
+                    }),
+                    _ => (),
+                }
+                if let Some(pointer) = mouse.then(|| pointer(event_type, event)).flatten() {
+                    handler(Action::Pointer {
+                        event: pointer,
+                        modifiers,
+                    });
+                }
+                let swallow = control.swallows(keyboard, key_code) || remap == Some(None);
+                if active && is_key && swallow {
+                    // A null event is dropped by the system
+                    let swallowed = event.clone();
+                    swallowed.set_type(CGEventType::Null);
+                    return Some(swallowed);
+                }
+                replacement
+            },
+        );
+        let result = match tap {
+            Ok(tap) => unsafe {
+                let Ok(loop_source) = tap.mach_port.create_runloop_source(0) else {
+                    return Err("Unable to connect the event tap to the run loop.".into());
+                };
+                curr_loop.add_source(&loop_source, kCFRunLoopCommonModes);
+                tap.enable();
+                health.port.store(
+                    tap.mach_port.as_concrete_TypeRef() as usize,
+                    Ordering::Relaxed,
+                );
+                CFRunLoop::run_current();
+                health.port.store(0, Ordering::Relaxed);
+                curr_loop.remove_source(&loop_source, kCFRunLoopCommonModes);
+                Ok(())
+            },
+            Err(_) => Err("Failed to create event tap.".into()),
+        };
+        unsafe {
+            curr_loop.remove_timer(&timer, kCFRunLoopCommonModes);
+        }
+        if result.is_err() || control.stop.load(Ordering::Relaxed) || control.is_active() == active
+        {
+            return result;
+        }
+    }
+}
+
+/// A key event like `event` with another key code, for `[remap]`. A new
+/// event rather than a changed one, so it types the new key's character.
+fn remapped_event(event: &CGEvent, key_code: i64, down: bool) -> Option<CGEvent> {
+    let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState).ok()?;
+    let remapped = CGEvent::new_keyboard_event(source, key_code as CGKeyCode, down).ok()?;
+    remapped.set_flags(event.get_flags());
+    remapped.set_integer_value_field(
+        EventField::KEYBOARD_EVENT_AUTOREPEAT,
+        event.get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT),
+    );
+    Some(remapped)
+}
+
+/// The click or scroll of a mouse event.
+fn pointer(event_type: CGEventType, event: &CGEvent) -> Option<Pointer> {
+    match event_type {
+        CGEventType::LeftMouseDown => Some(Pointer::Click(1)),
+        CGEventType::RightMouseDown => Some(Pointer::Click(2)),
+        CGEventType::OtherMouseDown => Some(Pointer::Click(
+            event.get_integer_value_field(EventField::MOUSE_EVENT_BUTTON_NUMBER) + 1,
+        )),
+        CGEventType::ScrollWheel => {
+            let vertical =
+                event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_1);
+            let horizontal =
+                event.get_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_2);
+            // Mostly one axis, the other is a trackpad's wobble
+            let direction = match vertical.abs() >= horizontal.abs() {
+                true if vertical > 0 => ScrollDirection::Up,
+                true if vertical < 0 => ScrollDirection::Down,
+                false if horizontal > 0 => ScrollDirection::Left,
+                false if horizontal < 0 => ScrollDirection::Right,
+                _ => return None,
+            };
+            Some(Pointer::Scroll(direction))
+        }
+        _ => None,
     }
 }
 
@@ -162,5 +1343,93 @@ fn flags_to_modifiers(flags: &CGEventFlags) -> ModifierKeys {
         option: flags.contains(CGEventFlags::CGEventFlagAlternate),
         command: flags.contains(CGEventFlags::CGEventFlagCommand),
         func: flags.contains(CGEventFlags::CGEventFlagSecondaryFn),
+        held: MODIFIER_KEYS
+            .iter()
+            .map(|(_, flag)| flag & flags.bits())
+            .fold(0, |held, flag| held | flag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotkey_chords() {
+        let hotkey = Hotkey::parse("Cmd + Option+Up").unwrap();
+        let held = ModifierKeys {
+            command: true,
+            option: true,
+            ..Default::default()
+        };
+        assert!(hotkey.matches("Up", &held));
+        assert!(!hotkey.matches("down", &held));
+        assert!(!hotkey.matches(
+            "up",
+            &ModifierKeys {
+                shift: true,
+                ..held
+            }
+        ));
+        // macOS sets fn for arrows, which doesn't count unless asked for
+        assert!(hotkey.matches("up", &ModifierKeys { func: true, ..held }));
+        let with_fn = Hotkey::parse("fn+cmd+option+up").unwrap();
+        assert!(!with_fn.matches("up", &held));
+
+        let error = |chord| Hotkey::parse(chord).unwrap_err().to_string();
+        assert_eq!(error("cmd+"), "no key after the modifiers");
+        assert_eq!(error("cmd+upp"), "unknown key \"upp\"");
+        assert_eq!(error("hyper+k"), "unknown modifier \"hyper\"");
+    }
+
+    #[test]
+    fn key_repeats_start_fine() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(1, false);
+        assert_eq!(repeat.step(1, 0.08), 0.08);
+        repeat.press(1, true);
+        assert_eq!(repeat.step(1, 0.08), 0.08 / FINE_STEPS);
+        // Only the held key repeats
+        assert_eq!(repeat.step(2, 0.08), 0.08);
+        repeat.release(2);
+        assert_eq!(repeat.step(1, 0.08), 0.08 / FINE_STEPS);
+        repeat.release(1);
+        assert_eq!(repeat.step(1, 0.08), 0.08);
+    }
+
+    #[test]
+    fn key_repeats_go_full_after_a_while() {
+        let mut repeat = KeyRepeat {
+            held: Some((1, Instant::now() - FINE_REPEAT)),
+            repeating: false,
+        };
+        repeat.press(1, true);
+        assert_eq!(repeat.step(1, 0.08), 0.08);
+    }
+
+    #[test]
+    fn modifier_taps() {
+        let combo = parse_modifier_combo("left-shift + right-shift").unwrap();
+        assert_eq!(combo, 0x6);
+        assert!(parse_modifier_combo("left-shift+hyper").is_err());
+
+        let mut tap = ModifierTap::default();
+        assert_eq!(tap.update(0x2), None);
+        assert_eq!(tap.update(0x6), None);
+        assert_eq!(tap.update(0x4), None);
+        assert_eq!(tap.update(0), Some(combo));
+        // Nothing held, nothing to tap
+        assert_eq!(tap.update(0), None);
+
+        // With another key between, it's a shortcut
+        tap.update(0x800000);
+        tap.spoil();
+        assert_eq!(tap.update(0), None);
+        tap.update(0x800000);
+        assert_eq!(tap.update(0), Some(0x800000));
+
+        // Held too long
+        tap.pressed = Some((0x8, Instant::now() - MODIFIER_TAP_TIME));
+        assert_eq!(tap.update(0), None);
     }
 }
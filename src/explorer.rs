@@ -0,0 +1,285 @@
+//! Raw CoreAudio properties, for debugging odd hardware: `mac-controls
+//! property get|set` and the property screen (`e` in detail mode).
+//! Queries are a selector followed by any of a scope, element and type:
+//!
+//! ```text
+//! vmvc output 1 f32
+//! lnam string
+//! nsrt f64 = 48000
+//! ```
+//!
+//! Selectors and scopes are four-char codes or numbers. Types are `f32`,
+//! `f64`, `u32`, `i32`, `fourcc`, `string` (a CFString) and `hex`, the
+//! default. Arrays are decoded element by element. `string` only decodes
+//! selectors known to hold a CFString, others show as hex, since any other
+//! bytes taken for a CFString would crash.
+
+use core_foundation::base::TCFType;
+use core_foundation::string::{CFString, CFStringRef};
+
+use crate::audio::{self, AudioState};
+use crate::config::Config;
+use crate::coreaudio::*;
+use crate::listener::fourcc;
+
+const USAGE: &str = "Usage: mac-controls property get <device> <selector> [scope] [element] [type]
+       mac-controls property set <device> <selector> [scope] [element] <type> <value>
+Devices are a UID, name, alias or `system`.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    F32,
+    F64,
+    U32,
+    I32,
+    Fourcc,
+    String,
+    Hex,
+}
+
+impl Kind {
+    const ALL: [Kind; 7] = [
+        Kind::F32,
+        Kind::F64,
+        Kind::U32,
+        Kind::I32,
+        Kind::Fourcc,
+        Kind::String,
+        Kind::Hex,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::F32 => "f32",
+            Kind::F64 => "f64",
+            Kind::U32 => "u32",
+            Kind::I32 => "i32",
+            Kind::Fourcc => "fourcc",
+            Kind::String => "string",
+            Kind::Hex => "hex",
+        }
+    }
+
+    /// Bytes per value, for splitting arrays.
+    fn size(self) -> usize {
+        match self {
+            Kind::F64 => 8,
+            Kind::String => std::mem::size_of::<CFStringRef>(),
+            Kind::Hex => 1,
+            _ => 4,
+        }
+    }
+}
+
+/// Selectors whose value is a CFString, like `lnam` and `uid `.
+const STRING_SELECTORS: [&str; 12] = [
+    "lnam", "lmak", "lmod", "lchn", "lccn", "lcnn", "uid ", "muid", "snum", "fwvn", "capp", "pbid",
+];
+
+/// A property address and how to decode it.
+#[derive(Debug, Clone, Copy)]
+pub struct Query {
+    selector: AudioObjectPropertySelector,
+    scope: AudioObjectPropertyScope,
+    element: AudioObjectPropertyElement,
+    kind: Kind,
+}
+
+impl Query {
+    pub fn parse(words: &[&str]) -> Result<Query, String> {
+        let (selector, rest) = words.split_first().ok_or("Missing a selector")?;
+        let mut query = Query {
+            selector: parse_code(selector)?,
+            scope: kAudioObjectPropertyScopeGlobal,
+            element: kAudioObjectPropertyElementMain,
+            kind: Kind::Hex,
+        };
+        for word in rest {
+            if let Some(kind) = Kind::ALL.into_iter().find(|k| k.as_str() == *word) {
+                query.kind = kind;
+                continue;
+            }
+            query.scope = match *word {
+                "global" => kAudioObjectPropertyScopeGlobal,
+                "input" => kAudioDevicePropertyScopeInput,
+                "output" => kAudioDevicePropertyScopeOutput,
+                "wildcard" => kAudioObjectPropertyScopeWildcard,
+                word if word.len() == 4 => parse_code(word)?,
+                word => {
+                    query.element = word
+                        .parse()
+                        .map_err(|_| format!("Expected a scope, element or type, not {word}"))?;
+                    continue;
+                }
+            };
+        }
+        Ok(query)
+    }
+
+    /// The address, for showing with results.
+    pub fn describe(&self) -> String {
+        format!(
+            "'{}' '{}' {}",
+            fourcc(self.selector),
+            fourcc(self.scope),
+            self.element
+        )
+    }
+}
+
+/// A four-char code like `vmvc`, or a number.
+fn parse_code(word: &str) -> Result<u32, String> {
+    match word.parse::<u32>() {
+        Ok(code) => Ok(code),
+        Err(_) if word.len() == 4 && word.is_ascii() => Ok(u32::from_be_bytes(
+            word.as_bytes().try_into().unwrap_or_default(),
+        )),
+        Err(_) => Err(format!("Expected a four-char code or number, not {word}")),
+    }
+}
+
+/// The property's value, decoded.
+pub fn get(id: &AudioObjectID, query: &Query) -> Result<String, String> {
+    let bytes = audio::property_bytes(id, query.selector, query.scope, query.element)?;
+    let hex = || {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        format!("{} bytes: {}", bytes.len(), hex.join(" "))
+    };
+    if query.kind == Kind::Hex {
+        return Ok(hex());
+    }
+    if query.kind == Kind::String && !is_string_selector(query.selector) {
+        return Ok(format!(
+            "'{}' isn't known to be a string, {}",
+            fourcc(query.selector),
+            hex()
+        ));
+    }
+    let size = query.kind.size();
+    if bytes.is_empty() || bytes.len() % size != 0 {
+        return Err(format!(
+            "{} bytes don't split into {size} byte {} values",
+            bytes.len(),
+            query.kind.as_str()
+        ));
+    }
+    let values: Vec<String> = bytes
+        .chunks_exact(size)
+        .map(|b| decode(query.kind, b))
+        .collect();
+    Ok(values.join(", "))
+}
+
+fn is_string_selector(selector: AudioObjectPropertySelector) -> bool {
+    STRING_SELECTORS
+        .iter()
+        .any(|code| parse_code(code) == Ok(selector))
+}
+
+/// One value. `Kind::String` only for `STRING_SELECTORS`, the bytes are a
+/// CFString pointer that's dereferenced and released.
+fn decode(kind: Kind, bytes: &[u8]) -> String {
+    let word = || u32::from_ne_bytes(bytes.try_into().unwrap_or_default());
+    match kind {
+        Kind::F32 => f32::from_bits(word()).to_string(),
+        Kind::F64 => f64::from_ne_bytes(bytes.try_into().unwrap_or_default()).to_string(),
+        Kind::U32 => word().to_string(),
+        Kind::I32 => (word() as i32).to_string(),
+        Kind::Fourcc => format!("'{}'", fourcc(word())),
+        Kind::String => {
+            let pointer = usize::from_ne_bytes(bytes.try_into().unwrap_or_default());
+            if pointer == 0 {
+                return "null".to_string();
+            }
+            // Copied out to us, ours to release
+            let string = unsafe { CFString::wrap_under_create_rule(pointer as CFStringRef) };
+            format!("{:?}", string.to_string())
+        }
+        Kind::Hex => format!("{:02x}", bytes[0]),
+    }
+}
+
+/// Write a value of the query's type.
+pub fn set(id: &AudioObjectID, query: &Query, value: &str) -> Result<(), String> {
+    let invalid = || format!("{value} isn't a {}", query.kind.as_str());
+    let bytes = match query.kind {
+        Kind::F32 => value
+            .parse::<f32>()
+            .map_err(|_| invalid())?
+            .to_ne_bytes()
+            .to_vec(),
+        Kind::F64 => value
+            .parse::<f64>()
+            .map_err(|_| invalid())?
+            .to_ne_bytes()
+            .to_vec(),
+        Kind::U32 => value
+            .parse::<u32>()
+            .map_err(|_| invalid())?
+            .to_ne_bytes()
+            .to_vec(),
+        Kind::I32 => value
+            .parse::<i32>()
+            .map_err(|_| invalid())?
+            .to_ne_bytes()
+            .to_vec(),
+        Kind::Fourcc => parse_code(value)?.to_ne_bytes().to_vec(),
+        Kind::String | Kind::Hex => {
+            return Err(format!(
+                "Pick a number type to set, not {}",
+                query.kind.as_str()
+            ))
+        }
+    };
+    audio::set_property_bytes(id, query.selector, query.scope, query.element, &bytes)
+        .map_err(String::from)
+}
+
+/// Run a query typed on the property screen, `<query> = <value>` to set.
+pub fn run_line(id: &AudioObjectID, line: &str) -> Result<String, String> {
+    let (query, value) = match line.split_once('=') {
+        Some((query, value)) => (query, Some(value.trim())),
+        None => (line, None),
+    };
+    let words: Vec<&str> = query.split_whitespace().collect();
+    let query = Query::parse(&words)?;
+    match value {
+        Some(value) => {
+            set(id, &query, value)?;
+            Ok(format!("{} set to {value}", query.describe()))
+        }
+        None => Ok(format!("{}: {}", query.describe(), get(id, &query)?)),
+    }
+}
+
+/// `mac-controls property get|set ...`.
+pub fn run(args: &[String]) -> Result<String, String> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let (command, device, rest) = match args.as_slice() {
+        [command, device, rest @ ..] if !rest.is_empty() => (*command, *device, rest),
+        _ => return Err(USAGE.to_string()),
+    };
+    let id = match device {
+        "system" => kAudioObjectSystemObject,
+        key => {
+            let audio = AudioState::new(&Config::load());
+            audio
+                .device_list()
+                .into_iter()
+                .map(|(_, _, _, device)| device)
+                .find(|device| device.matches(key))
+                .map(|device| device.id)
+                .ok_or(format!("No device {key}"))?
+        }
+    };
+    match command {
+        "get" => get(&id, &Query::parse(rest)?),
+        "set" => {
+            let (value, rest) = rest.split_last().ok_or(USAGE)?;
+            let query = Query::parse(rest)?;
+            set(&id, &query, value)?;
+            Ok(format!("{} set to {value}", query.describe()))
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
@@ -0,0 +1,39 @@
+//! The app in front, which receives the keys being pressed. Read from the
+//! window list: NSWorkspace's frontmost app is only kept up to date for apps
+//! running an AppKit run loop, which a terminal app doesn't.
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_graphics::window::{
+    copy_window_info, kCGNullWindowID, kCGWindowLayer, kCGWindowListOptionExcludeDesktopElements,
+    kCGWindowListOptionOnScreenOnly, kCGWindowOwnerName,
+};
+
+/// Layer of normal app windows, menus and the Dock are above it.
+const NORMAL_LAYER: i64 = 0;
+
+pub fn app_name() -> Option<String> {
+    let windows = copy_window_info(
+        kCGWindowListOptionOnScreenOnly | kCGWindowListOptionExcludeDesktopElements,
+        kCGNullWindowID,
+    )?;
+    let (layer_key, owner_key) = unsafe {
+        (
+            CFString::wrap_under_get_rule(kCGWindowLayer),
+            CFString::wrap_under_get_rule(kCGWindowOwnerName),
+        )
+    };
+    // Front to back, so the first normal window belongs to the frontmost app
+    windows.iter().find_map(|window| {
+        let window: CFDictionary<CFString, CFType> =
+            unsafe { CFDictionary::wrap_under_get_rule(*window as CFDictionaryRef) };
+        let layer = window.find(&layer_key)?.downcast::<CFNumber>()?.to_i64()?;
+        if layer != NORMAL_LAYER {
+            return None;
+        }
+        let owner = window.find(&owner_key)?.downcast::<CFString>()?;
+        Some(owner.to_string())
+    })
+}
@@ -0,0 +1,145 @@
+//! Trackpad gestures through the private MultitouchSupport framework, only
+//! built with the `gestures` feature. Three-finger vertical swipes: on the
+//! right half of the trackpad they change the output volume, one step per
+//! tenth of the pad travelled; a downward swipe on the left half toggles the
+//! mic mute.
+
+use std::os::raw::{c_int, c_void};
+use std::sync::Mutex;
+
+use crate::events::{Action, MediaKey};
+
+/// Travel (normalized trackpad height) per volume step.
+const SWIPE_STEP: f32 = 0.1;
+/// Travel needed for the mute swipe.
+const MUTE_SWIPE: f32 = 0.25;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MtPoint {
+    x: f32,
+    y: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MtReadout {
+    position: MtPoint,
+    velocity: MtPoint,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Finger {
+    frame: c_int,
+    timestamp: f64,
+    identifier: c_int,
+    state: c_int,
+    unknown1: c_int,
+    unknown2: c_int,
+    normalized: MtReadout,
+    size: f32,
+    zero1: c_int,
+    angle: f32,
+    major_axis: f32,
+    minor_axis: f32,
+    mm: MtReadout,
+    zero2: [c_int; 2],
+    unknown3: f32,
+}
+
+type MtDeviceRef = *mut c_void;
+type ContactCallback = extern "C" fn(c_int, *const Finger, c_int, f64, c_int) -> c_int;
+
+extern "C" {
+    fn MTDeviceCreateDefault() -> MtDeviceRef;
+    fn MTRegisterContactFrameCallback(device: MtDeviceRef, callback: ContactCallback);
+    fn MTDeviceStart(device: MtDeviceRef, mode: c_int);
+}
+
+/// A three-finger swipe in progress.
+struct Swipe {
+    /// Horizontal start position, picks the zone.
+    x: f32,
+    /// Vertical position of the last step taken.
+    y: f32,
+    muted: bool,
+}
+
+struct Recognizer {
+    handler: Box<dyn Fn(Action) + Send>,
+    swipe: Option<Swipe>,
+}
+
+/// The contact callback has no user data, so the recognizer is global.
+static RECOGNIZER: Mutex<Option<Recognizer>> = Mutex::new(None);
+
+/// Start recognizing gestures on the built-in trackpad, calling the handler
+/// from the framework's thread.
+pub fn start<F>(handler: F) -> Result<(), String>
+where
+    F: Fn(Action) + Send + 'static,
+{
+    *RECOGNIZER.lock().unwrap() = Some(Recognizer {
+        handler: Box::new(handler),
+        swipe: None,
+    });
+    unsafe {
+        let device = MTDeviceCreateDefault();
+        if device.is_null() {
+            return Err("No multitouch device.".to_string());
+        }
+        MTRegisterContactFrameCallback(device, on_contacts);
+        MTDeviceStart(device, 0);
+    }
+    Ok(())
+}
+
+extern "C" fn on_contacts(
+    _device: c_int,
+    fingers: *const Finger,
+    count: c_int,
+    _timestamp: f64,
+    _frame: c_int,
+) -> c_int {
+    let fingers = match fingers.is_null() {
+        true => &[][..],
+        false => unsafe { std::slice::from_raw_parts(fingers, count.max(0) as usize) },
+    };
+    if let Some(recognizer) = RECOGNIZER.lock().unwrap().as_mut() {
+        recognizer.frame(fingers);
+    }
+    0
+}
+
+impl Recognizer {
+    fn frame(&mut self, fingers: &[Finger]) {
+        if fingers.len() != 3 {
+            self.swipe = None;
+            return;
+        }
+        let count = fingers.len() as f32;
+        let x = fingers.iter().map(|f| f.normalized.position.x).sum::<f32>() / count;
+        let y = fingers.iter().map(|f| f.normalized.position.y).sum::<f32>() / count;
+        let swipe = self.swipe.get_or_insert(Swipe { x, y, muted: false });
+        let travel = y - swipe.y;
+        if swipe.x > 0.5 {
+            // Output volume, y grows towards the top of the pad
+            if travel.abs() >= SWIPE_STEP {
+                let key = match travel > 0.0 {
+                    true => MediaKey::VolumeUp,
+                    false => MediaKey::VolumeDown,
+                };
+                swipe.y += SWIPE_STEP * travel.signum();
+                (self.handler)(Action::MediaKey {
+                    key,
+                    repeating: false,
+                    fine: false,
+                });
+            }
+        } else if travel <= -MUTE_SWIPE && !swipe.muted {
+            swipe.muted = true;
+            (self.handler)(Action::ToggleInputMute);
+        }
+    }
+}
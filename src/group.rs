@@ -0,0 +1,68 @@
+//! Output volume groups. Members follow each other's volume changes, each
+//! keeping its own offset, like grouped speakers.
+
+use crate::store::Store;
+
+const GROUPS_FILE: &str = "groups.toml";
+
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub name: String,
+    /// (device UID, offset from the group level)
+    pub members: Vec<(String, f32)>,
+}
+
+impl Group {
+    pub fn new(name: &str) -> Self {
+        Group {
+            name: name.to_string(),
+            members: Vec::new(),
+        }
+    }
+
+    pub fn offset(&self, uid: &str) -> Option<f32> {
+        self.members
+            .iter()
+            .find(|(member, _)| member == uid)
+            .map(|(_, offset)| *offset)
+    }
+
+    pub fn contains(&self, uid: &str) -> bool {
+        self.offset(uid).is_some()
+    }
+
+    /// Add a member, or remove it if already grouped.
+    pub fn toggle(&mut self, uid: &str, offset: f32) {
+        if let Some(i) = self.members.iter().position(|(member, _)| member == uid) {
+            self.members.remove(i);
+        } else {
+            self.members.push((uid.to_string(), offset));
+        }
+    }
+}
+
+/// Load groups, one section per group with `uid = offset` entries.
+pub fn load() -> Vec<Group> {
+    Store::open(GROUPS_FILE)
+        .sections
+        .into_iter()
+        .map(|(name, section)| Group {
+            name,
+            members: section
+                .into_iter()
+                .map(|(uid, offset)| (uid, offset.parse().unwrap_or(0.0)))
+                .collect(),
+        })
+        .collect()
+}
+
+pub fn save(groups: &[Group]) -> Result<(), String> {
+    let mut store = Store::open(GROUPS_FILE);
+    store.sections.clear();
+    for group in groups {
+        for (uid, offset) in &group.members {
+            store.set(&group.name, uid, format!("{offset:.2}"));
+        }
+    }
+    store.save()
+}
@@ -0,0 +1,117 @@
+//! Device connection history keyed by UID, stored in `history.toml`.
+//! Helps track down flaky connections, and remembers devices that aren't
+//! currently attached.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::store::Store;
+
+const HISTORY_FILE: &str = "history.toml";
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceHistory {
+    pub uid: String,
+    pub name: String,
+    /// Unix timestamps in seconds.
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub connects: u32,
+    pub disconnects: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    store: Store,
+}
+
+impl History {
+    pub fn load() -> Self {
+        History {
+            store: Store::open(HISTORY_FILE),
+        }
+    }
+
+    /// Record a device appearing. Devices already attached at startup are
+    /// seen, but not counted as a new connection.
+    pub fn connected(&mut self, uid: &str, name: &str, counted: bool) {
+        let now = now();
+        if self.store.get(uid, "first_seen").is_none() {
+            self.store.set(uid, "first_seen", now);
+        }
+        self.store.set(uid, "name", name);
+        self.store.set(uid, "last_seen", now);
+        if counted {
+            let connects = self.count(uid, "connects") + 1;
+            self.store.set(uid, "connects", connects);
+        }
+    }
+
+    pub fn disconnected(&mut self, uid: &str) {
+        let disconnects = self.count(uid, "disconnects") + 1;
+        self.store.set(uid, "disconnects", disconnects);
+        self.store.set(uid, "last_seen", now());
+    }
+
+    /// Mark attached devices as seen right now (in memory only).
+    pub fn seen(&mut self, uid: &str) {
+        self.store.set(uid, "last_seen", now());
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.store.save()
+    }
+
+    /// All known devices, most recently seen first.
+    pub fn entries(&self) -> Vec<DeviceHistory> {
+        let mut entries: Vec<DeviceHistory> = self
+            .store
+            .sections
+            .keys()
+            .map(|uid| DeviceHistory {
+                uid: uid.clone(),
+                name: self.store.get(uid, "name").unwrap_or(uid).to_string(),
+                first_seen: self.number(uid, "first_seen"),
+                last_seen: self.number(uid, "last_seen"),
+                connects: self.count(uid, "connects"),
+                disconnects: self.count(uid, "disconnects"),
+            })
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.last_seen));
+        entries
+    }
+
+    fn count(&self, uid: &str, key: &str) -> u32 {
+        self.number(uid, key)
+    }
+
+    fn number<T: FromStr + Default>(&self, uid: &str, key: &str) -> T {
+        self.store
+            .get(uid, key)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Current unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Short relative time, like "5m ago".
+pub fn ago(timestamp: u64) -> String {
+    format!("{} ago", duration(now().saturating_sub(timestamp)))
+}
+
+/// Short length of time, like "5m".
+pub fn duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
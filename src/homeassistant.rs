@@ -0,0 +1,265 @@
+//! Home Assistant over MQTT. Discovery messages make the Mac show up as a
+//! device with, per channel, the default device's volume (a number), its
+//! mute (a switch) and the default device itself (a select). States are
+//! published when they change, commands from Home Assistant come back as
+//! actions.
+//!
+//! Topics are under `mac-controls/<host>`, like `…/output/volume` and
+//! `…/output/volume/set`.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audio::{AudioState, Channel};
+use crate::audit;
+use crate::config::{self, MqttConfig};
+use crate::events::Action;
+use crate::log;
+use crate::mqtt::{Client, Will};
+use crate::server::json_string;
+
+/// Wait between connection attempts.
+const RECONNECT_TIME: Duration = Duration::from_secs(30);
+
+const CHANNELS: [(Channel, &str, &str); 2] = [
+    (Channel::Input, "input", "Input"),
+    (Channel::Output, "output", "Output"),
+];
+
+/// (label, UID) of devices offered as default, per channel like `CHANNELS`.
+type Choices = Arc<Mutex<[Vec<(String, String)>; 2]>>;
+
+pub struct HomeAssistant {
+    config: MqttConfig,
+    /// Host name as a topic and ID part.
+    node: String,
+    client: Option<Client>,
+    last_attempt: Option<Instant>,
+    handler: Arc<dyn Fn(Action) + Send + Sync>,
+    choices: Choices,
+    /// Payloads as last published, by topic, so only changes are sent.
+    published: Vec<(String, String)>,
+}
+
+impl HomeAssistant {
+    pub fn new(config: MqttConfig, handler: impl Fn(Action) + Send + Sync + 'static) -> Self {
+        let node = config::host_name()
+            .unwrap_or_else(|| "mac".to_string())
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        HomeAssistant {
+            config,
+            node,
+            client: None,
+            last_attempt: None,
+            handler: Arc::new(handler),
+            choices: Arc::default(),
+            published: Vec::new(),
+        }
+    }
+
+    fn base(&self) -> String {
+        format!("mac-controls/{}", self.node)
+    }
+
+    /// Publish what changed since the last sync, connecting first if
+    /// needed.
+    pub fn sync(&mut self, audio: &AudioState) {
+        if !self.client.as_ref().is_some_and(|c| c.is_alive()) && !self.connect() {
+            return;
+        }
+        let mut choices = [Vec::new(), Vec::new()];
+        for (_, _, _, device) in audio.device_list() {
+            for (i, (channel, ..)) in CHANNELS.iter().enumerate() {
+                let volume = device.volume(*channel).borrow();
+                if volume.enabled && volume.selectable {
+                    choices[i].push((device.label().to_string(), device.uid.clone()));
+                }
+            }
+        }
+        if *self.choices.lock().unwrap() != choices {
+            *self.choices.lock().unwrap() = choices;
+            self.publish_discovery();
+        }
+
+        let base = self.base();
+        let mut states = Vec::new();
+        for (channel, name, _) in CHANNELS {
+            let Some(device) = audio.active_device(channel) else {
+                continue;
+            };
+            let level = match channel {
+                Channel::Input => audio.input(&device.id),
+                Channel::Output => audio.output(&device.id),
+            };
+            states.push((format!("{base}/{name}/default"), device.label().to_string()));
+            if let Some((level, muted)) = level {
+                let percent = (level * 100.0).round() as u8;
+                states.push((format!("{base}/{name}/volume"), percent.to_string()));
+                let muted = if muted { "ON" } else { "OFF" };
+                states.push((format!("{base}/{name}/mute"), muted.to_string()));
+            }
+        }
+        for (topic, payload) in states {
+            if !self.published.contains(&(topic.clone(), payload.clone())) {
+                self.publish(&topic, &payload);
+                self.published.retain(|(t, _)| *t != topic);
+                self.published.push((topic, payload));
+            }
+        }
+    }
+
+    /// Mark the Mac offline, before exiting.
+    pub fn disconnect(&mut self) {
+        if let Some(client) = self.client.take() {
+            let _ = client.publish(&format!("{}/status", self.base()), "offline", true);
+            client.disconnect();
+        }
+    }
+
+    fn connect(&mut self) -> bool {
+        if self
+            .last_attempt
+            .is_some_and(|t| t.elapsed() < RECONNECT_TIME)
+        {
+            return false;
+        }
+        self.last_attempt = Some(Instant::now());
+        let base = self.base();
+        let will = Will {
+            topic: format!("{base}/status"),
+            payload: "offline".to_string(),
+        };
+        let login = self
+            .config
+            .username
+            .as_deref()
+            .map(|user| (user, self.config.password.as_deref().unwrap_or("")));
+        let handler = self.handler.clone();
+        let choices = self.choices.clone();
+        let command_base = base.clone();
+        let connected = Client::connect(
+            &self.config.host,
+            self.config.port,
+            &format!("mac-controls-{}", self.node),
+            login,
+            &will,
+            move |topic, payload| {
+                let payload = String::from_utf8_lossy(payload);
+                let action = command(&command_base, topic, payload.trim(), &choices)
+                    .ok_or("unknown command".to_string());
+                let _ = audit::dispatch("mqtt", &format!("{topic} {payload}"), action, &*handler);
+            },
+        );
+        let client = match connected {
+            Ok(client) => client,
+            Err(e) => {
+                log::warn(e);
+                return false;
+            }
+        };
+        log::info(format!(
+            "Connected to MQTT broker {}:{}",
+            self.config.host, self.config.port
+        ));
+        let subscribed = client.subscribe(&format!("{base}/+/+/set"));
+        if let Err(e) = subscribed {
+            log::warn(e);
+        }
+        self.client = Some(client);
+        self.publish(&format!("{base}/status"), "online");
+        // Republish everything, the broker may have restarted
+        self.published.clear();
+        self.publish_discovery();
+        true
+    }
+
+    fn publish(&self, topic: &str, payload: &str) {
+        if let Some(client) = &self.client {
+            if let Err(e) = client.publish(topic, payload, true) {
+                log::warn(e);
+            }
+        }
+    }
+
+    /// Entity configs for Home Assistant's MQTT discovery.
+    fn publish_discovery(&self) {
+        let base = self.base();
+        let node = &self.node;
+        let prefix = &self.config.discovery_prefix;
+        let device = format!(
+            "{{\"identifiers\":[\"mac_controls_{node}\"],\"name\":{},\"manufacturer\":\"Mac Controls\"}}",
+            json_string(&format!("{node} audio"))
+        );
+        let choices = self.choices.lock().unwrap().clone();
+        for (i, (_, name, title)) in CHANNELS.iter().enumerate() {
+            let common = |entity: &str, label: &str| {
+                format!(
+                    "\"name\":\"{title} {label}\",\"unique_id\":\"mac_controls_{node}_{name}_{entity}\",\
+                     \"state_topic\":\"{base}/{name}/{entity}\",\"command_topic\":\"{base}/{name}/{entity}/set\",\
+                     \"availability_topic\":\"{base}/status\",\"device\":{device}"
+                )
+            };
+            let options: Vec<String> = choices[i].iter().map(|(l, _)| json_string(l)).collect();
+            for (component, entity, config) in [
+                (
+                    "number",
+                    "volume",
+                    format!(
+                        "{{{},\"min\":0,\"max\":100,\"step\":1,\"unit_of_measurement\":\"%\"}}",
+                        common("volume", "volume")
+                    ),
+                ),
+                ("switch", "mute", format!("{{{}}}", common("mute", "mute"))),
+                (
+                    "select",
+                    "default",
+                    format!(
+                        "{{{},\"options\":[{}]}}",
+                        common("default", "device"),
+                        options.join(",")
+                    ),
+                ),
+            ] {
+                let topic = format!("{prefix}/{component}/{node}/{name}_{entity}/config");
+                self.publish(&topic, &config);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for HomeAssistant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HomeAssistant")
+            .field("node", &self.node)
+            .field("connected", &self.client.is_some())
+            .finish()
+    }
+}
+
+/// Action for a command topic like `…/output/volume/set`.
+fn command(base: &str, topic: &str, payload: &str, choices: &Choices) -> Option<Action> {
+    let rest = topic.strip_prefix(base)?.strip_prefix('/')?;
+    let (name, entity) = rest.strip_suffix("/set")?.split_once('/')?;
+    let i = CHANNELS.iter().position(|(_, n, _)| *n == name)?;
+    let channel = CHANNELS[i].0;
+    match entity {
+        "volume" => {
+            let percent = payload.parse::<f32>().ok()?.round().clamp(0.0, 100.0);
+            Some(Action::SetVolume(channel, percent as u8))
+        }
+        "mute" => match payload {
+            "ON" => Some(Action::SetMute(channel, true)),
+            "OFF" => Some(Action::SetMute(channel, false)),
+            _ => None,
+        },
+        "default" => {
+            let choices = choices.lock().unwrap();
+            let (_, uid) = choices[i].iter().find(|(label, _)| label == payload)?;
+            Some(Action::SetDefault(channel, uid.clone()))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,80 @@
+//! Request heads for the small HTTP servers, `quick` and `server`.
+//! Reading is bounded in time and size, so a slow or hostile client on the
+//! network can't hold a thread open or fill memory.
+
+use std::io::{BufRead, BufReader, Read};
+use std::time::Duration;
+
+use crate::tls::Stream;
+
+/// How long a client gets for each read of its request.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request or header line, in bytes.
+const MAX_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+/// Request line and headers. The body, if any, is never read.
+#[derive(Debug, Default)]
+pub struct Request {
+    pub method: String,
+    /// Path and query, like `/quick/mute-input?x=1`.
+    pub target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Read the head of a request, `None` when it's too slow, too long or
+    /// isn't HTTP.
+    pub fn read(stream: &mut Stream) -> Option<Self> {
+        stream.tcp().set_read_timeout(Some(READ_TIMEOUT)).ok()?;
+        let mut reader = BufReader::new(stream);
+        let line = read_line(&mut reader)?;
+        let mut parts = line.split_whitespace();
+        let mut request = Request {
+            method: parts.next()?.to_string(),
+            target: parts.next()?.to_string(),
+            headers: Vec::new(),
+        };
+        loop {
+            let line = read_line(&mut reader)?;
+            if line.trim().is_empty() {
+                return Some(request);
+            }
+            if request.headers.len() == MAX_HEADERS {
+                return None;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let header = (name.trim().to_ascii_lowercase(), value.trim().to_string());
+                request.headers.push(header);
+            }
+        }
+    }
+
+    /// Value of a header, by case-insensitive name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The target without its query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or(&self.target)
+    }
+}
+
+/// One line of at most `MAX_LINE` bytes, `None` past that or at the end.
+fn read_line(reader: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE as u64 + 1)
+        .read_line(&mut line)
+        .ok()?;
+    match read {
+        0 => None,
+        _ if line.len() > MAX_LINE => None,
+        _ => Some(line),
+    }
+}
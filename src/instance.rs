@@ -0,0 +1,87 @@
+//! One running instance per user. The instance holds a lock on
+//! `instance.lock` in the data directory, released by the OS however it
+//! exits, so a second launch doesn't register its taps and listeners on
+//! top. `--takeover` leaves a `takeover` file that the running instance
+//! picks up on its poll timer, exiting like it does on `ctrl-c`.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::store;
+
+const LOCK_FILE: &str = "instance.lock";
+const TAKEOVER_FILE: &str = "takeover";
+/// How long the running instance gets to clean up and exit.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Instance {
+    /// Held open for the lock.
+    _file: File,
+}
+
+impl Instance {
+    /// Take the lock, failing while another instance holds it.
+    pub fn acquire() -> Result<Instance, String> {
+        let _ = fs::create_dir_all(store::data_dir());
+        let path = store::data_dir().join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("Unable to open {path:?}: {e}"))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(format!(
+                    "Already running (pid {}), use --takeover to replace it",
+                    pid.trim()
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(format!("Unable to lock {path:?}: {e}")),
+        }
+        // A takeover meant for an instance that's gone
+        let _ = fs::remove_file(takeover_path());
+        let _ = file.set_len(0);
+        let _ = file.rewind();
+        let _ = write!(file, "{}", std::process::id());
+        Ok(Instance { _file: file })
+    }
+
+    /// Ask the running instance to exit, then take the lock.
+    pub fn take_over() -> Result<Instance, String> {
+        if let Ok(instance) = Instance::acquire() {
+            return Ok(instance);
+        }
+        File::create(takeover_path())
+            .map_err(|e| format!("Unable to ask the running instance to exit: {e}"))?;
+        let start = Instant::now();
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            match Instance::acquire() {
+                Ok(instance) => return Ok(instance),
+                Err(_) if start.elapsed() < TAKEOVER_TIMEOUT => continue,
+                Err(e) => {
+                    let _ = fs::remove_file(takeover_path());
+                    return Err(format!("The running instance didn't exit. {e}"));
+                }
+            }
+        }
+    }
+
+    /// Whether another launch asked this one to exit.
+    pub fn takeover_requested(&self) -> bool {
+        fs::remove_file(takeover_path()).is_ok()
+    }
+}
+
+fn takeover_path() -> PathBuf {
+    store::data_dir().join(TAKEOVER_FILE)
+}
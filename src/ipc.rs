@@ -0,0 +1,523 @@
+//! Control API of `mac-controls daemon`, over a Unix socket at
+//! `daemon.sock` in the data directory. Clients send a JSON object per
+//! line and get one back, `{"ok":true}` or `{"ok":false,"error":"…"}`:
+//!
+//! - `{"cmd":"status"}`: the default devices, as `input` and `output`
+//!   with `name`, `uid`, `manufacturer`, `model` (null when the device
+//!   doesn't say), `level`, `muted` and `battery` (the lowest level
+//!   of a Bluetooth device, else null), and for the input `for_secs`, how
+//!   long it's been muted or unmuted
+//! - `{"cmd":"set_volume","channel":"output","level":0.5}`
+//! - `{"cmd":"mute","channel":"input","muted":true}`
+//! - `{"cmd":"set_default","channel":"output","device":"<UID or name>"}`
+//! - `{"cmd":"action","name":"media mute"}`, any recorded action
+//! - `{"cmd":"quit"}` stops the daemon
+//! - `{"cmd":"attach"}` marks the connection as an attached terminal,
+//!   whose commands aren't rate limited
+//! - `{"cmd":"auth","token":"…"}` comes first on remote connections
+//!
+//! `MAC_CONTROLS_SOCKET` moves the socket, for a second daemon or a test.
+//! Only the user can connect, the socket file is private to them. The TUI
+//! attaches to a running daemon through `Client` instead of starting its
+//! own event tap.
+//!
+//! With `remote_port` in `[daemon]` the same commands are taken over TCP
+//! from other machines, for `mac-controls attach <host>:<port>`. Those
+//! connections send `{"cmd":"auth","token":"…"}` first, with a token from
+//! `mac-controls server token`, and are limited by address like quick
+//! actions. They're plain TCP, `[tls]` doesn't cover them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::iter::Peekable;
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::Chars;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::audio::{self, Channel};
+use crate::audit;
+use crate::bluetooth;
+use crate::events::Action;
+use crate::server::json_string;
+use crate::store;
+use crate::tasks::Tasks;
+use crate::tokens;
+
+const SOCKET_FILE: &str = "daemon.sock";
+/// Socket path instead of the data directory's, for daemon and clients.
+const SOCKET_ENV: &str = "MAC_CONTROLS_SOCKET";
+/// Remote terminals at once, more are hung up on.
+const MAX_REMOTE: usize = 16;
+const STATUS: &str = "{\"cmd\":\"status\"}";
+
+/// The default input's mute state and since when, kept by the main thread.
+static MUTE_SINCE: Mutex<Option<(bool, Instant)>> = Mutex::new(None);
+
+pub fn set_mute_since(since: Option<(bool, Instant)>) {
+    *MUTE_SINCE.lock().unwrap() = since;
+}
+
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None => store::data_dir().join(SOCKET_FILE),
+    }
+}
+
+/// Listen on the socket, handling clients on background threads until the
+/// tasks are cancelled, which also hangs up on clients. Fails when another
+/// daemon has it.
+pub fn start(
+    tasks: &mut Tasks,
+    handler: impl Fn(Action) + Send + Sync + 'static,
+) -> Result<(), String> {
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        return Err(format!("Another daemon is listening on {path:?}"));
+    }
+    // Left behind by a daemon that didn't exit cleanly
+    let _ = fs::remove_file(&path);
+    let _ = fs::create_dir_all(store::data_dir());
+    let listener =
+        UnixListener::bind(&path).map_err(|e| format!("Unable to listen on {path:?}: {e}"))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Unable to protect {path:?}: {e}"))?;
+    let handler = Arc::new(handler);
+    // Open connections by number, to hang up on cancel
+    let clients: Arc<Mutex<HashMap<u64, UnixStream>>> = Arc::default();
+    tasks.spawn("socket", move |cancel| {
+        // Accept blocks, a connection of our own gets it to check
+        cancel.on_cancel(move || {
+            let _ = UnixStream::connect(&path);
+        });
+        for (number, stream) in (0..).zip(listener.incoming()) {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if let Ok(clone) = stream.try_clone() {
+                clients.lock().unwrap().insert(number, clone);
+            }
+            let Ok(reader) = stream.try_clone() else {
+                continue;
+            };
+            let handler = handler.clone();
+            let clients = clients.clone();
+            thread::spawn(move || {
+                handle(reader, stream, format!("socket:{number}"), &*handler);
+                clients.lock().unwrap().remove(&number);
+            });
+        }
+        for client in clients.lock().unwrap().values() {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    });
+    Ok(())
+}
+
+/// Take the same commands over TCP on every interface, from terminals on
+/// other machines, until the tasks are cancelled.
+pub fn start_remote(
+    tasks: &mut Tasks,
+    port: u16,
+    handler: impl Fn(Action) + Send + Sync + 'static,
+) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .map_err(|e| format!("Unable to listen on port {port}: {e}"))?;
+    let handler = Arc::new(handler);
+    let clients: Arc<Mutex<HashMap<u64, TcpStream>>> = Arc::default();
+    tasks.spawn("remote terminals", move |cancel| {
+        // Accept blocks, a connection of our own gets it to check
+        cancel.on_cancel(move || {
+            let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, port));
+        });
+        for (number, stream) in (0..).zip(listener.incoming()) {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let (Ok(address), Ok(reader), Ok(clone)) =
+                (stream.peer_addr(), stream.try_clone(), stream.try_clone())
+            else {
+                continue;
+            };
+            {
+                let mut clients = clients.lock().unwrap();
+                if clients.len() >= MAX_REMOTE {
+                    continue;
+                }
+                clients.insert(number, clone);
+            }
+            let handler = handler.clone();
+            let clients = clients.clone();
+            thread::spawn(move || {
+                handle(reader, stream, address.ip().to_string(), &*handler);
+                clients.lock().unwrap().remove(&number);
+            });
+        }
+        for client in clients.lock().unwrap().values() {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    });
+    Ok(())
+}
+
+/// Remove the socket, on exit.
+pub fn stop() {
+    let _ = fs::remove_file(socket_path());
+}
+
+/// Connection to a running daemon, on this Mac or another.
+pub struct Client {
+    writer: Box<dyn Write + Send>,
+    reader: BufReader<Box<dyn Read + Send>>,
+}
+
+impl Client {
+    /// None when no daemon is listening.
+    pub fn connect() -> Option<Client> {
+        let writer = UnixStream::connect(socket_path()).ok()?;
+        let reader = writer.try_clone().ok()?;
+        Some(Client::new(reader, writer))
+    }
+
+    /// The daemon on another machine at `host:port`, with a token made
+    /// there by `mac-controls server token`.
+    pub fn connect_remote(address: &str, token: &str) -> Result<Client, String> {
+        let writer =
+            TcpStream::connect(address).map_err(|e| format!("Unable to reach {address}: {e}"))?;
+        let reader = writer
+            .try_clone()
+            .map_err(|e| format!("Unable to reach {address}: {e}"))?;
+        let mut client = Client::new(reader, writer);
+        client.send(&format!(
+            "{{\"cmd\":\"auth\",\"token\":{}}}",
+            json_string(token)
+        ))?;
+        Ok(client)
+    }
+
+    fn new(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Client {
+        Client {
+            writer: Box::new(writer),
+            reader: BufReader::new(Box::new(reader)),
+        }
+    }
+
+    /// The raw reply, None once the daemon is gone.
+    fn request(&mut self, line: &str) -> Option<String> {
+        writeln!(self.writer, "{line}").ok()?;
+        let mut reply = String::new();
+        match self.reader.read_line(&mut reply) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(reply),
+        }
+    }
+
+    /// The daemon's status, with nested fields like `output.name`. None
+    /// once the daemon is gone.
+    pub fn status(&mut self) -> Option<HashMap<String, String>> {
+        parse_object(&self.request(STATUS)?)
+    }
+
+    /// Run a command, with the daemon's error if it refused.
+    pub fn send(&mut self, line: &str) -> Result<(), String> {
+        let reply = self.request(line).ok_or("Lost the daemon")?;
+        let fields = parse_object(&reply).ok_or(format!("Unexpected reply {reply:?}"))?;
+        match fields.get("ok").map(|v| v.as_str()) {
+            Some("true") => Ok(()),
+            _ => Err(fields
+                .get("error")
+                .cloned()
+                .unwrap_or("The daemon refused".to_string())),
+        }
+    }
+}
+
+/// Serve a connection. Each is its own source for the rate limit, so one
+/// busy client doesn't hold up the rest: a socket connection like
+/// `socket:3`, or a remote one's address, which needs a token first.
+fn handle(reader: impl Read, mut writer: impl Write, mut source: String, handler: &dyn Fn(Action)) {
+    let mut authorized = source.starts_with("socket:");
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = parse_object(&line).unwrap_or_default();
+        let reply = match fields.get("cmd").map(|cmd| cmd.as_str()) {
+            Some("auth") => {
+                let valid = fields.get("token").is_some_and(|t| tokens::is_valid(t));
+                let result = audit::authenticate(&source, valid);
+                authorized |= result.is_ok();
+                reply(result)
+            }
+            _ if !authorized => reply(Err(
+                "Send a token first, {\"cmd\":\"auth\",\"token\":\"…\"}".to_string(),
+            )),
+            Some("attach") => {
+                source = format!("{}{source}", audit::ATTACHED);
+                respond(&line, &source, handler)
+            }
+            _ => respond(&line, &source, handler),
+        };
+        if writeln!(writer, "{reply}").is_err() {
+            return;
+        }
+    }
+}
+
+/// Run a command line, with the JSON reply. `source` is for the audit log
+/// and its rate limit.
+pub fn respond(line: &str, source: &str, handler: &dyn Fn(Action)) -> String {
+    let action = match command(line) {
+        // Not audited or limited, status bars poll it
+        Ok(Reply::Status(status)) => return format!("{{\"ok\":true,{status}}}"),
+        // Only means something on the socket, see `handle`
+        Ok(Reply::Attach) => return "{\"ok\":true}".to_string(),
+        Ok(Reply::Action(action)) => Ok(action),
+        Err(e) => Err(e),
+    };
+    reply(audit::dispatch(source, line.trim(), action, handler))
+}
+
+fn reply(result: Result<(), String>) -> String {
+    match result {
+        Ok(()) => "{\"ok\":true}".to_string(),
+        Err(e) => format!("{{\"ok\":false,\"error\":{}}}", json_string(&e)),
+    }
+}
+
+enum Reply {
+    Action(Action),
+    /// JSON fields of the status.
+    Status(String),
+    /// An attached terminal says hello.
+    Attach,
+}
+
+fn command(line: &str) -> Result<Reply, String> {
+    let fields = parse_object(line).ok_or("Expected a JSON object")?;
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .map(|v| v.as_str())
+            .ok_or(format!("Missing \"{name}\""))
+    };
+    let channel = || match field("channel")? {
+        "input" => Ok(Channel::Input),
+        "output" => Ok(Channel::Output),
+        other => Err(format!("Unknown channel \"{other}\"")),
+    };
+    let action = match field("cmd")? {
+        "status" => return Ok(Reply::Status(status())),
+        "attach" => return Ok(Reply::Attach),
+        "set_volume" => {
+            let level = field("level")?;
+            match level.parse::<f32>() {
+                Ok(level) if (0.0..=1.0).contains(&level) => {
+                    Action::SetVolume(channel()?, (level * 100.0).round() as u8)
+                }
+                _ => return Err(format!("Level must be 0 to 1, not {level}")),
+            }
+        }
+        "mute" => match field("muted")? {
+            "true" => Action::SetMute(channel()?, true),
+            "false" => Action::SetMute(channel()?, false),
+            other => return Err(format!("Muted must be true or false, not {other}")),
+        },
+        "set_default" => {
+            let key = field("device")?;
+            let devices: Vec<(String, String)> = audio::device_ids()?
+                .iter()
+                .map(|id| (audio::device_uid(id), audio::device_name(id)))
+                .collect();
+            let entries: Vec<(&str, &str)> = devices
+                .iter()
+                .map(|(uid, name)| (uid.as_str(), name.as_str()))
+                .collect();
+            let i = audio::resolve_device(&entries, key)?;
+            Action::SetDefault(channel()?, devices[i].0.clone())
+        }
+        "action" => {
+            let name = field("name")?;
+            Action::from_record(name).ok_or(format!("Unknown action \"{name}\""))?
+        }
+        "quit" => Action::Exit,
+        other => return Err(format!("Unknown command \"{other}\"")),
+    };
+    Ok(Reply::Action(action))
+}
+
+/// Default devices, read straight from CoreAudio as the state lives on
+/// the main thread.
+fn status() -> String {
+    let channels = [(Channel::Input, "input"), (Channel::Output, "output")];
+    let fields: Vec<String> = channels
+        .iter()
+        .map(|(channel, name)| {
+            let id = audio::default_device(*channel);
+            if id == 0 {
+                return format!("\"{name}\":null");
+            }
+            let (levels, mutes) = (audio::volume_level(&id), audio::device_mutes(&id));
+            let (level, muted) = match channel {
+                Channel::Input => (levels.0, mutes.0),
+                Channel::Output => (levels.1, mutes.1),
+            };
+            let muted = muted.unwrap_or(false);
+            let since = match (channel, *MUTE_SINCE.lock().unwrap()) {
+                (Channel::Input, Some((state, since))) if state == muted => {
+                    format!(",\"for_secs\":{}", since.elapsed().as_secs())
+                }
+                (Channel::Input, _) => ",\"for_secs\":null".to_string(),
+                (Channel::Output, _) => String::new(),
+            };
+            let uid = audio::device_uid(&id);
+            let battery = bluetooth::battery(&uid).and_then(|battery| battery.lowest());
+            format!(
+                "\"{name}\":{{\"name\":{},\"uid\":{},\"manufacturer\":{},\"model\":{},\"level\":{},\"muted\":{muted},\"battery\":{}{since}}}",
+                json_string(&audio::device_name(&id)),
+                json_string(&uid),
+                audio::device_manufacturer(&id).map_or("null".to_string(), |m| json_string(&m)),
+                audio::device_model(&id).map_or("null".to_string(), |m| json_string(&m)),
+                level.map_or("null".to_string(), |l| l.to_string()),
+                battery.map_or("null".to_string(), |b| b.to_string()),
+            )
+        })
+        .collect();
+    fields.join(",")
+}
+
+/// Fields of a JSON object, strings unquoted and other values as
+/// written, like `0.5` or `true`. Nested objects' fields are prefixed with
+/// their key, like `output.name`.
+fn parse_object(text: &str) -> Option<HashMap<String, String>> {
+    let mut chars = text.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    if chars.next()? != '{' {
+        return None;
+    }
+    parse_fields(&mut chars, "", &mut fields)?;
+    chars.next().is_none().then_some(fields)
+}
+
+/// Rest of an object, after the opening brace.
+fn parse_fields(
+    chars: &mut Peekable<Chars>,
+    prefix: &str,
+    fields: &mut HashMap<String, String>,
+) -> Option<()> {
+    skip_spaces(chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Some(());
+    }
+    loop {
+        skip_spaces(chars);
+        if chars.next()? != '"' {
+            return None;
+        }
+        let key = format!("{prefix}{}", parse_string(chars)?);
+        skip_spaces(chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_spaces(chars);
+        if chars.next_if_eq(&'{').is_some() {
+            parse_fields(chars, &format!("{key}."), fields)?;
+        } else {
+            let value = match chars.next_if_eq(&'"') {
+                Some(_) => parse_string(chars)?,
+                None => {
+                    let mut value = String::new();
+                    while let Some(c) =
+                        chars.next_if(|c| !matches!(c, ',' | '}') && !c.is_whitespace())
+                    {
+                        value.push(c);
+                    }
+                    if value.is_empty() {
+                        return None;
+                    }
+                    value
+                }
+            };
+            fields.insert(key, value);
+        }
+        skip_spaces(chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+/// Rest of a JSON string, after the opening quote.
+fn parse_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => match chars.next()? {
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'u' => {
+                    let hex: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                    s.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => s.push(c),
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_fields_are_prefixed() {
+        let fields =
+            parse_object(r#"{"ok":true,"input":null,"output":{"name":"Speakers","level":0.5}}"#)
+                .unwrap();
+        assert_eq!(fields["input"], "null");
+        assert_eq!(fields["output.name"], "Speakers");
+        assert_eq!(fields["output.level"], "0.5");
+        assert_eq!(parse_object(r#"{"output":{"level":0.5}"#), None);
+    }
+
+    #[test]
+    fn remote_connections_need_a_token() {
+        store::use_temp_home();
+        let lines = "{\"cmd\":\"quit\"}\n{\"cmd\":\"auth\",\"token\":\"guess\"}\n";
+        let mut replies = Vec::new();
+        handle(
+            lines.as_bytes(),
+            &mut replies,
+            "192.0.2.1".to_string(),
+            &|action| panic!("ran {action:?}"),
+        );
+        let replies = String::from_utf8(replies).unwrap();
+        let replies: Vec<&str> = replies.lines().collect();
+        assert!(replies[0].contains("Send a token first"));
+        assert!(replies[1].contains("Invalid token"));
+    }
+}
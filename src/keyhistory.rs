@@ -0,0 +1,116 @@
+//! Recent key chords for the keys pane, with the time and the app they
+//! went to. With `history_file` in `[keys]`, every chord is also appended
+//! to a file, one tab separated line each, for screencasts or checking
+//! what a key remapper sends.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use core_foundation::date::CFDate;
+use core_foundation::timezone::CFTimeZone;
+
+use crate::store;
+
+/// Chords kept without `history` in `[keys]`.
+pub const DEFAULT_LEN: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct KeyPress {
+    /// Local time, like "14:03:27.512".
+    pub time: String,
+    pub chord: String,
+    pub app: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct KeyHistory {
+    entries: VecDeque<KeyPress>,
+    len: usize,
+    file: Option<File>,
+}
+
+impl KeyHistory {
+    /// Keep the last `len` chords, appending them to `path` if given.
+    pub fn new(len: usize, path: Option<&str>) -> Result<Self, String> {
+        let file = match path {
+            Some(path) => {
+                let path = store::expand_home(path);
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| format!("Unable to open the key history file {path:?}: {e}"))?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(KeyHistory {
+            entries: VecDeque::with_capacity(len),
+            len,
+            file,
+        })
+    }
+
+    pub fn push(&mut self, chord: String, app: Option<String>) {
+        let press = KeyPress {
+            time: local_time(),
+            chord,
+            app,
+        };
+        if let Some(file) = self.file.as_mut() {
+            let line = format!(
+                "{}\t{}\t{}\n",
+                press.time,
+                press.chord,
+                press.app.as_deref().unwrap_or_default()
+            );
+            // A full disk shouldn't cost the pane its history
+            if file.write_all(line.as_bytes()).is_err() {
+                self.file = None;
+            }
+        }
+        if self.entries.len() == self.len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(press);
+    }
+
+    /// Newest first.
+    pub fn recent(&self) -> impl Iterator<Item = &KeyPress> {
+        self.entries.iter().rev()
+    }
+
+    pub fn is_logging(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+impl Default for KeyHistory {
+    fn default() -> Self {
+        KeyHistory {
+            entries: VecDeque::new(),
+            len: DEFAULT_LEN,
+            file: None,
+        }
+    }
+}
+
+/// Wall clock time of day with milliseconds, in the local time zone.
+fn local_time() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    let offset = CFTimeZone::system().seconds_from_gmt(CFDate::now()) as i64;
+    let millis = (now + offset * 1000).rem_euclid(24 * 60 * 60 * 1000);
+    let seconds = millis / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis % 1000
+    )
+}
@@ -0,0 +1,421 @@
+//! Terminal key bindings per UI mode. Each mode has its own layer on top of
+//! the global one, and the config can change either: `["keymap output"]`
+//! with `"/" = "toggle-mute"` entries, or `[keymap]` for every mode. An
+//! empty action unbinds a key.
+
+use termion::event::Key;
+
+use crate::events::{Action, UiMode};
+use crate::panes::Pane;
+
+/// Key names and actions, in the order they're shown in the footer.
+type Layer = Vec<(String, String)>;
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    global: Layer,
+    /// Layers by mode, indexed like `UiMode::ALL`.
+    modes: Vec<Layer>,
+}
+
+const GLOBAL: &[(&str, &str)] = &[
+    ("ctrl-c", "exit"),
+    ("i", "mode input"),
+    ("o", "mode output"),
+    ("/", "mode search"),
+    ("enter", "mode detail"),
+    ("esc", "mode back"),
+    (":", "mode palette"),
+    ("p", "mode profiles"),
+    ("m", "record-macro"),
+    ("j", "cursor-next"),
+    ("k", "cursor-prev"),
+    ("pagedown", "cursor-page-next"),
+    ("pageup", "cursor-page-prev"),
+    ("r", "toggle-key-routing"),
+    ("b", "toggle-swallow"),
+    ("B", "broadcast"),
+    ("d", "toggle-doctor"),
+    ("h", "toggle-history"),
+    ("1", "toggle-pane mixer"),
+    ("2", "toggle-pane keyboard"),
+    ("3", "toggle-pane meters"),
+    ("4", "toggle-pane log"),
+    ("5", "toggle-pane keys"),
+    ("tab", "focus-pane"),
+    ("+", "grow-pane"),
+    ("-", "shrink-pane"),
+    ("l", "cycle-log-level"),
+    ("u", "fix-silent"),
+    ("n", "align-sample-rates"),
+    ("z", "toggle-section"),
+    ("w", "toggle-virtual"),
+    ("a", "mode apps"),
+    ("t", "mode system"),
+    ("e", "mode alert"),
+];
+
+/// The arrows browse the list, only `enter` changes the default device.
+const EDIT: &[(&str, &str)] = &[
+    ("up", "cursor-prev"),
+    ("down", "cursor-next"),
+    ("left", "volume-down"),
+    ("right", "volume-up"),
+    ("shift-left", "volume-down fine"),
+    ("shift-right", "volume-up fine"),
+    ("m", "toggle-mute"),
+    ("p", "cycle-mute-policy"),
+    ("f", "cycle-sample-rate"),
+    ("S", "cycle-data-source"),
+    ("C", "cycle-clock-source"),
+    ("v", "toggle-db-steps"),
+    // Shift and the number row, digits alone start typing a volume
+    ("!", "preset 1"),
+    ("@", "preset 2"),
+    ("#", "preset 3"),
+    ("$", "preset 4"),
+    ("space", "toggle-mark"),
+    ("x", "hide-marked"),
+    ("R", "mode rename"),
+    ("T", "play-tone"),
+    ("enter", "make-default"),
+];
+
+/// Everything else typed in search mode goes into the query.
+const SEARCH: &[(&str, &str)] = &[
+    ("esc", "search-cancel"),
+    ("enter", "mode back"),
+    ("backspace", "backspace"),
+    ("up", "cursor-prev"),
+    ("down", "cursor-next"),
+];
+
+/// Like search, typing goes into the palette's query.
+const PALETTE: &[(&str, &str)] = &[
+    ("esc", "mode back"),
+    ("enter", "palette-run"),
+    ("backspace", "backspace"),
+    ("up", "palette-prev"),
+    ("down", "palette-next"),
+];
+
+/// Editing an aggregate device's members.
+const DETAIL: &[(&str, &str)] = &[
+    ("up", "member-prev"),
+    ("down", "member-next"),
+    ("space", "toggle-member"),
+    ("f", "toggle-drift"),
+    ("e", "mode property"),
+    ("[", "stereo-left"),
+    ("]", "stereo-right"),
+    ("c", "stereo-pair"),
+    ("m", "toggle-mirror"),
+    ("x", "toggle-hog"),
+    ("R", "mode rename"),
+];
+
+/// Typing goes into the query, see `explorer`.
+const PROPERTY: &[(&str, &str)] = &[
+    ("esc", "mode back"),
+    ("enter", "property-run"),
+    ("backspace", "backspace"),
+];
+
+const APPS: &[(&str, &str)] = &[
+    ("up", "app-prev"),
+    ("down", "app-next"),
+    ("left", "app-volume-down"),
+    ("right", "app-volume-up"),
+    ("/", "app-mute"),
+];
+
+const SYSTEM: &[(&str, &str)] = &[
+    ("left", "backlight-down"),
+    ("right", "backlight-up"),
+    ("s", "display-sleep"),
+    ("l", "lock-screen"),
+    ("m", "mic-modes"),
+];
+
+/// Like input and output, `enter` plays alerts on the highlighted device.
+const ALERT: &[(&str, &str)] = &[
+    ("up", "cursor-prev"),
+    ("down", "cursor-next"),
+    ("left", "alert-volume-down"),
+    ("right", "alert-volume-up"),
+    ("enter", "make-default"),
+];
+
+/// Entered by typing a digit in input or output mode.
+const VOLUME: &[(&str, &str)] = &[
+    ("esc", "mode back"),
+    ("enter", "volume-set"),
+    ("backspace", "backspace"),
+];
+
+/// Typing filters the profiles, or names a new one.
+const PROFILES: &[(&str, &str)] = &[
+    ("esc", "mode back"),
+    ("enter", "profile-apply"),
+    ("tab", "profile-save"),
+    ("backspace", "backspace"),
+    ("up", "profile-prev"),
+    ("down", "profile-next"),
+];
+
+const MACRO_NAME: &[(&str, &str)] = &[
+    ("esc", "macro-discard"),
+    ("enter", "macro-save"),
+    ("backspace", "backspace"),
+];
+
+/// Starts with the device's current name, empty goes back to its own.
+const RENAME: &[(&str, &str)] = &[
+    ("esc", "mode back"),
+    ("enter", "rename-save"),
+    ("backspace", "backspace"),
+];
+
+impl Keymap {
+    /// Default bindings with the config's changes applied. Entries are
+    /// (mode, key, action), with no mode for the global layer.
+    pub fn new(config: &[(Option<UiMode>, String, String)]) -> Self {
+        let layer = |bindings: &[(&str, &str)]| -> Layer {
+            bindings
+                .iter()
+                .map(|(key, action)| (key.to_string(), action.to_string()))
+                .collect()
+        };
+        let mut keymap = Keymap {
+            global: layer(GLOBAL),
+            modes: UiMode::ALL
+                .iter()
+                .map(|mode| match mode {
+                    UiMode::EditInput => {
+                        let mut bindings = layer(EDIT);
+                        bindings.extend(layer(&[("c", "calibrate"), ("a", "apply-gain")]));
+                        bindings
+                    }
+                    UiMode::EditOutput => {
+                        let mut bindings = layer(EDIT);
+                        bindings.extend(layer(&[
+                            ("g", "toggle-group"),
+                            ("[", "balance-left"),
+                            ("]", "balance-right"),
+                            ("=", "balance-center"),
+                        ]));
+                        bindings
+                    }
+                    UiMode::Search => layer(SEARCH),
+                    UiMode::Palette => layer(PALETTE),
+                    UiMode::MacroName => layer(MACRO_NAME),
+                    UiMode::Volume => layer(VOLUME),
+                    UiMode::Profiles => layer(PROFILES),
+                    UiMode::Property => layer(PROPERTY),
+                    UiMode::Apps => layer(APPS),
+                    UiMode::System => layer(SYSTEM),
+                    UiMode::Alert => layer(ALERT),
+                    UiMode::Rename => layer(RENAME),
+                    UiMode::Detail => layer(DETAIL),
+                    UiMode::View => Vec::new(),
+                })
+                .collect(),
+        };
+        for (mode, key, action) in config {
+            let layer = match mode {
+                Some(mode) => &mut keymap.modes[mode.index()],
+                None => &mut keymap.global,
+            };
+            match layer.iter_mut().find(|(k, _)| k == key) {
+                Some(binding) => binding.1 = action.clone(),
+                None => layer.push((key.clone(), action.clone())),
+            }
+        }
+        keymap
+    }
+
+    /// Action for a key in a mode. Search and palette modes type unbound
+    /// characters instead of falling back to the global layer.
+    pub fn action(&self, mode: UiMode, key: Key) -> Option<Action> {
+        self.lookup(mode, key_name(key)?, key)
+    }
+
+    /// Action for an arrow key with shift, like "shift-right".
+    pub fn shifted_action(&self, mode: UiMode, key: Key) -> Option<Action> {
+        let name = format!("shift-{}", key_name(key)?);
+        self.lookup(mode, name, Key::Null)
+    }
+
+    fn lookup(&self, mode: UiMode, name: String, key: Key) -> Option<Action> {
+        let find = |layer: &Layer| {
+            layer
+                .iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, action)| action.clone())
+        };
+        let action = match find(&self.modes[mode.index()]) {
+            Some(action) => action,
+            None if mode.takes_text() => match key {
+                Key::Char(c) => return Some(Action::TextInput(c)),
+                Key::Ctrl('c') => "exit".to_string(),
+                _ => return None,
+            },
+            // Unbound digits start typing an exact volume
+            None if matches!(mode, UiMode::EditInput | UiMode::EditOutput) => match key {
+                Key::Char(c) if c.is_ascii_digit() => return Some(Action::TextInput(c)),
+                _ => find(&self.global)?,
+            },
+            None => find(&self.global)?,
+        };
+        parse_action(&action)
+    }
+
+    /// (key, action) pairs available in a mode, mode keys first.
+    pub fn bindings(&self, mode: UiMode) -> Vec<(&str, &str)> {
+        let local = &self.modes[mode.index()];
+        let mut bindings: Vec<(&str, &str)> = local
+            .iter()
+            .map(|(k, a)| (k.as_str(), a.as_str()))
+            .collect();
+        if !mode.takes_text() {
+            bindings.extend(
+                self.global
+                    .iter()
+                    .filter(|(k, _)| !local.iter().any(|(l, _)| l == k))
+                    .map(|(k, a)| (k.as_str(), a.as_str())),
+            );
+        }
+        bindings.retain(|(_, action)| !action.is_empty());
+        bindings
+    }
+}
+
+/// Actions by name: anything `Action::from_record` knows, plus the ones
+/// that only make sense from the keyboard. Running a macro isn't recorded
+/// itself, its actions are.
+pub fn parse_action(name: &str) -> Option<Action> {
+    let action = match name {
+        "exit" => Action::Exit,
+        "toggle-doctor" => Action::ToggleDoctor,
+        "toggle-history" => Action::ToggleHistory,
+        "backspace" => Action::TextBackspace,
+        "search-cancel" => Action::SearchCancel,
+        "palette-next" => Action::PaletteNext,
+        "palette-prev" => Action::PalettePrev,
+        "palette-run" => Action::PaletteRun,
+        "profile-next" => Action::ProfileNext,
+        "profile-prev" => Action::ProfilePrev,
+        "profile-apply" => Action::ProfileApply,
+        "profile-save" => Action::ProfileSave,
+        "record-macro" => Action::ToggleMacroRecording,
+        "macro-save" => Action::MacroSave,
+        "macro-discard" => Action::MacroDiscard,
+        "rename-save" => Action::RenameSave,
+        "volume-set" => Action::VolumeEntrySubmit,
+        "property-run" => Action::PropertyRun,
+        "stereo-left" => Action::CycleStereoLeft,
+        "stereo-right" => Action::CycleStereoRight,
+        "stereo-pair" => Action::NextStereoPair,
+        "app-next" => Action::AppNext,
+        "app-prev" => Action::AppPrev,
+        "app-volume-up" => Action::AppVolumeUp,
+        "app-volume-down" => Action::AppVolumeDown,
+        "app-mute" => Action::AppMute,
+        "member-next" => Action::MemberNext,
+        "member-prev" => Action::MemberPrev,
+        "toggle-member" => Action::ToggleMember,
+        "toggle-drift" => Action::ToggleDrift,
+        "toggle-mirror" => Action::ToggleMirror,
+        "toggle-hog" => Action::ToggleHog,
+        "play-tone" => Action::PlayTone,
+        "focus-pane" => Action::FocusNextPane,
+        "cycle-log-level" => Action::CycleLogLevel,
+        "toggle-section" => Action::ToggleSection,
+        "toggle-virtual" => Action::ToggleVirtual,
+        "make-default" => Action::MakeDefault,
+        "grow-pane" => Action::ResizePane { grow: true },
+        "shrink-pane" => Action::ResizePane { grow: false },
+        name => {
+            if let Some(pane) = name.strip_prefix("toggle-pane ") {
+                return Pane::parse(pane).map(Action::TogglePane);
+            }
+            match name.strip_prefix("macro ") {
+                Some(name) => Action::RunMacro(name.to_string()),
+                None => return Action::from_record(name),
+            }
+        }
+    };
+    Some(action)
+}
+
+/// Config name of a key, like "a", "space", "up" or "ctrl-c".
+pub fn key_name(key: Key) -> Option<String> {
+    let name = match key {
+        Key::Char(' ') => "space".to_string(),
+        Key::Char('\n') => "enter".to_string(),
+        Key::Char('\t') => "tab".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("ctrl-{c}"),
+        Key::Alt(c) => format!("alt-{c}"),
+        Key::F(n) => format!("f{n}"),
+        Key::Esc => "esc".to_string(),
+        Key::Backspace => "backspace".to_string(),
+        Key::Delete => "delete".to_string(),
+        Key::Up => "up".to_string(),
+        Key::Down => "down".to_string(),
+        Key::Left => "left".to_string(),
+        Key::Right => "right".to_string(),
+        Key::Home => "home".to_string(),
+        Key::End => "end".to_string(),
+        Key::PageUp => "pageup".to_string(),
+        Key::PageDown => "pagedown".to_string(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// An arrow key with shift at the start of terminal input, as xterm sends
+/// it, and how many bytes it takes.
+pub fn parse_shifted(bytes: &[u8]) -> Option<(Key, usize)> {
+    let rest = bytes.strip_prefix(b"\x1b[1;2")?;
+    let key = match rest.first()? {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        _ => return None,
+    };
+    Some((key, 7))
+}
+
+/// Whether a config key name can be typed.
+pub fn is_key_name(name: &str) -> bool {
+    let named = [
+        "space",
+        "enter",
+        "tab",
+        "esc",
+        "backspace",
+        "delete",
+        "up",
+        "down",
+        "left",
+        "right",
+        "home",
+        "end",
+        "pageup",
+        "pagedown",
+        "shift-up",
+        "shift-down",
+        "shift-left",
+        "shift-right",
+    ];
+    let modified = ["ctrl-", "alt-"]
+        .iter()
+        .any(|prefix| matches!(name.strip_prefix(prefix), Some(c) if c.chars().count() == 1));
+    let function = matches!(
+        name.strip_prefix('f').map(|n| n.parse::<u8>()),
+        Some(Ok(1..=12))
+    );
+    name.chars().count() == 1 || named.contains(&name) || modified || function
+}
@@ -0,0 +1,218 @@
+//! Active keyboard layout, through the Text Input Sources (TIS) API. Key
+//! codes are positions on the keyboard, so their names depend on the layout.
+
+use core_foundation::base::{CFRelease, CFTypeRef, TCFType};
+use core_foundation::data::{CFData, CFDataRef};
+use core_foundation::string::{CFString, CFStringRef};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+
+type TISInputSourceRef = *const c_void;
+
+/// kUCKeyActionDisplay
+const KEY_ACTION_DISPLAY: u16 = 3;
+/// kUCKeyTranslateNoDeadKeysMask
+const NO_DEAD_KEYS: u32 = 1;
+
+extern "C" {
+    static kTISPropertyInputSourceID: CFStringRef;
+    static kTISPropertyLocalizedName: CFStringRef;
+    static kTISPropertyUnicodeKeyLayoutData: CFStringRef;
+
+    fn TISCopyCurrentKeyboardLayoutInputSource() -> TISInputSourceRef;
+    fn TISGetInputSourceProperty(source: TISInputSourceRef, key: CFStringRef) -> CFTypeRef;
+    fn LMGetKbdType() -> u8;
+    fn UCKeyTranslate(
+        layout: *const u8,
+        key_code: u16,
+        key_action: u16,
+        modifier_state: u32,
+        keyboard_type: u32,
+        options: u32,
+        dead_key_state: *mut u32,
+        max_length: usize,
+        actual_length: *mut usize,
+        unicode: *mut u16,
+    ) -> i32;
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyboardLayout {
+    /// Input source ID, e.g. "com.apple.keylayout.German".
+    pub id: String,
+    pub name: String,
+    /// Printable key names by key code.
+    names: HashMap<i64, String>,
+}
+
+impl KeyboardLayout {
+    /// The layout currently selected in the menu bar. Must be called from
+    /// the main thread.
+    pub fn current() -> Option<Self> {
+        unsafe {
+            let source = TISCopyCurrentKeyboardLayoutInputSource();
+            if source.is_null() {
+                return None;
+            }
+            let string = |key| {
+                let value = TISGetInputSourceProperty(source, key) as CFStringRef;
+                match value.is_null() {
+                    true => String::new(),
+                    false => CFString::wrap_under_get_rule(value).to_string(),
+                }
+            };
+            let id = string(kTISPropertyInputSourceID);
+            let name = string(kTISPropertyLocalizedName);
+            let data = TISGetInputSourceProperty(source, kTISPropertyUnicodeKeyLayoutData);
+            let names = match data.is_null() {
+                true => HashMap::new(),
+                false => key_names(&CFData::wrap_under_get_rule(data as CFDataRef)),
+            };
+            CFRelease(source);
+            Some(KeyboardLayout { id, name, names })
+        }
+    }
+
+    /// Name of a key code, like "a", "ö" or "return".
+    pub fn key_name(&self, key_code: i64) -> String {
+        match special_key(key_code) {
+            Some(name) => name.to_string(),
+            None => self
+                .names
+                .get(&key_code)
+                .cloned()
+                .unwrap_or_else(|| key_code.to_string()),
+        }
+    }
+}
+
+/// Translate every key code without modifiers.
+unsafe fn key_names(layout: &CFData) -> HashMap<i64, String> {
+    let keyboard_type = LMGetKbdType() as u32;
+    let mut names = HashMap::new();
+    for key_code in 0..128u16 {
+        let mut dead_keys = 0;
+        let mut length = 0;
+        let mut chars = [0u16; 4];
+        let status = UCKeyTranslate(
+            layout.bytes().as_ptr(),
+            key_code,
+            KEY_ACTION_DISPLAY,
+            0,
+            keyboard_type,
+            NO_DEAD_KEYS,
+            &mut dead_keys,
+            chars.len(),
+            &mut length,
+            chars.as_mut_ptr(),
+        );
+        if status == 0 && length > 0 {
+            let name = String::from_utf16_lossy(&chars[..length]);
+            if !name.trim().is_empty() && !name.chars().any(|c| c.is_control()) {
+                names.insert(key_code as i64, name);
+            }
+        }
+    }
+    names
+}
+
+/// Modifier keys, shown through the modifier flags in chords rather than
+/// as keys of their own.
+pub fn is_modifier(key_code: i64) -> bool {
+    (54..=63).contains(&key_code)
+}
+
+/// Modifier names from `ModifierKeys::list_active` and their symbols, in
+/// the order macOS menus show them.
+const MODIFIER_SYMBOLS: &[(&str, &str)] = &[
+    ("fn", "fn"),
+    ("caps lock", "⇪"),
+    ("control", "⌃"),
+    ("option", "⌥"),
+    ("shift", "⇧"),
+    ("command", "⌘"),
+];
+
+/// Held keys as a chord like "⌘ + ⇧ + A", or "-" when nothing is held.
+pub fn chord(modifiers: &[String], keys: &[String]) -> String {
+    let modifiers = MODIFIER_SYMBOLS
+        .iter()
+        .filter(|(name, _)| modifiers.iter().any(|m| m == name))
+        .map(|(_, symbol)| symbol.to_string());
+    let keys = keys.iter().map(|key| match key.chars().count() {
+        1 => key.to_uppercase(),
+        // "page up" -> "Page Up"
+        _ => key
+            .split(' ')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" "),
+    });
+    let parts: Vec<String> = modifiers.chain(keys).collect();
+    match parts.is_empty() {
+        true => "-".to_string(),
+        false => parts.join(" + "),
+    }
+}
+
+/// Whether a hotkey's key, lowercased, can be a key name: a character on
+/// some layout, a key that doesn't type anything, a function key or an
+/// unnamed key code.
+pub fn is_key_name(name: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    name.chars().count() == 1
+        || digits(name)
+        || name.strip_prefix('f').is_some_and(digits)
+        || (0..128).any(|code| special_key(code).is_some_and(|n| n.to_lowercase() == name))
+}
+
+/// Keys that don't type anything are named the same on every layout.
+fn special_key(key_code: i64) -> Option<&'static str> {
+    let name = match key_code {
+        36 => "return",
+        48 => "tab",
+        49 => "space",
+        51 => "delete",
+        53 => "escape",
+        54 => "right command",
+        55 => "command",
+        56 => "shift",
+        57 => "caps lock",
+        58 => "option",
+        59 => "control",
+        60 => "right shift",
+        61 => "right option",
+        62 => "right control",
+        63 => "fn",
+        76 => "enter",
+        117 => "forward delete",
+        115 => "home",
+        119 => "end",
+        116 => "page up",
+        121 => "page down",
+        123 => "left",
+        124 => "right",
+        125 => "down",
+        126 => "up",
+        122 => "F1",
+        120 => "F2",
+        99 => "F3",
+        118 => "F4",
+        96 => "F5",
+        97 => "F6",
+        98 => "F7",
+        100 => "F8",
+        101 => "F9",
+        109 => "F10",
+        103 => "F11",
+        111 => "F12",
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,103 @@
+//! Audio device control for macOS through CoreAudio: list devices, make
+//! one the default, change volumes and mutes, and tap into system-wide key
+//! events. The `mac-controls` TUI is built on it.
+//!
+//! ```no_run
+//! use mac_controls::audio::{AudioState, Channel};
+//! use mac_controls::config::Config;
+//!
+//! let mut audio = AudioState::new(&Config::default());
+//! for (input, output, muted, device) in audio.device_list() {
+//!     println!("{} in:{input} out:{output} muted:{muted}", device.label());
+//! }
+//! audio.move_volume(Channel::Output, 0.1);
+//! ```
+//!
+//! [`events::EventTap`] turns key, modifier and mouse events into
+//! [`events::Action`]s whatever app is in front, which needs the
+//! Accessibility permission. [`listener::Listeners`] reports CoreAudio
+//! property changes the same way.
+//!
+//! Failures are [`error::ControlError`]s, with messages meant for the
+//! user.
+//!
+//! Device settings, history and groups are kept under
+//! `~/.config/mac-controls`, shared with the app.
+
+pub mod audio;
+pub mod backend;
+pub mod config;
+pub mod coreaudio;
+pub mod error;
+pub mod events;
+pub mod listener;
+pub mod log;
+
+// The app itself, for the binary and the benchmarks
+#[doc(hidden)]
+pub mod app;
+#[doc(hidden)]
+pub mod state;
+#[doc(hidden)]
+pub mod tui;
+
+mod alerts;
+mod apps;
+mod attach;
+mod audit;
+mod autogain;
+mod backup;
+mod bluetooth;
+mod call;
+mod cli;
+mod console;
+mod display;
+mod explorer;
+mod frontmost;
+#[cfg(feature = "gestures")]
+mod gestures;
+mod group;
+mod history;
+mod homeassistant;
+mod http;
+mod instance;
+mod ipc;
+mod keyhistory;
+mod keymap;
+mod layout;
+mod macros;
+mod mediakeys;
+mod meter;
+mod metrics;
+mod micmode;
+mod mqtt;
+mod mutes;
+mod night;
+mod objc;
+mod palette;
+mod panes;
+mod permissions;
+mod plugins;
+mod profiles;
+mod quick;
+mod recorder;
+mod reducer;
+mod registry;
+mod rules;
+mod server;
+mod session;
+mod shortcut;
+mod sinks;
+mod splash;
+mod stats;
+mod store;
+mod summary;
+mod system;
+mod tasks;
+mod template;
+mod tls;
+mod tokens;
+mod tone;
+mod watch;
+mod websocket;
+mod wizard;
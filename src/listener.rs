@@ -0,0 +1,176 @@
+//! CoreAudio property listeners, so device changes show up right away
+//! instead of at the next poll.
+//!
+//! Some USB interfaces fire floods of notifications, so events are debounced
+//! per device and property. Dropped events aren't replayed; the regular poll
+//! picks up whatever the last suppressed change was.
+
+use std::collections::BTreeMap;
+use std::os::raw::c_void;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::coreaudio::*;
+use crate::events::Action;
+
+/// Minimum time between forwarded events for the same device/property.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+const SYSTEM_PROPERTIES: [AudioObjectPropertySelector; 4] = [
+    kAudioHardwarePropertyDevices,
+    kAudioHardwarePropertyDefaultInputDevice,
+    kAudioHardwarePropertyDefaultOutputDevice,
+    kAudioHardwarePropertyDefaultSystemOutputDevice,
+];
+const DEVICE_PROPERTIES: [AudioObjectPropertySelector; 2] =
+    [kAudioDevicePropertyVolumeScalar, kAudioDevicePropertyMute];
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counter {
+    pub received: u64,
+    pub forwarded: u64,
+    last_forward: Option<Instant>,
+}
+
+/// Event counters keyed by (object ID, property selector).
+pub type ListenerStats =
+    Arc<Mutex<BTreeMap<(AudioObjectID, AudioObjectPropertySelector), Counter>>>;
+
+/// Registered listeners. Unregisters everything when dropped.
+pub struct Listeners {
+    context: Box<ListenerContext>,
+    devices: Vec<AudioObjectID>,
+}
+
+struct ListenerContext {
+    handler: Mutex<Box<dyn Fn(Action) + Send>>,
+    stats: ListenerStats,
+}
+
+impl Listeners {
+    /// Listen for device list and default device changes. The handler is
+    /// called with `Action::PropertyChanged` on a CoreAudio thread.
+    pub fn new<F>(stats: ListenerStats, handler: F) -> Self
+    where
+        F: Fn(Action) + Send + 'static,
+    {
+        let listeners = Listeners {
+            context: Box::new(ListenerContext {
+                handler: Mutex::new(Box::new(handler)),
+                stats,
+            }),
+            devices: Vec::new(),
+        };
+        for selector in SYSTEM_PROPERTIES {
+            listeners.add(kAudioObjectSystemObject, selector);
+        }
+        listeners
+    }
+
+    /// Follow volume and mute changes on exactly the given devices.
+    pub fn watch(&mut self, ids: &[AudioObjectID]) {
+        for id in self.devices.clone() {
+            if !ids.contains(&id) {
+                for selector in DEVICE_PROPERTIES {
+                    self.remove(id, selector);
+                }
+            }
+        }
+        for id in ids {
+            if !self.devices.contains(id) {
+                for selector in DEVICE_PROPERTIES {
+                    self.add(*id, selector);
+                }
+            }
+        }
+        self.devices = ids.to_vec();
+    }
+
+    fn add(&self, id: AudioObjectID, selector: AudioObjectPropertySelector) {
+        let address = wildcard_address(selector);
+        unsafe {
+            AudioObjectAddPropertyListener(id, &address, on_change, self.client_data());
+        }
+    }
+
+    fn remove(&self, id: AudioObjectID, selector: AudioObjectPropertySelector) {
+        let address = wildcard_address(selector);
+        unsafe {
+            AudioObjectRemovePropertyListener(id, &address, on_change, self.client_data());
+        }
+    }
+
+    fn client_data(&self) -> *mut c_void {
+        &*self.context as *const ListenerContext as *mut c_void
+    }
+}
+
+impl Drop for Listeners {
+    fn drop(&mut self) {
+        self.watch(&[]);
+        for selector in SYSTEM_PROPERTIES {
+            self.remove(kAudioObjectSystemObject, selector);
+        }
+    }
+}
+
+impl ListenerContext {
+    fn notify(&self, id: AudioObjectID, selector: AudioObjectPropertySelector) {
+        let forward = {
+            let mut stats = self.stats.lock().unwrap();
+            let counter = stats.entry((id, selector)).or_default();
+            counter.received += 1;
+            let quiet = !matches!(counter.last_forward, Some(t) if t.elapsed() < DEBOUNCE);
+            if quiet {
+                counter.forwarded += 1;
+                counter.last_forward = Some(Instant::now());
+            }
+            quiet
+        };
+        if forward {
+            (self.handler.lock().unwrap())(Action::PropertyChanged { id, selector });
+        }
+    }
+}
+
+extern "C" fn on_change(
+    id: AudioObjectID,
+    count: UInt32,
+    addresses: *const AudioObjectPropertyAddress,
+    client_data: *mut c_void,
+) -> OSStatus {
+    if addresses.is_null() || client_data.is_null() {
+        return NO_ERR;
+    }
+    unsafe {
+        let context = &*(client_data as *const ListenerContext);
+        for address in std::slice::from_raw_parts(addresses, count as usize) {
+            context.notify(id, address.mSelector);
+        }
+    }
+    NO_ERR
+}
+
+/// Match the property on every scope and channel element.
+fn wildcard_address(selector: AudioObjectPropertySelector) -> AudioObjectPropertyAddress {
+    AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: kAudioObjectPropertyScopeWildcard,
+        mElement: kAudioObjectPropertyElementWildcard,
+    }
+}
+
+/// Property selectors are four-char codes, e.g. 'volm'.
+pub fn fourcc(selector: AudioObjectPropertySelector) -> String {
+    selector
+        .to_be_bytes()
+        .iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,97 @@
+//! In-memory log shown in the log pane: why a rule fired, which CoreAudio
+//! call failed. Any thread can write to it.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Entries kept, older ones are dropped.
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Level::Debug, Level::Info, Level::Warn, Level::Error]
+            .into_iter()
+            .find(|level| level.as_str() == name)
+    }
+
+    /// The next level up, wrapping around, for the level filter.
+    pub fn next(self) -> Self {
+        match self {
+            Level::Debug => Level::Info,
+            Level::Info => Level::Warn,
+            Level::Warn => Level::Error,
+            Level::Error => Level::Debug,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Since the app started.
+    pub time: Duration,
+    pub level: Level,
+    pub message: String,
+}
+
+static ENTRIES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+pub fn write(level: Level, message: impl Into<String>) {
+    let time = STARTED.get_or_init(Instant::now).elapsed();
+    let mut entries = ENTRIES.lock().unwrap();
+    if entries.len() == MAX_ENTRIES {
+        entries.remove(0);
+    }
+    entries.push(Entry {
+        time,
+        level,
+        message: message.into(),
+    });
+}
+
+pub fn debug(message: impl Into<String>) {
+    write(Level::Debug, message);
+}
+
+pub fn info(message: impl Into<String>) {
+    write(Level::Info, message);
+}
+
+pub fn warn(message: impl Into<String>) {
+    write(Level::Warn, message);
+}
+
+pub fn error(message: impl Into<String>) {
+    write(Level::Error, message);
+}
+
+/// The newest entries at or above a level, oldest first.
+pub fn tail(level: Level, count: usize) -> Vec<Entry> {
+    let entries = ENTRIES.lock().unwrap();
+    let mut tail: Vec<Entry> = entries
+        .iter()
+        .rev()
+        .filter(|entry| entry.level >= level)
+        .take(count)
+        .cloned()
+        .collect();
+    tail.reverse();
+    tail
+}
@@ -0,0 +1,45 @@
+//! Named sequences of actions recorded in the TUI, kept in `macros.toml`
+//! with a section per macro and numbered steps:
+//!
+//! ```toml
+//! [desk]
+//! 1 = "default output BuiltInSpeakerDevice"
+//! 2 = "mute-input"
+//! ```
+
+use crate::events::Action;
+use crate::store::Store;
+
+const MACROS_FILE: &str = "macros.toml";
+
+/// Save a macro, replacing any with the same name.
+pub fn save(name: &str, steps: &[String]) -> Result<(), String> {
+    let mut store = Store::open(MACROS_FILE);
+    store.sections.remove(name);
+    for (i, step) in steps.iter().enumerate() {
+        store.set(name, &(i + 1).to_string(), step);
+    }
+    store.save()
+}
+
+/// A macro's actions, in order.
+pub fn load(name: &str) -> Result<Vec<Action>, String> {
+    let store = Store::open(MACROS_FILE);
+    let section = store
+        .sections
+        .get(name)
+        .ok_or_else(|| format!("No macro named \"{name}\""))?;
+    let mut steps = Vec::new();
+    for (key, record) in section {
+        let action = Action::from_record(record)
+            .ok_or_else(|| format!("Macro \"{name}\": invalid action \"{record}\""))?;
+        let index = key.parse::<usize>().unwrap_or(usize::MAX);
+        steps.push((index, action));
+    }
+    steps.sort_by_key(|(index, _)| *index);
+    Ok(steps.into_iter().map(|(_, action)| action).collect())
+}
+
+pub fn names() -> Vec<String> {
+    Store::open(MACROS_FILE).sections.into_keys().collect()
+}
@@ -0,0 +1,258 @@
+//! Hardware volume keys. They arrive as system-defined events rather than
+//! key events, so they need their own tap, and the payload is only readable
+//! through `NSEvent`.
+//!
+//! While routing is on, the keys are swallowed and forwarded to the app,
+//! otherwise macOS handles them as usual.
+//!
+//! The play key can also be posted, to pause whatever is playing.
+
+use core_foundation::base::TCFType;
+use core_foundation::mach_port::{CFMachPort, CFMachPortRef};
+use core_foundation::runloop::{
+    kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopRef, CFRunLoopRunInMode,
+    CFRunLoopStop,
+};
+use core_graphics::sys::CGEventRef;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::events::{Action, MediaKey};
+use crate::objc::{
+    self, msg_send, msg_send_get_long, msg_send_get_short, msg_send_object,
+    objc_autoreleasePoolPop, objc_autoreleasePoolPush, objc_getClass, sel_registerName,
+};
+use crate::tasks::Cancel;
+
+/// NX_SYSDEFINED
+const SYSTEM_DEFINED: u32 = 14;
+/// NX_SUBTYPE_AUX_CONTROL_BUTTONS
+const AUX_CONTROL_BUTTONS: i16 = 8;
+const TAP_DISABLED_BY_TIMEOUT: u32 = 0xFFFFFFFE;
+const TAP_DISABLED_BY_USER_INPUT: u32 = 0xFFFFFFFF;
+// NX_KEYTYPE_* codes
+const KEY_SOUND_UP: isize = 0;
+const KEY_SOUND_DOWN: isize = 1;
+const KEY_MUTE: isize = 7;
+const KEY_PLAY: isize = 16;
+/// kCGHIDEventTap
+const HID_EVENT_TAP: u32 = 0;
+/// kCGEventFlagMaskShift and kCGEventFlagMaskAlternate, held together for
+/// quarter steps.
+const FINE_FLAGS: u64 = 0x20000 | 0x80000;
+/// Longest run of the tap's run loop between cancellation checks.
+const RUN_INTERVAL: Duration = Duration::from_secs(1);
+
+type EventTapCallBack = extern "C" fn(*const c_void, u32, CGEventRef, *mut c_void) -> CGEventRef;
+
+extern "C" {
+    fn CGEventTapCreate(
+        tap: u32,
+        place: u32,
+        options: u32,
+        events_of_interest: u64,
+        callback: EventTapCallBack,
+        user_info: *mut c_void,
+    ) -> CFMachPortRef;
+    fn CGEventTapEnable(tap: CFMachPortRef, enable: bool);
+    fn CGEventPost(tap: u32, event: CGEventRef);
+    fn CGEventGetFlags(event: CGEventRef) -> u64;
+
+}
+
+struct TapContext {
+    route: Arc<AtomicBool>,
+    handler: Box<dyn Fn(Action)>,
+    port: CFMachPortRef,
+}
+
+/// Tap the volume keys, calling the handler with `Action::MediaKey` for
+/// every press while `route` is set. Runs the current thread's run loop
+/// until cancelled.
+pub fn media_key_tap<F>(route: Arc<AtomicBool>, cancel: Cancel, handler: F) -> Result<(), String>
+where
+    F: Fn(Action) + 'static,
+{
+    let context = Box::into_raw(Box::new(TapContext {
+        route,
+        handler: Box::new(handler),
+        port: std::ptr::null(),
+    }));
+    unsafe {
+        // HID location, head insert, active (not listen only)
+        let port = CGEventTapCreate(
+            0,
+            0,
+            0,
+            1 << SYSTEM_DEFINED,
+            on_event,
+            context as *mut c_void,
+        );
+        if port.is_null() {
+            drop(Box::from_raw(context));
+            return Err("Failed to create media key tap.".to_string());
+        }
+        (*context).port = port;
+        let port = CFMachPort::wrap_under_create_rule(port);
+        let Ok(loop_source) = port.create_runloop_source(0) else {
+            return Err("Failed to connect the media key tap to the run loop.".to_string());
+        };
+        CFRunLoop::get_current().add_source(&loop_source, kCFRunLoopCommonModes);
+        CGEventTapEnable(port.as_concrete_TypeRef(), true);
+        let run_loop = CFRunLoop::get_current().as_concrete_TypeRef() as usize;
+        cancel.on_cancel(move || CFRunLoopStop(run_loop as CFRunLoopRef));
+        // Cancelled before the loop started, the stop above came too early
+        while !cancel.is_cancelled() {
+            CFRunLoopRunInMode(kCFRunLoopDefaultMode, RUN_INTERVAL.as_secs_f64(), 0);
+        }
+        CGEventTapEnable(port.as_concrete_TypeRef(), false);
+        CFRunLoop::get_current().remove_source(&loop_source, kCFRunLoopCommonModes);
+        drop(Box::from_raw(context));
+    }
+    Ok(())
+}
+
+extern "C" fn on_event(
+    _proxy: *const c_void,
+    event_type: u32,
+    event: CGEventRef,
+    user_info: *mut c_void,
+) -> CGEventRef {
+    let context = unsafe { &*(user_info as *const TapContext) };
+    if event_type == TAP_DISABLED_BY_TIMEOUT || event_type == TAP_DISABLED_BY_USER_INPUT {
+        unsafe { CGEventTapEnable(context.port, true) };
+        return event;
+    }
+    if event_type != SYSTEM_DEFINED || !context.route.load(Ordering::Relaxed) {
+        return event;
+    }
+    match unsafe { decode(event) } {
+        Some((key, down, repeating)) => {
+            if down {
+                let fine = unsafe { CGEventGetFlags(event) } & FINE_FLAGS == FINE_FLAGS;
+                (context.handler)(Action::MediaKey {
+                    key,
+                    repeating,
+                    fine,
+                });
+            }
+            // Swallow both press and release
+            std::ptr::null_mut()
+        }
+        None => event,
+    }
+}
+
+/// Read a volume key, whether it's pressed and whether it's a repeat from a
+/// system-defined event.
+unsafe fn decode(event: CGEventRef) -> Option<(MediaKey, bool, bool)> {
+    let pool = objc_autoreleasePoolPush();
+    let ns_event = msg_send_object(
+        objc_getClass(c"NSEvent".as_ptr()),
+        sel_registerName(c"eventWithCGEvent:".as_ptr()),
+        event as *mut c_void,
+    );
+    let decoded = if ns_event.is_null() {
+        None
+    } else {
+        let subtype = msg_send_get_short(ns_event, sel_registerName(c"subtype".as_ptr()));
+        let data = msg_send_get_long(ns_event, sel_registerName(c"data1".as_ptr()));
+        let code = (data & 0xFFFF0000) >> 16;
+        let state = (data & 0xFF00) >> 8;
+        let repeating = data & 0x1 == 1;
+        let key = match code {
+            KEY_SOUND_UP => Some(MediaKey::VolumeUp),
+            KEY_SOUND_DOWN => Some(MediaKey::VolumeDown),
+            KEY_MUTE => Some(MediaKey::Mute),
+            _ => None,
+        };
+        match subtype {
+            AUX_CONTROL_BUTTONS => key.map(|key| (key, state == 0xA, repeating)),
+            _ => None,
+        }
+    };
+    objc_autoreleasePoolPop(pool);
+    decoded
+}
+
+/// Press and release the play key, like the keyboard's. Needs permission
+/// to post events.
+pub fn post_play_pause() -> Result<(), String> {
+    // Down, then up, in the key state byte and the modifier flags
+    for state in [0xA, 0xB] {
+        unsafe {
+            let pool = objc_autoreleasePoolPush();
+            let ns_event = msg_send_other_event(
+                objc_getClass(c"NSEvent".as_ptr()),
+                sel_registerName(
+                    c"otherEventWithType:location:modifierFlags:timestamp:windowNumber:context:subtype:data1:data2:"
+                        .as_ptr(),
+                ),
+                SYSTEM_DEFINED as usize,
+                Point { x: 0.0, y: 0.0 },
+                (state as usize) << 8,
+                0.0,
+                0,
+                std::ptr::null_mut(),
+                AUX_CONTROL_BUTTONS,
+                (KEY_PLAY << 16) | (state << 8),
+                -1,
+            );
+            let event = match ns_event.is_null() {
+                true => std::ptr::null_mut(),
+                false => msg_send(ns_event, sel_registerName(c"CGEvent".as_ptr())) as CGEventRef,
+            };
+            if !event.is_null() {
+                CGEventPost(HID_EVENT_TAP, event);
+            }
+            objc_autoreleasePoolPop(pool);
+            if event.is_null() {
+                return Err("Unable to make a play key event".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// NSPoint
+#[repr(C)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+type OtherEvent = extern "C" fn(
+    *mut c_void,
+    *mut c_void,
+    usize,
+    Point,
+    usize,
+    f64,
+    isize,
+    *mut c_void,
+    i16,
+    isize,
+    isize,
+) -> *mut c_void;
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn msg_send_other_event(
+    class: *mut c_void,
+    sel: *mut c_void,
+    event_type: usize,
+    location: Point,
+    flags: usize,
+    timestamp: f64,
+    window: isize,
+    context: *mut c_void,
+    subtype: i16,
+    data1: isize,
+    data2: isize,
+) -> *mut c_void {
+    let send: OtherEvent = objc::send();
+    send(
+        class, sel, event_type, location, flags, timestamp, window, context, subtype, data1, data2,
+    )
+}
@@ -0,0 +1,250 @@
+//! Input level metering. An IOProc on the input device sums up incoming
+//! samples and reports RMS/peak levels back through an Action handler.
+
+use std::collections::VecDeque;
+use std::os::raw::c_void;
+use std::time::{Duration, Instant};
+
+use crate::coreaudio::*;
+use crate::events::Action;
+use crate::store::Store;
+
+/// How often levels are reported from the audio thread.
+pub const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+/// Number of reports kept for the noise floor estimate (~10 seconds).
+const HISTORY_LEN: usize = 100;
+/// Reports needed before a noise floor estimate is trusted.
+const MIN_HISTORY: usize = 20;
+/// A noise floor above this (dBFS) is considered a noisy environment.
+const NOISY_FLOOR_DB: f32 = -50.0;
+/// Lowest level shown, anything quieter is treated as silence.
+pub const SILENCE_DB: f32 = -96.0;
+
+/// A running IOProc on an input device. Stops when dropped.
+pub struct Meter {
+    device: AudioDeviceID,
+    proc_id: AudioDeviceIOProcID,
+    context: *mut MeterContext,
+}
+
+struct MeterContext {
+    handler: Box<dyn Fn(Action) + Send>,
+    last_report: Instant,
+    sum_squares: f64,
+    samples: u64,
+    peak: f32,
+}
+
+impl Meter {
+    /// Start metering a device's input, calling handler with
+    /// `Action::Meter` on the audio thread.
+    pub fn start<F>(device: AudioDeviceID, handler: F) -> Result<Self, String>
+    where
+        F: Fn(Action) + Send + 'static,
+    {
+        let context = Box::into_raw(Box::new(MeterContext {
+            handler: Box::new(handler),
+            last_report: Instant::now(),
+            sum_squares: 0.0,
+            samples: 0,
+            peak: 0.0,
+        }));
+        let mut proc_id: AudioDeviceIOProcID = None;
+        unsafe {
+            if AudioDeviceCreateIOProcID(device, meter_proc, context as *mut c_void, &mut proc_id)
+                != NO_ERR
+            {
+                drop(Box::from_raw(context));
+                return Err("Unable to create input IOProc".to_string());
+            }
+            if AudioDeviceStart(device, proc_id) != NO_ERR {
+                AudioDeviceDestroyIOProcID(device, proc_id);
+                drop(Box::from_raw(context));
+                return Err("Unable to start input IOProc".to_string());
+            }
+        }
+        Ok(Meter {
+            device,
+            proc_id,
+            context,
+        })
+    }
+
+    /// Device being metered.
+    pub fn device(&self) -> AudioDeviceID {
+        self.device
+    }
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        unsafe {
+            // Stop waits for the IOProc to return, so the context is no
+            // longer in use once destroyed.
+            AudioDeviceStop(self.device, self.proc_id);
+            AudioDeviceDestroyIOProcID(self.device, self.proc_id);
+            drop(Box::from_raw(self.context));
+        }
+    }
+}
+
+/// Runs on CoreAudio's IO thread. Samples are the HAL's native 32-bit floats.
+extern "C" fn meter_proc(
+    _device: AudioObjectID,
+    _now: *const AudioTimeStamp,
+    input: *const AudioBufferList,
+    _input_time: *const AudioTimeStamp,
+    _output: *mut AudioBufferList,
+    _output_time: *const AudioTimeStamp,
+    client_data: *mut c_void,
+) -> OSStatus {
+    if input.is_null() || client_data.is_null() {
+        return NO_ERR;
+    }
+    unsafe {
+        let ctx = &mut *(client_data as *mut MeterContext);
+        let list = &*input;
+        let buffers =
+            std::slice::from_raw_parts(list.mBuffers.as_ptr(), list.mNumberBuffers as usize);
+        for buffer in buffers {
+            if buffer.mData.is_null() {
+                continue;
+            }
+            let len = buffer.mDataByteSize as usize / std::mem::size_of::<Float32>();
+            let samples = std::slice::from_raw_parts(buffer.mData as *const Float32, len);
+            for sample in samples {
+                ctx.sum_squares += (*sample as f64) * (*sample as f64);
+                ctx.peak = ctx.peak.max(sample.abs());
+            }
+            ctx.samples += len as u64;
+        }
+        if ctx.last_report.elapsed() >= REPORT_INTERVAL && ctx.samples > 0 {
+            let rms = (ctx.sum_squares / ctx.samples as f64).sqrt() as f32;
+            (ctx.handler)(Action::Meter {
+                rms,
+                peak: ctx.peak,
+            });
+            ctx.last_report = Instant::now();
+            ctx.sum_squares = 0.0;
+            ctx.samples = 0;
+            ctx.peak = 0.0;
+        }
+    }
+    NO_ERR
+}
+
+/// Latest levels plus a short history used to estimate background noise.
+#[derive(Debug, Default)]
+pub struct MeterState {
+    pub rms: f32,
+    pub peak: f32,
+    history: VecDeque<f32>,
+}
+
+impl MeterState {
+    /// Record a new level report.
+    pub fn push(&mut self, rms: f32, peak: f32) {
+        self.rms = rms;
+        self.peak = peak;
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(to_db(rms));
+    }
+
+    /// True once the first level report came in.
+    pub fn has_levels(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    /// Forget levels, e.g. when the metered device changes.
+    pub fn reset(&mut self) {
+        *self = MeterState::default();
+    }
+
+    /// Estimated noise floor in dBFS. Uses a low percentile of recent RMS
+    /// levels, so speech doesn't count as noise.
+    pub fn noise_floor(&self) -> Option<f32> {
+        if self.history.len() < MIN_HISTORY {
+            return None;
+        }
+        let mut levels: Vec<f32> = self.history.iter().copied().collect();
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Some(levels[levels.len() / 10])
+    }
+
+    /// True when background noise is unusually high.
+    pub fn is_noisy(&self) -> bool {
+        matches!(self.noise_floor(), Some(db) if db > NOISY_FLOOR_DB)
+    }
+}
+
+/// Convert a linear amplitude to dBFS.
+pub fn to_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return SILENCE_DB;
+    }
+    (20.0 * amplitude.log10()).max(SILENCE_DB)
+}
+
+/// Calibration run length, long enough for a couple of sentences.
+pub const CALIBRATION_TIME: Duration = Duration::from_secs(10);
+/// Peak level (linear, about -6 dBFS) speech should reach at the suggested gain.
+const TARGET_PEAK: f32 = 0.5;
+/// Peaks below this mean nobody spoke, so there's nothing to suggest.
+const MIN_PEAK: f32 = 0.001;
+const GAIN_FILE: &str = "gain.toml";
+
+/// Tracks input peaks while the user speaks normally, then suggests an input
+/// volume that keeps peaks clear of clipping.
+#[derive(Debug)]
+pub struct Calibration {
+    pub uid: String,
+    level: f32,
+    started: Instant,
+    max_peak: f32,
+}
+
+impl Calibration {
+    /// Start calibrating a device currently at the given input volume.
+    pub fn new(uid: &str, level: f32) -> Self {
+        Calibration {
+            uid: uid.to_string(),
+            level,
+            started: Instant::now(),
+            max_peak: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, peak: f32) {
+        self.max_peak = self.max_peak.max(peak);
+    }
+
+    pub fn remaining(&self) -> Duration {
+        CALIBRATION_TIME.saturating_sub(self.started.elapsed())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Suggested input volume, assuming peaks scale with the volume scalar.
+    pub fn suggestion(&self) -> Option<f32> {
+        if self.max_peak < MIN_PEAK {
+            return None;
+        }
+        Some((self.level * TARGET_PEAK / self.max_peak).clamp(0.05, 1.0))
+    }
+}
+
+/// Load the last gain suggestion for a device.
+pub fn saved_gain(uid: &str) -> Option<f32> {
+    Store::open(GAIN_FILE).get(uid, "gain")?.parse().ok()
+}
+
+/// Remember a gain suggestion for a device.
+pub fn save_gain(uid: &str, level: f32) -> Result<(), String> {
+    let mut store = Store::open(GAIN_FILE);
+    store.set(uid, "gain", format!("{level:.2}"));
+    store.save()
+}
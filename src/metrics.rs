@@ -0,0 +1,84 @@
+//! What each poll costs, for the doctor panel: how long the update, the
+//! whole poll and a frame take, and how many CoreAudio queries a poll
+//! makes. Only the last `SAMPLES` of each are kept.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::audio;
+
+/// Samples kept per timing.
+pub const SAMPLES: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    timings: RefCell<BTreeMap<&'static str, VecDeque<Duration>>>,
+    queries: RefCell<VecDeque<u64>>,
+    /// Query count at the last `count_queries`.
+    last_count: Cell<u64>,
+}
+
+/// Average and worst of the kept samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary<T> {
+    pub average: T,
+    pub max: T,
+    pub samples: usize,
+}
+
+impl Metrics {
+    pub fn record(&self, name: &'static str, took: Duration) {
+        push(self.timings.borrow_mut().entry(name).or_default(), took);
+    }
+
+    /// Run `f`, recording how long it took under `name`.
+    pub fn time<T>(&self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(name, started.elapsed());
+        result
+    }
+
+    /// Record the CoreAudio queries made since the last call, once a poll.
+    pub fn count_queries(&self) {
+        let count = audio::query_count();
+        let since = count - self.last_count.replace(count);
+        push(&mut self.queries.borrow_mut(), since);
+    }
+
+    /// Every timing by name.
+    pub fn timings(&self) -> Vec<(&'static str, Summary<Duration>)> {
+        self.timings
+            .borrow()
+            .iter()
+            .map(|(name, samples)| {
+                let total: Duration = samples.iter().sum();
+                let summary = Summary {
+                    average: total / samples.len() as u32,
+                    max: samples.iter().max().copied().unwrap_or_default(),
+                    samples: samples.len(),
+                };
+                (*name, summary)
+            })
+            .collect()
+    }
+
+    /// CoreAudio queries per poll, none before the first one.
+    pub fn queries(&self) -> Option<Summary<u64>> {
+        let samples = self.queries.borrow();
+        let max = samples.iter().max().copied()?;
+        Some(Summary {
+            average: samples.iter().sum::<u64>() / samples.len() as u64,
+            max,
+            samples: samples.len(),
+        })
+    }
+}
+
+fn push<T>(samples: &mut VecDeque<T>, sample: T) {
+    if samples.len() == SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
@@ -0,0 +1,99 @@
+//! The microphone mode macOS applies to apps doing voice processing, like
+//! call apps: Standard, Voice Isolation or Wide Spectrum, normally picked
+//! under Mic Mode in Control Center while the mic is in use.
+//!
+//! AVCaptureDevice reports the mode, macOS 12 and up, but apps can't set
+//! it. Changing it means opening the same system picker for the user,
+//! which `show_picker` does.
+
+use std::os::raw::c_void;
+
+use crate::objc::{msg_send_get_long, msg_send_long, objc_getClass, responds, sel_registerName};
+
+/// `AVCaptureSystemUserInterfaceMicrophoneModes`.
+const MICROPHONE_MODES_UI: isize = 2;
+
+/// `AVCaptureMicrophoneMode`, in its raw order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicMode {
+    Standard,
+    WideSpectrum,
+    VoiceIsolation,
+}
+
+impl MicMode {
+    pub const ALL: [MicMode; 3] = [
+        MicMode::Standard,
+        MicMode::WideSpectrum,
+        MicMode::VoiceIsolation,
+    ];
+
+    /// Name in profiles.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MicMode::Standard => "standard",
+            MicMode::WideSpectrum => "wide-spectrum",
+            MicMode::VoiceIsolation => "voice-isolation",
+        }
+    }
+
+    /// Name as Control Center shows it.
+    pub fn label(self) -> &'static str {
+        match self {
+            MicMode::Standard => "Standard",
+            MicMode::WideSpectrum => "Wide Spectrum",
+            MicMode::VoiceIsolation => "Voice Isolation",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        MicMode::ALL.into_iter().find(|mode| mode.as_str() == name)
+    }
+}
+
+/// The mode last picked in Control Center.
+pub fn preferred() -> Result<MicMode, String> {
+    read_mode(c"preferredMicrophoneMode")
+}
+
+/// The mode in effect, which differs from the preferred one while the
+/// app using the mic doesn't do voice processing.
+pub fn active() -> Result<MicMode, String> {
+    read_mode(c"activeMicrophoneMode")
+}
+
+/// Open Control Center's mic mode picker.
+pub fn show_picker() -> Result<(), String> {
+    unsafe {
+        let class = capture_device()?;
+        let sel = sel_registerName(c"showSystemUserInterface:".as_ptr());
+        if !responds(class, sel) {
+            return Err("Mic modes need macOS 12 or later".to_string());
+        }
+        msg_send_long(class, sel, MICROPHONE_MODES_UI);
+    }
+    Ok(())
+}
+
+fn read_mode(property: &std::ffi::CStr) -> Result<MicMode, String> {
+    unsafe {
+        let class = capture_device()?;
+        let sel = sel_registerName(property.as_ptr());
+        if !responds(class, sel) {
+            return Err("Mic modes need macOS 12 or later".to_string());
+        }
+        let raw = msg_send_get_long(class, sel);
+        usize::try_from(raw)
+            .ok()
+            .and_then(|i| MicMode::ALL.get(i).copied())
+            .ok_or(format!("Unknown mic mode {raw}"))
+    }
+}
+
+unsafe fn capture_device() -> Result<*mut c_void, String> {
+    let class = objc_getClass(c"AVCaptureDevice".as_ptr());
+    match class.is_null() {
+        true => Err("AVFoundation isn't available".to_string()),
+        false => Ok(class),
+    }
+}
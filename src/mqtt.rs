@@ -0,0 +1,197 @@
+//! Just enough of MQTT 3.1.1 for Home Assistant: connect with a last will,
+//! publish and subscribe at QoS 0, and keep the connection alive.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Seconds the broker waits for a packet before dropping us, pings are
+/// sent at half of it.
+const KEEP_ALIVE: u16 = 60;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+/// Retained message the broker publishes when the connection drops.
+#[derive(Debug, Clone)]
+pub struct Will {
+    pub topic: String,
+    pub payload: String,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    stream: Arc<Mutex<TcpStream>>,
+    alive: Arc<AtomicBool>,
+}
+
+impl Client {
+    /// Connect and start reading messages for subscribed topics into the
+    /// handler, on a background thread.
+    pub fn connect(
+        host: &str,
+        port: u16,
+        client_id: &str,
+        login: Option<(&str, &str)>,
+        will: &Will,
+        handler: impl Fn(&str, &[u8]) + Send + 'static,
+    ) -> Result<Self, String> {
+        let mut stream = TcpStream::connect((host, port))
+            .map_err(|e| format!("Unable to connect to MQTT broker {host}:{port}: {e}"))?;
+        // Clean session, retained will at QoS 0
+        let mut flags = 0x02 | 0x04 | 0x20;
+        if login.is_some() {
+            flags |= 0x80 | 0x40;
+        }
+        let mut body = Vec::new();
+        push_string(&mut body, "MQTT");
+        body.extend([4, flags]);
+        body.extend(KEEP_ALIVE.to_be_bytes());
+        push_string(&mut body, client_id);
+        push_string(&mut body, &will.topic);
+        push_string(&mut body, &will.payload);
+        if let Some((user, password)) = login {
+            push_string(&mut body, user);
+            push_string(&mut body, password);
+        }
+        stream
+            .write_all(&packet(CONNECT, &body))
+            .map_err(|e| format!("Unable to send to the MQTT broker: {e}"))?;
+        let (kind, body) =
+            read_packet(&mut stream).ok_or("No answer from the MQTT broker".to_string())?;
+        match (kind & 0xf0, body.get(1)) {
+            (CONNACK, Some(0)) => {}
+            (CONNACK, Some(4 | 5)) => return Err("MQTT broker refused the login".to_string()),
+            _ => return Err("MQTT broker refused the connection".to_string()),
+        }
+
+        let alive = Arc::new(AtomicBool::new(true));
+        let mut reader = stream
+            .try_clone()
+            .map_err(|e| format!("Unable to read from the MQTT broker: {e}"))?;
+        let stream = Arc::new(Mutex::new(stream));
+        let reading = alive.clone();
+        thread::spawn(move || {
+            while let Some((kind, body)) = read_packet(&mut reader) {
+                if kind & 0xf0 == PUBLISH {
+                    if let Some((topic, payload)) = parse_publish(kind, &body) {
+                        handler(&topic, payload);
+                    }
+                }
+            }
+            reading.store(false, Ordering::Relaxed);
+        });
+        let pinging = alive.clone();
+        let ping_stream = stream.clone();
+        thread::spawn(move || {
+            while pinging.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_secs(KEEP_ALIVE as u64 / 2));
+                if ping_stream
+                    .lock()
+                    .unwrap()
+                    .write_all(&[PINGREQ, 0])
+                    .is_err()
+                {
+                    pinging.store(false, Ordering::Relaxed);
+                }
+            }
+        });
+        Ok(Client { stream, alive })
+    }
+
+    /// Whether the connection is still up.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    pub fn publish(&self, topic: &str, payload: &str, retain: bool) -> Result<(), String> {
+        let mut body = Vec::new();
+        push_string(&mut body, topic);
+        body.extend(payload.as_bytes());
+        self.send(&packet(PUBLISH | retain as u8, &body))
+    }
+
+    /// Subscribe at QoS 0, topics can have `+` and `#` wildcards.
+    pub fn subscribe(&self, topic: &str) -> Result<(), String> {
+        // Packet ID, unused as SUBACKs aren't checked
+        let mut body = vec![0, 1];
+        push_string(&mut body, topic);
+        body.push(0);
+        self.send(&packet(SUBSCRIBE, &body))
+    }
+
+    /// Leave cleanly, which skips the will.
+    pub fn disconnect(&self) {
+        let _ = self.send(&[DISCONNECT, 0]);
+        self.alive.store(false, Ordering::Relaxed);
+    }
+
+    fn send(&self, packet: &[u8]) -> Result<(), String> {
+        let sent = self.stream.lock().unwrap().write_all(packet);
+        sent.map_err(|e| {
+            self.alive.store(false, Ordering::Relaxed);
+            format!("Lost the MQTT broker: {e}")
+        })
+    }
+}
+
+/// Fixed header and body.
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    // Remaining length, 7 bits per byte
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        match len {
+            0 => {
+                packet.push(byte);
+                break;
+            }
+            _ => packet.push(byte | 0x80),
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    out.extend((s.len() as u16).to_be_bytes());
+    out.extend(s.as_bytes());
+}
+
+fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    let mut byte = [0u8];
+    stream.read_exact(&mut byte).ok()?;
+    let kind = byte[0];
+    let mut len = 0;
+    for shift in (0..4).map(|i| i * 7) {
+        stream.read_exact(&mut byte).ok()?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).ok()?;
+    Some((kind, body))
+}
+
+/// Topic and payload of an incoming PUBLISH.
+fn parse_publish(kind: u8, body: &[u8]) -> Option<(String, &[u8])> {
+    let len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + len)?).ok()?;
+    // QoS 1 and 2 messages carry a packet ID
+    let start = match (kind >> 1) & 0x03 {
+        0 => 2 + len,
+        _ => 4 + len,
+    };
+    Some((topic.to_string(), body.get(start..)?))
+}
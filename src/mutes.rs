@@ -0,0 +1,162 @@
+//! The mute workaround's decisions, as functions of a channel's `Volume`
+//! that update it and return the CoreAudio writes to make instead of
+//! making them. `AudioState` runs the effects; everything here can be
+//! followed (or replayed) without a device.
+
+use crate::audio::{Channel, Volume};
+use crate::coreaudio::AudioDeviceID;
+
+/// A write to a device, decided here and made by `AudioState`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    SetVolume(AudioDeviceID, Channel, f32),
+    SetMute(AudioDeviceID, Channel, bool),
+}
+
+/// Toggle a channel's mute. With `native` the system mute is used, else
+/// the volume-zero workaround; `sys_muted` is the system mute as read.
+/// The volume is updated assuming the writes work, the next update checks.
+pub fn toggle(
+    id: AudioDeviceID,
+    channel: Channel,
+    volume: &mut Volume,
+    native: bool,
+    sys_muted: bool,
+) -> Vec<Effect> {
+    let muted = volume.workaround_mute;
+    let effect = if native && sys_muted {
+        Effect::SetMute(id, channel, false)
+    } else if muted {
+        Effect::SetVolume(id, channel, volume.cache)
+    } else if native {
+        Effect::SetMute(id, channel, true)
+    } else {
+        Effect::SetVolume(id, channel, 0.0)
+    };
+    if native && !muted {
+        volume.native_mute = !sys_muted;
+    } else {
+        volume.workaround_mute = !muted;
+    }
+    // Or the next update takes the unmute for a jump and pulls it back
+    if let Effect::SetVolume(_, _, level) = effect {
+        volume.level = level;
+    }
+    vec![effect]
+}
+
+/// Follow the system mute read from a channel. When `takes_over`, a
+/// system mute becomes a workaround mute: volume to zero, the level kept
+/// to unmute to, and the system mute cleared. Otherwise it's mirrored, and
+/// a flip while workaround-muted hands the channel back to the system
+/// mute, which the second value reports.
+pub fn observe(
+    id: AudioDeviceID,
+    channel: Channel,
+    volume: &mut Volume,
+    takes_over: bool,
+    muted: Option<bool>,
+) -> (Vec<Effect>, bool) {
+    let mut effects = Vec::new();
+    // A taken over mute is unset below
+    let native = !takes_over && muted == Some(true);
+    let lifted = !takes_over && volume.workaround_mute && native != volume.native_mute;
+    if lifted {
+        effects.push(Effect::SetVolume(id, channel, volume.cache));
+        volume.level = volume.cache;
+        volume.workaround_mute = false;
+    }
+    volume.native_mute = native;
+    if takes_over && muted == Some(true) {
+        effects.push(Effect::SetVolume(id, channel, 0.0));
+        // Keep the level to unmute to, unless already muted at zero
+        if !volume.workaround_mute && volume.level > 0.0 {
+            volume.cache = volume.level;
+        }
+        volume.level = 0.0;
+        volume.workaround_mute = true;
+        effects.push(Effect::SetMute(id, channel, false));
+    }
+    (effects, lifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(level: f32) -> Volume {
+        Volume {
+            enabled: true,
+            selectable: true,
+            level,
+            cache: level,
+            native_mute: false,
+            workaround_mute: false,
+            settable: true,
+        }
+    }
+
+    #[test]
+    fn toggle_uses_the_system_mute_when_there_is_one() {
+        let mut v = volume(0.5);
+        let effects = toggle(7, Channel::Output, &mut v, true, false);
+        assert_eq!(effects, [Effect::SetMute(7, Channel::Output, true)]);
+        assert!(v.native_mute && !v.workaround_mute);
+
+        let effects = toggle(7, Channel::Output, &mut v, true, true);
+        assert_eq!(effects, [Effect::SetMute(7, Channel::Output, false)]);
+        assert!(!v.native_mute);
+    }
+
+    #[test]
+    fn toggle_works_around_a_missing_mute() {
+        let mut v = volume(0.5);
+        let effects = toggle(7, Channel::Input, &mut v, false, false);
+        assert_eq!(effects, [Effect::SetVolume(7, Channel::Input, 0.0)]);
+        assert!(v.workaround_mute);
+        assert_eq!((v.level, v.cache), (0.0, 0.5));
+
+        let effects = toggle(7, Channel::Input, &mut v, false, false);
+        assert_eq!(effects, [Effect::SetVolume(7, Channel::Input, 0.5)]);
+        assert!(!v.workaround_mute);
+        assert_eq!(v.level, 0.5);
+    }
+
+    #[test]
+    fn observe_takes_over_a_system_mute() {
+        let mut v = volume(0.4);
+        let (effects, lifted) = observe(7, Channel::Output, &mut v, true, Some(true));
+        assert_eq!(
+            effects,
+            [
+                Effect::SetVolume(7, Channel::Output, 0.0),
+                Effect::SetMute(7, Channel::Output, false),
+            ]
+        );
+        assert!(!lifted);
+        assert!(v.workaround_mute && !v.native_mute);
+        assert_eq!((v.level, v.cache), (0.0, 0.4));
+
+        // Cleared by the takeover, so nothing more to do
+        let (effects, _) = observe(7, Channel::Output, &mut v, true, Some(false));
+        assert!(effects.is_empty());
+        assert!(v.workaround_mute);
+    }
+
+    #[test]
+    fn observe_hands_back_to_a_system_mute() {
+        let mut v = volume(0.4);
+        let (effects, lifted) = observe(7, Channel::Output, &mut v, false, Some(true));
+        assert!(effects.is_empty() && !lifted);
+        assert!(v.native_mute);
+
+        // Workaround-muted, then the system mute flips: the volume comes back
+        toggle(7, Channel::Output, &mut v, false, true);
+        assert!(v.workaround_mute);
+        let (effects, lifted) = observe(7, Channel::Output, &mut v, false, Some(false));
+        assert_eq!(effects, [Effect::SetVolume(7, Channel::Output, 0.4)]);
+        assert!(lifted);
+        assert!(!v.workaround_mute && !v.native_mute);
+        assert_eq!(v.level, 0.4);
+    }
+}
@@ -0,0 +1,69 @@
+//! Night volume cap: between two times of day, speakers get a lower
+//! maximum volume on top of their own caps, while headphones are left
+//! alone. From `[night]`:
+//!
+//! ```toml
+//! [night]
+//! start = "22:00"
+//! end = "7:30"
+//! cap = 0.3
+//! headphones = "Desk Headset"
+//! ```
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use core_foundation::date::CFDate;
+use core_foundation::timezone::CFTimeZone;
+
+use crate::audio::{Category, Device};
+
+const DAY_MINUTES: u32 = 24 * 60;
+
+#[derive(Debug, Clone)]
+pub struct NightConfig {
+    /// Minutes after midnight.
+    pub start: u32,
+    pub end: u32,
+    pub cap: f32,
+    /// More headphones by UID, name or alias, beyond the ones recognized
+    /// by their transport or UID.
+    pub headphones: Vec<String>,
+}
+
+impl NightConfig {
+    /// Whether it's night at a time of day, in minutes after midnight.
+    /// Nights can wrap past midnight.
+    pub fn is_night(&self, minutes: u32) -> bool {
+        match self.start <= self.end {
+            true => (self.start..self.end).contains(&minutes),
+            false => minutes >= self.start || minutes < self.end,
+        }
+    }
+
+    /// Whether a device is headphones, which keep their volume at night.
+    /// Bluetooth outputs nearly always are, and the built-in jack has its
+    /// own UID.
+    pub fn is_headphones(&self, device: &Device) -> bool {
+        device.category == Category::Bluetooth
+            || device.uid.to_lowercase().contains("headphone")
+            || self.headphones.iter().any(|key| device.matches(key))
+    }
+}
+
+/// A time like "22:00" or "7", in minutes after midnight.
+pub fn parse_time(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':').unwrap_or((value.trim(), "0"));
+    let hours: u32 = hours.parse().ok().filter(|h| *h < 24)?;
+    let minutes: u32 = minutes.parse().ok().filter(|m| *m < 60)?;
+    Some(hours * 60 + minutes)
+}
+
+/// Local time of day, in minutes after midnight.
+pub fn local_minutes() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let offset = CFTimeZone::system().seconds_from_gmt(CFDate::now()) as i64;
+    ((now + offset).rem_euclid(DAY_MINUTES as i64 * 60) / 60) as u32
+}
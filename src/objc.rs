@@ -0,0 +1,77 @@
+//! The Objective-C runtime, for the AppKit, AVFoundation, IOBluetooth and
+//! private framework calls that have no C API.
+//!
+//! `objc_msgSend` has to be called through the exact signature of the
+//! method, so the common ones have a shim here and the rest go through
+//! `send`.
+
+use std::os::raw::{c_char, c_void};
+
+extern "C" {
+    pub fn objc_getClass(name: *const c_char) -> *mut c_void;
+    pub fn sel_registerName(name: *const c_char) -> *mut c_void;
+    pub fn objc_autoreleasePoolPush() -> *mut c_void;
+    pub fn objc_autoreleasePoolPop(pool: *mut c_void);
+    fn objc_msgSend();
+}
+
+/// `objc_msgSend` as `F`, an `extern "C" fn` with the method's signature
+/// after the receiver and selector.
+pub unsafe fn send<F: Copy>() -> F {
+    assert_eq!(std::mem::size_of::<F>(), std::mem::size_of::<fn()>());
+    std::mem::transmute_copy(&(objc_msgSend as unsafe extern "C" fn()))
+}
+
+/// Whether an object or class has a method, for private and newer API.
+pub unsafe fn responds(object: *mut c_void, sel: *mut c_void) -> bool {
+    let send: extern "C" fn(*mut c_void, *mut c_void, *mut c_void) -> bool = send();
+    send(
+        object,
+        sel_registerName(c"respondsToSelector:".as_ptr()),
+        sel,
+    )
+}
+
+pub unsafe fn msg_send(object: *mut c_void, sel: *mut c_void) -> *mut c_void {
+    let send: extern "C" fn(*mut c_void, *mut c_void) -> *mut c_void = send();
+    send(object, sel)
+}
+
+pub unsafe fn msg_send_object(
+    object: *mut c_void,
+    sel: *mut c_void,
+    arg: *mut c_void,
+) -> *mut c_void {
+    let send: extern "C" fn(*mut c_void, *mut c_void, *mut c_void) -> *mut c_void = send();
+    send(object, sel, arg)
+}
+
+pub unsafe fn msg_send_u32(object: *mut c_void, sel: *mut c_void, arg: u32) -> *mut c_void {
+    let send: extern "C" fn(*mut c_void, *mut c_void, u32) -> *mut c_void = send();
+    send(object, sel, arg)
+}
+
+pub unsafe fn msg_send_long(object: *mut c_void, sel: *mut c_void, arg: isize) {
+    let send: extern "C" fn(*mut c_void, *mut c_void, isize) = send();
+    send(object, sel, arg)
+}
+
+pub unsafe fn msg_send_bool(object: *mut c_void, sel: *mut c_void, arg: bool) {
+    let send: extern "C" fn(*mut c_void, *mut c_void, bool) = send();
+    send(object, sel, arg)
+}
+
+pub unsafe fn msg_send_get_long(object: *mut c_void, sel: *mut c_void) -> isize {
+    let send: extern "C" fn(*mut c_void, *mut c_void) -> isize = send();
+    send(object, sel)
+}
+
+pub unsafe fn msg_send_get_short(object: *mut c_void, sel: *mut c_void) -> i16 {
+    let send: extern "C" fn(*mut c_void, *mut c_void) -> i16 = send();
+    send(object, sel)
+}
+
+pub unsafe fn msg_send_get_byte(object: *mut c_void, sel: *mut c_void) -> u8 {
+    let send: extern "C" fn(*mut c_void, *mut c_void) -> u8 = send();
+    send(object, sel)
+}
@@ -0,0 +1,147 @@
+//! Command palette: fuzzy search over every action by name, so rarely used
+//! ones don't need a key of their own.
+
+use crate::macros;
+use crate::profiles;
+use crate::state::AppState;
+
+/// Actions offered by name, besides the per-device ones.
+const COMMANDS: &[&str] = &[
+    "mode view",
+    "mode input",
+    "mode output",
+    "mode search",
+    "mode detail",
+    "mode profiles",
+    "mode apps",
+    "mode system",
+    "mode alert",
+    "mode rename",
+    "alert-volume-up",
+    "alert-volume-down",
+    "backlight-up",
+    "backlight-down",
+    "display-sleep",
+    "lock-screen",
+    "record-output",
+    "mic-modes",
+    "mute-input",
+    "fix-silent",
+    "align-sample-rates",
+    "toggle-virtual",
+    "play-pause",
+    "boost",
+    "broadcast",
+    "toggle-mute",
+    "cycle-mute-policy",
+    "cycle-sample-rate",
+    "cycle-data-source",
+    "cycle-clock-source",
+    "toggle-db-steps",
+    "balance-center",
+    "toggle-group",
+    "toggle-mark",
+    "hide-marked",
+    "toggle-key-routing",
+    "toggle-swallow",
+    "media mute",
+    "calibrate",
+    "apply-gain",
+    "toggle-doctor",
+    "toggle-history",
+    "record-macro",
+    "toggle-pane mixer",
+    "toggle-pane keyboard",
+    "toggle-pane meters",
+    "toggle-pane log",
+    "toggle-pane keys",
+    "focus-pane",
+    "grow-pane",
+    "shrink-pane",
+    "cycle-log-level",
+    "exit",
+];
+
+/// How many matches are shown.
+pub const SHOWN: usize = 8;
+
+#[derive(Debug, Default)]
+pub struct Palette {
+    pub query: String,
+    /// Index into the matches.
+    pub selected: usize,
+}
+
+impl Palette {
+    pub fn move_selection(&mut self, step: isize, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected as isize + step).rem_euclid(count as isize) as usize;
+        }
+    }
+}
+
+/// (label, action) for every command.
+pub fn commands(state: &AppState) -> Vec<(String, String)> {
+    let mut commands: Vec<(String, String)> = COMMANDS
+        .iter()
+        .map(|name| (name.to_string(), name.to_string()))
+        .collect();
+    for name in macros::names() {
+        commands.push((format!("macro: {name}"), format!("macro {name}")));
+    }
+    for name in profiles::names() {
+        commands.push((format!("profile: {name}"), format!("profile {name}")));
+    }
+    for (i, percent) in state.presets.iter().enumerate() {
+        let slot = i + 1;
+        commands.push((
+            format!("preset {slot}: {percent}%"),
+            format!("preset {slot}"),
+        ));
+    }
+    for (_, _, _, device) in state.audio.device_list() {
+        for (channel, volume) in [("input", &device.input), ("output", &device.output)] {
+            let volume = volume.borrow();
+            if volume.enabled && volume.selectable {
+                commands.push((
+                    format!("default {channel}: {}", device.label()),
+                    format!("default {channel} {}", device.uid),
+                ));
+            }
+        }
+    }
+    commands
+}
+
+/// Commands matching the palette's query, best first.
+pub fn matches(state: &AppState) -> Vec<(String, String)> {
+    let mut scored: Vec<(i32, (String, String))> = commands(state)
+        .into_iter()
+        .filter_map(|command| Some((score(&state.palette.query, &command.0)?, command)))
+        .collect();
+    scored.sort_by_key(|(score, _)| -score);
+    scored.into_iter().map(|(_, command)| command).collect()
+}
+
+/// Fuzzy match: the query's characters in order, ignoring case. Runs of
+/// consecutive characters and word starts score higher.
+pub fn score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let i = next + text[next..].iter().position(|t| *t == c)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == i) {
+            score += 3;
+        }
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 2;
+        }
+        previous = Some(i);
+        next = i + 1;
+    }
+    // Shorter commands first among equal matches
+    Some(score * 100 - text.len() as i32)
+}
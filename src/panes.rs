@@ -0,0 +1,143 @@
+//! Which panes the TUI shows and how much of the terminal height each one
+//! gets, kept in `ui.toml` with a section per pane.
+
+use crate::store::Store;
+
+const UI_FILE: &str = "ui.toml";
+/// Height change per grow or shrink, in percent.
+const RESIZE_STEP: u16 = 5;
+const MIN_SIZE: u16 = 5;
+const MAX_SIZE: u16 = 90;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pane {
+    Mixer,
+    Keyboard,
+    /// Recent chords, see `keyhistory`.
+    Keys,
+    Meters,
+    Log,
+}
+
+impl Pane {
+    /// Top to bottom.
+    pub const ALL: [Pane; 5] = [
+        Pane::Mixer,
+        Pane::Keyboard,
+        Pane::Keys,
+        Pane::Meters,
+        Pane::Log,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Pane::Mixer => "mixer",
+            Pane::Keyboard => "keyboard",
+            Pane::Keys => "keys",
+            Pane::Meters => "meters",
+            Pane::Log => "log",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Pane::ALL.into_iter().find(|pane| pane.as_str() == name)
+    }
+
+    /// Percent of the terminal height, before any changes.
+    fn default_size(self) -> u16 {
+        match self {
+            Pane::Mixer => 40,
+            Pane::Keyboard => 5,
+            Pane::Keys => 15,
+            Pane::Meters => 10,
+            Pane::Log => 15,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Panes {
+    store: Store,
+    /// Shown and size per pane, indexed like `Pane::ALL`.
+    layout: [(bool, u16); 5],
+    /// Pane resized by the grow and shrink keys.
+    pub focused: Pane,
+}
+
+impl Panes {
+    pub fn load() -> Self {
+        let store = Store::open(UI_FILE);
+        let layout = Pane::ALL.map(|pane| {
+            // The key history is opt-in, the others start shown
+            let shown = match store.get(pane.as_str(), "shown") {
+                Some(shown) => shown != "false",
+                None => pane != Pane::Keys,
+            };
+            let size = store
+                .get(pane.as_str(), "size")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(pane.default_size())
+                .clamp(MIN_SIZE, MAX_SIZE);
+            (shown, size)
+        });
+        Panes {
+            store,
+            layout,
+            focused: Pane::Mixer,
+        }
+    }
+
+    pub fn is_shown(&self, pane: Pane) -> bool {
+        self.layout[pane as usize].0
+    }
+
+    /// Rows for a pane in a terminal this tall, at least one.
+    pub fn height(&self, pane: Pane, rows: u16) -> usize {
+        (rows as usize * self.layout[pane as usize].1 as usize / 100).max(1)
+    }
+
+    pub fn size(&self, pane: Pane) -> u16 {
+        self.layout[pane as usize].1
+    }
+
+    pub fn toggle(&mut self, pane: Pane) -> Result<(), String> {
+        let shown = !self.is_shown(pane);
+        self.layout[pane as usize].0 = shown;
+        if shown {
+            self.focused = pane;
+        } else if self.focused == pane {
+            self.focus_next();
+        }
+        self.save()
+    }
+
+    /// Move the focus to the next shown pane.
+    pub fn focus_next(&mut self) {
+        let start = self.focused as usize;
+        for i in 1..=Pane::ALL.len() {
+            let pane = Pane::ALL[(start + i) % Pane::ALL.len()];
+            if self.is_shown(pane) {
+                self.focused = pane;
+                return;
+            }
+        }
+    }
+
+    /// Grow or shrink the focused pane.
+    pub fn resize(&mut self, grow: bool) -> Result<(), String> {
+        let size = &mut self.layout[self.focused as usize].1;
+        *size = match grow {
+            true => (*size + RESIZE_STEP).min(MAX_SIZE),
+            false => size.saturating_sub(RESIZE_STEP).max(MIN_SIZE),
+        };
+        self.save()
+    }
+
+    fn save(&mut self) -> Result<(), String> {
+        for (pane, (shown, size)) in Pane::ALL.iter().zip(self.layout) {
+            self.store.set(pane.as_str(), "shown", shown);
+            self.store.set(pane.as_str(), "size", size);
+        }
+        self.store.save()
+    }
+}
@@ -0,0 +1,152 @@
+//! Privacy permissions the app asks macOS for. Key events need
+//! accessibility and input monitoring; the microphone is only needed for the
+//! input meter.
+
+use core_foundation::base::TCFType;
+use core_foundation::string::CFString;
+use std::os::raw::c_void;
+use std::process::Command;
+
+use crate::objc::{self, objc_getClass, sel_registerName};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+enum IOHIDRequestType {
+    PostEvent,
+    ListenEvent,
+}
+
+/// kIOHIDAccessTypeGranted
+const ACCESS_GRANTED: u32 = 0;
+/// kIOHIDAccessTypeDenied
+const ACCESS_DENIED: u32 = 1;
+/// AVAuthorizationStatusDenied and Authorized
+const MIC_DENIED: i64 = 2;
+const MIC_AUTHORIZED: i64 = 3;
+
+extern "C" {
+    fn IOHIDCheckAccess(request_type: IOHIDRequestType) -> u32;
+    fn IOHIDRequestAccess(request_type: IOHIDRequestType) -> bool;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Permission {
+    Accessibility,
+    InputMonitoring,
+    Microphone,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    Granted,
+    Denied,
+    /// Not asked yet.
+    Unknown,
+}
+
+impl Status {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Status::Granted => "granted",
+            Status::Denied => "denied",
+            Status::Unknown => "not asked",
+        }
+    }
+}
+
+impl Permission {
+    pub const ALL: [Permission; 3] = [
+        Permission::Accessibility,
+        Permission::InputMonitoring,
+        Permission::Microphone,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Permission::Accessibility => "Accessibility",
+            Permission::InputMonitoring => "Input Monitoring",
+            Permission::Microphone => "Microphone",
+        }
+    }
+
+    /// What stops working without it.
+    pub fn purpose(self) -> &'static str {
+        match self {
+            Permission::Accessibility => "volume keys, key bindings",
+            Permission::InputMonitoring => "key events, the keyboard pane",
+            Permission::Microphone => "optional, input meter and gain calibration",
+        }
+    }
+
+    pub fn is_required(self) -> bool {
+        self != Permission::Microphone
+    }
+
+    pub fn status(self) -> Status {
+        match self {
+            Permission::Accessibility => hid_status(IOHIDRequestType::PostEvent),
+            Permission::InputMonitoring => hid_status(IOHIDRequestType::ListenEvent),
+            Permission::Microphone => match unsafe { microphone_status() } {
+                MIC_AUTHORIZED => Status::Granted,
+                MIC_DENIED => Status::Denied,
+                _ => Status::Unknown,
+            },
+        }
+    }
+
+    /// Show the system prompt, which macOS only does once, and open the
+    /// settings pane for the permission so it can be granted after all.
+    pub fn request(self) {
+        match self {
+            Permission::Accessibility => unsafe {
+                IOHIDRequestAccess(IOHIDRequestType::PostEvent);
+            },
+            Permission::InputMonitoring => unsafe {
+                IOHIDRequestAccess(IOHIDRequestType::ListenEvent);
+            },
+            Permission::Microphone => (),
+        }
+        let pane = match self {
+            Permission::Accessibility => "Privacy_Accessibility",
+            Permission::InputMonitoring => "Privacy_ListenEvent",
+            Permission::Microphone => "Privacy_Microphone",
+        };
+        let _ = Command::new("open")
+            .arg(format!(
+                "x-apple.systempreferences:com.apple.preference.security?{pane}"
+            ))
+            .status();
+    }
+}
+
+/// Whether everything needed for key events is granted.
+pub fn has_key_access() -> bool {
+    Permission::ALL
+        .iter()
+        .filter(|p| p.is_required())
+        .all(|p| p.status() == Status::Granted)
+}
+
+fn hid_status(request_type: IOHIDRequestType) -> Status {
+    match unsafe { IOHIDCheckAccess(request_type) } {
+        ACCESS_GRANTED => Status::Granted,
+        ACCESS_DENIED => Status::Denied,
+        _ => Status::Unknown,
+    }
+}
+
+/// `[AVCaptureDevice authorizationStatusForMediaType:AVMediaTypeAudio]`
+unsafe fn microphone_status() -> i64 {
+    let class = objc_getClass(c"AVCaptureDevice".as_ptr());
+    if class.is_null() {
+        return 0;
+    }
+    // AVMediaTypeAudio, CFString is toll-free bridged to NSString
+    let media_type = CFString::new("soun");
+    let send: extern "C" fn(*mut c_void, *mut c_void, *const c_void) -> i64 = objc::send();
+    send(
+        class,
+        sel_registerName(c"authorizationStatusForMediaType:".as_ptr()),
+        media_type.as_concrete_TypeRef() as *const c_void,
+    )
+}
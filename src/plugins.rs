@@ -0,0 +1,111 @@
+//! Plugins are executables named `mac-controls-<name>` on the `PATH`.
+//!
+//! `mac-controls <name> [args]` runs one like a subcommand, the way git
+//! does. Plugins listed in `[plugins]` are started with the app instead,
+//! and get the events they asked for on stdin, one JSON object per line
+//! like the socket sink's:
+//!
+//! ```toml
+//! [plugins]
+//! hue = "default-output, mute, unmute"
+//! ```
+//!
+//! Lines they print are commands, in the format of the daemon's socket
+//! API (see `ipc`), answered on stdin.
+
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::events::Action;
+use crate::ipc;
+use crate::log;
+use crate::sinks::{Event, Sink};
+
+const PREFIX: &str = "mac-controls-";
+
+/// Path of a plugin's executable.
+pub fn find(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(format!("{PREFIX}{name}")))
+        .find(|file| {
+            file.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// Run a plugin as a subcommand, with its exit code.
+pub fn run(name: &str, args: &[String]) -> Result<i32, String> {
+    let path = find(name).ok_or(format!("Unknown command \"{name}\""))?;
+    let status = Command::new(&path)
+        .args(args)
+        .status()
+        .map_err(|e| format!("Unable to run {path:?}: {e}"))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// A plugin running alongside the app, as an event sink.
+#[derive(Debug)]
+pub struct Plugin {
+    name: String,
+    child: Mutex<Child>,
+    stdin: Arc<Mutex<ChildStdin>>,
+}
+
+impl Plugin {
+    /// Start a plugin, reading its commands into the handler on a
+    /// background thread.
+    pub fn start(name: &str, handler: impl Fn(Action) + Send + 'static) -> Result<Self, String> {
+        let path = find(name).ok_or(format!("No plugin {PREFIX}{name} on the PATH"))?;
+        let mut child = Command::new(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Unable to start {path:?}: {e}"))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(format!("No pipes to {path:?}"));
+        };
+        let stdin = Arc::new(Mutex::new(stdin));
+        let replies = stdin.clone();
+        let source = format!("plugin:{name}");
+        let plugin = name.to_string();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let reply = ipc::respond(&line, &source, &handler);
+                if writeln!(replies.lock().unwrap(), "{reply}").is_err() {
+                    break;
+                }
+            }
+            log::warn(format!("Plugin {plugin} stopped"));
+        });
+        log::info(format!("Started plugin {name}"));
+        Ok(Plugin {
+            name: name.to_string(),
+            child: Mutex::new(child),
+            stdin,
+        })
+    }
+
+    /// Stop the plugin, on exit.
+    pub fn stop(&self) {
+        let _ = self.child.lock().unwrap().kill();
+    }
+}
+
+impl Sink for Plugin {
+    fn send(&self, event: &Event) -> Result<(), String> {
+        writeln!(self.stdin.lock().unwrap(), "{}", event.to_json())
+            .map_err(|e| format!("Unable to send to plugin {}: {e}", self.name))
+    }
+}
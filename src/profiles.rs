@@ -0,0 +1,364 @@
+//! Named snapshots of the whole setup, default devices and every device's
+//! volumes, mutes and sample rate, kept in `profiles.toml` with a section
+//! per profile:
+//!
+//! ```toml
+//! [meeting]
+//! input = "AirPods-UID"
+//! output = "AirPods-UID"
+//! "AirPods-UID.input_muted" = true
+//! "AirPods-UID.input_volume" = 0.6
+//! "AirPods-UID.sample_rate" = 24000
+//! mic_mode = "voice-isolation"
+//! ```
+//!
+//! Devices that aren't connected when a profile is applied are skipped.
+//! The mic mode can't be set by apps, so applying a profile with another
+//! one opens the system picker for it.
+//!
+//! `profile apply` lists what would change and asks first, `--yes` skips
+//! that, and `profile diff` only lists it. The TUI's profile picker shows
+//! the list for the selected profile, enter applies it.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::audio::{AudioState, Channel, Device, MuteState};
+use crate::config::Config;
+use crate::micmode::{self, MicMode};
+use crate::palette;
+use crate::store::{Section, Store};
+
+const PROFILES_FILE: &str = "profiles.toml";
+const USAGE: &str = "Usage: mac-controls profile save|diff <name>
+       mac-controls profile apply <name> [--yes]
+       mac-controls profile list";
+
+/// `mac-controls profile save|apply|diff|list [name] [--yes]`.
+pub fn run(args: &[String]) -> Result<String, String> {
+    let yes = args.iter().any(|a| a == "--yes");
+    let args: Vec<&str> = args
+        .iter()
+        .map(|a| a.as_str())
+        .filter(|a| *a != "--yes")
+        .collect();
+    match (args.first().copied(), args.get(1).copied()) {
+        (Some("list"), None) => match names() {
+            names if names.is_empty() => Ok("No saved profiles".to_string()),
+            names => Ok(names.join("\n")),
+        },
+        (Some("save"), Some(name)) => {
+            save(name, &AudioState::new(&Config::load()))?;
+            Ok(format!("Saved profile \"{name}\""))
+        }
+        (Some("diff"), Some(name)) => {
+            let changes = diff(name, &AudioState::new(&Config::load()))?;
+            Ok(match changes.is_empty() {
+                true => format!("Profile \"{name}\" matches the current setup"),
+                false => changes.join("\n"),
+            })
+        }
+        (Some("apply"), Some(name)) => {
+            let mut audio = AudioState::new(&Config::load());
+            if !yes && !confirm(name, &audio)? {
+                return Ok("Not applied".to_string());
+            }
+            let applied = apply(name, &mut audio);
+            audio.finish_ramps();
+            applied
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// List the changes and ask on the terminal, true to go ahead.
+fn confirm(name: &str, audio: &AudioState) -> Result<bool, String> {
+    let changes = diff(name, audio)?;
+    if changes.is_empty() {
+        return Ok(true);
+    }
+    if !termion::is_tty(&std::io::stdin()) {
+        return Err(format!(
+            "Applying \"{name}\" changes:\n{}\nPass --yes to apply it without a terminal",
+            changes.join("\n")
+        ));
+    }
+    print!("{}\nApply profile \"{name}\"? [y/N] ", changes.join("\n"));
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| e.to_string())?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn channel_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Input => "input",
+        Channel::Output => "output",
+    }
+}
+
+/// Save the current setup, replacing any profile with the same name.
+pub fn save(name: &str, audio: &AudioState) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Name the profile".to_string());
+    }
+    let mut store = Store::open(PROFILES_FILE);
+    store.sections.remove(name);
+    for channel in [Channel::Input, Channel::Output] {
+        if let Some(device) = audio.active_device(channel) {
+            store.set(name, channel_name(channel), &device.uid);
+        }
+    }
+    for (_, _, _, device) in audio.device_list() {
+        for channel in [Channel::Input, Channel::Output] {
+            let volume = device.volume(channel).borrow();
+            if !volume.enabled {
+                continue;
+            }
+            let mute = audio.mute_state(device, channel);
+            // The workaround mute zeroes the volume, the real one is cached
+            let level = match mute {
+                MuteState::Workaround => volume.cache,
+                _ => volume.level,
+            };
+            let prefix = format!("{}.{}", device.uid, channel_name(channel));
+            store.set(name, &format!("{prefix}_volume"), level);
+            store.set(name, &format!("{prefix}_muted"), mute != MuteState::Unmuted);
+        }
+        if let Some(rate) = device.sample_rate.get() {
+            store.set(name, &format!("{}.sample_rate", device.uid), rate);
+        }
+    }
+    if let Ok(mode) = micmode::preferred() {
+        store.set(name, "mic_mode", mode.as_str());
+    }
+    store.save()
+}
+
+/// A saved device's settings.
+#[derive(Debug, Default)]
+struct Saved {
+    sample_rate: Option<f64>,
+    /// Volume and mute, by channel.
+    channels: Vec<(Channel, Option<f32>, bool)>,
+}
+
+/// A profile's section, and its settings by device UID.
+fn load<'a>(
+    store: &'a Store,
+    name: &str,
+) -> Result<(&'a Section, BTreeMap<&'a str, Saved>), String> {
+    let section = store
+        .sections
+        .get(name)
+        .ok_or_else(|| format!("No profile named \"{name}\""))?;
+    let mut settings: BTreeMap<&str, BTreeMap<&str, &str>> = BTreeMap::new();
+    for (key, value) in section {
+        if let Some((uid, setting)) = key.rsplit_once('.') {
+            settings.entry(uid).or_default().insert(setting, value);
+        }
+    }
+    let devices = settings
+        .into_iter()
+        .map(|(uid, settings)| {
+            let mut saved = Saved {
+                sample_rate: settings
+                    .get("sample_rate")
+                    .and_then(|r| r.parse::<f64>().ok()),
+                channels: Vec::new(),
+            };
+            for channel in [Channel::Input, Channel::Output] {
+                let setting = |suffix: &str| {
+                    settings
+                        .get(format!("{}_{suffix}", channel_name(channel)).as_str())
+                        .copied()
+                };
+                let level = setting("volume").and_then(|l| l.parse::<f32>().ok());
+                let muted = setting("muted") == Some("true");
+                saved.channels.push((channel, level, muted));
+            }
+            (uid, saved)
+        })
+        .collect();
+    Ok((section, devices))
+}
+
+/// What applying a profile would change, a line each.
+pub fn diff(name: &str, audio: &AudioState) -> Result<Vec<String>, String> {
+    let store = Store::open(PROFILES_FILE);
+    let (section, devices) = load(&store, name)?;
+    let mut changes = Vec::new();
+    for channel in [Channel::Input, Channel::Output] {
+        let Some(device) = section
+            .get(channel_name(channel))
+            .and_then(|uid| find(audio, uid))
+        else {
+            continue;
+        };
+        let current = audio.active_device(channel);
+        if current.map(|d| d.id) != Some(device.id) {
+            changes.push(format!(
+                "Default {}: {} → {}",
+                channel_name(channel),
+                current.map_or("none".to_string(), |d| audio.unique_name(d)),
+                audio.unique_name(device)
+            ));
+        }
+    }
+    for (uid, saved) in devices {
+        let Some(device) = find(audio, uid) else {
+            changes.push(format!("{uid}: not connected, skipped"));
+            continue;
+        };
+        let label = audio.unique_name(device);
+        let rate = device.sample_rate.get();
+        if let Some(to) = saved.sample_rate.filter(|to| rate != Some(*to)) {
+            let from = rate.map_or("-".to_string(), |r| format!("{r} Hz"));
+            changes.push(format!("{label} sample rate: {from} → {to} Hz"));
+        }
+        for (channel, level, muted) in saved.channels {
+            let volume = device.volume(channel).borrow();
+            if !volume.enabled {
+                continue;
+            }
+            let mute = audio.mute_state(device, channel);
+            let current = match mute {
+                MuteState::Workaround => volume.cache,
+                _ => volume.level,
+            };
+            let name = channel_name(channel);
+            let percent = |level: f32| (level * 100.0).round();
+            if let Some(level) = level.filter(|l| percent(*l) != percent(current)) {
+                changes.push(format!(
+                    "{label} {name} volume: {}% → {}%",
+                    percent(current),
+                    percent(level)
+                ));
+            }
+            match (mute != MuteState::Unmuted, muted) {
+                (false, true) => changes.push(format!("{label} {name}: unmuted → muted")),
+                (true, false) => changes.push(format!("{label} {name}: muted → unmuted")),
+                _ => {}
+            }
+        }
+    }
+    let mic_mode = section.get("mic_mode").and_then(|m| MicMode::parse(m));
+    let current = micmode::preferred().ok();
+    if let Some(mode) = mic_mode.filter(|mode| current != Some(*mode)) {
+        changes.push(format!(
+            "Mic mode: {} → {}, in the system picker",
+            current.map_or("-", |m| m.label()),
+            mode.label()
+        ));
+    }
+    Ok(changes)
+}
+
+/// Apply a saved profile, with what was done.
+pub fn apply(name: &str, audio: &mut AudioState) -> Result<String, String> {
+    let store = Store::open(PROFILES_FILE);
+    let (section, devices) = load(&store, name)?;
+    let mut skipped = 0;
+    for (uid, saved) in devices {
+        let Some(device) = find(audio, uid) else {
+            skipped += 1;
+            continue;
+        };
+        let (id, rate) = (device.id, device.sample_rate.get());
+        if let Some(to) = saved.sample_rate.filter(|to| rate != Some(*to)) {
+            audio.set_sample_rate_of(&id, to)?;
+        }
+        for (channel, level, muted) in saved.channels {
+            audio.restore_channel(&id, channel, level, muted);
+        }
+    }
+    // Defaults last, so they switch with their volumes already set
+    for channel in [Channel::Input, Channel::Output] {
+        if let Some(uid) = section.get(channel_name(channel)) {
+            if find(audio, uid).is_some() {
+                audio.set_default(channel, uid)?;
+            }
+        }
+    }
+    let mut message = match skipped {
+        0 => format!("Applied profile \"{name}\""),
+        n => format!("Applied profile \"{name}\", {n} devices not connected"),
+    };
+    let mic_mode = section.get("mic_mode").and_then(|m| MicMode::parse(m));
+    if let Some(mode) = mic_mode.filter(|mode| micmode::preferred().ok() != Some(*mode)) {
+        micmode::show_picker()?;
+        message.push_str(&format!(", pick {} as the mic mode", mode.label()));
+    }
+    Ok(message)
+}
+
+fn find<'a>(audio: &'a AudioState, uid: &str) -> Option<&'a Device> {
+    audio
+        .device_list()
+        .into_iter()
+        .map(|(_, _, _, device)| device)
+        .find(|device| device.uid == uid)
+}
+
+pub fn names() -> Vec<String> {
+    Store::open(PROFILES_FILE).sections.into_keys().collect()
+}
+
+/// Profiles matching the picker's query, best first.
+pub fn matches(query: &str) -> Vec<String> {
+    let mut scored: Vec<(i32, String)> = names()
+        .into_iter()
+        .filter_map(|name| Some((palette::score(query, &name)?, name)))
+        .collect();
+    scored.sort_by_key(|(score, _)| -score);
+    scored.into_iter().map(|(_, name)| name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{AudioBackend, MockBackend, MockDevice};
+    use crate::store;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn diff_lists_what_applying_changes() {
+        store::use_temp_home();
+        let backend = MockBackend::new();
+        backend.add(
+            1,
+            MockDevice {
+                uid: "profiles.speakers".to_string(),
+                name: "Speakers".to_string(),
+                channels: [false, true],
+                volumes: [None, Some(0.5)],
+                mutes: [None, Some(false)],
+                ..MockDevice::default()
+            },
+        );
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let config = Config {
+            volume_ramp: Duration::ZERO,
+            ..Config::default()
+        };
+        let mut audio = AudioState::with_backend(&config, Arc::new(backend.clone()));
+        save("diff", &audio).unwrap();
+        assert_eq!(diff("diff", &audio), Ok(Vec::new()));
+
+        audio.set_level_of(&1, Channel::Output, 0.8);
+        audio.toggle_mute_of(&1, Channel::Output);
+        assert_eq!(
+            diff("diff", &audio),
+            Ok(vec![
+                "Speakers output volume: 80% → 50%".to_string(),
+                "Speakers output: muted → unmuted".to_string(),
+            ])
+        );
+        apply("diff", &mut audio).unwrap();
+        assert_eq!(diff("diff", &audio), Ok(Vec::new()));
+        assert!(diff("missing", &audio).is_err());
+    }
+}
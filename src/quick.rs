@@ -0,0 +1,142 @@
+//! Quick actions over plain HTTP GET, for iPhone Shortcuts and the like on
+//! the same network. The path is a recorded action name with `/` for
+//! spaces, like `/quick/mute-input`, `/quick/media/volume-up` or
+//! `/quick/macro/Meeting`. Every request needs the `[quick]` token or one
+//! made by `mac-controls server token`, as an `Authorization: Bearer`
+//! header, never in the URL. With `[tls]` it's HTTPS instead.
+
+use std::io::Write;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::audit;
+use crate::events::Action;
+use crate::http::Request;
+use crate::tasks::Tasks;
+use crate::tls::{Acceptor, Stream};
+use crate::tokens;
+
+/// Requests handled at once, more are hung up on.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Listen on localhost, or every interface with `lan`, handling requests
+/// on background threads until the tasks are cancelled, over TLS from `tls`
+/// if set up. Requests go through the audit log's rate limit, by client
+/// address.
+pub fn start(
+    tasks: &mut Tasks,
+    port: u16,
+    lan: bool,
+    token: Option<String>,
+    tls: Acceptor,
+    handler: impl Fn(Action) + Send + Sync + 'static,
+) -> Result<(), String> {
+    let address = match lan {
+        true => Ipv4Addr::UNSPECIFIED,
+        false => Ipv4Addr::LOCALHOST,
+    };
+    let listener = TcpListener::bind((address, port))
+        .map_err(|e| format!("Unable to listen on port {port}: {e}"))?;
+    let connections = Arc::new(AtomicUsize::new(0));
+    let token = Arc::new(token);
+    let handler = Arc::new(handler);
+    tasks.spawn("quick actions", move |cancel| {
+        // Accept blocks, a connection of our own gets it to check
+        cancel.on_cancel(move || {
+            let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, port));
+        });
+        for stream in listener.incoming().flatten() {
+            if cancel.is_cancelled() {
+                break;
+            }
+            if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+            let token = token.clone();
+            let handler = handler.clone();
+            let connections = connections.clone();
+            let tls = tls.clone();
+            thread::spawn(move || {
+                if let Ok(stream) = tls.accept(stream) {
+                    let _ = handle(stream, &token, &*handler);
+                }
+                connections.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(mut stream: Stream, token: &Option<String>, handler: &dyn Fn(Action)) -> Option<()> {
+    let client = stream.tcp().peer_addr().ok()?.ip();
+    let request = Request::read(&mut stream)?;
+    let method = request.method.as_str();
+    let path = request.path();
+    let authorized = match tokens::from_authorization(request.header("authorization")) {
+        Some(given) => {
+            let configured = token.as_deref().is_some_and(|t| tokens::same(t, &given));
+            configured | tokens::is_valid(&given)
+        }
+        None => false,
+    };
+    let action = match path.strip_prefix("/quick/") {
+        _ if !authorized => Err("wrong or missing token".to_string()),
+        Some(name) if method == "GET" => {
+            parse(&decode(&name.replace('/', " "))).ok_or("unknown action".to_string())
+        }
+        _ => Err("unknown action".to_string()),
+    };
+    let command = format!("{method} {path}");
+    let (status, result) = match audit::dispatch(&client.to_string(), &command, action, handler) {
+        Ok(()) => ("200 OK", "ok".to_string()),
+        Err(e) if e == audit::RATE_LIMITED => ("429 Too Many Requests", e),
+        Err(e) if !authorized => ("401 Unauthorized", e),
+        Err(e) => ("404 Not Found", e),
+    };
+    respond(&mut stream, status, &format!("{result}\n"));
+    Some(())
+}
+
+/// Recorded actions and saved macros, not view-only keys like `exit`.
+fn parse(name: &str) -> Option<Action> {
+    match name.strip_prefix("macro ") {
+        Some(name) => Some(Action::RunMacro(name.to_string())),
+        None => Action::from_record(name),
+    }
+}
+
+fn respond(stream: &mut Stream, status: &str, body: &str) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.flush();
+}
+
+/// Undo URL escapes like `%20` and `+`.
+fn decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(byte) => {
+                        bytes.push(byte);
+                        rest = &rest[2..];
+                    }
+                    None => bytes.push(b'%'),
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
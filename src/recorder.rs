@@ -0,0 +1,267 @@
+//! Recording what the Mac plays to a WAV file, without a loopback driver
+//! like BlackHole. A global process tap of every app, left unmuted, is
+//! read through a private aggregate with the default output, the same way
+//! per-app volume plays taps back, and a thread writes what the IOProc
+//! hands over. Needs process taps, macOS 14.2 and up.
+
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::CFString;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::apps;
+use crate::audio::{self, Channel};
+use crate::coreaudio::*;
+use crate::history;
+
+/// Taps are mixed down to stereo.
+const CHANNELS: u16 = 2;
+/// Used when the aggregate doesn't report its rate.
+const FALLBACK_RATE: f64 = 48000.0;
+/// RIFF header up to the samples, for a float `fmt ` chunk.
+const HEADER_LEN: u32 = 44;
+
+/// A recording in progress. Stopped with `stop`, or when dropped.
+#[derive(Debug)]
+pub struct Recording {
+    path: PathBuf,
+    tap: AudioObjectID,
+    aggregate: AudioObjectID,
+    proc_id: AudioDeviceIOProcID,
+    context: *mut Sender<Vec<f32>>,
+    destroy: AudioHardwareDestroyProcessTap,
+    /// Writes samples as they come, and the header once they stop.
+    writer: Option<JoinHandle<Result<u64, String>>>,
+    rate: u32,
+}
+
+/// `mac-controls <unix time>.wav` on the desktop, for recordings started
+/// without a file.
+pub fn default_path() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_default();
+    PathBuf::from(home)
+        .join("Desktop")
+        .join(format!("mac-controls {}.wav", history::now()))
+}
+
+impl Recording {
+    /// Start recording everything playing on the default output.
+    pub fn start(path: &Path) -> Result<Self, String> {
+        let (create, destroy) = apps::tap_functions()
+            .ok_or("Recording the output needs process taps, macOS 14.2 or later")?;
+        let output = audio::default_device(Channel::Output);
+        if output == 0 {
+            return Err("No default output to record".to_string());
+        }
+        let file = File::create(path).map_err(|e| format!("Unable to create {path:?}: {e}"))?;
+        let (tap, tap_uid) = apps::create_global_tap(create)?;
+        let aggregate = match create_aggregate(&audio::device_uid(&output), &tap_uid) {
+            Ok(aggregate) => aggregate,
+            Err(e) => {
+                unsafe { destroy(tap) };
+                return Err(e);
+            }
+        };
+        let rate = audio::sample_rate(&aggregate).unwrap_or(FALLBACK_RATE) as u32;
+        let (sender, receiver) = mpsc::channel();
+        let writer = std::thread::spawn(move || write_wav(file, rate, receiver));
+        let context = Box::into_raw(Box::new(sender));
+        let mut proc_id: AudioDeviceIOProcID = None;
+        unsafe {
+            let failed = if AudioDeviceCreateIOProcID(
+                aggregate,
+                record_proc,
+                context as *mut c_void,
+                &mut proc_id,
+            ) != NO_ERR
+            {
+                Some("Unable to create the recording IOProc")
+            } else if AudioDeviceStart(aggregate, proc_id) != NO_ERR {
+                AudioDeviceDestroyIOProcID(aggregate, proc_id);
+                Some("Unable to start the recording IOProc")
+            } else {
+                None
+            };
+            if let Some(message) = failed {
+                AudioHardwareDestroyAggregateDevice(aggregate);
+                destroy(tap);
+                // Closes the channel, so the writer finishes an empty file
+                drop(Box::from_raw(context));
+                let _ = writer.join();
+                let _ = std::fs::remove_file(path);
+                return Err(message.to_string());
+            }
+        }
+        Ok(Recording {
+            path: path.to_path_buf(),
+            tap,
+            aggregate,
+            proc_id,
+            context,
+            destroy,
+            writer: Some(writer),
+            rate,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop and finish the file, with how long the recording is.
+    pub fn stop(mut self) -> Result<Duration, String> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<Duration, String> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(Duration::ZERO);
+        };
+        unsafe {
+            // Stop waits for the IOProc to return, like the meter's
+            AudioDeviceStop(self.aggregate, self.proc_id);
+            AudioDeviceDestroyIOProcID(self.aggregate, self.proc_id);
+            AudioHardwareDestroyAggregateDevice(self.aggregate);
+            (self.destroy)(self.tap);
+            drop(Box::from_raw(self.context));
+        }
+        let frames = writer
+            .join()
+            .map_err(|_| "The recording writer crashed".to_string())??;
+        Ok(Duration::from_secs_f64(frames as f64 / self.rate as f64))
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// A private aggregate of the output and the tap. The output only clocks
+/// the IOProc, nothing is played on it.
+fn create_aggregate(output_uid: &str, tap_uid: &str) -> Result<AudioObjectID, String> {
+    let pair = |key: &str, value: CFType| (CFString::new(key).as_CFType(), value);
+    let entry =
+        |uid: &str| CFDictionary::from_CFType_pairs(&[pair("uid", CFString::new(uid).as_CFType())]);
+    let tap = CFDictionary::from_CFType_pairs(&[
+        pair("uid", CFString::new(tap_uid).as_CFType()),
+        pair("drift", CFBoolean::true_value().as_CFType()),
+    ]);
+    let description = CFDictionary::from_CFType_pairs(&[
+        pair(
+            "uid",
+            CFString::new(&format!("mac-controls.record.{}", std::process::id())).as_CFType(),
+        ),
+        pair("name", CFString::new("mac-controls: recording").as_CFType()),
+        pair("private", CFBoolean::true_value().as_CFType()),
+        pair("master", CFString::new(output_uid).as_CFType()),
+        pair(
+            "subdevices",
+            CFArray::from_CFTypes(&[entry(output_uid)]).as_CFType(),
+        ),
+        pair("taps", CFArray::from_CFTypes(&[tap]).as_CFType()),
+        pair("tapautostart", CFBoolean::true_value().as_CFType()),
+    ]);
+    let mut aggregate: AudioObjectID = 0;
+    let status = unsafe {
+        AudioHardwareCreateAggregateDevice(description.as_concrete_TypeRef(), &mut aggregate)
+    };
+    match status {
+        NO_ERR => Ok(aggregate),
+        status => Err(format!("Unable to set up the recording: {status}")),
+    }
+}
+
+/// Runs on CoreAudio's IO thread. The tap is the aggregate's last input
+/// stream; its frames go to the writer as stereo, and the output is kept
+/// silent.
+extern "C" fn record_proc(
+    _device: AudioObjectID,
+    _now: *const AudioTimeStamp,
+    input: *const AudioBufferList,
+    _input_time: *const AudioTimeStamp,
+    output: *mut AudioBufferList,
+    _output_time: *const AudioTimeStamp,
+    client_data: *mut c_void,
+) -> OSStatus {
+    if client_data.is_null() {
+        return NO_ERR;
+    }
+    unsafe {
+        if let Some(list) = output.as_mut() {
+            let buffers = std::slice::from_raw_parts_mut(
+                list.mBuffers.as_mut_ptr(),
+                list.mNumberBuffers as usize,
+            );
+            for buffer in buffers.iter_mut().filter(|b| !b.mData.is_null()) {
+                std::ptr::write_bytes(buffer.mData as *mut u8, 0, buffer.mDataByteSize as usize);
+            }
+        }
+        let Some(source) = input.as_ref().and_then(|list| {
+            std::slice::from_raw_parts(list.mBuffers.as_ptr(), list.mNumberBuffers as usize)
+                .last()
+                .filter(|buffer| !buffer.mData.is_null() && buffer.mNumberChannels > 0)
+        }) else {
+            return NO_ERR;
+        };
+        let channels = source.mNumberChannels as usize;
+        let len = source.mDataByteSize as usize / std::mem::size_of::<Float32>();
+        let tapped = std::slice::from_raw_parts(source.mData as *const Float32, len);
+        let stereo = tapped
+            .chunks_exact(channels)
+            .flat_map(|frame| [frame[0], frame[1.min(channels - 1)]])
+            .collect();
+        let sender = &*(client_data as *const Sender<Vec<f32>>);
+        let _ = sender.send(stereo);
+    }
+    NO_ERR
+}
+
+/// Write 32-bit float WAV until the IOProc is gone, then fill in the
+/// sizes. Returns the frames written.
+fn write_wav(file: File, rate: u32, samples: Receiver<Vec<f32>>) -> Result<u64, String> {
+    let failed = |e: std::io::Error| format!("Unable to write the recording: {e}");
+    let mut out = BufWriter::new(file);
+    out.write_all(&header(rate, 0)).map_err(failed)?;
+    let mut written: u64 = 0;
+    for block in samples {
+        for sample in &block {
+            out.write_all(&sample.to_le_bytes()).map_err(failed)?;
+        }
+        written += block.len() as u64;
+    }
+    let data_len = (written * 4).min((u32::MAX - HEADER_LEN) as u64) as u32;
+    out.seek(SeekFrom::Start(0)).map_err(failed)?;
+    out.write_all(&header(rate, data_len)).map_err(failed)?;
+    out.flush().map_err(failed)?;
+    Ok(written / CHANNELS as u64)
+}
+
+/// RIFF/WAVE header for stereo float samples.
+fn header(rate: u32, data_len: u32) -> Vec<u8> {
+    let block_align = CHANNELS * 4;
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(HEADER_LEN - 8 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // WAVE_FORMAT_IEEE_FLOAT
+    header.extend_from_slice(&3u16.to_le_bytes());
+    header.extend_from_slice(&CHANNELS.to_le_bytes());
+    header.extend_from_slice(&rate.to_le_bytes());
+    header.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&32u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
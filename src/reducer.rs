@@ -0,0 +1,1386 @@
+//! What the app does for each action: `AppState::apply` changes the state
+//! and returns what has to happen outside of it, like drawing or feeding
+//! another action back through the loop, as `Effect`s for the main loop
+//! to run. Reads and writes outside the app, like the app in front, the
+//! key history, the usage stats and volume changes, are effects too, run
+//! by `AppState::run`. Other device changes still go through
+//! `AudioState`, whose mute decisions are effects of their own, see
+//! `mutes`.
+
+use std::time::Instant;
+
+use crate::alerts;
+use crate::apps;
+use crate::audio::{self, Channel, SourceKind};
+use crate::coreaudio::AudioDeviceID;
+use crate::events::{Action, MediaKey, UiMode, FINE_STEPS};
+use crate::explorer;
+use crate::frontmost;
+use crate::keymap;
+use crate::layout;
+use crate::log;
+use crate::macros;
+use crate::mediakeys;
+use crate::meter::{self, Calibration};
+use crate::micmode;
+use crate::palette;
+use crate::profiles;
+use crate::state::AppState;
+use crate::store;
+use crate::system;
+
+/// Volume change per hardware volume key press, same as macOS.
+const KEY_STEP: f32 = 1.0 / 16.0;
+/// Volume change per arrow key press in the TUI.
+const TUI_STEP: f32 = 0.1;
+/// Key codes of the left and right arrows, which adjust volume.
+const KEY_LEFT: i64 = 123;
+const KEY_RIGHT: i64 = 124;
+
+/// Balance change per `[` or `]` press.
+const BALANCE_STEP: f32 = 0.1;
+
+/// Something for the main loop to do after an action.
+#[derive(Debug)]
+pub enum Effect {
+    /// Draw what changed on screen.
+    Draw,
+    /// Draw the whole screen again, after a resize.
+    Redraw,
+    /// Run an action through the loop, so it's recorded, counted and
+    /// refused when read-only like one from a key.
+    Dispatch(Action),
+    /// The devices may have changed: meter the default input and listen to
+    /// the devices there are now.
+    WatchDevices,
+    /// Publish the devices to Home Assistant.
+    SyncHomeAssistant,
+    /// Stop the app.
+    Exit,
+    /// Count a recorded action in the usage stats.
+    Count(String),
+    /// Note the app in front, for the key history and broadcasts.
+    ReadFrontmost,
+    /// Add a chord to the key history, with the app in front.
+    History(String),
+    /// Send a key going down (true) or up to the web clients.
+    BroadcastKey(i64, bool),
+    /// Set the volume of some devices to a level.
+    SetLevel(Volumes, Channel, f32),
+    /// Move the volume of some devices by an amount.
+    MoveVolume(Volumes, Channel, f32),
+    /// Read the devices again, after changing one.
+    UpdateDevices,
+}
+
+/// The devices a volume effect is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Volumes {
+    /// The channel's default device.
+    Default,
+    /// The default, the marked devices or the group, see `AudioState::targets`.
+    Targets,
+    /// One device, default or not. The devices aren't read again, so a
+    /// held key doesn't read them on every repeat.
+    Device(AudioDeviceID),
+}
+
+impl AppState {
+    /// The action for a terminal key or mouse button, from the keymap and
+    /// the config, any other action as is. None when it's unbound.
+    pub fn resolve(&self, action: Action) -> Option<Action> {
+        match action {
+            Action::MouseDown { button } => self.mouse_binding(button),
+            Action::TerminalKey(key) => self.keymap.action(self.mode, key),
+            Action::ShiftedKey(key) => self.keymap.shifted_action(self.mode, key),
+            action => Some(action),
+        }
+    }
+
+    /// Apply an action, with the effects to run in order.
+    pub fn apply(&mut self, action: Action) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        if let Some(record) = action.to_record() {
+            self.last_user_action = Some(Instant::now());
+            if let Some(steps) = self.macro_recording.as_mut() {
+                steps.push(record.clone());
+            }
+            if !self.replaying {
+                effects.push(Effect::Count(record));
+            }
+        }
+        if action.is_mutation() && audio::is_read_only() {
+            self.notify("Read-only mode, changes are disabled".to_string());
+            effects.push(Effect::Draw);
+            return effects;
+        }
+        match action {
+            Action::KeyDown {
+                key_code,
+                modifiers,
+                repeating,
+                keyboard,
+            } => {
+                self.key_repeat.press(key_code, repeating);
+                self.modifier_tap.spoil();
+                self.last_keyboard = Some(keyboard);
+                if let Some(push) = self.push_key(key_code).filter(|_| !repeating) {
+                    self.push_down(push, key_code);
+                }
+                if !repeating {
+                    effects.push(Effect::ReadFrontmost);
+                    let bound = self
+                        .key_binding(keyboard, key_code)
+                        .or_else(|| self.hotkey(key_code, &modifiers));
+                    if let Some(action) = bound {
+                        effects.push(Effect::Dispatch(action));
+                    }
+                    self.keys.push(key_code);
+                    self.key_modifiers = modifiers.list_active();
+                    if !layout::is_modifier(key_code) {
+                        effects.push(Effect::History(self.held_chord()));
+                    }
+                    effects.push(Effect::BroadcastKey(key_code, true));
+                    effects.push(Effect::Draw);
+                }
+            }
+            Action::KeyUp {
+                key_code,
+                modifiers,
+            } => {
+                self.key_repeat.release(key_code);
+                self.push_up(key_code);
+                effects.push(Effect::BroadcastKey(key_code, false));
+                if let Some(i) = self.keys.iter().position(|k| *k == key_code) {
+                    self.keys.remove(i);
+                    self.key_modifiers = modifiers.list_active();
+                    effects.push(Effect::Draw);
+                }
+            }
+            Action::Modifier { modifiers } => {
+                let tapped = self.modifier_tap.update(modifiers.held);
+                if let Some(action) = tapped.and_then(|combo| self.modifier_binding(combo)) {
+                    effects.push(Effect::Dispatch(action));
+                }
+                self.key_modifiers = modifiers.list_active();
+                self.caps_lock_changed(modifiers.caps_lock);
+                effects.push(Effect::Draw);
+            }
+            Action::ModeSwitch(mode) => {
+                self.switch_mode(mode);
+                effects.push(Effect::Draw);
+            }
+            Action::ModeBack => {
+                self.mode_back();
+                effects.push(Effect::Draw);
+            }
+            Action::TextInput(c) => {
+                match self.mode {
+                    UiMode::Search => {
+                        self.search.push(c);
+                        self.move_cursor(0);
+                    }
+                    UiMode::Palette => {
+                        self.palette.query.push(c);
+                        self.palette.selected = 0;
+                    }
+                    UiMode::Profiles => {
+                        self.profiles.query.push(c);
+                        self.profiles.selected = 0;
+                    }
+                    UiMode::Property => self.property_query.push(c),
+                    UiMode::MacroName => self.macro_name.push(c),
+                    UiMode::Rename => self.rename_entry.push(c),
+                    UiMode::EditInput | UiMode::EditOutput => {
+                        self.volume_channel = match self.mode {
+                            UiMode::EditInput => Channel::Input,
+                            _ => Channel::Output,
+                        };
+                        self.volume_entry = c.to_string();
+                        self.switch_mode(UiMode::Volume);
+                    }
+                    UiMode::Volume if c.is_ascii_digit() && self.volume_entry.len() < 3 => {
+                        self.volume_entry.push(c)
+                    }
+                    _ => return effects,
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::TextBackspace => {
+                match self.mode {
+                    UiMode::Search => {
+                        self.search.pop();
+                    }
+                    UiMode::Palette => {
+                        self.palette.query.pop();
+                        self.palette.selected = 0;
+                    }
+                    UiMode::Profiles => {
+                        self.profiles.query.pop();
+                        self.profiles.selected = 0;
+                    }
+                    UiMode::MacroName => {
+                        self.macro_name.pop();
+                    }
+                    UiMode::Rename => {
+                        self.rename_entry.pop();
+                    }
+                    UiMode::Volume => {
+                        self.volume_entry.pop();
+                    }
+                    UiMode::Property => {
+                        self.property_query.pop();
+                    }
+                    _ => return effects,
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::PaletteNext | Action::PalettePrev => {
+                let step = match action {
+                    Action::PaletteNext => 1,
+                    _ => -1,
+                };
+                let count = palette::matches(self).len().min(palette::SHOWN);
+                self.palette.move_selection(step, count);
+                effects.push(Effect::Draw);
+            }
+            Action::PaletteRun => {
+                let command = palette::matches(self)
+                    .into_iter()
+                    .nth(self.palette.selected)
+                    .and_then(|(_, name)| keymap::parse_action(&name));
+                // Run in the mode the palette was opened from
+                self.mode_back();
+                if let Some(action) = command {
+                    effects.push(Effect::Dispatch(action));
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleMacroRecording => {
+                if self.mode == UiMode::MacroName {
+                    return effects;
+                }
+                if self.macro_recording.is_some() {
+                    self.macro_name.clear();
+                    self.switch_mode(UiMode::MacroName);
+                } else {
+                    self.macro_recording = Some(Vec::new());
+                    self.notify("Recording a macro, 'm' to stop".to_string());
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::MacroSave => {
+                let name = self.macro_name.trim().to_string();
+                if name.is_empty() {
+                    self.notify("Name the macro, or esc to discard it".to_string());
+                } else if let Some(steps) = self.macro_recording.take() {
+                    match macros::save(&name, &steps) {
+                        Ok(()) => {
+                            self.notify(format!("Saved macro \"{name}\" ({} steps)", steps.len()))
+                        }
+                        Err(e) => self.notify(e),
+                    }
+                    self.mode_back();
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::RenameSave => {
+                if let Some(device) = self.cursor_device() {
+                    let (uid, name) = (device.uid.clone(), device.name.clone());
+                    let alias = self.rename_entry.trim().to_string();
+                    let alias = Some(alias.as_str()).filter(|a| !a.is_empty() && *a != name);
+                    if let Err(e) = self.audio.set_alias(&uid, alias) {
+                        self.notify(e);
+                    } else if let Some(device) = self.cursor_device() {
+                        self.notify(format!("{name} is shown as \"{}\"", device.label()));
+                    }
+                }
+                self.mode_back();
+                effects.push(Effect::Draw);
+            }
+            Action::MacroDiscard => {
+                self.macro_recording = None;
+                self.notify("Macro discarded".to_string());
+                self.mode_back();
+                effects.push(Effect::Draw);
+            }
+            Action::RunMacro(name) => {
+                match macros::load(&name) {
+                    Ok(actions) => {
+                        for action in actions {
+                            effects.push(Effect::Dispatch(action));
+                        }
+                    }
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ProfileNext | Action::ProfilePrev => {
+                let step = match action {
+                    Action::ProfileNext => 1,
+                    _ => -1,
+                };
+                let count = profiles::matches(&self.profiles.query).len();
+                self.profiles
+                    .move_selection(step, count.min(palette::SHOWN));
+                effects.push(Effect::Draw);
+            }
+            Action::ProfileApply => {
+                let name = profiles::matches(&self.profiles.query)
+                    .into_iter()
+                    .nth(self.profiles.selected);
+                self.mode_back();
+                // Through the channel, so it's recorded and refused when
+                // read-only
+                if let Some(name) = name {
+                    effects.push(Effect::Dispatch(Action::ApplyProfile(name)));
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ProfileSave => {
+                let name = self.profiles.query.trim().to_string();
+                match profiles::save(&name, &self.audio) {
+                    Ok(()) => {
+                        self.notify(format!("Saved profile \"{name}\""));
+                        self.mode_back();
+                    }
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ApplyProfile(name) => {
+                match profiles::apply(&name, &mut self.audio) {
+                    Ok(message) => self.notify(message),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::TogglePane(pane) => {
+                if let Err(e) = self.panes.toggle(pane) {
+                    self.notify(e);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::CycleLogLevel => {
+                self.log_level = self.log_level.next();
+                effects.push(Effect::Draw);
+            }
+            Action::FocusNextPane => {
+                self.panes.focus_next();
+                effects.push(Effect::Draw);
+            }
+            Action::ResizePane { grow } => {
+                if let Err(e) = self.panes.resize(grow) {
+                    self.notify(e);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::MakeDefault if self.mode == UiMode::Alert => {
+                if self.cursor_device().is_none() {
+                    self.move_cursor(0);
+                }
+                let Some(device) = self.cursor_device() else {
+                    return effects;
+                };
+                if self.audio.can_be_alert(device.id) {
+                    effects.push(Effect::Dispatch(Action::SetAlertDevice(device.uid.clone())));
+                } else {
+                    self.notify(format!("{} can't play alerts", device.label()));
+                    effects.push(Effect::Draw);
+                }
+            }
+            Action::SetAlertDevice(uid) => {
+                if let Err(e) = self.audio.set_alert(&uid) {
+                    self.notify(e);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::AlertVolumeUp | Action::AlertVolumeDown => {
+                let step = match action {
+                    Action::AlertVolumeUp => alerts::STEP,
+                    _ => -alerts::STEP,
+                };
+                if let Err(e) = alerts::set_volume(alerts::volume() + step) {
+                    self.notify(e);
+                }
+                self.alert_volume = alerts::volume();
+                effects.push(Effect::Draw);
+            }
+            Action::MakeDefault => {
+                let (channel, name) = match self.mode {
+                    UiMode::EditInput => (Channel::Input, "input"),
+                    UiMode::EditOutput => (Channel::Output, "output"),
+                    _ => return effects,
+                };
+                if self.cursor_device().is_none() {
+                    self.move_cursor(0);
+                }
+                let Some(device) = self.cursor_device() else {
+                    return effects;
+                };
+                let volume = device.volume(channel).borrow();
+                if volume.enabled && volume.selectable {
+                    // Through the channel, so it's recorded like the palette's
+                    effects.push(Effect::Dispatch(Action::SetDefault(
+                        channel,
+                        device.uid.clone(),
+                    )));
+                } else {
+                    let message = format!("{} can't be the default {name}", device.label());
+                    drop(volume);
+                    self.notify(message);
+                    effects.push(Effect::Draw);
+                }
+            }
+            Action::SetDefault(channel, uid) => {
+                if let Err(e) = self.audio.set_default(channel, &uid) {
+                    self.notify(e);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::VolumeEntrySubmit => {
+                match self.volume_entry.parse::<u8>() {
+                    Ok(percent) if percent <= 100 => {
+                        self.mode_back();
+                        // Through the channel, so it's recorded like the step keys
+                        effects.push(Effect::Dispatch(Action::SetVolume(
+                            self.volume_channel,
+                            percent,
+                        )));
+                    }
+                    _ => self.notify("Type a volume from 0 to 100".to_string()),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::CycleStereoLeft | Action::CycleStereoRight | Action::NextStereoPair => {
+                let Some(id) = self.cursor_device().map(|d| d.id) else {
+                    return effects;
+                };
+                let count = audio::output_channel_count(&id);
+                let (left, right) = match audio::stereo_channels(&id) {
+                    Some(pair) if count >= 2 => pair,
+                    _ => {
+                        self.notify("No stereo channels to choose from".to_string());
+                        effects.push(Effect::Draw);
+                        return effects;
+                    }
+                };
+                let next = |channel: u32| channel % count + 1;
+                let pair = match action {
+                    Action::CycleStereoLeft => (next(left), right),
+                    Action::CycleStereoRight => (left, next(right)),
+                    _ => {
+                        // Pairs start on odd channels: 1-2, 3-4 and so on
+                        let start = left.max(1) - (left.max(1) - 1) % 2 + 2;
+                        match start < count {
+                            true => (start, start + 1),
+                            false => (1, 2),
+                        }
+                    }
+                };
+                match self.audio.set_stereo_channels_of(&id, pair) {
+                    Ok(()) => self.notify(format!("Stereo on channels {} and {}", pair.0, pair.1)),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::AppNext | Action::AppPrev => {
+                let count = self.apps.as_ref().map_or(0, |apps| apps.len());
+                self.app_cursor = match action {
+                    Action::AppNext => (self.app_cursor + 1).min(count.saturating_sub(1)),
+                    _ => self.app_cursor.saturating_sub(1),
+                };
+                effects.push(Effect::Draw);
+            }
+            Action::AppVolumeUp | Action::AppVolumeDown | Action::AppMute => {
+                let Some(app) = self.cursor_app().cloned() else {
+                    return effects;
+                };
+                let result = match action {
+                    Action::AppVolumeUp => self.app_volumes.move_level(&app, apps::STEP),
+                    Action::AppVolumeDown => self.app_volumes.move_level(&app, -apps::STEP),
+                    _ => self.app_volumes.toggle_mute(&app),
+                };
+                if let Err(e) = result {
+                    self.notify(e);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::PropertyRun => {
+                let Some(id) = self.cursor_device().map(|d| d.id) else {
+                    return effects;
+                };
+                self.property_result = Some(explorer::run_line(&id, &self.property_query));
+                // A set may have changed what the list shows
+                self.audio.update();
+                effects.push(Effect::Draw);
+            }
+            Action::SetMute(channel, muted) => {
+                let id = self.audio.active_id(channel);
+                let current = id.and_then(|id| match channel {
+                    Channel::Input => self.audio.input(&id),
+                    Channel::Output => self.audio.output(&id),
+                });
+                if let (Some(id), Some((_, current))) = (id, current) {
+                    if current != muted {
+                        self.audio.toggle_mute_of(&id, channel);
+                    }
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::SetVolume(channel, percent) => {
+                let level = percent as f32 / 100.0;
+                effects.push(Effect::SetLevel(Volumes::Default, channel, level));
+                effects.push(Effect::Draw);
+            }
+            Action::VolumePreset(slot) => {
+                let Some(&percent) = self.presets.get(slot as usize - 1) else {
+                    self.notify(format!("No volume preset {slot}, see `presets` in [keys]"));
+                    effects.push(Effect::Draw);
+                    return effects;
+                };
+                let level = percent as f32 / 100.0;
+                let (channel, target) = match self.mode {
+                    UiMode::EditInput => (Channel::Input, self.edit_target(Channel::Input)),
+                    UiMode::EditOutput => (Channel::Output, self.edit_target(Channel::Output)),
+                    _ => (Channel::Output, None),
+                };
+                match target {
+                    Some(id) => {
+                        effects.push(Effect::SetLevel(Volumes::Device(id), channel, level));
+                        effects.push(Effect::UpdateDevices);
+                    }
+                    None => effects.push(Effect::SetLevel(Volumes::Targets, channel, level)),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::MemberNext | Action::MemberPrev => {
+                let step = match action {
+                    Action::MemberNext => 1,
+                    _ => -1,
+                };
+                self.move_member_cursor(step);
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleMember => {
+                if let Some((id, member)) = self.cursor_member() {
+                    self.audio.toggle_member(&id, &member.uid);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleDrift => {
+                if let Some((id, member)) = self.cursor_member() {
+                    self.audio.toggle_drift(&id, &member.uid);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::HideDevice(uid) => {
+                self.audio.hide(&uid);
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleMirror => {
+                let Some(uid) = self.cursor_device().map(|d| d.uid.clone()) else {
+                    return effects;
+                };
+                match self.audio.toggle_mirror(&uid) {
+                    Ok(message) => self.notify(message),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleHog => {
+                let Some(uid) = self.cursor_device().map(|d| d.uid.clone()) else {
+                    return effects;
+                };
+                match self.audio.toggle_hog(&uid) {
+                    Ok(message) => self.notify(message),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::PlayTone => {
+                self.play_tone();
+                effects.push(Effect::Draw);
+            }
+            Action::SearchCancel => {
+                self.search.clear();
+                self.mode_back();
+                effects.push(Effect::Draw);
+            }
+            Action::SelectNext => {
+                match self.mode {
+                    UiMode::EditInput => {
+                        self.audio.next_input();
+                    }
+                    UiMode::EditOutput => {
+                        self.audio.next_output();
+                    }
+                    _ => return effects,
+                };
+                effects.push(Effect::Draw);
+            }
+            Action::SelectPrev => {
+                match self.mode {
+                    UiMode::EditInput => {
+                        self.audio.prev_input();
+                    }
+                    UiMode::EditOutput => {
+                        self.audio.prev_output();
+                    }
+                    _ => return effects,
+                };
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleMute => {
+                let channel = match self.mode {
+                    UiMode::EditInput => Channel::Input,
+                    UiMode::EditOutput => Channel::Output,
+                    _ => return effects,
+                };
+                match self.edit_target(channel) {
+                    Some(id) => {
+                        self.audio.toggle_mute_of(&id, channel);
+                        self.audio.update();
+                    }
+                    None => self.audio.toggle_mute(channel),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::VolumeUp { fine } | Action::VolumeDown { fine } => {
+                let (key, sign) = match action {
+                    Action::VolumeUp { .. } => (KEY_RIGHT, 1.0),
+                    _ => (KEY_LEFT, -1.0),
+                };
+                let channel = match self.mode {
+                    UiMode::EditInput => Channel::Input,
+                    UiMode::EditOutput => Channel::Output,
+                    _ => return effects,
+                };
+                let target = self.edit_target(channel);
+                let Some(id) = target.or_else(|| self.audio.active_id(channel)) else {
+                    return effects;
+                };
+                let scale = match fine {
+                    true => sign / FINE_STEPS,
+                    false => sign,
+                };
+                // dB steps follow the device's curve, devices without one
+                // keep the scalar step
+                let db_amount = match self.db_steps {
+                    true => {
+                        let step = self.key_repeat.step(key, self.db_step_for(&id));
+                        audio::db_step(&id, channel, scale * step)
+                    }
+                    false => None,
+                };
+                let amount = db_amount.unwrap_or_else(|| {
+                    scale
+                        * self
+                            .key_repeat
+                            .step(key, self.volume_step_for(&id, TUI_STEP))
+                });
+                match target {
+                    Some(id) => {
+                        effects.push(Effect::MoveVolume(Volumes::Device(id), channel, amount));
+                        effects.push(Effect::UpdateDevices);
+                    }
+                    None => effects.push(Effect::MoveVolume(Volumes::Targets, channel, amount)),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleSection => {
+                self.toggle_section();
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleVirtual => {
+                self.hide_virtual = !self.hide_virtual;
+                // The highlight may have been on a hidden device
+                self.move_cursor(0);
+                effects.push(Effect::Draw);
+            }
+            Action::FixSilent => {
+                self.audio.fix_silent(Channel::Output);
+                effects.push(Effect::Draw);
+            }
+            Action::AlignSampleRates => {
+                match self.audio.align_sample_rates() {
+                    Ok(rate) => self.notify(format!(
+                        "Input and output run at {}",
+                        audio::describe_rate(rate)
+                    )),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleInputMute => {
+                self.toggle_input_mute();
+                effects.push(Effect::Draw);
+            }
+            Action::BacklightUp | Action::BacklightDown => {
+                let step = match action {
+                    Action::BacklightUp => system::BACKLIGHT_STEP,
+                    _ => -system::BACKLIGHT_STEP,
+                };
+                let result =
+                    system::backlight().and_then(|level| system::set_backlight(level + step));
+                if let Err(e) = result {
+                    self.notify(e);
+                }
+                self.backlight = system::backlight();
+                effects.push(Effect::Draw);
+            }
+            Action::DisplaySleep | Action::LockScreen => {
+                let result = match action {
+                    Action::DisplaySleep => system::display_sleep(),
+                    _ => system::lock_screen(),
+                };
+                if let Err(e) = result {
+                    self.notify(e);
+                    effects.push(Effect::Draw);
+                }
+            }
+            Action::MicModes => {
+                if let Err(e) = micmode::show_picker() {
+                    self.notify(e);
+                    effects.push(Effect::Draw);
+                }
+            }
+            Action::RecordOutput => {
+                self.toggle_recording();
+                effects.push(Effect::Draw);
+            }
+            Action::Broadcast => {
+                match self.audio.toggle_broadcast() {
+                    Ok(message) => self.notify(message),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::Boost => {
+                self.boost();
+                effects.push(Effect::Draw);
+            }
+            Action::PlayPause => {
+                if !self.permissions.can_post() {
+                    self.notify("Play/pause needs the Accessibility permission".to_string());
+                } else if let Err(e) = mediakeys::post_play_pause() {
+                    self.notify(e);
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::CycleMutePolicy => {
+                if !matches!(self.mode, UiMode::EditInput | UiMode::EditOutput) {
+                    return effects;
+                }
+                let Some(uid) = self.cursor_device().map(|d| d.uid.clone()) else {
+                    return effects;
+                };
+                self.audio.cycle_mute_policy(&uid);
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleDbSteps => {
+                self.db_steps = !self.db_steps;
+                self.notify(match self.db_steps {
+                    true => format!("Volume steps of {} dB", self.db_step),
+                    false => "Volume steps in percent".to_string(),
+                });
+                effects.push(Effect::Draw);
+            }
+            Action::CycleSampleRate => {
+                let channel = match self.mode {
+                    UiMode::EditInput => Channel::Input,
+                    UiMode::EditOutput => Channel::Output,
+                    _ => return effects,
+                };
+                let device = match self.edit_target(channel) {
+                    Some(_) => self.cursor_device(),
+                    None => self.audio.active_device(channel),
+                };
+                let Some(device) = device else {
+                    return effects;
+                };
+                let (id, label) = (device.id, device.label().to_string());
+                match self.audio.cycle_sample_rate_of(&id) {
+                    Ok(rate) => self.notify(format!("{label}: {}", audio::describe_rate(rate))),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::CycleDataSource | Action::CycleClockSource => {
+                let channel = match self.mode {
+                    UiMode::EditInput => Channel::Input,
+                    UiMode::EditOutput => Channel::Output,
+                    _ => return effects,
+                };
+                let device = match self.edit_target(channel) {
+                    Some(_) => self.cursor_device(),
+                    None => self.audio.active_device(channel),
+                };
+                let Some(device) = device else {
+                    return effects;
+                };
+                let (id, label) = (device.id, device.label().to_string());
+                let kind = match action {
+                    Action::CycleDataSource => SourceKind::Data(channel),
+                    _ => SourceKind::Clock,
+                };
+                match self.audio.cycle_source_of(&id, kind) {
+                    Ok(name) => self.notify(format!("{label}: {name}")),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::BalanceLeft | Action::BalanceRight | Action::CenterBalance => {
+                if self.mode != UiMode::EditOutput {
+                    return effects;
+                }
+                let device = match self.edit_target(Channel::Output) {
+                    Some(_) => self.cursor_device(),
+                    None => self.audio.active_device(Channel::Output),
+                };
+                let Some(device) = device else {
+                    return effects;
+                };
+                let (id, label) = (device.id, device.label().to_string());
+                let Some(balance) = device.balance() else {
+                    self.notify(format!("{label} has no volume per channel"));
+                    effects.push(Effect::Draw);
+                    return effects;
+                };
+                let balance = match action {
+                    Action::BalanceLeft => balance - BALANCE_STEP,
+                    Action::BalanceRight => balance + BALANCE_STEP,
+                    _ => 0.0,
+                };
+                let balance = (balance.clamp(-1.0, 1.0) / BALANCE_STEP).round() * BALANCE_STEP;
+                match self.audio.set_balance_of(&id, balance) {
+                    Ok(()) => self.notify(format!("{label}: {}", audio::describe_balance(balance))),
+                    Err(e) => self.notify(e),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleGroup => {
+                if self.mode != UiMode::EditOutput {
+                    return effects;
+                }
+                self.audio.toggle_grouped();
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleMark => {
+                if !matches!(self.mode, UiMode::EditInput | UiMode::EditOutput) {
+                    return effects;
+                }
+                let Some(uid) = self.cursor_device().map(|d| d.uid.clone()) else {
+                    return effects;
+                };
+                self.audio.toggle_mark(&uid);
+                effects.push(Effect::Draw);
+            }
+            Action::HideMarked => {
+                if self.mode == UiMode::View {
+                    return effects;
+                }
+                self.audio.hide_marked();
+                self.collect_notices();
+                effects.push(Effect::Draw);
+            }
+            Action::CursorNext => {
+                self.move_cursor(1);
+                effects.push(Effect::Draw);
+            }
+            Action::CursorPrev => {
+                self.move_cursor(-1);
+                effects.push(Effect::Draw);
+            }
+            Action::CursorPageNext | Action::CursorPagePrev => {
+                let pages = match action {
+                    Action::CursorPageNext => 1,
+                    _ => -1,
+                };
+                self.page_cursor(pages);
+                effects.push(Effect::Draw);
+            }
+            Action::Resize => effects.push(Effect::Redraw),
+            Action::ToggleSwallow => {
+                let swallow = !self.is_swallowing();
+                self.set_swallowing(swallow);
+                match (swallow, self.is_swallowing()) {
+                    (true, true) => self.notify("Bound keys are kept from other apps".to_string()),
+                    (true, false) if !self.permissions.can_post() => {
+                        self.notify("Swallowing keys needs Accessibility".to_string())
+                    }
+                    (true, false) => self.notify("No key bindings to swallow".to_string()),
+                    _ => self.notify("Bound keys reach other apps".to_string()),
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleKeyRouting => {
+                if self.is_routing_keys() {
+                    self.set_routing_keys(false);
+                    self.notify("Volume keys control the default output".to_string());
+                } else if !self.permissions.can_post() {
+                    self.notify("Routing volume keys needs Accessibility".to_string());
+                } else {
+                    if self.cursor_device().is_none() {
+                        self.move_cursor(0);
+                    }
+                    if let Some(device) = self.cursor_device() {
+                        let message = format!(
+                            "Volume keys control {}, its input in input mode",
+                            device.label()
+                        );
+                        self.set_routing_keys(true);
+                        self.notify(message);
+                    }
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::MediaKey {
+                key,
+                repeating,
+                fine,
+            } => {
+                self.media_repeat.press(key as i64, repeating);
+                let channel = self.routed_channel();
+                // Fall back to the channel's default if the device went away
+                let id = self
+                    .cursor_device()
+                    .filter(|d| self.is_routing_keys() && d.volume(channel).borrow().enabled)
+                    .or_else(|| self.audio.active_device(channel))
+                    .map(|d| d.id);
+                if let Some(id) = id {
+                    let step = self
+                        .media_repeat
+                        .step(key as i64, self.volume_step_for(&id, KEY_STEP));
+                    let step = match fine {
+                        true => step / FINE_STEPS,
+                        false => step,
+                    };
+                    let device = Volumes::Device(id);
+                    match key {
+                        MediaKey::VolumeUp => {
+                            effects.push(Effect::MoveVolume(device, channel, step))
+                        }
+                        MediaKey::VolumeDown => {
+                            effects.push(Effect::MoveVolume(device, channel, -step))
+                        }
+                        MediaKey::Mute if !repeating => self.audio.toggle_mute_of(&id, channel),
+                        MediaKey::Mute => {}
+                    }
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::Calibrate => {
+                if self.mode != UiMode::EditInput {
+                    return effects;
+                }
+                if let Some(device) = self.audio.active_device(Channel::Input) {
+                    let level = device.input.borrow().level;
+                    self.calibration = Some(Calibration::new(&device.uid, level));
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ApplyGain => {
+                if self.mode != UiMode::EditInput {
+                    return effects;
+                }
+                if let Some((_, level)) = self.gain_suggestion {
+                    effects.push(Effect::SetLevel(Volumes::Default, Channel::Input, level));
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::Meter { rms, peak } => {
+                self.meter.push(rms, peak);
+                // Calibrating needs the volume to hold still
+                let level = self
+                    .audio
+                    .active_device(Channel::Input)
+                    .filter(|d| !self.audio.is_muted(d, Channel::Input))
+                    .map(|d| d.input.borrow().level)
+                    .filter(|_| self.calibration.is_none());
+                let floor = self.meter.noise_floor();
+                let change = self
+                    .auto_gain
+                    .as_mut()
+                    .zip(level)
+                    .and_then(|(auto_gain, level)| auto_gain.push(rms, floor, level));
+                if let Some(level) = change {
+                    effects.push(Effect::SetLevel(Volumes::Default, Channel::Input, level));
+                }
+                if let Some(calibration) = self.calibration.as_mut() {
+                    calibration.push(peak);
+                    if calibration.is_done() {
+                        if let Some(level) = calibration.suggestion() {
+                            let _ = meter::save_gain(&calibration.uid, level);
+                            self.gain_suggestion = Some((calibration.uid.clone(), level));
+                        }
+                        self.calibration = None;
+                    }
+                }
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleDoctor => {
+                self.show_doctor = !self.show_doctor;
+                effects.push(Effect::Draw);
+            }
+            Action::ToggleHistory => {
+                self.show_history = !self.show_history;
+                effects.push(Effect::Draw);
+            }
+            Action::PropertyChanged { id, selector } => {
+                self.last_change = Some((id, selector));
+                self.audio.update();
+                self.devices_changed(&mut effects);
+                effects.push(Effect::Draw);
+            }
+            Action::DeviceAdded(label) => {
+                self.notify(format!("🔌 {label} connected"));
+                effects.push(Effect::Draw);
+            }
+            Action::DeviceRemoved(label) => {
+                self.notify(format!("🔌 {label} disconnected"));
+                effects.push(Effect::Draw);
+            }
+            Action::Poll => {
+                let started = Instant::now();
+                if let Some((trigger, actions)) = self.display.check() {
+                    log::info(format!(
+                        "Display {trigger}, running {} actions",
+                        actions.len()
+                    ));
+                    effects.extend(actions.into_iter().map(Effect::Dispatch));
+                }
+                self.check_layout();
+                self.audio.retry_volumes();
+                self.audio.check_night();
+                self.metrics.time("update", || self.audio.update());
+                self.devices_changed(&mut effects);
+                self.check_batteries();
+                self.check_boost();
+                self.check_tone();
+                self.check_volume_notice();
+                // Playing apps come and go
+                if self.mode == UiMode::Apps {
+                    self.refresh_apps();
+                }
+                self.record_volumes();
+                store::flush(false);
+                self.metrics.record("poll", started.elapsed());
+                self.metrics.count_queries();
+                effects.push(Effect::Draw);
+            }
+            Action::Pointer { event, modifiers } => {
+                if let Some(chord) = self.pointer_chord(event, &modifiers) {
+                    effects.push(Effect::ReadFrontmost);
+                    effects.push(Effect::History(chord));
+                }
+                effects.push(Effect::Draw);
+            }
+            // Resolved before they get here
+            Action::MouseDown { .. } | Action::TerminalKey(_) | Action::ShiftedKey(_) => {}
+            Action::Exit => effects.push(Effect::Exit),
+        }
+        effects
+    }
+
+    /// Run an effect that reads or writes outside the app. The ones for
+    /// the main loop, like drawing, do nothing here.
+    pub fn run(&mut self, effect: Effect) {
+        match effect {
+            Effect::Count(record) => {
+                if let Err(e) = self.stats.count(&record) {
+                    log::warn(e);
+                }
+            }
+            Effect::ReadFrontmost => self.frontmost_app = frontmost::app_name(),
+            Effect::History(chord) => self.key_history.push(chord, self.frontmost_app.clone()),
+            Effect::BroadcastKey(key_code, down) => self.broadcast_key(key_code, down),
+            Effect::SetLevel(volumes, channel, level) => match volumes {
+                Volumes::Default => self.audio.set_level(channel, level),
+                Volumes::Targets => self.audio.set_targets_level(channel, level),
+                Volumes::Device(id) => self.audio.set_level_of(&id, channel, level),
+            },
+            Effect::MoveVolume(volumes, channel, amount) => match volumes {
+                Volumes::Default => {
+                    if let Some(id) = self.audio.active_id(channel) {
+                        self.audio.move_volume_of(&id, channel, amount);
+                        self.audio.update();
+                    }
+                }
+                Volumes::Targets => self.audio.move_volume(channel, amount),
+                Volumes::Device(id) => self.audio.move_volume_of(&id, channel, amount),
+            },
+            Effect::UpdateDevices => self.audio.update(),
+            Effect::Draw
+            | Effect::Redraw
+            | Effect::Dispatch(_)
+            | Effect::WatchDevices
+            | Effect::SyncHomeAssistant
+            | Effect::Exit => {}
+        }
+    }
+
+    /// After reading the devices: pick up connections, notices and mute
+    /// changes, and run the rules they trigger.
+    fn devices_changed(&mut self, effects: &mut Vec<Effect>) {
+        effects.push(Effect::WatchDevices);
+        self.collect_notices();
+        for (label, connected) in self.audio.take_connections() {
+            effects.push(Effect::Dispatch(match connected {
+                true => Action::DeviceAdded(label),
+                false => Action::DeviceRemoved(label),
+            }));
+        }
+        self.check_osd();
+        self.check_mute_since();
+        self.check_rate_mismatch();
+        // Device rules from the config, run through the loop like key bindings
+        for (trigger, actions) in self.rules.check(&mut self.audio) {
+            match actions {
+                Ok(actions) => {
+                    log::info(format!(
+                        "Rule for {trigger}, running {} actions",
+                        actions.len()
+                    ));
+                    effects.extend(actions.into_iter().map(Effect::Dispatch));
+                }
+                Err(e) => self.notify(format!("Rule for {trigger}: {e}")),
+            }
+        }
+        self.sinks.check(&self.audio);
+        effects.push(Effect::SyncHomeAssistant);
+        self.sync_caps_lock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioState;
+    use crate::backend::{AudioBackend, MockBackend, MockDevice};
+    use crate::config::Config;
+    use crate::events::{ModifierKeys, PermissionStatus};
+    use crate::permissions::Status;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// An app on one mock output at half volume, without permissions.
+    fn app(test: &str) -> (MockBackend, AppState) {
+        store::use_temp_home();
+        let backend = MockBackend::new();
+        backend.add(
+            1,
+            MockDevice {
+                uid: format!("{test}.speakers"),
+                name: "Speakers".to_string(),
+                channels: [false, true],
+                volumes: [None, Some(0.5)],
+                mutes: [None, Some(false)],
+                ..MockDevice::default()
+            },
+        );
+        backend.set_default_device(Channel::Output, 1).unwrap();
+        let config = Config {
+            volume_ramp: Duration::ZERO,
+            ..Config::default()
+        };
+        let audio = AudioState::with_backend(&config, Arc::new(backend.clone()));
+        let permissions = PermissionStatus {
+            listen: Status::Denied,
+            post: Status::Denied,
+        };
+        let state = AppState::with_audio(&config, audio, permissions, None);
+        (backend, state)
+    }
+
+    /// Apply an action and run what reads or writes outside the app, with
+    /// the effects left for the main loop.
+    fn step(state: &mut AppState, action: Action) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        for effect in state.apply(action) {
+            match effect {
+                Effect::Draw
+                | Effect::Redraw
+                | Effect::Dispatch(_)
+                | Effect::WatchDevices
+                | Effect::SyncHomeAssistant
+                | Effect::Exit => effects.push(effect),
+                effect => state.run(effect),
+            }
+        }
+        effects
+    }
+
+    #[test]
+    fn search_takes_text() {
+        let (_, mut state) = app("search");
+        let effects = state.apply(Action::ModeSwitch(UiMode::Search));
+        let [Effect::Count(record), Effect::Draw] = &effects[..] else {
+            panic!("unexpected effects {effects:?}");
+        };
+        assert_eq!(record, "mode search");
+        step(&mut state, Action::TextInput('s'));
+        step(&mut state, Action::TextInput('p'));
+        assert_eq!(state.search, "sp");
+        step(&mut state, Action::ModeBack);
+        assert_eq!(state.mode, UiMode::View);
+        // Outside of a text mode, text does nothing
+        assert!(state.apply(Action::TextInput('x')).is_empty());
+    }
+
+    #[test]
+    fn typed_volume_goes_through_the_loop() {
+        let (backend, mut state) = app("typed");
+        step(&mut state, Action::ModeSwitch(UiMode::EditOutput));
+        step(&mut state, Action::TextInput('4'));
+        assert_eq!(state.mode, UiMode::Volume);
+        step(&mut state, Action::TextInput('2'));
+        let effects = step(&mut state, Action::VolumeEntrySubmit);
+        assert_eq!(state.mode, UiMode::EditOutput);
+        let [Effect::Dispatch(action), Effect::Draw] = &effects[..] else {
+            panic!("unexpected effects {effects:?}");
+        };
+        assert!(matches!(action, Action::SetVolume(Channel::Output, 42)));
+
+        step(&mut state, Action::SetVolume(Channel::Output, 42));
+        assert_eq!(backend.volume(1, Channel::Output), Some(0.42));
+    }
+
+    #[test]
+    fn volume_changes_are_effects() {
+        let (backend, mut state) = app("effects");
+        let writes = backend.writes();
+        let effects = state.apply(Action::SetVolume(Channel::Output, 30));
+        let [Effect::Count(_), Effect::SetLevel(Volumes::Default, Channel::Output, level), Effect::Draw] =
+            effects[..]
+        else {
+            panic!("unexpected effects {effects:?}");
+        };
+        assert_eq!(level, 0.3);
+        // Nothing is written until the effect runs
+        assert_eq!(backend.writes(), writes);
+
+        let effects = state.apply(Action::MediaKey {
+            key: MediaKey::VolumeDown,
+            repeating: false,
+            fine: false,
+        });
+        let [Effect::Count(_), Effect::MoveVolume(Volumes::Device(1), Channel::Output, amount), Effect::Draw] =
+            effects[..]
+        else {
+            panic!("unexpected effects {effects:?}");
+        };
+        assert_eq!(amount, -KEY_STEP);
+        state.run(Effect::MoveVolume(
+            Volumes::Device(1),
+            Channel::Output,
+            amount,
+        ));
+        assert_eq!(backend.volume(1, Channel::Output), Some(0.5 - KEY_STEP));
+    }
+
+    #[test]
+    fn keys_go_to_the_history_and_clients_as_effects() {
+        let (_, mut state) = app("keys");
+        let modifiers = ModifierKeys {
+            shift: true,
+            ..ModifierKeys::default()
+        };
+        let effects = state.apply(Action::KeyDown {
+            key_code: 0,
+            repeating: false,
+            modifiers,
+            keyboard: 0,
+        });
+        let [Effect::ReadFrontmost, Effect::History(chord), Effect::BroadcastKey(0, true), Effect::Draw] =
+            &effects[..]
+        else {
+            panic!("unexpected effects {effects:?}");
+        };
+        assert_eq!(
+            chord,
+            &layout::chord(&["shift".to_string()], &[state.key_name(0)])
+        );
+        assert_eq!(state.keys, [0]);
+
+        // A repeat changes nothing outside
+        let effects = state.apply(Action::KeyDown {
+            key_code: 0,
+            repeating: true,
+            modifiers,
+            keyboard: 0,
+        });
+        assert!(effects.is_empty());
+
+        let effects = state.apply(Action::KeyUp {
+            key_code: 0,
+            modifiers,
+        });
+        assert!(matches!(
+            effects[..],
+            [Effect::BroadcastKey(0, false), Effect::Draw]
+        ));
+        assert!(state.keys.is_empty());
+    }
+
+    #[test]
+    fn replays_are_not_counted() {
+        let (_, mut state) = app("replay");
+        state.replaying = true;
+        let effects = state.apply(Action::ModeSwitch(UiMode::Search));
+        assert!(matches!(effects[..], [Effect::Draw]));
+    }
+
+    #[test]
+    fn out_of_range_volume_stays_in_the_entry() {
+        let (_, mut state) = app("range");
+        step(&mut state, Action::ModeSwitch(UiMode::EditOutput));
+        for c in ['1', '0', '1'] {
+            step(&mut state, Action::TextInput(c));
+        }
+        let effects = step(&mut state, Action::VolumeEntrySubmit);
+        assert!(matches!(effects[..], [Effect::Draw]));
+        assert_eq!(state.mode, UiMode::Volume);
+    }
+
+    #[test]
+    fn cursor_picks_the_new_default() {
+        let (backend, mut state) = app("cursor");
+        let headphones = MockDevice {
+            uid: "cursor.headphones".to_string(),
+            name: "Headphones".to_string(),
+            channels: [false, true],
+            volumes: [None, Some(0.3)],
+            mutes: [None, None],
+            ..MockDevice::default()
+        };
+        backend.add(2, headphones);
+        state.audio.update();
+        step(&mut state, Action::ModeSwitch(UiMode::EditOutput));
+        // From the default, then around the list
+        step(&mut state, Action::CursorNext);
+        let first = state.cursor.clone();
+        assert!(first.is_some());
+        step(&mut state, Action::CursorNext);
+        assert_ne!(state.cursor, first);
+        step(&mut state, Action::CursorPrev);
+        assert_eq!(state.cursor, first);
+
+        state.cursor = Some("cursor.headphones".to_string());
+        let effects = step(&mut state, Action::MakeDefault);
+        let [Effect::Dispatch(Action::SetDefault(Channel::Output, uid))] = &effects[..] else {
+            panic!("unexpected effects {effects:?}");
+        };
+        assert_eq!(uid, "cursor.headphones");
+        step(&mut state, Action::SetDefault(Channel::Output, uid.clone()));
+        assert_eq!(backend.default_device(Channel::Output), 2);
+        assert_eq!(state.audio.active_id(Channel::Output), Some(2));
+    }
+
+    #[test]
+    fn loop_effects() {
+        let (_, mut state) = app("loop");
+        assert!(matches!(state.apply(Action::Resize)[..], [Effect::Redraw]));
+        assert!(matches!(step(&mut state, Action::Exit)[..], [Effect::Exit]));
+    }
+}
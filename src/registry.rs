@@ -0,0 +1,263 @@
+//! Known-device registry: per-device settings keyed by device UID, stored in
+//! `devices.toml`. Consulted whenever a device (re)appears.
+//!
+//! ```toml
+//! ["AppleUSBAudioEngine:Generic:USB Audio:1234:1"]
+//! alias = "Desk DAC"
+//! hidden = false
+//! preferred_volume = 0.4
+//! preferred_input_volume = 0.7
+//! preferred_mute = false
+//! preferred_input_mute = true
+//! apply_at_start = true
+//! volume_cap = 0.8
+//! mute_takeover = "auto"
+//! priority = 10
+//! mirror = "BuiltInSpeakerDevice"
+//! volume_step = 0.02
+//! db_step = 1
+//! muted_input_volume = 0.6
+//! ```
+//!
+//! `muted_volume` and `muted_input_volume` are written by the app while a
+//! channel is muted by the volume-zero workaround, so the level to unmute
+//! to survives a restart. `last_volume` and `last_input_volume` are the
+//! last levels above zero, for a device that reconnects at zero.
+
+use crate::audio::Channel;
+use crate::store::Store;
+
+const DEVICES_FILE: &str = "devices.toml";
+
+/// When to take over a system mute with the volume-zero workaround.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MutePolicy {
+    /// Only for devices with both input and output mutes, the ones hit by
+    /// the Monterey bug.
+    #[default]
+    Auto,
+    /// Any device reporting a system mute.
+    Always,
+    /// Never, leave the native mute alone. Good for devices whose hardware
+    /// mute buttons sync state.
+    Never,
+}
+
+impl MutePolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(MutePolicy::Auto),
+            "always" => Some(MutePolicy::Always),
+            "never" => Some(MutePolicy::Never),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MutePolicy::Auto => "auto",
+            MutePolicy::Always => "always",
+            MutePolicy::Never => "never",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            MutePolicy::Auto => MutePolicy::Always,
+            MutePolicy::Always => MutePolicy::Never,
+            MutePolicy::Never => MutePolicy::Auto,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceSettings {
+    /// Display name used instead of the device's own name.
+    pub alias: Option<String>,
+    /// Left out of the device list.
+    pub hidden: bool,
+    /// Output volume applied when the device connects.
+    pub preferred_volume: Option<f32>,
+    /// Input volume applied when the device connects.
+    pub preferred_input_volume: Option<f32>,
+    /// System mutes applied when the device connects.
+    pub preferred_mute: Option<bool>,
+    pub preferred_input_mute: Option<bool>,
+    /// Apply the preferred volumes and mutes to the device when it's
+    /// already connected as the app starts, too.
+    pub apply_at_start: bool,
+    /// Maximum output volume.
+    pub volume_cap: Option<f32>,
+    pub mute_policy: MutePolicy,
+    /// Higher priority devices are listed first.
+    pub priority: i32,
+    /// UID of an output this one's volume is mirrored with, both ways.
+    pub mirror: Option<String>,
+    /// Arrow and volume key steps for this device, over the config's.
+    pub volume_step: Option<f32>,
+    pub db_step: Option<f32>,
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    store: Store,
+}
+
+impl Registry {
+    pub fn load() -> Self {
+        Registry {
+            store: Store::open(DEVICES_FILE),
+        }
+    }
+
+    /// Settings for a device, defaults for unknown ones.
+    pub fn settings(&self, uid: &str) -> DeviceSettings {
+        let level = |key| {
+            self.store
+                .get(uid, key)
+                .and_then(|v| v.parse::<f32>().ok())
+                .map(|v| v.clamp(0.0, 1.0))
+        };
+        let flag = |key| self.store.get(uid, key).map(|v| v == "true");
+        DeviceSettings {
+            alias: self.store.get(uid, "alias").map(|a| a.to_string()),
+            hidden: self.store.get(uid, "hidden") == Some("true"),
+            preferred_volume: level("preferred_volume"),
+            preferred_input_volume: level("preferred_input_volume"),
+            preferred_mute: flag("preferred_mute"),
+            preferred_input_mute: flag("preferred_input_mute"),
+            apply_at_start: flag("apply_at_start") == Some(true),
+            volume_cap: level("volume_cap"),
+            mute_policy: self.mute_policy(uid),
+            priority: self
+                .store
+                .get(uid, "priority")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            mirror: self.store.get(uid, "mirror").map(|m| m.to_string()),
+            volume_step: level("volume_step").filter(|v| *v > 0.0),
+            db_step: self
+                .store
+                .get(uid, "db_step")
+                .and_then(|v| v.parse::<f32>().ok())
+                .filter(|v| *v > 0.0 && *v <= 24.0),
+        }
+    }
+
+    pub fn mute_policy(&self, uid: &str) -> MutePolicy {
+        self.store
+            .get(uid, "mute_takeover")
+            .and_then(MutePolicy::parse)
+            .unwrap_or_default()
+    }
+
+    pub fn set_hidden(&mut self, uid: &str, hidden: bool) -> Result<(), String> {
+        self.store.set(uid, "hidden", hidden);
+        self.store.save()
+    }
+
+    pub fn set_priority(&mut self, uid: &str, priority: i32) -> Result<(), String> {
+        self.store.set(uid, "priority", priority);
+        self.store.save()
+    }
+
+    /// Link two outputs' volumes, or unlink a device with `None`.
+    /// Set or remove the name shown instead of the device's own.
+    pub fn set_alias(&mut self, uid: &str, alias: Option<&str>) -> Result<(), String> {
+        match alias {
+            Some(alias) => self.store.set(uid, "alias", alias),
+            None => {
+                if let Some(section) = self.store.sections.get_mut(uid) {
+                    section.remove("alias");
+                }
+            }
+        }
+        self.store.save()
+    }
+
+    pub fn set_mirror(&mut self, uid: &str, mirror: Option<&str>) -> Result<(), String> {
+        match mirror {
+            Some(mirror) => self.store.set(uid, "mirror", mirror),
+            None => {
+                if let Some(section) = self.store.sections.get_mut(uid) {
+                    section.remove("mirror");
+                }
+            }
+        }
+        self.store.save()
+    }
+
+    /// Level a workaround-muted channel unmutes to, if it was muted when
+    /// last seen.
+    pub fn muted_volume(&self, uid: &str, channel: Channel) -> Option<f32> {
+        self.store
+            .get(uid, muted_volume_key(channel))
+            .and_then(|v| v.parse::<f32>().ok())
+            .map(|v| v.clamp(0.0, 1.0))
+    }
+
+    /// Remember or forget a channel's workaround mute, saving only when it
+    /// changed.
+    pub fn set_muted_volume(
+        &mut self,
+        uid: &str,
+        channel: Channel,
+        level: Option<f32>,
+    ) -> Result<(), String> {
+        if self.muted_volume(uid, channel) == level {
+            return Ok(());
+        }
+        let key = muted_volume_key(channel);
+        match level {
+            Some(level) => self.store.set(uid, key, level),
+            None => {
+                if let Some(section) = self.store.sections.get_mut(uid) {
+                    section.remove(key);
+                }
+            }
+        }
+        self.store.save()
+    }
+
+    /// Last level above zero a channel had.
+    pub fn last_volume(&self, uid: &str, channel: Channel) -> Option<f32> {
+        self.store
+            .get(uid, last_volume_key(channel))
+            .and_then(|v| v.parse::<f32>().ok())
+            .filter(|v| *v > 0.0)
+            .map(|v| v.min(1.0))
+    }
+
+    /// Saving only when it changed.
+    pub fn set_last_volume(
+        &mut self,
+        uid: &str,
+        channel: Channel,
+        level: f32,
+    ) -> Result<(), String> {
+        if self.last_volume(uid, channel) == Some(level) {
+            return Ok(());
+        }
+        self.store.set(uid, last_volume_key(channel), level);
+        self.store.save()
+    }
+
+    pub fn set_mute_policy(&mut self, uid: &str, policy: MutePolicy) -> Result<(), String> {
+        self.store.set(uid, "mute_takeover", policy.as_str());
+        self.store.save()
+    }
+}
+
+fn last_volume_key(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Input => "last_input_volume",
+        Channel::Output => "last_volume",
+    }
+}
+
+fn muted_volume_key(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Input => "muted_input_volume",
+        Channel::Output => "muted_volume",
+    }
+}
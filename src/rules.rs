@@ -0,0 +1,176 @@
+//! Rules reacting to devices coming and going, or becoming the default.
+//! `[rules]` keys are a trigger and part of a device's name, alias or its
+//! UID, values are actions like in `[display]`:
+//!
+//! ```toml
+//! [rules]
+//! "connect AirPods" = "default output, volume output 40, default input MacBook Pro Microphone"
+//! "connect DELL" = "hide"
+//! "default-output Headphones" = "mute-input"
+//! ```
+//!
+//! A bare `default input`, `default output` or `hide` acts on the device
+//! that triggered the rule, and other devices can be named in `default`
+//! actions instead of given by UID.
+
+use std::collections::HashMap;
+
+use crate::audio::{AudioState, Channel, Device};
+use crate::events::Action;
+
+/// Trigger names in `[rules]` keys.
+pub const TRIGGERS: &[&str] = &["connect", "disconnect", "default-input", "default-output"];
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub trigger: String,
+    /// Part of the device's name or alias, or its UID.
+    pub device: String,
+    /// Recorded action names, with the shortcuts above.
+    pub actions: Vec<String>,
+}
+
+impl Rule {
+    /// A `[rules]` entry, `None` without a trigger and a device.
+    pub fn parse(key: &str, actions: &str) -> Option<Self> {
+        let (trigger, device) = key.trim().split_once(' ')?;
+        Some(Rule {
+            trigger: trigger.to_string(),
+            device: device.trim().to_string(),
+            actions: actions
+                .split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect(),
+        })
+    }
+
+    /// The rule's actions for a device, or why one doesn't make sense.
+    fn resolve(&self, uid: &str, audio: &AudioState) -> Result<Vec<Action>, String> {
+        self.actions
+            .iter()
+            .map(|record| match record.as_str() {
+                "hide" => Ok(Action::HideDevice(uid.to_string())),
+                "default input" => Ok(Action::SetDefault(Channel::Input, uid.to_string())),
+                "default output" => Ok(Action::SetDefault(Channel::Output, uid.to_string())),
+                record => resolve_record(record, audio),
+            })
+            .collect()
+    }
+}
+
+/// Whether a rule's device part means a device, by its UID and names.
+fn matches(pattern: &str, uid: &str, names: &[&str]) -> bool {
+    let pattern_lower = pattern.to_lowercase();
+    uid == pattern
+        || names
+            .iter()
+            .any(|name| name.to_lowercase().contains(&pattern_lower))
+}
+
+fn names(device: &Device) -> [&str; 2] {
+    [device.label(), &device.name]
+}
+
+/// An action, with a device name in `default input`/`default output`
+/// looked up among the connected devices.
+fn resolve_record(record: &str, audio: &AudioState) -> Result<Action, String> {
+    let action = Action::from_record(record).ok_or(format!("unknown action \"{record}\""))?;
+    let Action::SetDefault(channel, name) = &action else {
+        return Ok(action);
+    };
+    if audio.devices().iter().any(|d| d.uid == *name) {
+        return Ok(action);
+    }
+    audio
+        .devices()
+        .iter()
+        .find(|d| matches(name, &d.uid, &names(d)))
+        .map(|d| Action::SetDefault(*channel, d.uid.clone()))
+        .ok_or(format!("no device \"{name}\" connected"))
+}
+
+/// Watches the devices and defaults for rules, checked on device changes.
+#[derive(Debug, Default)]
+pub struct RuleWatch {
+    rules: Vec<Rule>,
+    /// Devices and defaults at the last check.
+    seen: Snapshot,
+}
+
+/// Device names by UID, and the default input and output UIDs.
+type Snapshot = (HashMap<String, [String; 2]>, [Option<String>; 2]);
+
+fn snapshot(audio: &AudioState) -> Snapshot {
+    let devices = audio
+        .devices()
+        .iter()
+        .map(|d| (d.uid.clone(), names(d).map(str::to_string)))
+        .collect();
+    let defaults = [Channel::Input, Channel::Output]
+        .map(|channel| audio.active_device(channel).map(|d| d.uid.clone()));
+    (devices, defaults)
+}
+
+impl RuleWatch {
+    /// Start watching from the devices there are now.
+    pub fn new(rules: &[Rule], audio: &AudioState) -> Self {
+        RuleWatch {
+            rules: rules.to_vec(),
+            seen: snapshot(audio),
+        }
+    }
+
+    /// Rules that fire for the changes since the last check, as a
+    /// description and the actions to run, or the error resolving them.
+    /// Default switches are dropped while they're left to AirPods.
+    pub fn check(&mut self, audio: &mut AudioState) -> Vec<(String, Result<Vec<Action>, String>)> {
+        let (devices, defaults) = snapshot(audio);
+        let (prev_devices, prev_defaults) =
+            std::mem::replace(&mut self.seen, (devices.clone(), defaults.clone()));
+        // (trigger, UID, names), disconnected devices by their last names
+        let mut events: Vec<(&str, &String, &[String; 2])> = Vec::new();
+        for (uid, names) in &devices {
+            if !prev_devices.contains_key(uid) {
+                events.push(("connect", uid, names));
+            }
+        }
+        for (uid, names) in &prev_devices {
+            if !devices.contains_key(uid) {
+                events.push(("disconnect", uid, names));
+            }
+        }
+        for (trigger, (default, prev)) in ["default-input", "default-output"]
+            .into_iter()
+            .zip(defaults.iter().zip(&prev_defaults))
+        {
+            let Some(uid) = default.as_ref().filter(|_| default != prev) else {
+                continue;
+            };
+            if let Some(names) = devices.get(uid) {
+                events.push((trigger, uid, names));
+            }
+        }
+        let mut fired = Vec::new();
+        for (trigger, uid, [label, name]) in events {
+            for rule in self.rules.iter().filter(|r| r.trigger == trigger) {
+                if matches(&rule.device, uid, &[label, name]) {
+                    let description = format!("{trigger} {label}");
+                    let actions = rule.resolve(uid, audio).map(|actions| {
+                        actions
+                            .into_iter()
+                            .filter(|action| match action {
+                                Action::SetDefault(channel, _) => {
+                                    !audio.leaves_default_to_airpods(*channel, name)
+                                }
+                                _ => true,
+                            })
+                            .collect()
+                    });
+                    fired.push((description, actions));
+                }
+            }
+        }
+        fired
+    }
+}
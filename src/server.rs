@@ -0,0 +1,160 @@
+//! Local socket API. Browsers (e.g. an OBS browser source) connect to
+//! `ws://127.0.0.1:<port>/keys` and get a JSON message per key event, and
+//! per state change for events with the socket sink. With `lan` it's
+//! reachable from the network too.
+//!
+//! Every key typed goes out, passwords included, so a token from
+//! `mac-controls server token` is needed by connections from other
+//! machines and by browsers, which send an `Origin` header: any web page
+//! open on this Mac could connect otherwise. Local programs that aren't
+//! browsers connect without one. With `[tls]` it's `wss://` instead.
+
+use std::io::Write;
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::http::Request;
+use crate::tls::{Acceptor, Stream};
+use crate::tokens;
+use crate::websocket;
+
+/// Connections at once, handshakes included, more are hung up on.
+const MAX_CONNECTIONS: usize = 16;
+/// Messages waiting for a client, one that falls this far behind is
+/// dropped instead of holding up the app.
+const QUEUE: usize = 256;
+/// Time a write to a client may take before it's dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Frames for a client, written out on a thread of its own.
+type Queue = SyncSender<Arc<[u8]>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct Server {
+    clients: Arc<Mutex<Vec<Queue>>>,
+}
+
+impl Server {
+    /// Listen on localhost, or every interface with `lan`, handling
+    /// connections on background threads, over TLS from `tls` if set up.
+    pub fn start(port: u16, lan: bool, tls: Acceptor) -> Result<Self, String> {
+        let address = match lan {
+            true => Ipv4Addr::UNSPECIFIED,
+            false => Ipv4Addr::LOCALHOST,
+        };
+        let listener = TcpListener::bind((address, port))
+            .map_err(|e| format!("Unable to listen on port {port}: {e}"))?;
+        let server = Server::default();
+        let clients = server.clients.clone();
+        let connections = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                    continue;
+                }
+                let clients = clients.clone();
+                let connections = connections.clone();
+                let tls = tls.clone();
+                thread::spawn(move || {
+                    if let Some(stream) = tls.accept(stream).ok().and_then(accept) {
+                        serve(stream, &clients);
+                    }
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    /// Queue a JSON message for every connected client, dropping the ones
+    /// that went away or fell behind. Never waits on a client.
+    pub fn broadcast(&self, json: &str) {
+        let frame: Arc<[u8]> = websocket::text_frame(json).into();
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.try_send(frame.clone()).is_ok());
+    }
+}
+
+/// Write a client's queue out until it goes away or is dropped.
+fn serve(mut stream: Stream, clients: &Mutex<Vec<Queue>>) {
+    if stream.tcp().set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+        return;
+    }
+    let (queue, frames) = mpsc::sync_channel(QUEUE);
+    clients.lock().unwrap().push(queue);
+    for frame in frames {
+        if stream
+            .write_all(&frame)
+            .and_then(|_| stream.flush())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Read the HTTP request, upgrading `/keys` to a WebSocket. Other
+/// machines and browsers need a token.
+fn accept(mut stream: Stream) -> Option<Stream> {
+    let local = stream.tcp().peer_addr().ok()?.ip().is_loopback();
+    let request = Request::read(&mut stream)?;
+    let browser = request.header("origin").is_some();
+    // Clients stay connected for broadcasts, which are writes only
+    stream.tcp().set_read_timeout(None).ok()?;
+    let key = request.header("sec-websocket-key").map(str::to_string);
+    let protocol = tokens::from_protocols(request.header("sec-websocket-protocol"));
+    let token = tokens::from_authorization(request.header("authorization"))
+        .or_else(|| protocol.as_ref().map(|(token, _)| token.clone()));
+    let authorized = (local && !browser) || token.is_some_and(|token| tokens::is_valid(&token));
+    if !authorized {
+        let _ = stream.write_all(
+            b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+        let _ = stream.flush();
+        return None;
+    }
+    match (request.path(), key) {
+        ("/keys", Some(key)) => {
+            stream
+                .write_all(
+                    websocket::handshake_response(&key, protocol.map(|(_, p)| p).as_deref())
+                        .as_bytes(),
+                )
+                .ok()?;
+            stream.flush().ok()?;
+            Some(stream)
+        }
+        _ => {
+            let _ = stream.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+            let _ = stream.flush();
+            None
+        }
+    }
+}
+
+/// Quote a string for JSON.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
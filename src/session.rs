@@ -0,0 +1,72 @@
+//! Session recordings: a timestamped log of performed actions that can be
+//! replayed later to reproduce a bug or repeat a setup sequence.
+//!
+//! Each line is `<milliseconds since start>\t<action>`.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::events::Action;
+use crate::tasks::Cancel;
+
+pub struct Recorder {
+    file: File,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Create (or truncate) a recording file.
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Unable to create {path}: {e}"))?;
+        Ok(Recorder {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    /// Append an action if it's one worth replaying.
+    pub fn record(&mut self, action: &Action) {
+        if let Some(record) = action.to_record() {
+            let millis = self.started.elapsed().as_millis();
+            let _ = writeln!(self.file, "{millis}\t{record}");
+        }
+    }
+}
+
+/// Load a recording as (offset from start, action) pairs.
+pub fn load(path: &str) -> Result<Vec<(Duration, Action)>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {path}: {e}"))?;
+    let mut actions = vec![];
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed = line.split_once('\t').and_then(|(millis, record)| {
+            let millis = millis.trim().parse::<u64>().ok()?;
+            Some((Duration::from_millis(millis), Action::from_record(record)?))
+        });
+        match parsed {
+            Some(entry) => actions.push(entry),
+            None => return Err(format!("{path}:{}: invalid action \"{line}\"", i + 1)),
+        }
+    }
+    Ok(actions)
+}
+
+/// Feed recorded actions to the handler with their original timing, until
+/// cancelled.
+pub fn replay<F>(actions: Vec<(Duration, Action)>, cancel: &Cancel, handler: F)
+where
+    F: Fn(Action),
+{
+    let started = Instant::now();
+    for (offset, action) in actions {
+        if let Some(wait) = offset.checked_sub(started.elapsed()) {
+            if !cancel.sleep(wait) {
+                return;
+            }
+        }
+        handler(action);
+    }
+}
@@ -0,0 +1,93 @@
+//! `mac-controls shortcut`, the main operations with arguments and exit
+//! codes that stay the same between versions, for Shortcuts' "Run Shell
+//! Script", Stream Deck buttons and other automation:
+//!
+//! ```text
+//! mac-controls shortcut volume input|output <level> [device]
+//! mac-controls shortcut mute|unmute|toggle-mute input|output [device]
+//! mac-controls shortcut default input|output <device>
+//! mac-controls shortcut profile <name>
+//! ```
+//!
+//! Levels are percent, `40` or `40%`, or a change like `+10` or `-10`,
+//! read and set like the `set-volume` command's. Without a device the channel's default device is used. A URL scheme
+//! would need an app bundle, which the app doesn't have.
+
+use crate::audio::{AudioState, Channel, Device};
+use crate::cli;
+use crate::config::Config;
+use crate::profiles;
+
+/// The change couldn't be made.
+pub const EXIT_FAILED: i32 = 1;
+/// The arguments don't make a shortcut.
+pub const EXIT_USAGE: i32 = 2;
+/// No such device or profile.
+pub const EXIT_NOT_FOUND: i32 = 3;
+
+const USAGE: &str = "Usage:
+  mac-controls shortcut volume input|output <level> [device]
+  mac-controls shortcut mute|unmute|toggle-mute input|output [device]
+  mac-controls shortcut default input|output <device>
+  mac-controls shortcut profile <name>";
+
+/// Run a shortcut, with what it did or its exit code and why.
+pub fn run(args: &[String]) -> Result<String, (i32, String)> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let usage = || (EXIT_USAGE, USAGE.to_string());
+    let mut audio = AudioState::new(&Config::load());
+    let done = match args.as_slice() {
+        ["volume", channel, level, device @ ..] => {
+            let channel = parse_channel(channel).ok_or_else(usage)?;
+            let level = cli::parse_level(level).map_err(|e| (EXIT_USAGE, e))?;
+            let device = find(&audio, channel, device.first())?;
+            cli::set_volume(&audio, device, channel, level)
+        }
+        [command @ ("mute" | "unmute" | "toggle-mute"), channel, device @ ..] => {
+            let channel = parse_channel(channel).ok_or_else(usage)?;
+            let uid = find(&audio, channel, device.first())?.uid.clone();
+            let mute = match *command {
+                "mute" => Some(true),
+                "unmute" => Some(false),
+                _ => None,
+            };
+            cli::set_mute(&mut audio, &uid, channel, mute)
+        }
+        ["default", channel, device] => {
+            let channel = parse_channel(channel).ok_or_else(usage)?;
+            let device = find(&audio, channel, Some(device))?;
+            let (uid, label) = (device.uid.clone(), device.label().to_string());
+            audio
+                .set_default(channel, &uid)
+                .map(|()| format!("{label} is the default {}", channel.as_str()))
+                .map_err(String::from)
+        }
+        ["profile", name] => {
+            if !profiles::names().iter().any(|n| n == name) {
+                return Err((EXIT_NOT_FOUND, format!("No profile named \"{name}\"")));
+            }
+            profiles::apply(name, &mut audio)
+        }
+        _ => return Err(usage()),
+    };
+    // Exiting would stop a fade half way
+    audio.finish_ramps();
+    done.map_err(|e| (EXIT_FAILED, e))
+}
+
+fn parse_channel(name: &str) -> Option<Channel> {
+    match name {
+        "input" => Some(Channel::Input),
+        "output" => Some(Channel::Output),
+        _ => None,
+    }
+}
+
+/// The named device, or the channel's default.
+fn find<'a>(
+    audio: &'a AudioState,
+    channel: Channel,
+    key: Option<&&str>,
+) -> Result<&'a Device, (i32, String)> {
+    cli::find_device(audio, channel, key.copied()).map_err(|e| (EXIT_NOT_FOUND, e))
+}
@@ -0,0 +1,445 @@
+//! Where state changes go. `[events]` attaches sinks to event types:
+//!
+//! ```toml
+//! [events]
+//! connected = "notification, log"
+//! default-output = "shell"
+//!
+//! [sinks]
+//! shell = "~/bin/on-audio-change"
+//! webhook = "https://example.com/hooks/audio"
+//! ```
+//!
+//! Changes are found by comparing the audio state with the last check, so
+//! changes made by other apps are sent too.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+use crate::audio::{AudioState, Channel};
+use crate::config::Config;
+use crate::log;
+use crate::server::{json_string, Server};
+
+/// Sink names usable in `[events]`.
+pub const SINK_NAMES: &[&str] = &["log", "notification", "shell", "socket", "webhook"];
+/// Webhook attempts after the first, waiting 1s, 2s, 4s... in between.
+const WEBHOOK_RETRIES: &str = "4";
+/// Longest a webhook attempt may take, in seconds.
+const WEBHOOK_TIMEOUT: &str = "10";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Connected,
+    Disconnected,
+    DefaultInput,
+    DefaultOutput,
+    Mute,
+    Unmute,
+    /// Volume of the default input or output.
+    Volume,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 7] = [
+        EventKind::Connected,
+        EventKind::Disconnected,
+        EventKind::DefaultInput,
+        EventKind::DefaultOutput,
+        EventKind::Mute,
+        EventKind::Unmute,
+        EventKind::Volume,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Connected => "connected",
+            EventKind::Disconnected => "disconnected",
+            EventKind::DefaultInput => "default-input",
+            EventKind::DefaultOutput => "default-output",
+            EventKind::Mute => "mute",
+            EventKind::Unmute => "unmute",
+            EventKind::Volume => "volume",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub uid: String,
+    pub device: String,
+    /// The channel of mute and volume events, a headset's mic and
+    /// speakers being muted on their own.
+    pub channel: Option<Channel>,
+    /// Volume in percent, for volume events.
+    pub level: Option<u8>,
+}
+
+impl Event {
+    /// One line for people, like "AirPods connected".
+    pub fn message(&self) -> String {
+        let device = &self.device;
+        let channel = match self.channel {
+            Some(channel) => format!(" {}", channel.as_str()),
+            None => String::new(),
+        };
+        match self.kind {
+            EventKind::Connected => format!("{device} connected"),
+            EventKind::Disconnected => format!("{device} disconnected"),
+            EventKind::DefaultInput => format!("Default input: {device}"),
+            EventKind::DefaultOutput => format!("Default output: {device}"),
+            EventKind::Mute => format!("{device}{channel} muted"),
+            EventKind::Unmute => format!("{device}{channel} unmuted"),
+            EventKind::Volume => format!("{device} volume {}%", self.level.unwrap_or(0)),
+        }
+    }
+
+    /// With the message as `text`, which chat webhooks like Slack's show.
+    pub fn to_json(&self) -> String {
+        let level = match self.level {
+            Some(level) => level.to_string(),
+            None => "null".to_string(),
+        };
+        let channel = match self.channel {
+            Some(channel) => json_string(channel.as_str()),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"type\":\"event\",\"event\":\"{}\",\"uid\":{},\"device\":{},\"channel\":{channel},\"level\":{level},\"text\":{}}}",
+            self.kind.as_str(),
+            json_string(&self.uid),
+            json_string(&self.device),
+            json_string(&self.message()),
+        )
+    }
+}
+
+pub trait Sink: Debug + Send + Sync {
+    fn send(&self, event: &Event) -> Result<(), String>;
+}
+
+/// The app log.
+#[derive(Debug)]
+struct LogSink;
+
+impl Sink for LogSink {
+    fn send(&self, event: &Event) -> Result<(), String> {
+        log::info(event.message());
+        Ok(())
+    }
+}
+
+/// A macOS notification banner.
+#[derive(Debug)]
+struct NotificationSink;
+
+impl Sink for NotificationSink {
+    fn send(&self, event: &Event) -> Result<(), String> {
+        notification(&event.message())
+    }
+}
+
+/// Post a notification banner, through AppleScript so it works from a
+/// terminal app without a bundle.
+pub fn notification(message: &str) -> Result<(), String> {
+    let script = format!(
+        "display notification {} with title \"Mac Controls\"",
+        json_string(message)
+    );
+    spawn(Command::new("osascript").args(["-e", &script]))
+}
+
+/// A command run by `sh`, with the event in `MAC_CONTROLS_EVENT`,
+/// `MAC_CONTROLS_UID`, `MAC_CONTROLS_DEVICE`, `MAC_CONTROLS_CHANNEL` and
+/// `MAC_CONTROLS_LEVEL`.
+#[derive(Debug)]
+struct ShellSink {
+    command: String,
+}
+
+impl Sink for ShellSink {
+    fn send(&self, event: &Event) -> Result<(), String> {
+        let level = event.level.map(|l| l.to_string()).unwrap_or_default();
+        let channel = event.channel.map(Channel::as_str).unwrap_or_default();
+        spawn(
+            Command::new("sh")
+                .args(["-c", &self.command])
+                .env("MAC_CONTROLS_EVENT", event.kind.as_str())
+                .env("MAC_CONTROLS_UID", &event.uid)
+                .env("MAC_CONTROLS_DEVICE", &event.device)
+                .env("MAC_CONTROLS_CHANNEL", channel)
+                .env("MAC_CONTROLS_LEVEL", level),
+        )
+    }
+}
+
+/// Clients of the local socket API, next to key events.
+#[derive(Debug)]
+struct SocketSink {
+    server: Server,
+}
+
+impl Sink for SocketSink {
+    fn send(&self, event: &Event) -> Result<(), String> {
+        self.server.broadcast(&event.to_json());
+        Ok(())
+    }
+}
+
+/// The event as JSON, POSTed by the system `curl` (for HTTPS), which
+/// retries with backoff on failure.
+#[derive(Debug)]
+struct WebhookSink {
+    url: String,
+}
+
+impl Sink for WebhookSink {
+    fn send(&self, event: &Event) -> Result<(), String> {
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail"])
+            .args(["--retry", WEBHOOK_RETRIES, "--retry-all-errors"])
+            .args(["--max-time", WEBHOOK_TIMEOUT])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-", &self.url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Unable to run curl: {e}"))?;
+        let body = event.to_json();
+        let url = self.url.clone();
+        // Retries take a while, so the result is logged from another thread
+        thread::spawn(move || {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(body.as_bytes());
+            }
+            match child.wait_with_output() {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => log::warn(format!(
+                    "Webhook {url} failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )),
+                Err(e) => log::warn(format!("Webhook {url} failed: {e}")),
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Start a command without waiting for it, reaping it on another thread.
+fn spawn(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Unable to run {program}: {e}"))?;
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Channels in the order of `Snapshot`'s per channel arrays.
+const CHANNELS: [Channel; 2] = [Channel::Input, Channel::Output];
+
+/// Audio state as of the last check.
+#[derive(Debug, Default, PartialEq)]
+struct Snapshot {
+    /// (UID, label, muted per channel) of every device.
+    devices: Vec<(String, String, [bool; 2])>,
+    /// Default device UID per channel.
+    defaults: [Option<String>; 2],
+    /// Default device volume in percent per channel.
+    levels: [Option<u8>; 2],
+}
+
+impl Snapshot {
+    fn take(audio: &AudioState) -> Self {
+        let devices = audio
+            .device_list()
+            .into_iter()
+            .map(|(_, _, _, d)| {
+                let muted = CHANNELS.map(|channel| audio.is_muted(d, channel));
+                (d.uid.clone(), d.label().to_string(), muted)
+            })
+            .collect();
+        let mut snapshot = Snapshot {
+            devices,
+            ..Default::default()
+        };
+        for (i, channel) in CHANNELS.into_iter().enumerate() {
+            let Some(device) = audio.active_device(channel) else {
+                continue;
+            };
+            snapshot.defaults[i] = Some(device.uid.clone());
+            let level = match channel {
+                Channel::Input => audio.input(&device.id),
+                Channel::Output => audio.output(&device.id),
+            };
+            snapshot.levels[i] = level.map(|(level, _)| (level * 100.0).round() as u8);
+        }
+        snapshot
+    }
+
+    fn label(&self, uid: &str) -> String {
+        self.devices
+            .iter()
+            .find(|(u, ..)| u == uid)
+            .map(|(_, label, _)| label.clone())
+            .unwrap_or_else(|| uid.to_string())
+    }
+
+    /// What changed since `prev`.
+    fn changes(&self, prev: &Snapshot) -> Vec<Event> {
+        let event = |kind, uid: &str, channel, level| Event {
+            kind,
+            uid: uid.to_string(),
+            device: self.label(uid),
+            channel,
+            level,
+        };
+        let mut events = Vec::new();
+        for (uid, _, muted) in &self.devices {
+            let Some((_, _, was_muted)) = prev.devices.iter().find(|(u, ..)| u == uid) else {
+                events.push(event(EventKind::Connected, uid, None, None));
+                continue;
+            };
+            for (i, channel) in CHANNELS.into_iter().enumerate() {
+                if was_muted[i] == muted[i] {
+                    continue;
+                }
+                let kind = match muted[i] {
+                    true => EventKind::Mute,
+                    false => EventKind::Unmute,
+                };
+                events.push(event(kind, uid, Some(channel), None));
+            }
+        }
+        for (uid, label, _) in &prev.devices {
+            if !self.devices.iter().any(|(u, ..)| u == uid) {
+                events.push(Event {
+                    kind: EventKind::Disconnected,
+                    uid: uid.clone(),
+                    device: label.clone(),
+                    channel: None,
+                    level: None,
+                });
+            }
+        }
+        for (i, kind) in [EventKind::DefaultInput, EventKind::DefaultOutput]
+            .into_iter()
+            .enumerate()
+        {
+            let Some(uid) = &self.defaults[i] else {
+                continue;
+            };
+            if prev.defaults[i].as_ref() != Some(uid) {
+                events.push(event(kind, uid, None, None));
+            } else if self.levels[i] != prev.levels[i] && self.levels[i].is_some() {
+                let channel = Some(CHANNELS[i]);
+                events.push(event(EventKind::Volume, uid, channel, self.levels[i]));
+            }
+        }
+        events
+    }
+}
+
+/// Changes found by comparing the audio state with the last check.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// `None` until the first check, which finds nothing.
+    last: Option<Snapshot>,
+}
+
+impl Changes {
+    pub fn check(&mut self, audio: &AudioState) -> Vec<Event> {
+        let snapshot = Snapshot::take(audio);
+        let events = match &self.last {
+            Some(last) => snapshot.changes(last),
+            None => Vec::new(),
+        };
+        self.last = Some(snapshot);
+        events
+    }
+}
+
+/// Sinks by the event types attached to them.
+#[derive(Debug, Default)]
+pub struct Sinks {
+    routes: HashMap<EventKind, Vec<Arc<dyn Sink>>>,
+    changes: Changes,
+}
+
+impl Sinks {
+    pub fn new(config: &Config, server: Option<&Server>) -> Self {
+        let mut sinks: HashMap<&str, Arc<dyn Sink>> = HashMap::new();
+        sinks.insert("log", Arc::new(LogSink));
+        sinks.insert("notification", Arc::new(NotificationSink));
+        if let Some(command) = &config.shell_hook {
+            sinks.insert(
+                "shell",
+                Arc::new(ShellSink {
+                    command: command.clone(),
+                }),
+            );
+        }
+        if let Some(url) = &config.webhook_url {
+            sinks.insert("webhook", Arc::new(WebhookSink { url: url.clone() }));
+        }
+        if let Some(server) = server {
+            sinks.insert(
+                "socket",
+                Arc::new(SocketSink {
+                    server: server.clone(),
+                }),
+            );
+        }
+        let mut routes: HashMap<EventKind, Vec<Arc<dyn Sink>>> = HashMap::new();
+        for (event, names) in &config.events {
+            let Some(kind) = EventKind::parse(event) else {
+                continue;
+            };
+            for name in names {
+                if let Some(sink) = sinks.get(name.as_str()) {
+                    routes.entry(kind).or_default().push(sink.clone());
+                }
+            }
+        }
+        Sinks {
+            routes,
+            changes: Changes::default(),
+        }
+    }
+
+    /// Add a sink for event types by name, like a plugin's.
+    pub fn attach(&mut self, events: &[String], sink: Arc<dyn Sink>) {
+        for kind in events.iter().filter_map(|e| EventKind::parse(e)) {
+            self.routes.entry(kind).or_default().push(sink.clone());
+        }
+    }
+
+    /// Send the changes since the last check to their sinks.
+    pub fn check(&mut self, audio: &AudioState) {
+        if self.routes.is_empty() {
+            return;
+        }
+        for event in self.changes.check(audio) {
+            for sink in self.routes.get(&event.kind).into_iter().flatten() {
+                if let Err(e) = sink.send(&event) {
+                    log::warn(e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Permission checklist shown before the TUI on first run, or whenever key
+//! events aren't permitted. Missing permissions can be skipped, the app then
+//! runs without key events.
+
+use std::io::{Stdin, Stdout, Write};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::RawTerminal;
+
+use crate::permissions::{self, Permission, Status};
+use crate::store::Store;
+
+const UI_FILE: &str = "ui.toml";
+
+/// First run, or key events can't work yet.
+pub fn is_needed() -> bool {
+    let seen = Store::open(UI_FILE).get("splash", "seen") == Some("true");
+    !seen || !permissions::has_key_access()
+}
+
+/// Show the checklist until the user continues (true) or quits (false).
+pub fn run(out: &mut RawTerminal<Stdout>, stdin: &Stdin) -> bool {
+    // The permissions, then the continue row
+    let rows = Permission::ALL.len() + 1;
+    let mut selected = 0;
+    write!(out, "{}{}", termion::clear::All, termion::cursor::Hide).unwrap();
+    draw(out, selected);
+    for key in stdin.lock().keys().flatten() {
+        match key {
+            Key::Up | Key::Char('k') => selected = (selected + rows - 1) % rows,
+            Key::Down | Key::Char('j') => selected = (selected + 1) % rows,
+            Key::Char('\n') if selected < Permission::ALL.len() => {
+                Permission::ALL[selected].request()
+            }
+            Key::Char('\n') | Key::Char('s') => {
+                let mut store = Store::open(UI_FILE);
+                store.set("splash", "seen", true);
+                let _ = store.save();
+                return true;
+            }
+            Key::Char('q') | Key::Ctrl('c') => return false,
+            _ => {}
+        }
+        // Any key re-checks, permissions are granted in System Settings
+        draw(out, selected);
+    }
+    false
+}
+
+fn draw(out: &mut RawTerminal<Stdout>, selected: usize) {
+    let clear_line = termion::clear::CurrentLine;
+    let mut text = format!(
+        "{}{}Mac Controls needs a few permissions\r\n\r\n",
+        termion::cursor::Goto(1, 2),
+        clear_line
+    );
+    for (i, permission) in Permission::ALL.iter().enumerate() {
+        let status = match permission.status() {
+            Status::Granted => "✅ granted",
+            Status::Denied => "❌ denied",
+            Status::Unknown => "○  not asked",
+        };
+        text.push_str(&format!(
+            "{clear_line}{} {:<17} {status:<13} {}\r\n",
+            cursor(i == selected),
+            permission.name(),
+            permission.purpose()
+        ));
+    }
+    let proceed = match permissions::has_key_access() {
+        true => "Continue",
+        false => "Continue without key events",
+    };
+    text.push_str(&format!(
+        "\r\n{clear_line}{} {proceed}\r\n\r\n{clear_line}Enter opens the settings for a permission, any key re-checks. 's' skips, 'q' quits.\r\n{}",
+        cursor(selected == Permission::ALL.len()),
+        termion::clear::AfterCursor
+    ));
+    write!(out, "{text}").unwrap();
+    out.flush().unwrap();
+}
+
+fn cursor(selected: bool) -> &'static str {
+    match selected {
+        true => "▶",
+        false => " ",
+    }
+}
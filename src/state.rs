@@ -1,21 +1,1221 @@
-use crate::audio::AudioState;
-use crate::events::UiMode;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::alerts;
+use crate::apps::{self, AppVolumes};
+use crate::audio::{self, AudioState, Category, Channel, Device, Member};
+use crate::autogain::AutoGain;
+use crate::bluetooth::{self, Battery};
+use crate::config::{Config, MqttConfig, Remap, Theme};
+use crate::coreaudio::AudioDeviceID;
+use crate::display::DisplayWatch;
+use crate::events::{
+    self, Action, Hotkey, KeyRepeat, ModifierKeys, ModifierTap, PermissionStatus, Pointer,
+    TapControl, TapHealth, UiMode,
+};
+use crate::frontmost;
+use crate::ipc;
+use crate::keyhistory::KeyHistory;
+use crate::keymap::Keymap;
+use crate::layout::{self, KeyboardLayout};
+use crate::listener::ListenerStats;
+use crate::log;
+use crate::meter::{Calibration, MeterState};
+use crate::metrics::Metrics;
+use crate::micmode::{self, MicMode};
+use crate::palette::Palette;
+use crate::panes::{Pane, Panes};
+use crate::recorder::{self, Recording};
+use crate::registry::DeviceSettings;
+use crate::rules::RuleWatch;
+use crate::server::{json_string, Server};
+use crate::sinks::{self, Sinks};
+use crate::stats::Stats;
+use crate::system;
+use crate::tls::Acceptor;
+use crate::tone::{self, Tone};
 
 #[derive(Debug)]
 pub struct AppState {
     pub audio: AudioState,
     pub keys: Vec<i64>,
     pub key_modifiers: Vec<String>,
+    /// Recent chords, for the keys pane.
+    pub key_history: KeyHistory,
     pub mode: UiMode,
+    /// Modes to go back to, oldest first.
+    mode_stack: Vec<UiMode>,
+    pub keymap: Keymap,
+    /// Device list filter typed in search mode.
+    pub search: String,
+    /// Device list sections folded to their header.
+    pub collapsed: Vec<Category>,
+    pub palette: Palette,
+    /// Query and selection of the profile picker.
+    pub profiles: Palette,
+    /// Steps of the macro being recorded.
+    pub macro_recording: Option<Vec<String>>,
+    /// Name typed for a just recorded macro.
+    pub macro_name: String,
+    /// Name typed in rename mode for the highlighted device.
+    pub rename_entry: String,
+    /// Volume typed in volume mode, in percent, and its channel.
+    pub volume_entry: String,
+    pub volume_channel: Channel,
+    /// Query typed on the property screen, and what the last one returned.
+    pub property_query: String,
+    pub property_result: Option<Result<String, String>>,
+    /// Apps with audio in apps mode, or why they can't be listed.
+    pub apps: Result<Vec<apps::App>, String>,
+    pub app_cursor: usize,
+    /// Keyboard backlight level in system mode, or why it's unavailable.
+    pub backlight: Result<f32, String>,
+    /// Alert volume in alert mode.
+    pub alert_volume: f32,
+    /// Preferred and active mic modes in system mode.
+    pub mic_mode: Result<(MicMode, MicMode), String>,
+    /// Apps turned down or muted, each through a process tap.
+    pub app_volumes: AppVolumes,
+    /// Highlighted member of an aggregate device in detail mode.
+    pub member_cursor: usize,
+    pub meter: MeterState,
+    pub calibration: Option<Calibration>,
+    /// Suggested input gain as (device UID, level).
+    pub gain_suggestion: Option<(String, f32)>,
+    /// Input auto-gain, with `[autogain]`.
+    pub auto_gain: Option<AutoGain>,
+    pub listener_stats: ListenerStats,
+    /// Cost of recent polls and frames, for the doctor.
+    pub metrics: Metrics,
+    /// First device list row on screen, moved by drawing to keep the
+    /// highlight in view.
+    pub list_scroll: Cell<usize>,
+    /// Last forwarded CoreAudio notification as (object ID, selector).
+    pub last_change: Option<(u32, u32)>,
+    pub show_doctor: bool,
+    pub show_history: bool,
+    pub osd: Option<Osd>,
+    /// The app the TUI runs in, when volume changes are notified while
+    /// it's in the background.
+    terminal_app: Option<String>,
+    /// Volume change waiting to settle before it's notified.
+    volume_notice: Option<(String, Instant)>,
+    /// Transient message shown under the title, like a toast.
+    notice: Option<(String, Instant)>,
+    /// Lowest level shown in the log pane.
+    pub log_level: log::Level,
+    pub panes: Panes,
+    pub theme: Theme,
+    /// When the last action was performed from the TUI.
+    pub last_user_action: Option<Instant>,
+    /// Actions come from a replayed session, so they aren't counted.
+    pub replaying: bool,
+    /// Active keyboard layout, for naming keys.
+    pub layout: Option<KeyboardLayout>,
+    /// Highlighted device UID, moved independently of the defaults.
+    pub cursor: Option<String>,
+    /// Send the hardware volume keys to the highlighted device instead of
+    /// the default output. Shared with the media key tap.
+    pub route_keys: Arc<AtomicBool>,
+    /// Held arrow keys, from the event tap.
+    pub key_repeat: KeyRepeat,
+    /// Held hardware volume keys.
+    pub media_repeat: KeyRepeat,
+    /// Whether macOS lets the app see key events. Without, it runs with no
+    /// event taps.
+    pub key_access: bool,
+    /// Key event permissions at start, which decide the subsystems.
+    pub permissions: PermissionStatus,
+    /// Event tap liveness, shared with the tap thread.
+    pub tap_health: Arc<TapHealth>,
+    /// Keys the event tap swallows, shared with the tap thread.
+    pub tap_control: Arc<TapControl>,
+    /// Configured volume step, see `Config::volume_step`.
+    pub volume_step: Option<f32>,
+    /// Arrow step in dB mode, see `Config::db_step`.
+    pub db_step: f32,
+    /// The arrows move the volume in dB rather than scalar steps.
+    pub db_steps: bool,
+    /// Volume presets in percent, see `Config::presets`.
+    pub presets: Vec<u8>,
+    /// Push-to-talk and push-to-mute key names, see `Config::push_to_talk`.
+    push_to_talk: Option<String>,
+    push_to_mute: Option<String>,
+    /// The push key being held, with its key code and the input it's for.
+    pub push: Option<(Push, i64, AudioDeviceID)>,
+    /// Boost step and time, see `Config::boost_step`.
+    boost_step: f32,
+    boost_time: Duration,
+    /// The output turned up by "boost", until it's turned back down.
+    pub boost: Option<Boost>,
+    /// The output being recorded to a file, see `recorder`.
+    pub recording: Option<Recording>,
+    /// The test tone beeping on a device, see `tone`.
+    pub tone: Option<Tone>,
+    /// Caps Lock toggles the mic mute.
+    pub caps_lock_mute: bool,
+    /// Mouse button bindings from the config.
+    mouse: Vec<(i64, String)>,
+    /// Modifier-only bindings as (`ModifierKeys::held` flags, action).
+    modifier_bindings: Vec<(u64, String)>,
+    pub modifier_tap: ModifierTap,
+    /// Global chord bindings from the config.
+    hotkeys: Vec<(Hotkey, String)>,
+    /// Keyboard names and per-keyboard key bindings from the config.
+    keyboards: Vec<(i64, String)>,
+    key_bindings: Vec<(i64, i64, String)>,
+    /// Keys remapped to an action on every keyboard, from `[remap]`.
+    remap_actions: Vec<(i64, String)>,
+    /// Config problems, like conflicting bindings, shown in the doctor pane.
+    pub config_errors: Vec<String>,
+    /// Keyboard type of the last key pressed.
+    pub last_keyboard: Option<i64>,
+    /// Local usage counts, for `mac-controls stats`.
+    pub stats: Stats,
+    /// App in front at the last key press, which got the key.
+    pub frontmost_app: Option<String>,
+    /// Last click or scroll as a chord, and when, with `show_mouse`.
+    pub pointer: Option<(String, Instant)>,
+    /// Local socket API, if enabled in the config.
+    pub server: Option<Server>,
+    /// Port, token, `lan` and TLS for quick actions over HTTP, started with
+    /// the action channel.
+    pub quick: Option<(u16, Option<String>, bool, Acceptor)>,
+    /// Port the daemon takes remote terminals on.
+    pub remote_port: Option<u16>,
+    /// The default input's mute state and when it last changed.
+    pub mute_since: Option<(bool, Instant)>,
+    /// Input and output sample rates while they differ, logged once.
+    rate_mismatch: Option<(f64, f64)>,
+    /// Time between device checks.
+    pub poll_interval: Duration,
+    /// Virtual and aggregate devices are left out of the list, unless
+    /// they're a default.
+    pub hide_virtual: bool,
+    /// Actions for display sleep and wake.
+    pub display: DisplayWatch,
+    /// Actions for devices connecting and becoming the default.
+    pub rules: RuleWatch,
+    /// Event types by plugin name, started with the action channel.
+    pub plugins: Vec<(String, Vec<String>)>,
+    /// MQTT broker for Home Assistant, connected with the action channel.
+    pub mqtt: Option<MqttConfig>,
+    /// Where state changes are sent.
+    pub sinks: Sinks,
+    /// Last seen Caps Lock state.
+    caps_lock: Option<bool>,
+    /// Setting Caps Lock failed, and the user was told.
+    caps_lock_failed: bool,
+    /// Active (device, level, muted) per channel, as of the last check.
+    last_levels: [Option<(u32, f32, bool)>; 2],
+    /// Recent (input, output) volume samples by device UID, oldest first.
+    volume_history: HashMap<String, [VecDeque<f32>; 2]>,
+    /// Bluetooth battery levels by device UID, and when they were read.
+    pub batteries: HashMap<String, Battery>,
+    batteries_checked: Option<Instant>,
+}
+
+/// Volume samples kept per device and channel, one per poll.
+const VOLUME_SAMPLES: usize = 40;
+/// How long a notice stays up.
+const NOTICE_TIME: Duration = Duration::from_secs(5);
+/// How long the OSD stays up after a volume change.
+const OSD_TIME: Duration = Duration::from_millis(1500);
+/// Volume notifications wait for the level to stop changing, so holding a
+/// volume key posts one.
+const VOLUME_NOTICE_SETTLE: Duration = Duration::from_millis(700);
+/// Changes this soon after a TUI action are assumed to be caused by it.
+const USER_ACTION_GRACE: Duration = Duration::from_secs(1);
+/// Batteries drain slowly, and reading them wakes Bluetooth.
+const BATTERY_INTERVAL: Duration = Duration::from_secs(60);
+/// Scroll events closer than this are one scroll in the key history.
+const SCROLL_GAP: Duration = Duration::from_millis(500);
+/// How long a click or scroll stays in the keys display.
+pub const POINTER_TIME: Duration = Duration::from_secs(1);
+
+/// What holding a push key does to the default input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Push {
+    /// Unmuted while held.
+    Talk,
+    /// Muted while held.
+    Mute,
+}
+
+/// An output turned up for a while, and the level it goes back to.
+#[derive(Debug, Clone, Copy)]
+pub struct Boost {
+    pub id: AudioDeviceID,
+    pub previous: f32,
+    pub boosted: f32,
+    pub until: Instant,
+}
+
+/// Large-print volume overlay for changes made outside the TUI, like
+/// hotkeys or other apps, while nobody is looking at the list.
+#[derive(Debug, Clone)]
+pub struct Osd {
+    pub channel: Channel,
+    pub name: String,
+    pub level: f32,
+    pub muted: bool,
+    shown: Instant,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
-        AppState {
-            audio: AudioState::new(),
+        let config = Config::load();
+        let audio = AudioState::deferred(&config);
+        let permissions = events::permission_status();
+        AppState::with_audio(&config, audio, permissions, KeyboardLayout::current())
+    }
+
+    /// Like `new`, with the devices, permissions and keyboard layout given
+    /// instead of read from the system.
+    pub fn with_audio(
+        config: &Config,
+        audio: AudioState,
+        permissions: PermissionStatus,
+        layout: Option<KeyboardLayout>,
+    ) -> Self {
+        // Neither server starts when `[tls]` is broken, rather than in the clear
+        let tls = Acceptor::new(config.tls.as_ref());
+        let mut state = AppState {
+            audio,
             keys: Vec::new(),
             key_modifiers: Vec::new(),
+            key_history: KeyHistory::new(config.key_history, config.key_history_file.as_deref())
+                .unwrap_or_else(|e| {
+                    log::warn(e);
+                    KeyHistory::default()
+                }),
             mode: UiMode::View,
+            mode_stack: Vec::new(),
+            keymap: Keymap::new(&config.keymap),
+            search: String::new(),
+            collapsed: Vec::new(),
+            palette: Palette::default(),
+            profiles: Palette::default(),
+            macro_recording: None,
+            macro_name: String::new(),
+            rename_entry: String::new(),
+            volume_entry: String::new(),
+            property_query: String::new(),
+            property_result: None,
+            apps: Ok(Vec::new()),
+            app_cursor: 0,
+            app_volumes: AppVolumes::default(),
+            backlight: Err(String::new()),
+            mic_mode: Err(String::new()),
+            alert_volume: 1.0,
+            volume_channel: Channel::Output,
+            member_cursor: 0,
+            meter: MeterState::default(),
+            calibration: None,
+            gain_suggestion: None,
+            auto_gain: config.autogain.clone().map(AutoGain::new),
+            listener_stats: ListenerStats::default(),
+            metrics: Metrics::default(),
+            list_scroll: Cell::new(0),
+            last_change: None,
+            show_doctor: false,
+            show_history: false,
+            osd: None,
+            // Started from the terminal, so it's in front now
+            terminal_app: match config.volume_notifications {
+                true => frontmost::app_name(),
+                false => None,
+            },
+            volume_notice: None,
+            notice: None,
+            log_level: config.log_level,
+            panes: Panes::load(),
+            theme: config.theme.clone(),
+            last_user_action: None,
+            replaying: false,
+            layout,
+            cursor: None,
+            route_keys: Arc::default(),
+            key_repeat: KeyRepeat::default(),
+            media_repeat: KeyRepeat::default(),
+            key_access: permissions.can_listen(),
+            permissions,
+            tap_health: Arc::default(),
+            tap_control: Arc::default(),
+            volume_step: config.volume_step,
+            db_step: config.db_step,
+            db_steps: false,
+            presets: config.presets.clone(),
+            push_to_talk: config.push_to_talk.clone(),
+            push_to_mute: config.push_to_mute.clone(),
+            push: None,
+            boost_step: config.boost_step,
+            boost_time: config.boost_time,
+            boost: None,
+            recording: None,
+            tone: None,
+            caps_lock_mute: config.caps_lock_mute,
+            mouse: config.mouse.clone(),
+            modifier_bindings: config
+                .modifier_bindings
+                .iter()
+                .filter_map(|(combo, action)| {
+                    Some((events::parse_modifier_combo(combo).ok()?, action.clone()))
+                })
+                .collect(),
+            modifier_tap: ModifierTap::default(),
+            hotkeys: config
+                .hotkeys
+                .iter()
+                .filter_map(|(chord, action)| Some((Hotkey::parse(chord).ok()?, action.clone())))
+                .collect(),
+            keyboards: config.keyboards.clone(),
+            key_bindings: config.key_bindings.clone(),
+            remap_actions: config
+                .remaps
+                .iter()
+                .filter_map(|(key_code, remap)| match remap {
+                    Remap::Action(action) => Some((*key_code, action.clone())),
+                    Remap::Key(_) => None,
+                })
+                .collect(),
+            config_errors: config.validate(),
+            last_keyboard: None,
+            frontmost_app: None,
+            pointer: None,
+            stats: Stats::load(),
+            server: None,
+            quick: config
+                .quick_port
+                .zip(tls.clone().ok())
+                .map(|(port, tls)| (port, config.quick_token.clone(), config.quick_lan, tls)),
+            remote_port: config.remote_port,
+            mute_since: None,
+            rate_mismatch: None,
+            poll_interval: config.poll_interval(),
+            hide_virtual: config.hide_virtual,
+            display: DisplayWatch::new(&config.display),
+            rules: RuleWatch::default(),
+            plugins: config.plugins.clone(),
+            mqtt: config.mqtt.clone(),
+            sinks: Sinks::default(),
+            caps_lock: None,
+            caps_lock_failed: false,
+            last_levels: [None, None],
+            volume_history: HashMap::new(),
+            batteries: HashMap::new(),
+            batteries_checked: None,
+        };
+        state.rules = RuleWatch::new(&config.rules, &state.audio);
+        state.set_swallowing(config.swallow_bindings);
+        state.tap_control.set_mouse(config.show_mouse);
+        // Changing keys takes an active tap, which needs Accessibility
+        if state.permissions.can_post() {
+            let remaps = config.remaps.iter().map(|(key_code, remap)| match remap {
+                Remap::Key(to) => (*key_code, Some(*to)),
+                Remap::Action(_) => (*key_code, None),
+            });
+            state.tap_control.set_remapped(remaps.collect());
+        }
+        match state.config_errors.len() {
+            0 => (),
+            1 => state.notify(format!("Config: {}", state.config_errors[0])),
+            n => state.notify(format!("Config: {n} problems, see the doctor pane")),
+        }
+        match tls {
+            Ok(tls) => {
+                if let Some(port) = config.server_port {
+                    match Server::start(port, config.server_lan, tls) {
+                        Ok(server) => state.server = Some(server),
+                        Err(e) => state.notify(e),
+                    }
+                }
+            }
+            Err(e) if config.server_port.is_some() || config.quick_port.is_some() => {
+                state.notify(e)
+            }
+            Err(_) => {}
+        }
+        state.sinks = Sinks::new(config, state.server.as_ref());
+        state.sinks.check(&state.audio);
+        state
+    }
+
+    /// Pick up a keyboard layout switch, so key names stay correct.
+    pub fn check_layout(&mut self) {
+        let layout = KeyboardLayout::current();
+        let changed = match (&self.layout, &layout) {
+            (Some(prev), Some(next)) => prev.id != next.id,
+            (prev, next) => prev.is_some() != next.is_some(),
+        };
+        if changed {
+            if let Some(layout) = &layout {
+                self.notify(format!("Keyboard layout: {}", layout.name));
+            }
+            self.layout = layout;
+        }
+    }
+
+    /// Name of a key code on the active layout.
+    pub fn key_name(&self, key_code: i64) -> String {
+        match &self.layout {
+            Some(layout) => layout.key_name(key_code),
+            None => key_code.to_string(),
+        }
+    }
+
+    /// Toggle the mic mute when Caps Lock is pressed. Caps Lock is kept in
+    /// step with the mute, so a change matching the mute is our own
+    /// `sync_caps_lock` and is ignored.
+    pub fn caps_lock_changed(&mut self, caps_lock: bool) {
+        let prev = self.caps_lock.replace(caps_lock);
+        if !self.caps_lock_mute || prev.is_none() || prev == Some(caps_lock) {
+            return;
+        }
+        if matches!(self.input_mute(), Some((_, muted)) if muted != caps_lock) {
+            self.toggle_input_mute();
+        }
+    }
+
+    /// Set the Caps Lock light to the mic mute, after it changed elsewhere.
+    pub fn sync_caps_lock(&mut self) {
+        if !self.caps_lock_mute {
+            return;
+        }
+        let Some((_, muted)) = self.input_mute() else {
+            return;
+        };
+        if self.caps_lock == Some(muted) {
+            return;
+        }
+        match events::set_caps_lock(muted) {
+            Ok(()) => {
+                self.caps_lock = Some(muted);
+                self.caps_lock_failed = false;
+            }
+            // Tried again every poll, said once
+            Err(e) if !self.caps_lock_failed => {
+                self.caps_lock_failed = true;
+                self.notify(format!("Unable to set Caps Lock: {e}"));
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Action bound to a mouse button.
+    pub fn mouse_binding(&self, button: i64) -> Option<Action> {
+        self.mouse
+            .iter()
+            .find(|(bound, _)| *bound == button)
+            .and_then(|(_, action)| Action::from_record(action))
+    }
+
+    /// Action bound to tapping a combo of modifier keys.
+    pub fn modifier_binding(&self, combo: u64) -> Option<Action> {
+        self.modifier_bindings
+            .iter()
+            .find(|(bound, _)| *bound == combo)
+            .and_then(|(_, action)| Action::from_record(action))
+    }
+
+    /// Send a key press or release to socket API clients, in a shape meant
+    /// for keystroke overlays.
+    pub fn broadcast_key(&self, key_code: i64, down: bool) {
+        let server = match &self.server {
+            Some(server) => server,
+            None => return,
+        };
+        let key = self.key_name(key_code);
+        let modifiers: Vec<String> = self.key_modifiers.iter().map(|m| json_string(m)).collect();
+        let combo = self
+            .key_modifiers
+            .iter()
+            .chain(std::iter::once(&key))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("+");
+        let keyboard = match self.last_keyboard {
+            Some(keyboard) => json_string(&self.keyboard_name(keyboard)),
+            None => "null".to_string(),
+        };
+        let app = match &self.frontmost_app {
+            Some(app) => json_string(app),
+            None => "null".to_string(),
+        };
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        server.broadcast(&format!(
+            "{{\"type\":\"key\",\"event\":\"{}\",\"key\":{},\"code\":{key_code},\"modifiers\":[{}],\"combo\":{},\"keyboard\":{keyboard},\"app\":{app},\"time\":{time}}}",
+            if down { "down" } else { "up" },
+            json_string(&key),
+            modifiers.join(","),
+            json_string(&combo),
+        ));
+    }
+
+    /// Action bound to a key on a specific keyboard, or remapped to one on
+    /// all of them.
+    pub fn key_binding(&self, keyboard: i64, key_code: i64) -> Option<Action> {
+        let bound = self
+            .key_bindings
+            .iter()
+            .find(|(kind, code, _)| *kind == keyboard && *code == key_code)
+            .map(|(_, _, action)| action);
+        let remapped = || {
+            self.remap_actions
+                .iter()
+                .find(|(code, _)| *code == key_code)
+                .map(|(_, action)| action)
+        };
+        bound
+            .or_else(remapped)
+            .and_then(|action| Action::from_record(action))
+    }
+
+    /// Action bound to a chord, whatever app is in front.
+    pub fn hotkey(&self, key_code: i64, modifiers: &ModifierKeys) -> Option<Action> {
+        let key = self.key_name(key_code);
+        self.hotkeys
+            .iter()
+            .find(|(hotkey, _)| hotkey.matches(&key, modifiers))
+            .and_then(|(_, action)| Action::from_record(action))
+    }
+
+    /// Whether bound keys are kept from other apps.
+    pub fn is_swallowing(&self) -> bool {
+        self.tap_control.is_swallowing()
+    }
+
+    /// Swallow bound keys or let them through. The event tap is only made
+    /// active while there's something to swallow, which needs Accessibility.
+    pub fn set_swallowing(&mut self, swallow: bool) {
+        let keys = match swallow && self.permissions.can_post() {
+            true => self
+                .key_bindings
+                .iter()
+                .map(|(keyboard, key_code, _)| (*keyboard, *key_code))
+                .collect(),
+            false => Vec::new(),
+        };
+        self.tap_control.set_swallowed(keys);
+    }
+
+    /// Configured name of a keyboard, or its type.
+    pub fn keyboard_name(&self, keyboard: i64) -> String {
+        self.keyboards
+            .iter()
+            .find(|(kind, _)| *kind == keyboard)
+            .map(|(_, name)| name.clone())
+            .unwrap_or_else(|| format!("keyboard type {keyboard}"))
+    }
+
+    /// Toggle the default input's mute, from anywhere.
+    pub fn toggle_input_mute(&mut self) {
+        if let Some((id, _)) = self.input_mute() {
+            self.audio.toggle_mute_of(&id, Channel::Input);
+            self.sync_caps_lock();
+        }
+    }
+
+    /// Whether a key is the push-to-talk or push-to-mute key.
+    pub fn push_key(&self, key_code: i64) -> Option<Push> {
+        let name = Some(self.key_name(key_code).to_lowercase());
+        if self.push_to_talk == name {
+            Some(Push::Talk)
+        } else if self.push_to_mute == name {
+            Some(Push::Mute)
+        } else {
+            None
+        }
+    }
+
+    /// Push key pressed: unmute or mute the default input until it's
+    /// released.
+    pub fn push_down(&mut self, push: Push, key_code: i64) {
+        if self.push.is_some() {
+            return;
+        }
+        if let Some((id, _)) = self.input_mute() {
+            self.push = Some((push, key_code, id));
+            self.set_input_muted(&id, push == Push::Mute);
+        }
+    }
+
+    /// Push key released: back to muted for push-to-talk, unmuted for
+    /// push-to-mute.
+    pub fn push_up(&mut self, key_code: i64) {
+        if let Some((push, _, id)) = self.push.filter(|(_, key, _)| *key == key_code) {
+            self.push = None;
+            self.set_input_muted(&id, push == Push::Talk);
+        }
+    }
+
+    /// Mute or unmute an input through the workaround-aware toggle.
+    fn set_input_muted(&mut self, id: &AudioDeviceID, muted: bool) {
+        if self.audio.input(id).is_some_and(|(_, m)| m != muted) {
+            self.audio.toggle_mute_of(id, Channel::Input);
+            self.sync_caps_lock();
+        }
+    }
+
+    /// Default input and whether it's muted.
+    fn input_mute(&self) -> Option<(u32, bool)> {
+        let id = self.audio.active_id(Channel::Input)?;
+        self.audio.input(&id).map(|(_, muted)| (id, muted))
+    }
+
+    /// Enter a mode, remembering the current one. Going back to a mode
+    /// that's already on the stack drops everything above it.
+    pub fn switch_mode(&mut self, mode: UiMode) {
+        if mode == self.mode {
+            return;
+        }
+        match self.mode_stack.iter().position(|m| *m == mode) {
+            Some(i) => self.mode_stack.truncate(i),
+            None if mode == UiMode::View => self.mode_stack.clear(),
+            None => self.mode_stack.push(self.mode),
+        }
+        self.mode = mode;
+        if mode == UiMode::Palette {
+            self.palette = Palette::default();
+        }
+        if mode == UiMode::Profiles {
+            self.profiles = Palette::default();
+        }
+        if mode == UiMode::Apps {
+            self.refresh_apps();
+        }
+        if mode == UiMode::System {
+            self.backlight = system::backlight();
+            self.mic_mode = micmode::preferred().and_then(|p| Ok((p, micmode::active()?)));
+        }
+        if mode == UiMode::Alert {
+            self.alert_volume = alerts::volume();
+            self.cursor = self.audio.alert_device().map(|d| d.uid.clone());
+        }
+        if mode == UiMode::Detail && self.cursor_device().is_none() {
+            self.move_cursor(0);
+        }
+        if mode == UiMode::Rename {
+            self.rename_entry = self
+                .cursor_device()
+                .map(|d| d.label().to_string())
+                .unwrap_or_default();
+        }
+        // Browsing starts at the default device of the mode's channel
+        let channel = match mode {
+            UiMode::EditInput => Channel::Input,
+            UiMode::EditOutput => Channel::Output,
+            _ => return,
+        };
+        if self.edit_target(channel).is_none() {
+            if let Some(device) = self.audio.active_device(channel) {
+                self.cursor = Some(device.uid.clone());
+            }
+        }
+    }
+
+    /// Device the edit modes change, the highlighted one if it has the
+    /// channel. None with marked devices, which the audio state changes
+    /// together.
+    pub fn edit_target(&self, channel: Channel) -> Option<AudioDeviceID> {
+        if self.audio.marked_count() > 0 {
+            return None;
+        }
+        let device = self.cursor_device()?;
+        let enabled = device.volume(channel).borrow().enabled;
+        enabled.then_some(device.id)
+    }
+
+    /// List the apps with audio again, keeping the highlight in range and
+    /// letting go of apps that quit.
+    pub fn refresh_apps(&mut self) {
+        self.apps = apps::list();
+        if let Ok(list) = &self.apps {
+            self.app_cursor = self.app_cursor.min(list.len().saturating_sub(1));
+            self.app_volumes.retain(list);
         }
     }
+
+    /// The highlighted app in apps mode.
+    pub fn cursor_app(&self) -> Option<&apps::App> {
+        self.apps.as_ref().ok()?.get(self.app_cursor)
+    }
+
+    /// Leave the current mode for the previous one.
+    pub fn mode_back(&mut self) {
+        self.mode = self.mode_stack.pop().unwrap_or(UiMode::View);
+    }
+
+    /// Devices matching the search, all of them without one. A collapsed
+    /// section keeps only its first device, which stands for the header.
+    pub fn visible_devices(&self) -> Vec<(bool, bool, bool, &Device)> {
+        let query = self.search.to_lowercase();
+        let mut list = self.audio.device_list();
+        list.retain(|(_, _, _, d)| {
+            d.label().to_lowercase().contains(&query) || d.name.to_lowercase().contains(&query)
+        });
+        if self.hide_virtual {
+            list.retain(|(active_in, active_out, _, d)| {
+                *active_in || *active_out || !d.category.is_virtual()
+            });
+        }
+        let mut previous = None;
+        list.retain(|(_, _, _, d)| {
+            let first = previous != Some(d.category);
+            previous = Some(d.category);
+            first || !self.collapsed.contains(&d.category)
+        });
+        list
+    }
+
+    /// Fold or unfold the section of the highlighted device.
+    pub fn toggle_section(&mut self) {
+        let Some(category) = self.cursor_device().map(|d| d.category) else {
+            return;
+        };
+        match self.collapsed.iter().position(|c| *c == category) {
+            Some(i) => {
+                self.collapsed.remove(i);
+            }
+            None => {
+                self.collapsed.push(category);
+                // Keep the highlight on the header
+                let first = self
+                    .audio
+                    .device_list()
+                    .iter()
+                    .find(|(_, _, _, d)| d.category == category)
+                    .map(|(_, _, _, d)| d.uid.clone());
+                self.cursor = first;
+            }
+        }
+    }
+
+    /// Move the highlight through the device list, starting from the
+    /// default output.
+    /// Scalar volume step for a device: its own from `devices.toml`, the
+    /// config's, or `default`.
+    pub fn volume_step_for(&self, id: &AudioDeviceID, default: f32) -> f32 {
+        self.device_settings(id)
+            .and_then(|s| s.volume_step)
+            .or(self.volume_step)
+            .unwrap_or(default)
+    }
+
+    /// Step in dB mode for a device, its own or the config's.
+    pub fn db_step_for(&self, id: &AudioDeviceID) -> f32 {
+        self.device_settings(id)
+            .and_then(|s| s.db_step)
+            .unwrap_or(self.db_step)
+    }
+
+    fn device_settings(&self, id: &AudioDeviceID) -> Option<&DeviceSettings> {
+        let device = self.audio.devices().iter().find(|d| d.id == *id)?;
+        Some(&device.settings)
+    }
+
+    pub fn move_cursor(&mut self, step: isize) {
+        self.cursor_to(|i, len| (i as isize + step).rem_euclid(len as isize) as usize);
+    }
+
+    /// Move the highlight a list pane's height, stopping at the ends.
+    pub fn page_cursor(&mut self, pages: isize) {
+        let rows = termion::terminal_size().map(|(_, rows)| rows).unwrap_or(40);
+        let page = self.panes.height(Pane::Mixer, rows) as isize;
+        self.cursor_to(|i, len| (i as isize + pages * page).clamp(0, len as isize - 1) as usize);
+    }
+
+    /// Highlight the device `next` picks from the current index and the
+    /// list length, the first one without a current one.
+    fn cursor_to(&mut self, next: impl FnOnce(usize, usize) -> usize) {
+        let list = self.visible_devices();
+        if list.is_empty() {
+            return;
+        }
+        let current = self
+            .cursor
+            .as_ref()
+            .and_then(|uid| list.iter().position(|(_, _, _, d)| d.uid == *uid))
+            .or_else(|| list.iter().position(|(_, active_out, _, _)| *active_out));
+        let next = match current {
+            Some(i) => next(i, list.len()),
+            None => 0,
+        };
+        let uid = list[next].3.uid.clone();
+        if self.cursor.as_ref() != Some(&uid) {
+            self.member_cursor = 0;
+        }
+        self.cursor = Some(uid);
+    }
+
+    /// The highlighted device, if it's still connected.
+    pub fn cursor_device(&self) -> Option<&Device> {
+        let uid = self.cursor.as_ref()?;
+        self.audio
+            .device_list()
+            .into_iter()
+            .find(|(_, _, _, d)| d.uid == *uid)
+            .map(|(_, _, _, d)| d)
+    }
+
+    /// Aggregate device in detail mode and its highlighted member.
+    pub fn cursor_member(&self) -> Option<(AudioDeviceID, Member)> {
+        let device = self.cursor_device()?;
+        let members = self.audio.members(device);
+        let last = members.len().checked_sub(1)?;
+        let member = members.into_iter().nth(self.member_cursor.min(last))?;
+        Some((device.id, member))
+    }
+
+    pub fn move_member_cursor(&mut self, step: isize) {
+        let count = match self.cursor_device() {
+            Some(device) => self.audio.members(device).len(),
+            None => 0,
+        };
+        if count > 0 {
+            self.member_cursor =
+                (self.member_cursor as isize + step).rem_euclid(count as isize) as usize;
+        }
+    }
+
+    pub fn is_routing_keys(&self) -> bool {
+        self.route_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn set_routing_keys(&self, enabled: bool) {
+        self.route_keys.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Channel routed volume keys control: the mic while editing inputs,
+    /// the output otherwise.
+    pub fn routed_channel(&self) -> Channel {
+        match (self.is_routing_keys(), self.mode) {
+            (true, UiMode::EditInput) => Channel::Input,
+            _ => Channel::Output,
+        }
+    }
+
+    /// Show the OSD if the active output (or input) volume changed since the
+    /// last check, unless the change came from a TUI action.
+    /// Sample every device's volumes, for the sparklines.
+    pub fn record_volumes(&mut self) {
+        for (_, _, _, device) in self.audio.device_list() {
+            let history = self.volume_history.entry(device.uid.clone()).or_default();
+            for (samples, volume) in history.iter_mut().zip([&device.input, &device.output]) {
+                let volume = volume.borrow();
+                if !volume.enabled {
+                    continue;
+                }
+                if samples.len() == VOLUME_SAMPLES {
+                    samples.pop_front();
+                }
+                samples.push_back(volume.level);
+            }
+        }
+    }
+
+    /// Recent volumes of a device channel, oldest first.
+    pub fn volume_history(&self, uid: &str, channel: Channel) -> Option<&VecDeque<f32>> {
+        let samples = &self.volume_history.get(uid)?[channel as usize];
+        Some(samples).filter(|s| !s.is_empty())
+    }
+
+    /// Note when the default input was muted or unmuted, for the timer.
+    pub fn check_mute_since(&mut self) {
+        let muted = self
+            .audio
+            .active_device(Channel::Input)
+            .map(|device| self.audio.is_muted(device, Channel::Input));
+        match (muted, self.mute_since) {
+            (Some(muted), Some((prev, _))) if muted == prev => return,
+            (Some(muted), _) => self.mute_since = Some((muted, Instant::now())),
+            (None, _) => self.mute_since = None,
+        }
+        ipc::set_mute_since(self.mute_since);
+    }
+
+    /// Read the Bluetooth devices' batteries again, once a minute.
+    pub fn check_batteries(&mut self) {
+        if self
+            .batteries_checked
+            .is_some_and(|checked| checked.elapsed() < BATTERY_INTERVAL)
+        {
+            return;
+        }
+        self.batteries_checked = Some(Instant::now());
+        self.batteries = self
+            .audio
+            .device_list()
+            .into_iter()
+            .filter(|(_, _, _, device)| device.category == Category::Bluetooth)
+            .filter_map(|(_, _, _, device)| {
+                Some((device.uid.clone(), bluetooth::battery(&device.uid)?))
+            })
+            .collect();
+    }
+
+    /// Log the default input and output starting to run at different
+    /// sample rates.
+    pub fn check_rate_mismatch(&mut self) {
+        let mismatch = self.audio.rate_mismatch();
+        if mismatch == self.rate_mismatch {
+            return;
+        }
+        self.rate_mismatch = mismatch;
+        if let Some((input, output)) = mismatch {
+            log::warn(format!(
+                "Input runs at {} and output at {}, which can crackle",
+                audio::describe_rate(input),
+                audio::describe_rate(output)
+            ));
+        }
+    }
+
+    /// Turn the default output up by the boost step until the boost time
+    /// is over, or end a boost early.
+    pub fn boost(&mut self) {
+        if self.boost.is_some() {
+            self.end_boost();
+            return;
+        }
+        let Some((id, (previous, _))) = self
+            .audio
+            .active_id(Channel::Output)
+            .and_then(|id| Some((id, self.audio.output(&id)?)))
+        else {
+            self.notify("No output volume to boost".to_string());
+            return;
+        };
+        let boosted = (previous + self.boost_step).min(1.0);
+        self.audio.set_level_of(&id, Channel::Output, boosted);
+        self.audio.update();
+        self.boost = Some(Boost {
+            id,
+            previous,
+            boosted,
+            until: Instant::now() + self.boost_time,
+        });
+        self.notify(format!(
+            "Boosted to {:.0}% for {}s",
+            boosted * 100.0,
+            self.boost_time.as_secs()
+        ));
+    }
+
+    /// Record the output to a new file on the desktop, or stop and save
+    /// the recording.
+    pub fn toggle_recording(&mut self) {
+        let message = match self.recording.take() {
+            Some(recording) => {
+                let path = recording.path().display().to_string();
+                match recording.stop() {
+                    Ok(length) => format!("Saved {}s of output to {path}", length.as_secs()),
+                    Err(e) => e,
+                }
+            }
+            None => match Recording::start(&recorder::default_path()) {
+                Ok(recording) => {
+                    let message = format!("Recording the output to {}", recording.path().display());
+                    self.recording = Some(recording);
+                    message
+                }
+                Err(e) => e,
+            },
+        };
+        self.notify(message);
+    }
+
+    /// Beep on the highlighted device, whether it's the default or not, to
+    /// tell which speakers it is.
+    pub fn play_tone(&mut self) {
+        // One at a time, and the device's own IOProc goes first
+        self.tone = None;
+        let Some(device) = self.cursor_device() else {
+            return;
+        };
+        let (id, label) = (device.id, device.label().to_string());
+        let message = match audio::has_output(&id) {
+            false => format!("{label} has no output to beep on"),
+            true => match Tone::start(id, audio::sample_rate(&id).unwrap_or(tone::FALLBACK_RATE)) {
+                Ok(tone) => {
+                    self.tone = Some(tone);
+                    format!("Beeping on {label}")
+                }
+                Err(e) => format!("{label}: {e}"),
+            },
+        };
+        self.notify(message);
+    }
+
+    /// Stop a test tone that's over.
+    pub fn check_tone(&mut self) {
+        if self.tone.as_ref().is_some_and(Tone::is_done) {
+            self.tone = None;
+        }
+    }
+
+    /// End a boost whose time is up.
+    pub fn check_boost(&mut self) {
+        if self
+            .boost
+            .is_some_and(|boost| Instant::now() >= boost.until)
+        {
+            self.end_boost();
+        }
+    }
+
+    /// Put the boosted output back, unless its volume was changed since.
+    fn end_boost(&mut self) {
+        let Some(boost) = self.boost.take() else {
+            return;
+        };
+        let Some((level, _)) = self.audio.output(&boost.id) else {
+            return;
+        };
+        if (level - boost.boosted).abs() > 0.01 {
+            return;
+        }
+        self.audio
+            .set_level_of(&boost.id, Channel::Output, boost.previous);
+        self.audio.update();
+        self.notify(format!("Back to {:.0}%", boost.previous * 100.0));
+    }
+
+    pub fn check_osd(&mut self) {
+        let levels = [
+            self.active_level(Channel::Output),
+            self.active_level(Channel::Input),
+        ];
+        let external = match self.last_user_action {
+            Some(t) => t.elapsed() > USER_ACTION_GRACE,
+            None => true,
+        };
+        for (i, channel) in [Channel::Output, Channel::Input].into_iter().enumerate() {
+            match (self.last_levels[i], levels[i]) {
+                (Some((prev_id, ..)), Some((id, level, muted)))
+                    if prev_id == id && self.last_levels[i] != levels[i] =>
+                {
+                    let name = self
+                        .audio
+                        .active_device(channel)
+                        .map(|d| d.label().to_string())
+                        .unwrap_or_default();
+                    // Hotkeys are TUI actions too, but nobody sees the TUI
+                    if self.terminal_app.is_some() {
+                        let level = match muted {
+                            true => "muted".to_string(),
+                            false => format!("{:.0}%", level * 100.0),
+                        };
+                        let message = format!("{name} {} {level}", channel.as_str());
+                        self.volume_notice = Some((message, Instant::now()));
+                    }
+                    if external {
+                        self.osd = Some(Osd {
+                            channel,
+                            name,
+                            level,
+                            muted,
+                            shown: Instant::now(),
+                        });
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+        self.last_levels = levels;
+    }
+
+    /// Post the last volume change once it has settled, if the terminal
+    /// isn't in front to show it.
+    pub fn check_volume_notice(&mut self) {
+        if self
+            .volume_notice
+            .as_ref()
+            .is_none_or(|(_, changed)| changed.elapsed() < VOLUME_NOTICE_SETTLE)
+        {
+            return;
+        }
+        let Some((message, _)) = self.volume_notice.take() else {
+            return;
+        };
+        if frontmost::app_name() == self.terminal_app {
+            return;
+        }
+        if let Err(e) = sinks::notification(&message) {
+            log::warn(e);
+        }
+    }
+
+    /// Show a click or scroll in the keys display, with the chord for the
+    /// key history. A scroll goes on as many events, those make one entry.
+    pub fn pointer_chord(&mut self, pointer: Pointer, modifiers: &ModifierKeys) -> Option<String> {
+        let chord = layout::chord(&modifiers.list_active(), &[pointer.name()]);
+        let same_scroll = matches!(pointer, Pointer::Scroll(_))
+            && matches!(&self.pointer, Some((last, at)) if *last == chord && at.elapsed() < SCROLL_GAP);
+        self.pointer = Some((chord.clone(), Instant::now()));
+        (!same_scroll).then_some(chord)
+    }
+
+    /// The keys held now, for the key history after a key other than a
+    /// modifier went down.
+    pub fn held_chord(&self) -> String {
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .filter(|k| !layout::is_modifier(**k))
+            .map(|k| self.key_name(*k))
+            .collect();
+        layout::chord(&self.key_modifiers, &keys)
+    }
+
+    /// Show a transient message, kept in the log.
+    pub fn notify(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        log::info(message.clone());
+        self.notice = Some((message, Instant::now()));
+    }
+
+    /// Move messages from the audio state into the notice line.
+    pub fn collect_notices(&mut self) {
+        for message in self.audio.take_notices() {
+            self.notify(message);
+        }
+    }
+
+    /// The current notice, while it's still visible.
+    pub fn visible_notice(&self) -> Option<&str> {
+        match &self.notice {
+            Some((message, shown)) if shown.elapsed() < NOTICE_TIME => Some(message),
+            _ => None,
+        }
+    }
+
+    /// The OSD, while it's still visible.
+    pub fn visible_osd(&self) -> Option<&Osd> {
+        self.osd
+            .as_ref()
+            .filter(|osd| osd.shown.elapsed() < OSD_TIME)
+    }
+
+    fn active_level(&self, channel: Channel) -> Option<(u32, f32, bool)> {
+        let device = self.audio.active_device(channel)?;
+        let level = match channel {
+            Channel::Input => self.audio.input(&device.id),
+            Channel::Output => self.audio.output(&device.id),
+        }?;
+        Some((device.id, level.0, level.1))
+    }
 }
@@ -0,0 +1,159 @@
+//! Local usage counts of user actions, per day in `stats.toml`, for the
+//! `mac-controls stats` report. Nothing leaves the machine.
+//!
+//! Sections are days since the unix epoch, with a count per action:
+//!
+//! ```toml
+//! [20378]
+//! toggle-mute = 12
+//! "default output" = 3
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+use crate::history::now;
+use crate::store::Store;
+
+const STATS_FILE: &str = "stats.toml";
+const DAY: u64 = 24 * 60 * 60;
+/// Report columns, in days.
+const PERIODS: [u64; 2] = [7, 30];
+
+#[derive(Debug)]
+pub struct Stats {
+    store: Store,
+}
+
+impl Stats {
+    pub fn load() -> Self {
+        Stats {
+            store: Store::open(STATS_FILE),
+        }
+    }
+
+    /// Count a recorded action for today.
+    pub fn count(&mut self, record: &str) -> Result<(), String> {
+        let day = (now() / DAY).to_string();
+        let name = action_name(record);
+        let count = self.get(&day, name) + 1;
+        self.store.set(&day, name, count);
+        self.store.save()
+    }
+
+    fn get(&self, day: &str, name: &str) -> u64 {
+        self.store
+            .get(day, name)
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+/// Recorded actions without their arguments, like the device UID of
+/// "default output <uid>".
+fn action_name(record: &str) -> &str {
+    for prefix in [
+        "default input",
+        "default output",
+        "volume input",
+        "volume output",
+    ] {
+        if record.starts_with(prefix) {
+            return prefix;
+        }
+    }
+    record
+}
+
+/// Counts per action over the last week, month and all time, and the
+/// bindings that went unused for a month.
+pub fn report() -> String {
+    let stats = Stats::load();
+    let today = now() / DAY;
+    // Action to counts, for each period then all time
+    let mut counts: BTreeMap<&str, [u64; 3]> = BTreeMap::new();
+    for (day, section) in &stats.store.sections {
+        let Ok(day) = day.parse::<u64>() else {
+            continue;
+        };
+        for (name, count) in section {
+            let count: u64 = count.parse().unwrap_or(0);
+            let entry = counts.entry(name.as_str()).or_default();
+            for (i, period) in PERIODS.iter().enumerate() {
+                if today - day.min(today) < *period {
+                    entry[i] += count;
+                }
+            }
+            entry[2] += count;
+        }
+    }
+    if counts.is_empty() {
+        return "No actions counted yet.".to_string();
+    }
+
+    let total = |names: &[&str]| -> [u64; 3] {
+        let mut sum = [0; 3];
+        for name in names {
+            if let Some(c) = counts.get(name) {
+                for (s, c) in sum.iter_mut().zip(c) {
+                    *s += c;
+                }
+            }
+        }
+        sum
+    };
+    let switches = total(&[
+        "default input",
+        "default output",
+        "select-next",
+        "select-prev",
+    ]);
+    let mutes = total(&["toggle-mute", "mute-input", "media mute", "fix-silent"]);
+
+    let row = |name: &str, [week, month, all]: [u64; 3]| {
+        format!("{name:<28} {week:>7} {month:>7} {all:>7}\n")
+    };
+    let mut out = format!("{:<28} {:>7} {:>7} {:>7}\n", "", "7 days", "30 days", "all");
+    out.push_str(&row("Device switches", switches));
+    out.push_str(&row("Mute toggles", mutes));
+    out.push('\n');
+    let mut by_use: Vec<(&str, [u64; 3])> = counts.iter().map(|(n, c)| (*n, *c)).collect();
+    by_use.sort_by_key(|(_, c)| std::cmp::Reverse(c[2]));
+    for (name, action_counts) in by_use {
+        out.push_str(&row(name, action_counts));
+    }
+
+    let config = Config::load();
+    let bindings = config
+        .mouse
+        .iter()
+        .map(|(button, action)| (format!("button{button}"), action))
+        .chain(
+            config
+                .key_bindings
+                .iter()
+                .map(|(kb, code, action)| (format!("keyboard {kb} key {code}"), action)),
+        )
+        .chain(
+            config
+                .modifier_bindings
+                .iter()
+                .map(|(combo, action)| (format!("modifiers \"{combo}\""), action)),
+        )
+        .chain(
+            config
+                .hotkeys
+                .iter()
+                .map(|(chord, action)| (format!("hotkeys \"{chord}\""), action)),
+        );
+    let unused: Vec<String> = bindings
+        .filter(|(_, action)| counts.get(action_name(action)).is_none_or(|c| c[1] == 0))
+        .map(|(binding, action)| format!("  {binding} = \"{action}\""))
+        .collect();
+    if !unused.is_empty() {
+        out.push_str("\nBindings whose action wasn't used in the last 30 days:\n");
+        out.push_str(&unused.join("\n"));
+        out.push('\n');
+    }
+    out
+}
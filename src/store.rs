@@ -0,0 +1,387 @@
+//! Small files under `~/.config/mac-controls` for data that should survive
+//! restarts. The format is a TOML subset: `[section]` headers followed by
+//! `key = value` lines, values being strings, numbers or booleans, all kept
+//! as text. Arrays, inline tables and multi-line strings aren't read, lines
+//! with them end up in `problems`; lists are comma-separated strings.
+//!
+//! Writes are rate-limited per file: saves in quick succession, like
+//! holding the pane resize key, are batched and written by `flush`. Files
+//! are replaced atomically, so a crash leaves the old or the new version.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+pub type Section = BTreeMap<String, String>;
+
+/// Minimum time between writes of one file.
+const WRITE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Last write time and text waiting to be written, per file.
+struct Writes {
+    last: BTreeMap<PathBuf, Instant>,
+    pending: BTreeMap<PathBuf, String>,
+}
+
+impl Writes {
+    fn wrote_recently(&self, path: &Path) -> bool {
+        self.last
+            .get(path)
+            .is_some_and(|last| last.elapsed() < WRITE_INTERVAL)
+    }
+}
+
+static WRITES: Mutex<Writes> = Mutex::new(Writes {
+    last: BTreeMap::new(),
+    pending: BTreeMap::new(),
+});
+
+#[derive(Debug, Default)]
+pub struct Store {
+    path: PathBuf,
+    pub sections: BTreeMap<String, Section>,
+    /// Lines that couldn't be read, like `config.toml line 3: …`.
+    pub problems: Vec<String>,
+}
+
+impl Store {
+    /// Load a file from the data dir. A missing or unreadable file is empty.
+    pub fn open(file_name: &str) -> Self {
+        let path = data_dir().join(file_name);
+        // A batched save is newer than the file
+        let pending = WRITES.lock().unwrap().pending.get(&path).cloned();
+        let text = pending.or_else(|| fs::read_to_string(&path).ok());
+        let (sections, problems) = parse(&text.unwrap_or_default());
+        let problems = problems
+            .into_iter()
+            .map(|problem| format!("{file_name} {problem}"))
+            .collect();
+        Store {
+            path,
+            sections,
+            problems,
+        }
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections
+            .get(section)
+            .and_then(|s| s.get(key))
+            .map(|v| v.as_str())
+    }
+
+    pub fn set(&mut self, section: &str, key: &str, value: impl ToString) {
+        self.sections
+            .entry(section.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// Write back to disk, or within `WRITE_INTERVAL` of the last write,
+    /// on the next `flush`.
+    pub fn save(&self) -> Result<(), String> {
+        let text = serialize(&self.sections);
+        let mut writes = WRITES.lock().unwrap();
+        if writes.wrote_recently(&self.path) {
+            writes.pending.insert(self.path.clone(), text);
+            return Ok(());
+        }
+        writes.pending.remove(&self.path);
+        writes.last.insert(self.path.clone(), Instant::now());
+        write(&self.path, &text)
+    }
+}
+
+/// Write batched saves that are due, or all of them when `force`d, e.g. on
+/// exit. Errors can only be logged by now.
+pub fn flush(force: bool) {
+    let mut writes = WRITES.lock().unwrap();
+    let due: Vec<PathBuf> = writes
+        .pending
+        .keys()
+        .filter(|path| force || !writes.wrote_recently(path))
+        .cloned()
+        .collect();
+    for path in due {
+        if let Some(text) = writes.pending.remove(&path) {
+            writes.last.insert(path.clone(), Instant::now());
+            if let Err(e) = write(&path, &text) {
+                log::warn(e);
+            }
+        }
+    }
+}
+
+/// Replace a file through a temporary one, creating the data dir if needed.
+/// Only the user can read it, tokens and passwords live in these files.
+fn write(path: &Path, text: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Unable to create {dir:?}: {e}"))?;
+    }
+    // Next to the file, so the rename stays on one disk, and named after
+    // all of it and this process, so no other write shares it
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    let temp = PathBuf::from(temp);
+    // A leftover from a crash would keep its mode
+    let _ = fs::remove_file(&temp);
+    // On disk before the rename, or a crash can leave an empty file
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(text.as_bytes())?;
+            file.sync_all()
+        })
+        .map_err(|e| format!("Unable to write {temp:?}: {e}"))?;
+    fs::rename(&temp, path).map_err(|e| format!("Unable to replace {path:?}: {e}"))
+}
+
+/// Config and data live together, like most terminal tools.
+pub fn data_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_default();
+    PathBuf::from(home).join(".config").join("mac-controls")
+}
+
+/// A path from the config, `~/` for the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Keep what tests save out of the real home, in one for the test run.
+#[cfg(test)]
+pub(crate) fn use_temp_home() {
+    static HOME: std::sync::Once = std::sync::Once::new();
+    HOME.call_once(|| {
+        let home = std::env::temp_dir().join(format!("mac-controls-{}", std::process::id()));
+        std::env::set_var("HOME", home);
+    });
+}
+
+/// Sections, and the lines that couldn't be read, like `line 3: …`.
+fn parse(text: &str) -> (BTreeMap<String, Section>, Vec<String>) {
+    let mut sections = BTreeMap::new();
+    let mut problems = Vec::new();
+    let mut current = String::new();
+    for (number, line) in (1..).zip(text.lines()) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let header = strip_comment(line).trim_end();
+        if header.starts_with("[[") {
+            problems.push(format!("line {number}: arrays of tables aren't supported"));
+            continue;
+        }
+        if header.starts_with('[') && header.ends_with(']') {
+            current = unquote(&header[1..header.len() - 1]);
+            continue;
+        }
+        let Some((key, value)) = split_key(line) else {
+            problems.push(format!("line {number}: expected `key = value`"));
+            continue;
+        };
+        let value = strip_comment(value).trim();
+        if value.starts_with(['[', '{']) || value.starts_with("\"\"\"") || value.starts_with("'''")
+        {
+            problems.push(format!(
+                "line {number}: arrays, tables and multi-line strings aren't supported, \
+                 lists are strings like \"a, b\""
+            ));
+            continue;
+        }
+        sections
+            .entry(current.clone())
+            .or_insert_with(Section::new)
+            .insert(unquote(key), unquote(value));
+    }
+    (sections, problems)
+}
+
+fn serialize(sections: &BTreeMap<String, Section>) -> String {
+    let mut out = String::new();
+    for (name, section) in sections {
+        if !name.is_empty() {
+            out.push_str(&format!("[{}]\n", quote(name)));
+        }
+        for (key, value) in section {
+            let value = if value.parse::<f64>().is_ok() || value == "true" || value == "false" {
+                value.clone()
+            } else {
+                format!("\"{}\"", escape(value))
+            };
+            out.push_str(&format!("{} = {}\n", quote(key), value));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `key = value` at the first `=` outside a quoted key, like
+/// `"a=b" = 1`.
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    let key_end = match line.strip_prefix('"') {
+        Some(rest) => {
+            let mut escaped = false;
+            let close = rest.char_indices().find(|(_, c)| {
+                let close = *c == '"' && !escaped;
+                escaped = *c == '\\' && !escaped;
+                close
+            })?;
+            close.0 + 2
+        }
+        None => 0,
+    };
+    let equals = key_end + line[key_end..].find('=')?;
+    Some((&line[..equals], &line[equals + 1..]))
+}
+
+/// Drop a trailing `# comment`, unless the `#` is inside a string.
+fn strip_comment(value: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '\\' if in_string => escaped = !escaped,
+            '"' if !escaped => in_string = !in_string,
+            '#' if !in_string => return &value[..i],
+            _ => {}
+        }
+        if c != '\\' {
+            escaped = false;
+        }
+    }
+    value
+}
+
+/// A basic string with its escapes, a literal `'string'` as written, or a
+/// bare value.
+fn unquote(s: &str) -> String {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with('\'') && s.ends_with('\'') {
+        return s[1..s.len() - 1].to_string();
+    }
+    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+        return s.to_string();
+    }
+    let mut out = String::new();
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Bare keys are limited to A-Za-z0-9_- in TOML, anything else is quoted.
+fn quote(s: &str) -> String {
+    let bare = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        s.to_string()
+    } else {
+        format!("\"{}\"", escape(s))
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut sections = BTreeMap::new();
+        let mut keys = Section::new();
+        keys.insert("volume_step".to_string(), "0.05".to_string());
+        keys.insert("a=b".to_string(), "say \"hi\" # not a comment".to_string());
+        keys.insert("path".to_string(), "C:\\dir".to_string());
+        // Written `\\\"`, a backslash and then a quote, unescaped in one pass
+        keys.insert("tricky".to_string(), "a\\\"b\nc".to_string());
+        sections.insert("keys".to_string(), keys);
+        sections.insert("keyboard 40".to_string(), Section::new());
+        let text = serialize(&sections);
+        let mut expected = sections.clone();
+        // Empty sections don't survive, they have no lines
+        expected.remove("keyboard 40");
+        assert_eq!(parse(&text), (expected, Vec::new()));
+    }
+
+    #[test]
+    fn quoted_key_with_equals() {
+        let (sections, _) = parse("[remap]\n\"x=y\" = \"z\" # comment\nplain = 1\n");
+        let remap = &sections["remap"];
+        assert_eq!(remap["x=y"], "z");
+        assert_eq!(remap["plain"], "1");
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let (sections, _) = parse("# top\n\n[a]\nkey = \"#1\" # trailing\n");
+        assert_eq!(sections["a"]["key"], "#1");
+    }
+
+    #[test]
+    fn written_for_the_user_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir()
+            .join(format!("mac-controls-mode-{}", std::process::id()))
+            .join("tokens.toml");
+        write(&path, "[tokens]\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn arrays_are_reported_not_misread() {
+        let (sections, problems) =
+            parse("[night]\nheadphones = [\"AirPods\", \"Bose, QC\"]\nstart = '22:00'\nnope\n");
+        assert_eq!(sections["night"].get("headphones"), None);
+        assert_eq!(sections["night"]["start"], "22:00");
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("line 2: arrays"));
+        assert!(problems[1].starts_with("line 4: expected"));
+    }
+
+    #[test]
+    fn split_key_handles_escaped_quotes() {
+        assert_eq!(split_key(r#""a\"=b" = 1"#), Some((r#""a\"=b" "#, " 1")));
+        assert_eq!(split_key("novalue"), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! `mac-controls summary`: a short report of the defaults, device levels,
+//! Bluetooth batteries and permissions, for bug reports and dashboards.
+//! `--markdown` makes it a table.
+
+use std::collections::HashMap;
+use std::process::Command;
+
+use crate::audio::{AudioState, Category, Channel, Device};
+use crate::permissions::Permission;
+
+pub fn report(audio: &AudioState, markdown: bool) -> String {
+    let batteries = battery_levels();
+    let mut lines = Vec::new();
+    let version = env!("CARGO_PKG_VERSION");
+    let system = macos_version().unwrap_or_else(|| "unknown".to_string());
+    lines.push(match markdown {
+        true => format!("**mac-controls {version}**, macOS {system}"),
+        false => format!("mac-controls {version}, macOS {system}"),
+    });
+    lines.push(String::new());
+
+    for (channel, name) in [(Channel::Output, "Output"), (Channel::Input, "Input")] {
+        let default = match audio.active_device(channel) {
+            Some(device) => format!("{} {}", device.label(), level(device, channel)),
+            None => "none".to_string(),
+        };
+        lines.push(match markdown {
+            true => format!("- {name}: {}", escape(&default)),
+            false => format!("{name}: {default}"),
+        });
+    }
+    lines.push(String::new());
+
+    if markdown {
+        lines.push("| Device | Type | Input | Output | Battery |".to_string());
+        lines.push("|---|---|---|---|---|".to_string());
+    } else {
+        lines.push("Devices:".to_string());
+    }
+    for (_, _, _, device) in audio.device_list() {
+        let battery = match batteries.get(&device.name) {
+            Some(percent) => format!("{percent}%"),
+            None => "-".to_string(),
+        };
+        let (input, output) = (
+            level(device, Channel::Input),
+            level(device, Channel::Output),
+        );
+        let category = device.category.name();
+        lines.push(match markdown {
+            true => format!(
+                "| {} | {category} | {input} | {output} | {battery} |",
+                escape(device.label())
+            ),
+            false if device.category == Category::Bluetooth => format!(
+                "  {} ({category}): in {input}, out {output}, battery {battery}",
+                device.label()
+            ),
+            false => format!(
+                "  {} ({category}): in {input}, out {output}",
+                device.label()
+            ),
+        });
+    }
+    lines.push(String::new());
+
+    let permissions: Vec<String> = Permission::ALL
+        .iter()
+        .map(|p| format!("{} {}", p.name(), p.status().describe()))
+        .collect();
+    lines.push(match markdown {
+        true => format!("**Permissions:** {}", permissions.join(", ")),
+        false => format!("Permissions: {}", permissions.join(", ")),
+    });
+    lines.join("\n")
+}
+
+/// Like `40%`, `40% muted` or `-` without the channel.
+fn level(device: &Device, channel: Channel) -> String {
+    let volume = device.volume(channel).borrow();
+    if !volume.enabled {
+        return "-".to_string();
+    }
+    let percent = (volume.level * 100.0).round();
+    match volume.native_mute {
+        true => format!("{percent}% muted"),
+        false => format!("{percent}%"),
+    }
+}
+
+/// Keep names from breaking the table.
+fn escape(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn macos_version() -> Option<String> {
+    let output = Command::new("sw_vers")
+        .arg("-productVersion")
+        .output()
+        .ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!version.is_empty()).then_some(version)
+}
+
+/// Battery percent by product name, for Bluetooth devices that report it
+/// to the IO registry, like AirPods and most headsets. CoreAudio has no
+/// battery property.
+pub fn battery_levels() -> HashMap<String, u8> {
+    let Ok(output) = Command::new("ioreg")
+        .args(["-r", "-l", "-k", "BatteryPercent"])
+        .output()
+    else {
+        return HashMap::new();
+    };
+    let mut levels = HashMap::new();
+    let mut entry: (Option<String>, Option<u8>) = (None, None);
+    // Entries start with `+-o`, with properties like `"Product" = "Name"`
+    // in any order
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.contains("+-o") {
+            entry = (None, None);
+        }
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim_start_matches([' ', '|']) {
+            "\"Product\"" => entry.0 = Some(value.trim_matches('"').to_string()),
+            "\"BatteryPercent\"" => entry.1 = value.parse().ok(),
+            _ => continue,
+        }
+        if let (Some(name), Some(percent)) = &entry {
+            levels.insert(name.clone(), *percent);
+        }
+    }
+    levels
+}
@@ -0,0 +1,132 @@
+//! Small system controls next to the audio ones: keyboard backlight,
+//! display sleep and screen lock.
+//!
+//! None of them have public API. The backlight goes through CoreBrightness'
+//! `KeyboardBrightnessClient`, which is what Control Center uses, display
+//! sleep through the IOKit display wrangler like `pmset displaysleepnow`,
+//! and the lock through the login framework like the menu bar's Lock
+//! Screen. Each is looked up at runtime, so a macOS without it gets an error
+//! instead of a crash.
+
+use core_foundation::base::TCFType;
+use core_foundation::boolean::CFBoolean;
+use core_foundation::string::{CFString, CFStringRef};
+use std::os::raw::{c_char, c_int, c_void};
+
+use crate::objc::{
+    self, msg_send, objc_autoreleasePoolPop, objc_autoreleasePoolPush, objc_getClass, responds,
+    sel_registerName,
+};
+
+const CORE_BRIGHTNESS: &std::ffi::CStr =
+    c"/System/Library/PrivateFrameworks/CoreBrightness.framework/CoreBrightness";
+const LOGIN: &std::ffi::CStr =
+    c"/System/Library/PrivateFrameworks/login.framework/Versions/Current/login";
+const DISPLAY_WRANGLER: &std::ffi::CStr = c"IOService:/IOResources/IODisplayWrangler";
+const RTLD_LAZY: c_int = 1;
+/// The built-in keyboard, the one with a backlight on laptops.
+const BUILT_IN_KEYBOARD: u64 = 1;
+/// Backlight change per arrow press.
+pub const BACKLIGHT_STEP: f32 = 0.1;
+
+extern "C" {
+    fn dlopen(path: *const c_char, mode: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+
+    fn IORegistryEntryFromPath(main_port: u32, path: *const c_char) -> u32;
+    fn IORegistryEntrySetCFProperty(entry: u32, name: CFStringRef, value: *const c_void) -> c_int;
+    fn IOObjectRelease(object: u32) -> c_int;
+
+}
+
+/// Built-in keyboard backlight brightness, 0.0 - 1.0.
+pub fn backlight() -> Result<f32, String> {
+    with_brightness_client(|client| unsafe {
+        let get = sel_registerName(c"brightnessForKeyboard:".as_ptr());
+        if !responds(client, get) {
+            return Err("This Mac can't report its keyboard backlight".to_string());
+        }
+        let get_level: extern "C" fn(*mut c_void, *mut c_void, u64) -> f32 = objc::send();
+        Ok(get_level(client, get, BUILT_IN_KEYBOARD))
+    })
+}
+
+/// Set the built-in keyboard backlight, 0.0 - 1.0.
+pub fn set_backlight(level: f32) -> Result<(), String> {
+    let level = level.clamp(0.0, 1.0);
+    with_brightness_client(|client| unsafe {
+        let set = sel_registerName(c"setBrightness:forKeyboard:".as_ptr());
+        let set_level: extern "C" fn(*mut c_void, *mut c_void, f32, u64) -> bool = objc::send();
+        if !responds(client, set) || !set_level(client, set, level, BUILT_IN_KEYBOARD) {
+            return Err("Couldn't set the keyboard backlight".to_string());
+        }
+        Ok(())
+    })
+}
+
+/// Turn the displays off, leaving the Mac running.
+pub fn display_sleep() -> Result<(), String> {
+    unsafe {
+        let wrangler = IORegistryEntryFromPath(0, DISPLAY_WRANGLER.as_ptr());
+        if wrangler == 0 {
+            return Err("No display wrangler to put the displays to sleep".to_string());
+        }
+        let key = CFString::new("IORequestIdle");
+        let result = IORegistryEntrySetCFProperty(
+            wrangler,
+            key.as_concrete_TypeRef(),
+            CFBoolean::true_value().as_CFTypeRef(),
+        );
+        IOObjectRelease(wrangler);
+        match result {
+            0 => Ok(()),
+            e => Err(format!("Couldn't put the displays to sleep: error {e}")),
+        }
+    }
+}
+
+/// Lock the screen right away, like Lock Screen in the Apple menu.
+pub fn lock_screen() -> Result<(), String> {
+    unsafe {
+        let login = dlopen(LOGIN.as_ptr(), RTLD_LAZY);
+        let lock = match login.is_null() {
+            true => std::ptr::null_mut(),
+            false => dlsym(login, c"SACLockScreenImmediate".as_ptr()),
+        };
+        if lock.is_null() {
+            return Err("Locking the screen isn't available on this macOS".to_string());
+        }
+        let lock: extern "C" fn() -> c_int = std::mem::transmute(lock);
+        match lock() {
+            0 => Ok(()),
+            e => Err(format!("Couldn't lock the screen: error {e}")),
+        }
+    }
+}
+
+/// Run with a `KeyboardBrightnessClient`, released afterwards.
+fn with_brightness_client<T>(
+    f: impl FnOnce(*mut c_void) -> Result<T, String>,
+) -> Result<T, String> {
+    unsafe {
+        let class = match dlopen(CORE_BRIGHTNESS.as_ptr(), RTLD_LAZY).is_null() {
+            true => std::ptr::null_mut(),
+            false => objc_getClass(c"KeyboardBrightnessClient".as_ptr()),
+        };
+        if class.is_null() {
+            return Err("No keyboard backlight control on this macOS".to_string());
+        }
+        let pool = objc_autoreleasePoolPush();
+        let client = msg_send(class, sel_registerName(c"alloc".as_ptr()));
+        let client = msg_send(client, sel_registerName(c"init".as_ptr()));
+        let result = match client.is_null() {
+            true => Err("Couldn't reach the keyboard backlight".to_string()),
+            false => f(client),
+        };
+        if !client.is_null() {
+            msg_send(client, sel_registerName(c"release".as_ptr()));
+        }
+        objc_autoreleasePoolPop(pool);
+        result
+    }
+}
@@ -0,0 +1,141 @@
+//! Background tasks feeding the main loop: the stdin reader, the media key
+//! tap, servers and their clients, session replay. Each gets a `Cancel`
+//! and is expected to return soon after it fires, so exiting stops them
+//! all instead of leaving threads blocked in `accept` or a run loop.
+//!
+//! Blocking calls can't check a flag, so tasks register a wake-up with
+//! `Cancel::on_cancel`, like connecting to their own socket or stopping
+//! their run loop.
+
+use std::os::raw::{c_int, c_short};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+/// How often tasks waiting on a file descriptor check for cancellation.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const POLLIN: c_short = 1;
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn poll(fds: *mut PollFd, count: u32, timeout: c_int) -> c_int;
+}
+
+type Waker = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// Shared cancellation flag of the running tasks.
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<CancelState>);
+
+impl Cancel {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Run `wake` once cancelled, right away if already. For tasks blocked
+    /// where the flag isn't checked.
+    pub fn on_cancel(&self, wake: impl FnOnce() + Send + 'static) {
+        let mut wakers = self.0.wakers.lock().unwrap();
+        if self.is_cancelled() {
+            drop(wakers);
+            wake();
+            return;
+        }
+        wakers.push(Box::new(wake));
+    }
+
+    fn cancel(&self) {
+        let wakers = {
+            let mut wakers = self.0.wakers.lock().unwrap();
+            self.0.cancelled.store(true, Ordering::Relaxed);
+            std::mem::take(&mut *wakers)
+        };
+        for wake in wakers {
+            wake();
+        }
+    }
+
+    /// Sleep unless cancelled first, false if it was.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return true;
+            }
+            thread::sleep(left.min(CHECK_INTERVAL));
+        }
+        false
+    }
+
+    /// Wait until `fd` has something to read, false if cancelled first.
+    pub fn wait_readable(&self, fd: c_int) -> bool {
+        while !self.is_cancelled() {
+            let mut poll_fd = PollFd {
+                fd,
+                events: POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { poll(&mut poll_fd, 1, CHECK_INTERVAL.as_millis() as c_int) };
+            if ready != 0 {
+                // Errors too, the read reports them
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The running tasks, stopped together with `shutdown`.
+#[derive(Default)]
+pub struct Tasks {
+    cancel: Cancel,
+    running: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Tasks {
+    pub fn new() -> Self {
+        Tasks::default()
+    }
+
+    /// Run `task` on its own thread until it returns or is cancelled.
+    pub fn spawn(&mut self, name: &'static str, task: impl FnOnce(Cancel) + Send + 'static) {
+        let cancel = self.cancel.clone();
+        self.running.retain(|(_, thread)| !thread.is_finished());
+        self.running
+            .push((name, thread::spawn(move || task(cancel))));
+    }
+
+    /// Cancel everything and wait up to `timeout` for it to end. Tasks
+    /// still running then are logged and left to the exit.
+    pub fn shutdown(self, timeout: Duration) {
+        self.cancel.cancel();
+        let until = Instant::now() + timeout;
+        for (name, thread) in self.running {
+            while !thread.is_finished() && Instant::now() < until {
+                thread::sleep(Duration::from_millis(10));
+            }
+            match thread.is_finished() {
+                true => {
+                    let _ = thread.join();
+                }
+                false => log::warn(format!("The {name} task didn't stop in time")),
+            }
+        }
+    }
+}
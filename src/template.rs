@@ -0,0 +1,136 @@
+//! Output templates for `--template`, a small handlebars subset: values
+//! like `{{out.pct}}` and `{{#if in.muted}}…{{else}}…{{/if}}` blocks. A
+//! value counts as true unless it's empty or `false`. Nothing is escaped,
+//! the output goes to status bars, not HTML.
+//!
+//! `--format` is the one line kind, only values like `{out_vol}`, with
+//! `{{` and `}}` for braces.
+
+use std::collections::BTreeMap;
+
+/// Values by name, like `out.name`.
+pub type Values = BTreeMap<String, String>;
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Value(String),
+    /// Name, then and else.
+    If(String, Vec<Node>, Vec<Node>),
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rest = text;
+        match parse_nodes(&mut rest)? {
+            (nodes, None) => Ok(Template { nodes }),
+            (_, Some(tag)) => Err(format!("Template has {{{{{tag}}}}} without {{{{#if}}}}")),
+        }
+    }
+
+    /// A `--format` line, which has values but no blocks.
+    pub fn parse_format(text: &str) -> Result<Self, String> {
+        let mut nodes = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => literal.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => literal.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') if !name.trim().is_empty() => break,
+                            Some(c) if c != '{' && c != '}' => name.push(c),
+                            _ => return Err("Format has an unclosed {".to_string()),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut literal)));
+                    }
+                    nodes.push(Node::Value(name.trim().to_string()));
+                }
+                '}' => return Err("Format has a } without {, use }} for one".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            nodes.push(Node::Text(literal));
+        }
+        Ok(Template { nodes })
+    }
+
+    /// Fill in the values, failing on names that aren't there so typos
+    /// show up.
+    pub fn render(&self, values: &Values) -> Result<String, String> {
+        let mut out = String::new();
+        render_nodes(&self.nodes, values, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Nodes up to the end of the text or an `else` or `/if` tag, which is
+/// returned for the enclosing `#if`.
+fn parse_nodes<'a>(rest: &mut &'a str) -> Result<(Vec<Node>, Option<&'a str>), String> {
+    let mut nodes = Vec::new();
+    loop {
+        let Some(start) = rest.find("{{") else {
+            if !rest.is_empty() {
+                nodes.push(Node::Text(rest.to_string()));
+            }
+            *rest = "";
+            return Ok((nodes, None));
+        };
+        if start > 0 {
+            nodes.push(Node::Text(rest[..start].to_string()));
+        }
+        let tag_start = &rest[start + 2..];
+        let end = tag_start.find("}}").ok_or("Template has an unclosed {{")?;
+        let tag = tag_start[..end].trim();
+        *rest = &tag_start[end + 2..];
+        if let Some(name) = tag.strip_prefix("#if ") {
+            let (then, end) = parse_nodes(rest)?;
+            let otherwise = match end {
+                Some("/if") => Vec::new(),
+                Some("else") => match parse_nodes(rest)? {
+                    (otherwise, Some("/if")) => otherwise,
+                    _ => return Err(format!("Template is missing {{{{/if}}}} for {name}")),
+                },
+                _ => return Err(format!("Template is missing {{{{/if}}}} for {name}")),
+            };
+            nodes.push(Node::If(name.trim().to_string(), then, otherwise));
+        } else if tag == "else" || tag == "/if" {
+            return Ok((nodes, Some(tag)));
+        } else {
+            nodes.push(Node::Value(tag.to_string()));
+        }
+    }
+}
+
+fn render_nodes(nodes: &[Node], values: &Values, out: &mut String) -> Result<(), String> {
+    let get = |name: &str| {
+        values
+            .get(name)
+            .ok_or(format!("Template has an unknown value {name}"))
+    };
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value(name) => out.push_str(get(name)?),
+            Node::If(name, then, otherwise) => {
+                let value = get(name)?;
+                match value.is_empty() || value == "false" {
+                    false => render_nodes(then, values, out)?,
+                    true => render_nodes(otherwise, values, out)?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! TLS for the network servers, the socket API and quick actions, with a
+//! certificate and key from `[tls]`. Needs a build with the `tls` feature,
+//! without it `[tls]` is refused rather than served in plain text.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use crate::config::TlsConfig;
+#[cfg(feature = "tls")]
+use crate::store;
+
+/// A client connection, encrypted when `[tls]` is set.
+#[derive(Debug)]
+pub enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// The connection underneath, for its address and timeouts.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Makes `Stream`s of accepted connections, plain without `[tls]`.
+#[derive(Debug, Clone, Default)]
+pub struct Acceptor {
+    #[cfg(feature = "tls")]
+    config: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Acceptor {
+    /// The certificate and key are read now, so mistakes show at startup.
+    #[cfg(feature = "tls")]
+    pub fn new(tls: Option<&TlsConfig>) -> Result<Self, String> {
+        let Some(tls) = tls else {
+            return Ok(Acceptor::default());
+        };
+        let open = |path: &str| {
+            std::fs::File::open(store::expand_home(path))
+                .map(io::BufReader::new)
+                .map_err(|e| format!("tls: unable to open {path}: {e}"))
+        };
+        let certs = rustls_pemfile::certs(&mut open(&tls.cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("tls: bad certificate in {}: {e}", tls.cert))?;
+        if certs.is_empty() {
+            return Err(format!("tls: no certificate in {}", tls.cert));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&tls.key)?)
+            .map_err(|e| format!("tls: bad key in {}: {e}", tls.key))?
+            .ok_or(format!("tls: no private key in {}", tls.key))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| format!("tls: {e}"))?;
+        Ok(Acceptor {
+            config: Some(Arc::new(config)),
+        })
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn new(tls: Option<&TlsConfig>) -> Result<Self, String> {
+        match tls {
+            Some(_) => Err(NOT_BUILT.to_string()),
+            None => Ok(Acceptor::default()),
+        }
+    }
+
+    /// The handshake happens on the first read or write.
+    pub fn accept(&self, stream: TcpStream) -> Result<Stream, String> {
+        #[cfg(feature = "tls")]
+        if let Some(config) = &self.config {
+            let connection =
+                rustls::ServerConnection::new(config.clone()).map_err(|e| format!("tls: {e}"))?;
+            let stream = rustls::StreamOwned::new(connection, stream);
+            return Ok(Stream::Tls(Box::new(stream)));
+        }
+        Ok(Stream::Plain(stream))
+    }
+}
+
+/// Why `[tls]` is refused by a build without the feature.
+pub const NOT_BUILT: &str = "tls: built without TLS, rebuild with `--features tls`";
@@ -0,0 +1,97 @@
+//! Access tokens for the network facing APIs, made by
+//! `mac-controls server token` and kept in `tokens.toml`:
+//!
+//! ```toml
+//! [3f9c0a…]
+//! created = 1718000000
+//! ```
+
+use std::fs::File;
+use std::io::Read;
+
+use crate::history::now;
+use crate::store::Store;
+
+const TOKENS_FILE: &str = "tokens.toml";
+/// Random bytes per token, written as hex.
+const TOKEN_BYTES: usize = 16;
+/// WebSocket protocol carrying a token, see `from_protocols`.
+const PROTOCOL_PREFIX: &str = "token.";
+
+/// `mac-controls server token [revoke <token>]`
+pub fn run(args: &[String]) -> Result<String, String> {
+    match args.iter().map(|a| a.as_str()).collect::<Vec<_>>()[..] {
+        ["token"] => {
+            let token = create()?;
+            Ok(format!(
+                "{token}\nPass it as an `Authorization: Bearer` header, or from a browser \
+                 as the WebSocket protocol token.{token}."
+            ))
+        }
+        ["token", "revoke", token] => revoke(token).map(|_| format!("Revoked {token}")),
+        _ => Err("Usage: mac-controls server token [revoke <token>]".to_string()),
+    }
+}
+
+fn create() -> Result<String, String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .map_err(|e| format!("Unable to read random bytes: {e}"))?;
+    let token: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    let mut store = Store::open(TOKENS_FILE);
+    store.set(&token, "created", now());
+    store.save()?;
+    Ok(token)
+}
+
+fn revoke(token: &str) -> Result<(), String> {
+    let mut store = Store::open(TOKENS_FILE);
+    if store.sections.remove(token).is_none() {
+        return Err(format!("No token {token}"));
+    }
+    store.save()
+}
+
+/// Whether a token was made by `server token` and not revoked. Read from
+/// disk each time, so new tokens work without a restart.
+pub fn is_valid(token: &str) -> bool {
+    let store = Store::open(TOKENS_FILE);
+    // Every token is compared, so timing doesn't tell how close a guess was
+    let found = store
+        .sections
+        .keys()
+        .fold(false, |found, t| same(t, token) | found);
+    !token.is_empty() && found
+}
+
+/// Compare tokens in constant time for their length.
+pub fn same(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let diff = a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y));
+    a.len() == b.len() && diff == 0
+}
+
+/// Token of an HTTP request, from its `Authorization: Bearer` header.
+/// Never from the URL, which ends up in logs and browser history.
+pub fn from_authorization(authorization: Option<&str>) -> Option<String> {
+    authorization?
+        .trim()
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+/// Token of a browser WebSocket, which can't set headers but can offer
+/// protocols: `new WebSocket(url, ["token.<token>"])`. The protocol to
+/// answer with comes along.
+pub fn from_protocols(protocols: Option<&str>) -> Option<(String, String)> {
+    protocols?.split(',').map(str::trim).find_map(|protocol| {
+        let token = protocol.strip_prefix(PROTOCOL_PREFIX)?;
+        Some((token.to_string(), protocol.to_string()))
+    })
+}
+
+/// Whether any token was made.
+pub fn any() -> bool {
+    !Store::open(TOKENS_FILE).sections.is_empty()
+}
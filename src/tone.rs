@@ -0,0 +1,134 @@
+//! A short test tone on one output device, to tell which speakers a
+//! cryptically named device is. An IOProc on the device itself plays it,
+//! whatever the default output is.
+
+use std::f64::consts::TAU;
+use std::os::raw::c_void;
+use std::time::{Duration, Instant};
+
+use crate::coreaudio::*;
+
+/// Two beeps, then the IOProc is stopped.
+pub const TONE_TIME: Duration = Duration::from_millis(700);
+/// When each beep starts and stops, in seconds.
+const BEEPS: [(f64, f64); 2] = [(0.0, 0.2), (0.35, 0.55)];
+const FREQUENCY: f64 = 880.0;
+/// Quiet enough not to startle on a loud device, the volume still applies.
+const AMPLITUDE: f64 = 0.25;
+/// Ramp at each end of a beep, so it doesn't click.
+const FADE: f64 = 0.01;
+/// For devices that don't say their sample rate.
+pub const FALLBACK_RATE: f64 = 48000.0;
+
+/// A test tone playing on an output device. Stops when dropped.
+#[derive(Debug)]
+pub struct Tone {
+    device: AudioDeviceID,
+    proc_id: AudioDeviceIOProcID,
+    context: *mut ToneContext,
+    started: Instant,
+}
+
+struct ToneContext {
+    sample_rate: f64,
+    /// Frames played so far.
+    frame: u64,
+}
+
+impl Tone {
+    /// Start the tone on a device running at `sample_rate`.
+    pub fn start(device: AudioDeviceID, sample_rate: f64) -> Result<Self, String> {
+        let context = Box::into_raw(Box::new(ToneContext {
+            sample_rate,
+            frame: 0,
+        }));
+        let mut proc_id: AudioDeviceIOProcID = None;
+        unsafe {
+            if AudioDeviceCreateIOProcID(device, tone_proc, context as *mut c_void, &mut proc_id)
+                != NO_ERR
+            {
+                drop(Box::from_raw(context));
+                return Err("Unable to create output IOProc".to_string());
+            }
+            if AudioDeviceStart(device, proc_id) != NO_ERR {
+                AudioDeviceDestroyIOProcID(device, proc_id);
+                drop(Box::from_raw(context));
+                return Err("Unable to start output IOProc".to_string());
+            }
+        }
+        Ok(Tone {
+            device,
+            proc_id,
+            context,
+            started: Instant::now(),
+        })
+    }
+
+    /// True once the beeps are over.
+    pub fn is_done(&self) -> bool {
+        self.started.elapsed() >= TONE_TIME
+    }
+}
+
+impl Drop for Tone {
+    fn drop(&mut self) {
+        unsafe {
+            // Stop waits for the IOProc to return, like the meter's
+            AudioDeviceStop(self.device, self.proc_id);
+            AudioDeviceDestroyIOProcID(self.device, self.proc_id);
+            drop(Box::from_raw(self.context));
+        }
+    }
+}
+
+/// Level of the tone `seconds` in, between the beeps it's silent.
+fn sample_at(seconds: f64) -> f32 {
+    let envelope = BEEPS
+        .iter()
+        .filter(|(start, end)| (*start..*end).contains(&seconds))
+        .map(|(start, end)| ((seconds - start).min(end - seconds) / FADE).min(1.0))
+        .next()
+        .unwrap_or(0.0);
+    (envelope * AMPLITUDE * (TAU * FREQUENCY * seconds).sin()) as f32
+}
+
+/// Runs on CoreAudio's IO thread, filling every channel of every output
+/// buffer with the same native 32-bit float samples.
+extern "C" fn tone_proc(
+    _device: AudioObjectID,
+    _now: *const AudioTimeStamp,
+    _input: *const AudioBufferList,
+    _input_time: *const AudioTimeStamp,
+    output: *mut AudioBufferList,
+    _output_time: *const AudioTimeStamp,
+    client_data: *mut c_void,
+) -> OSStatus {
+    if output.is_null() || client_data.is_null() {
+        return NO_ERR;
+    }
+    unsafe {
+        let ctx = &mut *(client_data as *mut ToneContext);
+        let list = &mut *output;
+        let buffers = std::slice::from_raw_parts_mut(
+            list.mBuffers.as_mut_ptr(),
+            list.mNumberBuffers as usize,
+        );
+        // Every buffer covers the same frames, one channel or interleaved
+        let mut frames = 0;
+        for buffer in buffers {
+            if buffer.mData.is_null() || buffer.mNumberChannels == 0 {
+                continue;
+            }
+            let channels = buffer.mNumberChannels as usize;
+            let len = buffer.mDataByteSize as usize / std::mem::size_of::<Float32>();
+            let samples = std::slice::from_raw_parts_mut(buffer.mData as *mut Float32, len);
+            for (i, frame) in samples.chunks_mut(channels).enumerate() {
+                let seconds = (ctx.frame + i as u64) as f64 / ctx.sample_rate;
+                frame.fill(sample_at(seconds));
+            }
+            frames = len / channels;
+        }
+        ctx.frame += frames as u64;
+    }
+    NO_ERR
+}
@@ -1,92 +1,1348 @@
-use std::io::{Stdout, Write};
-use termion::raw::RawTerminal;
+use std::io::Write;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::events::UiMode;
-use crate::state::AppState;
+use crate::apps;
+use crate::audio::{self, Channel, Device, SourceKind};
+use crate::config::Theme;
+use crate::events::{Action, TapStatus, UiMode, DISABLED_NOTICE};
+use crate::history::{self, ago};
+use crate::layout;
+use crate::listener::fourcc;
+use crate::log;
+use crate::meter;
+use crate::palette;
+use crate::panes::Pane;
+use crate::profiles;
+use crate::state::{AppState, Push, POINTER_TIME};
+use crate::tasks::Cancel;
 
-pub fn draw(out: &mut RawTerminal<Stdout>, state: &AppState) {
-    let start = termion::cursor::Goto(1, 2);
+/// Shortest time between two frames, draws within it are coalesced.
+pub const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// Screen row the frame starts on.
+const TOP: u16 = 2;
+
+/// Cells of a volume bar, and of the short ones in narrow terminals.
+const BAR_CELLS: usize = 10;
+const NARROW_BAR_CELLS: usize = 5;
+/// Terminal width below which the list uses the short bars.
+const NARROW: usize = 60;
+/// How often the resize watcher checks for a SIGWINCH.
+const RESIZE_CHECK: Duration = Duration::from_millis(50);
+
+const SIGWINCH: c_int = 28;
+
+/// Set by the SIGWINCH handler, which can't do more than that.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+extern "C" fn on_resize(_: c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Send `Action::Resize` whenever the terminal changes size, until
+/// cancelled.
+pub fn watch_resize(cancel: &Cancel, send: impl Fn(Action)) {
+    unsafe { signal(SIGWINCH, on_resize) };
+    while cancel.sleep(RESIZE_CHECK) {
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            send(Action::Resize);
+        }
+    }
+}
+
+/// Keeps the frame that's on screen so a draw only rewrites the lines that
+/// changed, instead of clearing and repainting everything.
+#[derive(Default)]
+pub struct Renderer {
+    lines: Vec<String>,
+    osd: String,
+    size: Option<(u16, u16)>,
+    last: Option<Instant>,
+    pending: bool,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draws the state, or leaves it to `flush` when the last frame is too
+    /// recent, so a burst of actions ends in one frame.
+    pub fn draw(&mut self, out: &mut impl Write, state: &AppState) {
+        if self.last.is_some_and(|last| last.elapsed() < FRAME_TIME) {
+            self.pending = true;
+            return;
+        }
+        self.render(out, state);
+    }
+
+    /// How long until a coalesced frame is due, if one is waiting.
+    pub fn pending(&self) -> Option<Duration> {
+        let elapsed = self.last.map(|last| last.elapsed()).unwrap_or(FRAME_TIME);
+        self.pending.then(|| FRAME_TIME.saturating_sub(elapsed))
+    }
+
+    /// Draws a coalesced frame, if one is waiting.
+    pub fn flush(&mut self, out: &mut impl Write, state: &AppState) {
+        if self.pending {
+            self.render(out, state);
+        }
+    }
+
+    /// Forgets what's on screen, so the next frame repaints everything.
+    pub fn invalidate(&mut self) {
+        self.lines.clear();
+        self.osd.clear();
+    }
+
+    fn render(&mut self, out: &mut impl Write, state: &AppState) {
+        self.pending = false;
+        let started = Instant::now();
+        self.last = Some(started);
+        let clear_line = termion::clear::CurrentLine;
+        let mut screen = String::new();
+        let size = termion::terminal_size().ok();
+        if size != self.size {
+            self.size = size;
+            self.invalidate();
+            screen.push_str(termion::clear::All.as_ref());
+        }
+
+        let (frame, osd) = draw(state);
+        // Lines wider than the terminal would wrap and push the rest down
+        let width = size.map_or(usize::MAX, |(columns, _)| columns as usize);
+        let lines: Vec<String> = frame.split("\r\n").map(|line| fit(line, width)).collect();
+        // A closed or moved overlay leaves its box on the lines under it
+        let osd_lines = (OSD_TOP - TOP) as usize..(OSD_TOP - TOP + OSD_ROWS) as usize;
+        let osd_closed = osd != self.osd && !self.osd.is_empty();
+        let mut under_osd = false;
+        for (i, line) in lines.iter().enumerate() {
+            let covered = osd_lines.contains(&i);
+            if self.lines.get(i) == Some(line) && !(covered && osd_closed) {
+                continue;
+            }
+            under_osd |= covered;
+            screen.push_str(&format!(
+                "{}{clear_line}{line}",
+                termion::cursor::Goto(1, TOP + i as u16)
+            ));
+        }
+        if lines.len() < self.lines.len() {
+            screen.push_str(&format!(
+                "{}{}",
+                termion::cursor::Goto(1, TOP + lines.len() as u16),
+                termion::clear::AfterCursor
+            ));
+        }
+        if osd != self.osd || under_osd {
+            screen.push_str(&osd);
+        }
+        self.lines = lines;
+        self.osd = osd;
+        state.metrics.record("draw", started.elapsed());
+        if screen.is_empty() {
+            return;
+        }
+        // A closed terminal isn't worth crashing over, the app runs on
+        let _ = write!(out, "{screen}");
+        let _ = out.flush();
+    }
+}
+
+/// The frame's lines and the overlay drawn on top of them.
+fn draw(state: &AppState) -> (String, String) {
     let clear_line = termion::clear::CurrentLine;
     let title = match state.mode {
         UiMode::View => "Audio Devices",
         UiMode::EditInput => "Update Input",
         UiMode::EditOutput => "Update Output",
+        UiMode::Search => "Search",
+        UiMode::Detail => "Device Details",
+        UiMode::Palette => "Command Palette",
+        UiMode::MacroName => "Save Macro",
+        UiMode::Volume => "Set Volume",
+        UiMode::Profiles => "Profiles",
+        UiMode::Property => "Device Properties",
+        UiMode::Apps => "App Volumes",
+        UiMode::System => "System",
+        UiMode::Alert => "Alert Sounds",
+        UiMode::Rename => "Rename Device",
     };
-    let list = draw_list(state);
-    let mods = &state.key_modifiers;
-    let keys = &state.keys;
-    write!(
-        out,
-        "{start}{clear_line}{title}\r
--------------\r
-{list}\r-------------\r
-{clear_line}Keys: {mods:?}{keys:?}\r
-"
+    let mut title = title.to_string();
+    if audio::is_read_only() {
+        title.push_str(" (read-only)");
+    }
+    if state.hide_virtual {
+        title.push_str(" - virtual devices hidden");
+    }
+    if let Some(steps) = &state.macro_recording {
+        title.push_str(&format!(" - ● recording macro ({} steps)", steps.len()));
+    }
+    if state.recording.is_some() {
+        title.push_str(" - ● recording output");
+    }
+    let tap = state.tap_health.status();
+    if !state.key_access {
+        title.push_str(" - key events off (missing permissions)");
+    } else if tap != TapStatus::Running {
+        title.push_str(&format!(" - ⚠️  key events {}", tap.describe()));
+    } else if let Some((reason, at)) = state.tap_health.last_disabled() {
+        if at.elapsed() < DISABLED_NOTICE {
+            title.push_str(&format!(
+                " - key events back on (macOS disabled them: {reason})"
+            ));
+        }
+    }
+    if let Some((muted, since)) = state.mute_since {
+        let label = if muted { "🔇 muted" } else { "🎙 live" };
+        let time = history::duration(since.elapsed().as_secs());
+        title.push_str(&format!(" - {label} for {time}"));
+    }
+    if state.is_routing_keys() {
+        if let Some(device) = state.cursor_device() {
+            let channel = match state.routed_channel() {
+                Channel::Input => " input",
+                Channel::Output => "",
+            };
+            title.push_str(&format!(" - volume keys: {}{channel}", device.label()));
+        }
+    }
+    let panes = draw_panes(state);
+    let detail = draw_detail(state);
+    let osd = draw_osd(state);
+    let history = if state.show_history {
+        draw_history(state)
+    } else {
+        String::new()
+    };
+    let doctor = if state.show_doctor {
+        draw_doctor(state)
+    } else {
+        String::new()
+    };
+    let notice = match state.push {
+        Some((Push::Talk, ..)) => draw_push_banner("🎙  LIVE - push to talk"),
+        Some((Push::Mute, ..)) => draw_push_banner("🔇 MUTED - push to mute"),
+        None => state.visible_notice().unwrap_or_default().to_string(),
+    };
+    let footer = draw_footer(state);
+    let frame = format!(
+        "{clear_line}{title}\r
+{clear_line}{notice}\r
+{panes}{detail}{history}{doctor}{clear_line}-------------\r
+{clear_line}{footer}"
+    );
+    (frame, osd)
+}
+
+/// Cuts a line to `width` cells, keeping escape sequences whole. Emoji
+/// count as two cells, which is close enough for the list's marks.
+fn fit(line: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut cells = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            if let Some(bracket) = chars.next_if_eq(&'[') {
+                out.push(bracket);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        let cell = match c as u32 {
+            0x200D | 0xFE00..=0xFE0F => 0,
+            0x2600..=0x27BF | 0x1F000..=0x1FAFF => 2,
+            _ => 1,
+        };
+        if cells + cell > width {
+            out.push_str(termion::style::Reset.as_ref());
+            break;
+        }
+        cells += cell;
+        out.push(c);
+    }
+    out
+}
+
+/// A name cut to `cells` characters, marked with an ellipsis.
+fn truncate(name: &str, cells: usize) -> String {
+    if name.chars().count() <= cells {
+        return name.to_string();
+    }
+    let mut cut: String = name.chars().take(cells.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// First of `total` rows to show in `room`, moved only as far as it takes
+/// to keep the cursor's row in view.
+fn scroll_to(first: usize, cursor: Option<usize>, room: usize, total: usize) -> usize {
+    let first = first.min(total.saturating_sub(room));
+    match cursor {
+        Some(row) if row < first => row,
+        Some(row) if row >= first + room => row + 1 - room,
+        _ => first,
+    }
+}
+
+/// Inverted banner on the notice line while a push key is held, so it's
+/// seen from across the room.
+fn draw_push_banner(label: &str) -> String {
+    format!(
+        "{}{}  {label}  {}",
+        termion::style::Bold,
+        termion::style::Invert,
+        termion::style::Reset
     )
-    .unwrap();
-    out.flush().unwrap();
 }
 
-fn draw_list(state: &AppState) -> String {
+/// Shown panes, each padded or cut to its share of the terminal height.
+fn draw_panes(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let (columns, rows) = termion::terminal_size().unwrap_or((80, 40));
+    let mut out = String::new();
+    for pane in Pane::ALL {
+        if !state.panes.is_shown(pane) {
+            continue;
+        }
+        let focus = match state.panes.focused == pane {
+            true => "▶ ",
+            false => "",
+        };
+        let filter = match pane {
+            Pane::Log => format!(", {} and up ('l' to change)", state.log_level.as_str()),
+            Pane::Keys if state.key_history.is_logging() => {
+                ", also to the history file".to_string()
+            }
+            _ => String::new(),
+        };
+        let height = state.panes.height(pane, rows);
+        let mut scrolled = String::new();
+        let mut lines = match pane {
+            Pane::Mixer => {
+                let (list, cursor) = draw_list(state, columns as usize);
+                let list: Vec<&str> = list.split("\r\n").filter(|l| !l.is_empty()).collect();
+                // The query stays on top while the devices scroll
+                let query = match (state.mode, state.search.is_empty()) {
+                    (UiMode::Search, _) => Some(format!("Search: {}▏", state.search)),
+                    (_, false) => Some(format!("Filter: {}", state.search)),
+                    _ => None,
+                };
+                let room = height.saturating_sub(query.is_some() as usize).max(1);
+                let first = scroll_to(state.list_scroll.get(), cursor, room, list.len());
+                state.list_scroll.set(first);
+                if list.len() > room {
+                    let last = (first + room).min(list.len());
+                    scrolled = format!(", {}-{} of {}", first + 1, last, list.len());
+                }
+                query
+                    .into_iter()
+                    .chain(list.iter().skip(first).take(room).map(|l| l.to_string()))
+                    .collect()
+            }
+            Pane::Keyboard => vec![draw_keys(state)],
+            Pane::Keys => state
+                .key_history
+                .recent()
+                .map(|press| match &press.app {
+                    Some(app) => format!("{}  {:<24} → {app}", press.time, press.chord),
+                    None => format!("{}  {}", press.time, press.chord),
+                })
+                .collect(),
+            Pane::Meters => vec![draw_vu(state), draw_noise(state), draw_gain(state)],
+            Pane::Log => log::tail(state.log_level, state.panes.height(pane, rows))
+                .into_iter()
+                .map(|entry| {
+                    format!(
+                        "{:>7.1}s {:<5} {}",
+                        entry.time.as_secs_f32(),
+                        entry.level.as_str(),
+                        entry.message
+                    )
+                })
+                .collect(),
+        };
+        out.push_str(&format!(
+            "{clear_line}-------------  {focus}{} {}%{filter}{scrolled}\r\n",
+            pane.as_str(),
+            state.panes.size(pane)
+        ));
+        lines.resize(height, String::new());
+        for line in lines {
+            out.push_str(&format!("{clear_line}{line}\r\n"));
+        }
+    }
+    out
+}
+
+/// Held keys and where they come from.
+fn draw_keys(state: &AppState) -> String {
+    let keys: Vec<String> = state
+        .keys
+        .iter()
+        .filter(|k| !layout::is_modifier(**k))
+        .map(|k| state.key_name(*k))
+        .collect();
+    let mut source = match &state.layout {
+        Some(layout) => format!(" ({})", layout.name),
+        None => String::new(),
+    };
+    if let Some(keyboard) = state.last_keyboard {
+        source.push_str(&format!(" [{}]", state.keyboard_name(keyboard)));
+    }
+    if let Some(app) = &state.frontmost_app {
+        source.push_str(&format!(" → {app}"));
+    }
+    let pointer = match &state.pointer {
+        Some((chord, at)) if at.elapsed() < POINTER_TIME => format!(" 🖱 {chord}"),
+        _ => String::new(),
+    };
+    format!(
+        "Keys{source}: {}{pointer}",
+        layout::chord(&state.key_modifiers, &keys)
+    )
+}
+
+/// Underlines the part of a name the search matches, ignoring case.
+fn highlight_match(name: &str, query: &str) -> String {
+    let lower = name.to_lowercase();
+    let query = query.to_lowercase();
+    // Lowercasing can move offsets outside ASCII, those go unmarked
+    let found = match query.is_empty() || lower.len() != name.len() {
+        true => None,
+        false => lower.find(&query),
+    };
+    let Some(start) = found else {
+        return name.to_string();
+    };
+    let end = start + query.len();
+    match (name.get(..start), name.get(start..end), name.get(end..)) {
+        (Some(before), Some(found), Some(after)) => format!(
+            "{before}{}{found}{}{after}",
+            termion::style::Underline,
+            termion::style::NoUnderline
+        ),
+        _ => name.to_string(),
+    }
+}
+
+/// The device list for a terminal `width` wide, and the row of the
+/// highlighted device.
+fn draw_list(state: &AppState, width: usize) -> (String, Option<usize>) {
     let mut list = String::new();
+    let bar_cells = match width < NARROW {
+        true => NARROW_BAR_CELLS,
+        false => BAR_CELLS,
+    };
+    // Names give way before the bars do
+    let name_room = width.saturating_sub(2 * bar_cells + 10).max(6);
     let longest_name_len = state
-        .audio
-        .device_list()
+        .visible_devices()
         .iter()
         .fold(0, |acc, (_, _, _, device)| {
-            if device.name.len() > acc {
-                device.name.len()
+            let len = state.audio.unique_name(device).chars().count();
+            if len > acc {
+                len
             } else {
                 acc
             }
-        });
-    for (active_in, active_out, _muted, device) in state.audio.device_list() {
+        })
+        .min(name_room);
+    let mut rows = 0;
+    let mut cursor = None;
+    let mut section = None;
+    for (active_in, active_out, _muted, device) in state.visible_devices() {
+        if section != Some(device.category) {
+            section = Some(device.category);
+            let collapsed = state.collapsed.contains(&device.category);
+            let fold = if collapsed { "▸" } else { "▾" };
+            let mut header = format!("{fold} {}", device.category.name());
+            if collapsed {
+                let count = state
+                    .audio
+                    .device_list()
+                    .iter()
+                    .filter(|(_, _, _, d)| d.category == device.category)
+                    .count();
+                header.push_str(&format!(" ({count})"));
+            }
+            if collapsed && state.cursor.as_ref() == Some(&device.uid) {
+                cursor = Some(rows);
+                header = format!(
+                    "{}{}{}",
+                    termion::style::Invert,
+                    header,
+                    termion::style::Reset
+                );
+            }
+            list.push_str(&format!("{}{}\r\n", termion::clear::CurrentLine, header));
+            rows += 1;
+            if collapsed {
+                continue;
+            }
+        }
+        let theme = &state.theme;
         let mark = match (active_in, active_out) {
-            (true, true) => "↔️  ",
-            (true, false) => "🎤 ",
-            (false, true) => "🔊 ",
-            (false, false) => "   ",
+            (true, true) => &theme.active_both,
+            (true, false) => &theme.active_input,
+            (false, true) => &theme.active_output,
+            (false, false) => &theme.inactive,
         };
         let levels_in = {
             if let Some((vol, mute)) = state.audio.input(&device.id) {
-                draw_level(Some(vol), mute)
+                draw_bar(theme, Some(vol), mute, bar_cells)
             } else {
-                draw_level(None, false)
+                draw_bar(theme, None, false, bar_cells)
             }
         };
+        let levels_in = draw_locked(levels_in, state.audio.is_locked(device, Channel::Input));
+        let levels_in = levels_in
+            + draw_pending(state, device, Channel::Input)
+            + &draw_db(state, device, Channel::Input);
         let levels_out = {
             if let Some((vol, mute)) = state.audio.output(&device.id) {
-                draw_level(Some(vol), mute)
+                draw_bar(theme, Some(vol), mute, bar_cells)
             } else {
-                draw_level(None, false)
+                draw_bar(theme, None, false, bar_cells)
             }
         };
-        let spaces = " ".repeat(longest_name_len - device.name.len());
+        let levels_out = draw_locked(levels_out, state.audio.is_locked(device, Channel::Output));
+        let levels_out = levels_out
+            + draw_pending(state, device, Channel::Output)
+            + &draw_db(state, device, Channel::Output);
+        let marked = if state.audio.is_marked(device) {
+            theme.marked.clone()
+        } else {
+            " ".repeat(theme.marked.chars().count())
+        };
+        let name = truncate(&state.audio.unique_name(device), name_room);
+        let spaces = " ".repeat(longest_name_len.saturating_sub(name.chars().count()));
+        let shown = highlight_match(&name, &state.search);
+        if state.cursor.as_ref() == Some(&device.uid) {
+            cursor = Some(rows);
+        }
+        let label = if state.cursor.as_ref() == Some(&device.uid) {
+            format!("{}{shown}{}", termion::style::Invert, termion::style::Reset)
+        } else {
+            shown
+        };
+        let mut group = match device.sample_rate.get() {
+            Some(rate) => format!(" {}", audio::describe_rate(rate)),
+            None => String::new(),
+        };
+        if let Some(battery) = state.batteries.get(&device.uid) {
+            group.push_str(&format!(" 🔋 {}", battery.describe()));
+        }
+        if let Some(name) = state.audio.group_of(device) {
+            group.push_str(&format!(" 🔗 {name}"));
+        }
+        // Only while editing, where 'S' switches it
+        let editing = match state.mode {
+            UiMode::EditInput => Some(Channel::Input),
+            UiMode::EditOutput => Some(Channel::Output),
+            _ => None,
+        };
+        if let Some(channel) = editing {
+            let sources = device.sources.borrow();
+            let list = sources.get(SourceKind::Data(channel));
+            if let (true, Some(name)) = (list.available.len() > 1, list.active_name()) {
+                group.push_str(&format!(" ⇄ {name}"));
+            }
+        }
+        // Alerts usually follow the default output, only worth a mark when
+        // they don't
+        let alerts_here = state.audio.alert_device().map(|d| d.id) == Some(device.id);
+        if alerts_here && (!active_out || state.mode == UiMode::Alert) {
+            group.push_str(" 🔔 alerts");
+        }
+        if active_in {
+            if let Some(bar) = draw_vu_bar(state) {
+                group.push_str(&format!(" 🎙 {bar}"));
+            }
+            if state.auto_gain.as_ref().is_some_and(|a| a.is_adjusting()) {
+                group.push_str(" ↕ auto-gain");
+            }
+        }
+        if let Some(pid) = device.hog_owner.get() {
+            group.push_str(&format!(" ⛔ {}", draw_hog_owner(pid)));
+        }
+        if state.audio.is_locked(device, Channel::Input)
+            || state.audio.is_locked(device, Channel::Output)
+        {
+            group.push_str(" 🔒 volume locked by driver");
+        }
+        if active_out {
+            if let Some(silent) = state.audio.silent_state(device, Channel::Output) {
+                group.push_str(&format!(" ⚠️  {} ('u' to fix)", silent.describe()));
+            }
+            if let Some((input, _)) = state.audio.rate_mismatch() {
+                group.push_str(&format!(
+                    " ⚠️  input runs at {} ('n' to align)",
+                    audio::describe_rate(input)
+                ));
+            }
+        }
         let item = format!(
-            "{}{} {}{} : {} | {}\r\n",
+            "{}{}{}{}{} : {} | {}{}\r\n",
             termion::clear::CurrentLine,
             mark,
-            device.name,
+            marked,
+            label,
             spaces,
             levels_in,
-            levels_out
+            levels_out,
+            group
         );
         list.push_str(&item);
+        rows += 1;
+    }
+    (list, cursor)
+}
+
+/// Grayed out volume bar, for volumes the driver won't let us change.
+fn draw_locked(bar: String, locked: bool) -> String {
+    match locked {
+        true => format!("{}{bar}{}", termion::style::Faint, termion::style::Reset),
+        false => bar,
+    }
+}
+
+/// Marks a volume change the device refused and that's being retried.
+fn draw_pending(state: &AppState, device: &Device, channel: Channel) -> &'static str {
+    match state.audio.is_retrying(device, channel) {
+        true => "⏳",
+        false => "",
     }
-    list
 }
 
-fn draw_level(volume: Option<f32>, muted: bool) -> String {
+fn locked_note(state: &AppState, device: &audio::Device, channel: Channel) -> &'static str {
+    match state.audio.is_locked(device, channel) {
+        true => " (locked by driver)",
+        false => "",
+    }
+}
+
+fn draw_level(theme: &Theme, volume: Option<f32>, muted: bool) -> String {
+    draw_bar(theme, volume, muted, BAR_CELLS)
+}
+
+fn draw_bar(theme: &Theme, volume: Option<f32>, muted: bool, cells: usize) -> String {
     match volume {
         Some(vol) => {
             if vol == 0.0 || muted {
-                return "░".repeat(10);
+                return theme.bar_muted.repeat(cells);
             }
-            let steps = (vol * 10.0) as usize;
-            let amount = "▓".repeat(steps);
-            let fill = "▒".repeat(10 - steps);
+            let steps = ((vol * cells as f32) as usize).min(cells);
+            let amount = theme.bar_full.repeat(steps);
+            let fill = theme.bar_empty.repeat(cells - steps);
             format!("{}{}", amount, fill)
         }
-        None => "·".repeat(10),
+        None => theme.bar_none.repeat(cells),
+    }
+}
+
+/// The device's volume in dB after its bar, only in dB mode since every
+/// row queries the device.
+fn draw_db(state: &AppState, device: &Device, channel: Channel) -> String {
+    if !state.db_steps {
+        return String::new();
+    }
+    match audio::volume_db(&device.id, channel) {
+        Some(db) => format!(" {db:>6.1} dB"),
+        None => " ".repeat(10),
+    }
+}
+
+/// Volume in dB for the detail views, when the device reports it.
+fn db_note(device: &Device, channel: Channel) -> String {
+    match audio::volume_db(&device.id, channel) {
+        Some(db) => format!(" ({db:.1} dB)"),
+        None => String::new(),
+    }
+}
+
+/// Lowest level on the live meter, in dBFS.
+const VU_FLOOR_DB: f32 = -60.0;
+
+/// Live mic level bar, with the peak marked, or None before the meter
+/// reports anything (no microphone permission, no input).
+fn draw_vu_bar(state: &AppState) -> Option<String> {
+    if !state.meter.has_levels() {
+        return None;
+    }
+    let cells = |amplitude: f32| {
+        let db = meter::to_db(amplitude).max(VU_FLOOR_DB);
+        ((1.0 - db / VU_FLOOR_DB) * 10.0).round() as usize
+    };
+    let (rms, peak) = (cells(state.meter.rms), cells(state.meter.peak));
+    let theme = &state.theme;
+    let bar = (0..10).map(|cell| match cell {
+        _ if cell < rms => theme.bar_full.as_str(),
+        _ if cell + 1 == peak => "▏",
+        _ => theme.bar_empty.as_str(),
+    });
+    Some(bar.collect())
+}
+
+fn draw_vu(state: &AppState) -> String {
+    match draw_vu_bar(state) {
+        Some(bar) => format!(
+            "Mic level: {bar} {:.0} dBFS (peak {:.0})",
+            meter::to_db(state.meter.rms).max(VU_FLOOR_DB),
+            meter::to_db(state.meter.peak).max(VU_FLOOR_DB)
+        ),
+        None => "Mic level: no signal (needs the Microphone permission)".to_string(),
+    }
+}
+
+fn draw_noise(state: &AppState) -> String {
+    match state.meter.noise_floor() {
+        Some(db) if state.meter.is_noisy() => {
+            format!("⚠️  Mic noise floor: {:.0} dBFS (noisy background)", db)
+        }
+        Some(db) => format!("Mic noise floor: {:.0} dBFS", db),
+        None => "Mic noise floor: measuring...".to_string(),
+    }
+}
+
+fn draw_gain(state: &AppState) -> String {
+    if state.mode != UiMode::EditInput {
+        return String::new();
+    }
+    if let Some(calibration) = &state.calibration {
+        let secs = calibration.remaining().as_secs() + 1;
+        return format!("Calibrating, speak normally... {secs}s");
+    }
+    match &state.gain_suggestion {
+        Some((_, level)) => format!(
+            "Suggested input gain: {:.0}% ('a' to apply, 'c' to recalibrate)",
+            level * 100.0
+        ),
+        None => "Press 'c' to calibrate input gain".to_string(),
+    }
+}
+
+/// Archive of every device seen, with connection counts.
+fn draw_history(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let mut out = format!("{clear_line}-------------\r\n{clear_line}Device History\r\n");
+    let attached: Vec<&str> = state
+        .audio
+        .device_list()
+        .into_iter()
+        .map(|(_, _, _, d)| d.uid.as_str())
+        .collect();
+    for entry in state.audio.history().entries() {
+        let seen = if attached.contains(&entry.uid.as_str()) {
+            "connected".to_string()
+        } else {
+            format!("last seen {}", ago(entry.last_seen))
+        };
+        out.push_str(&format!(
+            "{clear_line}  {} - {seen}, first seen {}, {} connects, {} disconnects\r\n",
+            entry.name,
+            ago(entry.first_seen),
+            entry.connects,
+            entry.disconnects
+        ));
+    }
+    out
+}
+
+/// Diagnostics: CoreAudio notification counts per device and property.
+fn draw_doctor(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let mut out = format!("{clear_line}-------------\r\n{clear_line}Doctor\r\n");
+    let health = &state.tap_health;
+    out.push_str(&format!(
+        "{clear_line}Event tap: {}, {} ({} restarts, {} re-enables)\r\n",
+        health.status().describe(),
+        match health.active.load(Ordering::Relaxed) {
+            true => "active",
+            false => "passive",
+        },
+        health.restarts.load(Ordering::Relaxed),
+        health.reenables.load(Ordering::Relaxed)
+    ));
+    if let Some((reason, at)) = health.last_disabled() {
+        out.push_str(&format!(
+            "{clear_line}Last disabled: {reason}, {} ago\r\n",
+            history::duration(at.elapsed().as_secs())
+        ));
+    }
+    out.push_str(&format!(
+        "{clear_line}Permissions: listen {}, post {}\r\n",
+        state.permissions.listen.describe(),
+        state.permissions.post.describe()
+    ));
+    if !state.config_errors.is_empty() {
+        out.push_str(&format!("{clear_line}Config problems:\r\n"));
+        for error in &state.config_errors {
+            out.push_str(&format!("{clear_line}  {error}\r\n"));
+        }
+    }
+    out.push_str(&format!(
+        "{clear_line}Listener events (received / forwarded after debounce):\r\n"
+    ));
+    let stats = state.listener_stats.lock().unwrap();
+    for ((id, selector), counter) in stats.iter() {
+        out.push_str(&format!(
+            "{clear_line}  {} '{}': {} / {}\r\n",
+            object_name(state, *id),
+            fourcc(*selector),
+            counter.received,
+            counter.forwarded
+        ));
+    }
+    if let Some((id, selector)) = state.last_change {
+        out.push_str(&format!(
+            "{clear_line}Last change: {} '{}'\r\n",
+            object_name(state, id),
+            fourcc(selector)
+        ));
+    }
+    let timings = state.metrics.timings();
+    if !timings.is_empty() {
+        out.push_str(&format!("{clear_line}Cost (average / max):\r\n"));
+    }
+    for (name, timing) in timings {
+        out.push_str(&format!(
+            "{clear_line}  {name}: {:.1} / {:.1} ms over {}\r\n",
+            timing.average.as_secs_f64() * 1000.0,
+            timing.max.as_secs_f64() * 1000.0,
+            timing.samples
+        ));
+    }
+    if let Some(queries) = state.metrics.queries() {
+        out.push_str(&format!(
+            "{clear_line}  CoreAudio queries per poll: {} / {}\r\n",
+            queries.average, queries.max
+        ));
+    }
+    out
+}
+
+fn object_name(state: &AppState, id: u32) -> String {
+    state
+        .audio
+        .device_list()
+        .into_iter()
+        .find(|(_, _, _, d)| d.id == id)
+        .map(|(_, _, _, d)| d.label().to_string())
+        .unwrap_or_else(|| format!("object {id}"))
+}
+
+/// Active mode and its keys.
+fn draw_footer(state: &AppState) -> String {
+    let keys: Vec<String> = state
+        .keymap
+        .bindings(state.mode)
+        .into_iter()
+        .map(|(key, action)| format!("{key} {action}"))
+        .collect();
+    format!("[{}] {}", state.mode.as_str(), keys.join(" · "))
+}
+
+/// Details for the active device of the channel being edited.
+fn draw_detail(state: &AppState) -> String {
+    let channel = match state.mode {
+        UiMode::EditInput => Channel::Input,
+        UiMode::EditOutput => Channel::Output,
+        UiMode::Volume => state.volume_channel,
+        UiMode::Detail => return draw_device(state),
+        UiMode::Palette => return draw_palette(state),
+        UiMode::Profiles => return draw_profiles(state),
+        UiMode::Property => return draw_property(state),
+        UiMode::Apps => return draw_apps(state),
+        UiMode::System => return draw_system(state),
+        UiMode::Alert => return draw_alert(state),
+        UiMode::MacroName => {
+            let clear_line = termion::clear::CurrentLine;
+            return format!(
+                "{clear_line}-------------\r\n{clear_line}Macro name: {}▏\r\n",
+                state.macro_name
+            );
+        }
+        UiMode::Rename => {
+            let clear_line = termion::clear::CurrentLine;
+            let name = state
+                .cursor_device()
+                .map(|d| d.name.as_str())
+                .unwrap_or_default();
+            return format!(
+                "{clear_line}-------------\r\n{clear_line}Name for {name}: {}▏\r\n{clear_line}(empty for its own name)\r\n",
+                state.rename_entry
+            );
+        }
+        UiMode::View | UiMode::Search => return String::new(),
+    };
+    let device = match state.audio.active_device(channel) {
+        Some(device) => device,
+        None => return String::new(),
+    };
+    let clear_line = termion::clear::CurrentLine;
+    format!(
+        "{clear_line}-------------\r
+{clear_line}{}\r
+{clear_line}Name: {}\r
+{clear_line}UID: {}\r
+{clear_line}Mute: {}\r
+{clear_line}Volume: {}{}{}\r
+{clear_line}Mute takeover: {} ('p' to change)\r
+{clear_line}{}\r
+",
+        device.label(),
+        device.name,
+        device.uid,
+        state.audio.mute_state(device, channel).describe(),
+        draw_sparkline(state, &device.uid, channel),
+        db_note(device, channel),
+        locked_note(state, device, channel),
+        state.audio.mute_policy(device).as_str(),
+        match state.mode {
+            UiMode::Volume => format!("Set volume to: {}▏%", state.volume_entry),
+            _ => draw_marked(state),
+        }
+    )
+}
+
+/// Recent volumes of a device channel, to spot apps changing them.
+fn draw_sparkline(state: &AppState, uid: &str, channel: Channel) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    match state.volume_history(uid, channel) {
+        Some(samples) => samples
+            .iter()
+            .map(|level| BARS[((level * 7.0).round() as usize).min(7)])
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Query and best matches of the command palette.
+fn draw_palette(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let mut out = format!(
+        "{clear_line}-------------\r\n{clear_line}:{}▏\r\n",
+        state.palette.query
+    );
+    for (i, (label, _)) in palette::matches(state)
+        .iter()
+        .take(palette::SHOWN)
+        .enumerate()
+    {
+        if i == state.palette.selected {
+            let (invert, reset) = (termion::style::Invert, termion::style::Reset);
+            out.push_str(&format!("{clear_line}  {invert}{label}{reset}\r\n"));
+        } else {
+            out.push_str(&format!("{clear_line}  {label}\r\n"));
+        }
+    }
+    out
+}
+
+/// Saved profiles matching the query, which also names a new one, and what
+/// applying the selected one would change.
+fn draw_profiles(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let mut out = format!(
+        "{clear_line}-------------\r\n{clear_line}Profile: {}▏\r\n",
+        state.profiles.query
+    );
+    let matches = profiles::matches(&state.profiles.query);
+    if matches.is_empty() {
+        out.push_str(&format!(
+            "{clear_line}  No saved profiles, type a name and press tab to save one\r\n"
+        ));
+    }
+    for (i, name) in matches.iter().take(palette::SHOWN).enumerate() {
+        if i == state.profiles.selected {
+            let (invert, reset) = (termion::style::Invert, termion::style::Reset);
+            out.push_str(&format!("{clear_line}  {invert}{name}{reset}\r\n"));
+        } else {
+            out.push_str(&format!("{clear_line}  {name}\r\n"));
+        }
+    }
+    if let Some(name) = matches.get(state.profiles.selected) {
+        let changes = match profiles::diff(name, &state.audio) {
+            Ok(changes) if changes.is_empty() => vec!["Matches the current setup".to_string()],
+            Ok(changes) => changes,
+            Err(e) => vec![e],
+        };
+        out.push_str(&format!("{clear_line}\r\n"));
+        for change in changes {
+            out.push_str(&format!("{clear_line}  {change}\r\n"));
+        }
+    }
+    out
+}
+
+/// Apps with audio and their volumes, playing ones marked.
+fn draw_apps(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let mut out = format!("{clear_line}-------------\r\n");
+    let apps = match &state.apps {
+        Ok(apps) => apps,
+        Err(e) => {
+            out.push_str(&format!("{clear_line}  {e}\r\n"));
+            return out;
+        }
+    };
+    if apps.is_empty() {
+        out.push_str(&format!("{clear_line}  No apps with audio\r\n"));
+    }
+    for (i, app) in apps.iter().enumerate() {
+        let (level, muted) = state.app_volumes.get(app.pid);
+        let playing = if app.playing { "▶" } else { " " };
+        let name = format!("{playing} {:<24}", app.name);
+        let name = match i == state.app_cursor {
+            true => format!("{}{name}{}", termion::style::Invert, termion::style::Reset),
+            false => name,
+        };
+        let level = draw_level(&state.theme, Some(level), muted);
+        out.push_str(&format!("{clear_line}  {name} {level}\r\n"));
+    }
+    out
+}
+
+/// Keyboard backlight and the other system controls.
+fn draw_system(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let backlight = match &state.backlight {
+        Ok(level) => draw_level(&state.theme, Some(*level), false),
+        Err(e) => e.clone(),
+    };
+    let mic_mode = match &state.mic_mode {
+        Ok((preferred, active)) if preferred == active => preferred.label().to_string(),
+        Ok((preferred, active)) => format!("{} ({} right now)", preferred.label(), active.label()),
+        Err(e) => e.clone(),
+    };
+    format!(
+        "{clear_line}-------------\r
+{clear_line}Keyboard backlight: {backlight}\r
+{clear_line}Display sleep: 's', the Mac keeps running\r
+{clear_line}Lock screen: 'l'\r
+{clear_line}Mic mode: {mic_mode}, 'm' to change\r
+"
+    )
+}
+
+/// Where alerts play and how loud, in alert mode.
+fn draw_alert(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let device = match state.audio.alert_device() {
+        Some(device) if Some(device.id) == state.audio.active_id(Channel::Output) => {
+            format!("{} (the default output)", device.label())
+        }
+        Some(device) => device.label().to_string(),
+        None => "unknown".to_string(),
+    };
+    let volume = draw_level(&state.theme, Some(state.alert_volume), false);
+    format!(
+        "{clear_line}-------------\r
+{clear_line}Alerts play on: {device}, 'enter' moves them to the highlighted device\r
+{clear_line}Alert volume: {volume} (of the device's volume)\r
+"
+    )
+}
+
+/// Query and result of the property screen, for the highlighted device.
+fn draw_property(state: &AppState) -> String {
+    let clear_line = termion::clear::CurrentLine;
+    let device = state
+        .cursor_device()
+        .map_or("no device".to_string(), |d| d.label().to_string());
+    let result = match &state.property_result {
+        Some(Ok(text)) => text.clone(),
+        Some(Err(e)) => format!("⚠️  {e}"),
+        None => "e.g. 'vmvc output 1 f32', 'lnam string' or 'nsrt f64 = 48000'".to_string(),
+    };
+    format!(
+        "{clear_line}-------------\r\n{clear_line}{device}\r\n{clear_line}Property: {}▏\r\n{clear_line}{result}\r\n",
+        state.property_query
+    )
+}
+
+/// Everything about the highlighted device, in detail mode.
+fn draw_device(state: &AppState) -> String {
+    let device = match state.cursor_device() {
+        Some(device) => device,
+        None => return String::new(),
+    };
+    let clear_line = termion::clear::CurrentLine;
+    let level = |level: Option<(f32, bool)>| match level {
+        Some((volume, muted)) => format!(
+            "{:.0}%{}",
+            volume * 100.0,
+            if muted { " (muted)" } else { "" }
+        ),
+        None => "none".to_string(),
+    };
+    format!(
+        "{clear_line}-------------\r
+{clear_line}{}\r
+{clear_line}Name: {}\r
+{clear_line}UID: {}\r
+{clear_line}Made by: {}\r
+{clear_line}Input: {}{}{} {}\r
+{clear_line}Output: {}{}{} {}\r
+{clear_line}Transport: {}\r
+{clear_line}Battery: {}\r
+{clear_line}Sample rate: {}\r
+{clear_line}Balance: {}\r
+{clear_line}Stereo channels: {}\r
+{clear_line}Sources: {}\r
+{clear_line}Mute takeover: {}\r
+{clear_line}Group: {}\r
+{clear_line}Mirrors: {}\r
+{clear_line}Exclusive access: {}\r
+{clear_line}Marked: {}\r
+",
+        state.audio.unique_name(device),
+        device.name,
+        device.uid,
+        draw_maker(device),
+        level(state.audio.input(&device.id)),
+        db_note(device, Channel::Input),
+        locked_note(state, device, Channel::Input),
+        draw_sparkline(state, &device.uid, Channel::Input),
+        level(state.audio.output(&device.id)),
+        db_note(device, Channel::Output),
+        locked_note(state, device, Channel::Output),
+        draw_sparkline(state, &device.uid, Channel::Output),
+        device.category.name(),
+        state
+            .batteries
+            .get(&device.uid)
+            .map_or("none".to_string(), |battery| battery.describe()),
+        draw_format(device),
+        match device.stereo.get() {
+            Some(levels) => format!(
+                "{} (left {:.0}%, right {:.0}%)",
+                audio::describe_balance(audio::balance_of(levels)),
+                levels.0 * 100.0,
+                levels.1 * 100.0
+            ),
+            None => "none".to_string(),
+        },
+        draw_stereo_channels(device),
+        draw_sources(device),
+        state.audio.mute_policy(device).as_str(),
+        state.audio.group_of(device).unwrap_or("none"),
+        state
+            .audio
+            .mirror_of(device)
+            .unwrap_or("none ('m' to mirror the default output)".to_string()),
+        match device.hog_owner.get() {
+            Some(pid) => draw_hog_owner(pid),
+            None => "none, shared by every app ('x' to take it)".to_string(),
+        },
+        if state.audio.is_marked(device) {
+            "yes"
+        } else {
+            "no"
+        },
+    ) + &draw_members(state, device)
+}
+
+/// Who has a device to itself, like `hogged by Audirvana (5120)`.
+fn draw_hog_owner(pid: i32) -> String {
+    if pid == std::process::id() as i32 {
+        return "hogged by this app".to_string();
+    }
+    match apps::process_name(pid) {
+        Some(name) => format!("hogged by {name} ({pid})"),
+        None => format!("hogged by process {pid}"),
+    }
+}
+
+/// Manufacturer and model, like `Focusrite, Scarlett 2i2 USB`.
+fn draw_maker(device: &audio::Device) -> String {
+    match (&device.manufacturer, &device.model) {
+        (Some(maker), Some(model)) => format!("{maker}, {model}"),
+        (Some(known), None) | (None, Some(known)) => known.clone(),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Output channels stereo plays on, like `1 and 2 of 8`.
+fn draw_stereo_channels(device: &audio::Device) -> String {
+    match audio::stereo_channels(&device.id) {
+        Some((left, right)) => format!(
+            "{left} and {right} of {} ('[' ']' to change, 'c' for the next pair)",
+            audio::output_channel_count(&device.id)
+        ),
+        None => "none".to_string(),
+    }
+}
+
+/// Active data and clock sources, with how many there are to pick from,
+/// like `output Headphones (of 2), clock Internal`.
+fn draw_sources(device: &audio::Device) -> String {
+    let sources = device.sources.borrow();
+    let kinds = [
+        ("input", SourceKind::Data(Channel::Input)),
+        ("output", SourceKind::Data(Channel::Output)),
+        ("clock", SourceKind::Clock),
+    ];
+    let active: Vec<String> = kinds
+        .iter()
+        .filter_map(|(name, kind)| {
+            let list = sources.get(*kind);
+            let source = list.active_name()?;
+            Some(match list.available.len() {
+                1 => format!("{name} {source}"),
+                len => format!("{name} {source} (of {len})"),
+            })
+        })
+        .collect();
+    match active.is_empty() {
+        true => "none".to_string(),
+        false => active.join(", "),
+    }
+}
+
+/// Sample rate with the other rates and the bit depth per channel, like
+/// `48 kHz, 24-bit out (44.1, 48, 96 kHz available)`.
+fn draw_format(device: &audio::Device) -> String {
+    let Some(rate) = device.sample_rate.get() else {
+        return "none".to_string();
+    };
+    let mut format = audio::describe_rate(rate);
+    let depths = [(Channel::Input, "in"), (Channel::Output, "out")]
+        .iter()
+        .filter(|(channel, _)| device.volume(*channel).borrow().enabled)
+        .filter_map(|(channel, name)| Some((audio::bit_depth(&device.id, *channel)?, name)))
+        .map(|(bits, name)| format!("{bits}-bit {name}"))
+        .collect::<Vec<_>>();
+    if !depths.is_empty() {
+        format.push_str(&format!(", {}", depths.join(", ")));
+    }
+    let rates = audio::sample_rates(&device.id);
+    if rates.len() > 1 {
+        let rates: Vec<String> = rates
+            .iter()
+            .map(|rate| {
+                audio::describe_rate(*rate)
+                    .trim_end_matches(" kHz")
+                    .to_string()
+            })
+            .collect();
+        format.push_str(&format!(" ({} kHz available)", rates.join(", ")));
+    }
+    format
+}
+
+/// Members of an aggregate device, and the devices that could be added.
+fn draw_members(state: &AppState, device: &audio::Device) -> String {
+    let members = state.audio.members(device);
+    if members.is_empty() {
+        return String::new();
+    }
+    let clear_line = termion::clear::CurrentLine;
+    let mut text = format!(
+        "{clear_line}Sub-devices (space adds or removes, 'f' toggles drift correction):\r\n"
+    );
+    for (i, member) in members.iter().enumerate() {
+        let cursor = if i == state.member_cursor.min(members.len() - 1) {
+            "▶"
+        } else {
+            " "
+        };
+        let check = if member.is_member { "[x]" } else { "[ ]" };
+        let drift = match member.drift_correction {
+            Some(true) => " (drift correction)",
+            _ => "",
+        };
+        text.push_str(&format!(
+            "{clear_line}{cursor} {check} {}{drift}\r\n",
+            member.label
+        ));
+    }
+    text
+}
+
+fn draw_marked(state: &AppState) -> String {
+    match state.audio.marked_count() {
+        0 => "Space to mark devices for bulk changes".to_string(),
+        n => format!("{n} marked: arrows, '/' and 'x' (hide) apply to all"),
+    }
+}
+
+/// 3x5 block glyphs for the OSD's large print.
+fn big_glyph(c: char) -> [&'static str; 5] {
+    match c {
+        '0' => ["███", "█ █", "█ █", "█ █", "███"],
+        '1' => [" █ ", "██ ", " █ ", " █ ", "███"],
+        '2' => ["███", "  █", "███", "█  ", "███"],
+        '3' => ["███", "  █", "███", "  █", "███"],
+        '4' => ["█ █", "█ █", "███", "  █", "  █"],
+        '5' => ["███", "█  ", "███", "  █", "███"],
+        '6' => ["███", "█  ", "███", "█ █", "███"],
+        '7' => ["███", "  █", "  █", "  █", "  █"],
+        '8' => ["███", "█ █", "███", "█ █", "███"],
+        '9' => ["███", "█ █", "███", "  █", "███"],
+        '%' => ["█ █", "  █", " █ ", "█  ", "█ █"],
+        _ => ["   ", "   ", "   ", "   ", "   "],
+    }
+}
+
+/// Boxed volume overlay drawn over the top of the list.
+/// Screen rows taken by the overlay: a border, its 7 lines and a border.
+const OSD_TOP: u16 = 4;
+const OSD_ROWS: u16 = 9;
+
+fn draw_osd(state: &AppState) -> String {
+    let osd = match state.visible_osd() {
+        Some(osd) => osd,
+        None => return String::new(),
+    };
+    let channel = match osd.channel {
+        Channel::Input => "Input",
+        Channel::Output => "Output",
+    };
+    let label = if osd.muted {
+        format!("{channel}: {} (muted)", osd.name)
+    } else {
+        format!("{channel}: {}", osd.name)
+    };
+    let pct = format!("{:.0}%", osd.level * 100.0);
+    let width = label.chars().count().max(pct.len() * 4).max(10) + 2;
+    let mut lines = vec![format!(" {label}")];
+    for row in 0..5 {
+        let big: Vec<&str> = pct.chars().map(|c| big_glyph(c)[row]).collect();
+        lines.push(format!(" {}", big.join(" ")));
+    }
+    lines.push(format!(
+        " {}",
+        draw_level(&state.theme, Some(osd.level), osd.muted)
+    ));
+
+    let (x, y) = (4, OSD_TOP);
+    let mut out = format!("{}┌{}┐", termion::cursor::Goto(x, y), "─".repeat(width));
+    for (i, line) in lines.iter().enumerate() {
+        let pad = width.saturating_sub(line.chars().count());
+        out.push_str(&format!(
+            "{}│{line}{}│",
+            termion::cursor::Goto(x, y + 1 + i as u16),
+            " ".repeat(pad)
+        ));
     }
+    out.push_str(&format!(
+        "{}└{}┘",
+        termion::cursor::Goto(x, y + 1 + lines.len() as u16),
+        "─".repeat(width)
+    ));
+    out
 }
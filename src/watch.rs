@@ -0,0 +1,51 @@
+//! `mac-controls watch`: one JSON line on stdout per change, for status
+//! bars and scripts. The lines are the `[events]` events, like
+//!
+//! ```text
+//! {"type":"event","event":"volume","uid":"BuiltInSpeakerDevice","device":"MacBook Pro Speakers","channel":"output","level":40,"text":"MacBook Pro Speakers volume 40%"}
+//! ```
+//!
+//! Runs the CoreAudio listeners without the TUI or the event tap, so it
+//! needs no permissions. Nothing is changed, not even mute takeovers, so
+//! it can run next to the app.
+
+use std::io::{self, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+
+use crate::audio::{self, AudioState};
+use crate::config::Config;
+use crate::listener::{ListenerStats, Listeners};
+use crate::sinks::Changes;
+
+/// Print changes until stdout is closed.
+pub fn run() {
+    audio::set_read_only(true);
+    let config = Config::load();
+    let poll_interval = config.poll_interval();
+    let mut audio = AudioState::new(&config);
+    let (tx, rx) = mpsc::channel();
+    let mut listeners = Listeners::new(ListenerStats::default(), move |action| {
+        let _ = tx.send(action);
+    });
+    let mut changes = Changes::default();
+    changes.check(&audio);
+    let mut stdout = io::stdout().lock();
+    loop {
+        listeners.watch(&audio.device_ids());
+        // A notification checks right away, the poll catches the changes
+        // listeners don't cover
+        match rx.recv_timeout(poll_interval) {
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        while rx.try_recv().is_ok() {}
+        audio.update();
+        for event in changes.check(&audio) {
+            let written = writeln!(stdout, "{}", event.to_json()).and_then(|()| stdout.flush());
+            // The reader went away, like a status bar restarting
+            if written.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! First-run setup: a few questions about the connected devices and keys,
+//! answered before the TUI starts. Writes `config.toml` (even when skipped,
+//! so it's only asked once) and device settings.
+
+use std::io::{Stdin, Stdout, Write};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::RawTerminal;
+
+use crate::audio::{AudioState, Channel};
+use crate::config::{Config, CONFIG_FILE};
+use crate::log;
+use crate::registry::Registry;
+use crate::store::{self, Store};
+
+/// Priority given to the preferred output, so it's listed first.
+const PREFERRED_PRIORITY: i32 = 10;
+/// Volume step choices, as (label, step).
+const STEPS: &[(&str, f32)] = &[
+    ("5%", 0.05),
+    ("6.25%, like the macOS volume keys", 1.0 / 16.0),
+    ("10%", 0.1),
+];
+
+pub fn is_needed() -> bool {
+    !store::data_dir().join(CONFIG_FILE).exists()
+}
+
+pub fn run(out: &mut RawTerminal<Stdout>, stdin: &Stdin) -> Result<(), String> {
+    let mut config = Store::open(CONFIG_FILE);
+    let answers = ask(out, stdin);
+    if let Some((hidden, preferred, step, caps_lock_mute)) = answers {
+        let mut registry = Registry::load();
+        for uid in &hidden {
+            registry.set_hidden(uid, true)?;
+        }
+        if let Some(uid) = &preferred {
+            registry.set_priority(uid, PREFERRED_PRIORITY)?;
+        }
+        config.set("keys", "volume_step", step);
+        config.set("keys", "caps_lock_mute", caps_lock_mute);
+    }
+    config.save()
+}
+
+/// (hidden UIDs, preferred output UID, volume step, Caps Lock mute), or
+/// `None` when skipped.
+fn ask(
+    out: &mut RawTerminal<Stdout>,
+    stdin: &Stdin,
+) -> Option<(Vec<String>, Option<String>, f32, bool)> {
+    let mut audio = AudioState::new(&Config::default());
+    let devices: Vec<(String, String, bool)> = audio
+        .device_list()
+        .into_iter()
+        .map(|(_, _, _, d)| {
+            let output = d.output.borrow();
+            (
+                d.uid.clone(),
+                d.label().to_string(),
+                output.enabled && output.selectable,
+            )
+        })
+        .collect();
+
+    let names: Vec<&str> = devices.iter().map(|(_, name, _)| name.as_str()).collect();
+    let hidden = choose(
+        out,
+        stdin,
+        "Which devices should be hidden? (space to pick, enter when done)",
+        &names,
+        true,
+    )?;
+    let hidden: Vec<String> = hidden.into_iter().map(|i| devices[i].0.clone()).collect();
+
+    let outputs: Vec<&(String, String, bool)> = devices
+        .iter()
+        .filter(|(uid, _, output)| *output && !hidden.contains(uid))
+        .collect();
+    let mut names: Vec<&str> = outputs.iter().map(|(_, name, _)| name.as_str()).collect();
+    names.push("No preference");
+    let preferred = choose(out, stdin, "Preferred headset or speakers?", &names, false)?;
+    let preferred = outputs.get(preferred[0]).map(|(uid, _, _)| uid.clone());
+    if let Some(uid) = &preferred {
+        if let Err(e) = audio.set_default(Channel::Output, uid) {
+            log::warn(e);
+        }
+    }
+
+    let names: Vec<&str> = STEPS.iter().map(|(name, _)| *name).collect();
+    let step = choose(out, stdin, "Volume step?", &names, false)?;
+    let step = STEPS[step[0]].1;
+
+    let caps_lock_mute = choose(
+        out,
+        stdin,
+        "Use Caps Lock to mute the mic? Its light shows when muted.",
+        &["Yes", "No"],
+        false,
+    )?;
+    Some((hidden, preferred, step, caps_lock_mute[0] == 0))
+}
+
+/// Pick from a list, one option or (with `multiple`) any number. `None`
+/// when skipped with escape.
+fn choose(
+    out: &mut RawTerminal<Stdout>,
+    stdin: &Stdin,
+    question: &str,
+    options: &[&str],
+    multiple: bool,
+) -> Option<Vec<usize>> {
+    let mut selected = 0;
+    let mut picked: Vec<usize> = Vec::new();
+    draw(
+        out,
+        question,
+        options,
+        selected,
+        multiple.then_some(&picked),
+    );
+    for key in stdin.lock().keys().flatten() {
+        match key {
+            Key::Up | Key::Char('k') if !options.is_empty() => {
+                selected = (selected + options.len() - 1) % options.len()
+            }
+            Key::Down | Key::Char('j') if !options.is_empty() => {
+                selected = (selected + 1) % options.len()
+            }
+            Key::Char(' ') if multiple => match picked.iter().position(|i| *i == selected) {
+                Some(i) => {
+                    picked.remove(i);
+                }
+                None => picked.push(selected),
+            },
+            Key::Char('\n') if multiple => return Some(picked),
+            Key::Char('\n') => return Some(vec![selected]),
+            Key::Esc | Key::Ctrl('c') => return None,
+            _ => {}
+        }
+        draw(
+            out,
+            question,
+            options,
+            selected,
+            multiple.then_some(&picked),
+        );
+    }
+    None
+}
+
+fn draw(
+    out: &mut RawTerminal<Stdout>,
+    question: &str,
+    options: &[&str],
+    selected: usize,
+    picked: Option<&Vec<usize>>,
+) {
+    let clear_line = termion::clear::CurrentLine;
+    let mut text = format!(
+        "{}{}{}Setup\r\n\r\n{clear_line}{question}\r\n",
+        termion::clear::All,
+        termion::cursor::Goto(1, 2),
+        clear_line
+    );
+    for (i, option) in options.iter().enumerate() {
+        let cursor = if i == selected { "▶" } else { " " };
+        let check = match picked {
+            Some(picked) if picked.contains(&i) => "[x] ",
+            Some(_) => "[ ] ",
+            None => "",
+        };
+        text.push_str(&format!("{clear_line}{cursor} {check}{option}\r\n"));
+    }
+    text.push_str(&format!(
+        "\r\n{clear_line}Esc skips the setup, it can be done later in ~/.config/mac-controls.\r\n"
+    ));
+    write!(out, "{text}").unwrap();
+    out.flush().unwrap();
+}